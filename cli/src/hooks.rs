@@ -0,0 +1,233 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use redscript::bundle::{ConstantPool, PoolIndex};
+use redscript::definition::{AnyDefinition, Function};
+use redscript_compiler::parser::{self, SourceModule};
+use redscript_compiler::source_map::Files;
+use redscript_compiler::unit::{CompilationUnit, Hook};
+use redscript_decompiler::diff::function_signature;
+
+/// A hook whose target could not be resolved against the new pool.
+#[derive(Debug)]
+pub struct BrokenHook {
+    pub hook: Hook,
+    pub reason: Reason,
+}
+
+impl fmt::Display for BrokenHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind: &'static str = self.hook.kind.into();
+        let class = self.hook.class.as_deref().unwrap_or_default();
+        write!(f, "@{kind}({class}) func {}: {}", self.hook.name, self.reason)
+    }
+}
+
+#[derive(Debug)]
+pub enum Reason {
+    /// The target does not resolve against the old pool either.
+    Unresolved(String),
+    SignatureChanged {
+        old: String,
+        new: String,
+    },
+    Renamed(String),
+    Moved(String),
+    ClassRemoved(String),
+    Removed,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Unresolved(err) => write!(f, "does not resolve against the old bundle either: {err}"),
+            Reason::SignatureChanged { old, new } => write!(f, "signature changed from `{old}` to `{new}`"),
+            Reason::Renamed(name) => write!(f, "target was renamed to {name}"),
+            Reason::Moved(name) => write!(f, "target was moved to {name}"),
+            Reason::ClassRemoved(name) => write!(f, "class {name} was removed"),
+            Reason::Removed => write!(f, "target was removed"),
+        }
+    }
+}
+
+/// Parses the files, logging any syntax errors.
+pub fn parse_modules(files: &Files) -> anyhow::Result<Vec<SourceModule>> {
+    let mut modules = vec![];
+    let mut failed = false;
+    for file in files.files() {
+        let (module, errors) = parser::parse_file(file);
+        for diagnostic in &errors {
+            diagnostic.log(files);
+        }
+        failed |= !errors.is_empty();
+        modules.push(module);
+    }
+    if failed {
+        anyhow::bail!("The source files contain syntax errors");
+    }
+    Ok(modules)
+}
+
+/// Resolves the hooks against both pools and returns the ones that do not resolve against the new pool,
+/// along with the total number of hooks.
+pub fn check_hooks(
+    modules: &[SourceModule],
+    old: &mut ConstantPool,
+    new: &mut ConstantPool,
+) -> anyhow::Result<(Vec<BrokenHook>, usize)> {
+    let old_hooks = resolve_hooks(modules, old)?;
+    let new_hooks = resolve_hooks(modules, new)?;
+    let total = new_hooks.len();
+    let old_functions = Functions::collect(old)?;
+    let new_functions = Functions::collect(new)?;
+
+    let mut broken = vec![];
+    for (old_hook, hook) in old_hooks.into_iter().zip(new_hooks) {
+        if hook.target.is_ok() {
+            continue;
+        }
+        let reason = match old_hook.target {
+            Ok(idx) => {
+                let target = old_functions
+                    .get(idx)
+                    .ok_or_else(|| anyhow::anyhow!("Hook target {idx} is not a top-level function or method"))?;
+                find_reason(target, old, new, &old_functions, &new_functions)?
+            }
+            Err(err) => Reason::Unresolved(err.to_string()),
+        };
+        broken.push(BrokenHook { hook, reason });
+    }
+    Ok((broken, total))
+}
+
+fn resolve_hooks(modules: &[SourceModule], pool: &mut ConstantPool) -> anyhow::Result<Vec<Hook>> {
+    let unit = CompilationUnit::new_with_defaults(pool)
+        .map_err(|err| anyhow::anyhow!("Failed to create the compilation unit: {err}"))?;
+    unit.resolve_hooks(modules)
+        .map_err(|err| anyhow::anyhow!("Failed to resolve the hooks: {err}"))
+}
+
+// looks for the counterpart of a function in the new pool, candidates are only reported when they're unambiguous
+fn find_reason(
+    target: &FunctionInfo,
+    old: &ConstantPool,
+    new: &ConstantPool,
+    old_functions: &Functions,
+    new_functions: &Functions,
+) -> anyhow::Result<Reason> {
+    let same_class = |fun: &&FunctionInfo| fun.class == target.class;
+
+    if let Some(fun) = new_functions
+        .iter()
+        .filter(same_class)
+        .find(|fun| fun.name == target.name)
+    {
+        return Ok(Reason::SignatureChanged {
+            old: function_signature(old.function(target.index)?, old)?,
+            new: function_signature(new.function(fun.index)?, new)?,
+        });
+    }
+
+    let renamed = new_functions
+        .iter()
+        .filter(same_class)
+        .filter(|fun| fun.types == target.types)
+        .filter(|fun| {
+            !old_functions
+                .iter()
+                .any(|old| old.class == fun.class && old.name == fun.name)
+        });
+    if let Some(fun) = single(renamed) {
+        return Ok(Reason::Renamed(fun.qualified_name()));
+    }
+
+    let moved = new_functions
+        .iter()
+        .filter(|fun| fun.class != target.class && fun.name == target.name && fun.types == target.types);
+    if let Some(fun) = single(moved) {
+        return Ok(Reason::Moved(fun.qualified_name()));
+    }
+
+    match &target.class {
+        Some(class) if !new_functions.classes.contains(class) => Ok(Reason::ClassRemoved(class.clone())),
+        _ => Ok(Reason::Removed),
+    }
+}
+
+fn single<'a>(mut iter: impl Iterator<Item = &'a FunctionInfo>) -> Option<&'a FunctionInfo> {
+    let first = iter.next()?;
+    iter.next().is_none().then_some(first)
+}
+
+/// All top-level functions and methods of a pool.
+struct Functions {
+    functions: Vec<FunctionInfo>,
+    classes: HashSet<String>,
+}
+
+impl Functions {
+    fn collect(pool: &ConstantPool) -> anyhow::Result<Self> {
+        let mut functions = vec![];
+        let mut classes = HashSet::new();
+        for (idx, def) in pool.roots() {
+            match &def.value {
+                AnyDefinition::Class(class) => {
+                    let name = pool.names.get(def.name)?.to_string();
+                    for &idx in &class.functions {
+                        functions.push(FunctionInfo::new(idx, Some(name.clone()), pool)?);
+                    }
+                    classes.insert(name);
+                }
+                AnyDefinition::Function(_) => functions.push(FunctionInfo::new(idx.cast(), None, pool)?),
+                _ => {}
+            }
+        }
+        Ok(Self { functions, classes })
+    }
+
+    fn get(&self, index: PoolIndex<Function>) -> Option<&FunctionInfo> {
+        self.functions.iter().find(|fun| fun.index == index)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &FunctionInfo> {
+        self.functions.iter()
+    }
+}
+
+struct FunctionInfo {
+    index: PoolIndex<Function>,
+    class: Option<String>,
+    /// The name without the parameter types.
+    name: String,
+    /// The parameter and return types, used to recognize a function under a different name.
+    types: String,
+}
+
+impl FunctionInfo {
+    fn new(index: PoolIndex<Function>, class: Option<String>, pool: &ConstantPool) -> anyhow::Result<Self> {
+        let fun = pool.function(index)?;
+        let mangled = pool.def_name(index)?;
+        let mut types = vec![];
+        for &param in &fun.parameters {
+            types.push(pool.def_name(pool.parameter(param)?.type_)?);
+        }
+        let return_type = match fun.return_type {
+            Some(idx) => pool.def_name(idx)?,
+            None => "Void".into(),
+        };
+        let info = Self {
+            index,
+            class,
+            name: mangled.split(';').next().unwrap_or_default().to_owned(),
+            types: format!("({}) -> {return_type}", types.join(", ")),
+        };
+        Ok(info)
+    }
+
+    fn qualified_name(&self) -> String {
+        match &self.class {
+            Some(class) => format!("{class}.{}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
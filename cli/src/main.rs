@@ -1,18 +1,33 @@
 use std::fs::{self, File};
-use std::io;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use anyhow::Context;
 use argh::FromArgs;
 use flexi_logger::{LevelFilter, LogSpecBuilder, Logger};
-use redscript::bundle::ScriptBundle;
-use redscript::definition::AnyDefinition;
+use redscript::assembly;
+use redscript::bundle::{ConstantPool, PoolIndex, ScriptBundle};
+use redscript::bytecode::ControlFlowGraph;
+use redscript::compact::compact;
+use redscript::definition::{AnyDefinition, Definition};
+use redscript::verify::verify_pool;
+use redscript_compiler::diagnostics::Diagnostic;
+use redscript_compiler::formatter::format_file;
 use redscript_compiler::source_map::Files;
 use redscript_compiler::unit::CompilationUnit;
+use redscript_decompiler::diff::diff_pools;
 use redscript_decompiler::files::FileIndex;
+use redscript_decompiler::graph::{call_graph, class_graph, full_name};
 use redscript_decompiler::print::{write_definition, OutputMode};
+use redscript_decompiler::xref::{declaration, find_definitions, XrefIndex};
+use report::{Format, GraphFormat};
 use vmap::Map;
 
+mod hooks;
+mod report;
+mod test;
+
 /// redscript command line interface
 #[derive(Debug, FromArgs)]
 struct Args {
@@ -26,6 +41,14 @@ enum Command {
     Decompile(DecompileOpts),
     Compile(CompileOpts),
     Lint(LintOpts),
+    Test(TestOpts),
+    Fmt(FmtOpts),
+    Diff(DiffOpts),
+    Hooks(HooksOpts),
+    Verify(VerifyOpts),
+    Xref(XrefOpts),
+    Cfg(CfgOpts),
+    Graph(GraphOpts),
 }
 
 /// decompile a .redscripts file
@@ -63,6 +86,13 @@ struct CompileOpts {
     /// path to an output .redscripts file
     #[argh(option, short = 'o')]
     output: PathBuf,
+    /// diagnostics format, use 'text' for log messages, 'json' for an array of records or
+    /// 'sarif' for a SARIF log, the latter two are written to stdout
+    #[argh(option, default = "Format::Text")]
+    format: Format,
+    /// remove the pool entries that are no longer referenced before saving
+    #[argh(switch)]
+    compact: bool,
 }
 
 /// lint redscript source code
@@ -75,31 +105,186 @@ struct LintOpts {
     /// path to a .redscripts file to use for incremental compilation
     #[argh(option, short = 'b')]
     bundle: Option<PathBuf>,
+    /// diagnostics format, use 'text' for log messages, 'json' for an array of records or
+    /// 'sarif' for a SARIF log, the latter two are written to stdout
+    #[argh(option, default = "Format::Text")]
+    format: Format,
+}
+
+/// run functions annotated with @test on an offline interpreter
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "test")]
+struct TestOpts {
+    /// path to an input source file or directory
+    #[argh(option, short = 's')]
+    src: Vec<PathBuf>,
+    /// path to a .redscripts file to compile the tests against
+    #[argh(option, short = 'b')]
+    bundle: PathBuf,
+    /// only run tests with names containing this string
+    #[argh(option, short = 'f')]
+    filter: Option<String>,
+}
+
+/// format redscript source code
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "fmt")]
+struct FmtOpts {
+    /// path to an input source file or directory
+    #[argh(option, short = 's')]
+    src: Vec<PathBuf>,
+    /// list the files that are not formatted instead of formatting them, fails if there are any
+    #[argh(switch)]
+    check: bool,
+}
+
+/// compare the definitions of two .redscripts files
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "diff")]
+struct DiffOpts {
+    /// path to the old .redscripts file
+    #[argh(positional)]
+    old: PathBuf,
+    /// path to the new .redscripts file
+    #[argh(positional)]
+    new: PathBuf,
+    /// output format, use 'text' for a line per change or 'json' for an array of records
+    #[argh(option, default = "Format::Text")]
+    format: Format,
+}
+
+/// check which hooks of a mod break when the game scripts are updated
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "hooks")]
+struct HooksOpts {
+    /// path to an input source file or directory
+    #[argh(option, short = 's')]
+    src: Vec<PathBuf>,
+    /// path to the .redscripts file the hooks were written against
+    #[argh(option)]
+    old: PathBuf,
+    /// path to the updated .redscripts file
+    #[argh(option)]
+    new: PathBuf,
+}
+
+/// check a .redscripts file for structural errors that would crash the game
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "verify")]
+struct VerifyOpts {
+    /// path to the .redscripts file
+    #[argh(positional)]
+    bundle: PathBuf,
+    /// output format, use 'text' for a line per error or 'json' for an array of records
+    #[argh(option, default = "Format::Text")]
+    format: Format,
+}
+
+/// list the code referring to a class, field or function of a .redscripts file
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "xref")]
+struct XrefOpts {
+    /// name of a class or function, members are qualified with their class like 'Class.member'
+    #[argh(positional)]
+    name: String,
+    /// path to the .redscripts file
+    #[argh(option, short = 'b')]
+    bundle: PathBuf,
+}
+
+/// print the control-flow graph of a function of a .redscripts file in the Graphviz DOT format
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "cfg")]
+struct CfgOpts {
+    /// name of a function, methods are qualified with their class like 'Class.Method'
+    #[argh(positional)]
+    function: String,
+    /// path to the .redscripts file
+    #[argh(option, short = 'b')]
+    bundle: PathBuf,
+}
+
+/// export the class hierarchy or the call graph of a .redscripts file
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "graph")]
+struct GraphOpts {
+    /// kind of graph, use 'classes' for the class hierarchy or 'calls' for the call graph
+    #[argh(option)]
+    kind: GraphKind,
+    /// path to the .redscripts file
+    #[argh(option, short = 'b')]
+    bundle: PathBuf,
+    /// class or function to start from, all base classes or all functions are used when omitted
+    #[argh(option, short = 'r')]
+    root: Vec<String>,
+    /// maximum number of edges between a root and any other node
+    #[argh(option, short = 'd')]
+    depth: Option<usize>,
+    /// output format, use 'dot' for Graphviz or 'json' for a record of nodes and edges
+    #[argh(option, default = "GraphFormat::Dot")]
+    format: GraphFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GraphKind {
+    Classes,
+    Calls,
+}
+
+impl FromStr for GraphKind {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "classes" => Ok(Self::Classes),
+            "calls" => Ok(Self::Calls),
+            other => Err(format!("invalid graph kind '{other}', expected one of: classes, calls")),
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
-    setup_logger();
+    let args: Args = argh::from_env();
+    // stdout is reserved for output meant to be processed by other tools
+    let to_stderr = match &args.command {
+        Command::Compile(opts) => opts.format != Format::Text,
+        Command::Lint(opts) => opts.format != Format::Text,
+        Command::Diff(opts) => opts.format != Format::Text,
+        Command::Verify(opts) => opts.format != Format::Text,
+        Command::Cfg(_) | Command::Graph(_) => true,
+        Command::Decompile(_) | Command::Test(_) | Command::Fmt(_) | Command::Hooks(_) | Command::Xref(_) => false,
+    };
+    setup_logger(to_stderr);
 
-    run().map_err(|err| {
+    run(args).map_err(|err| {
         log::error!("{}", err);
         err
     })
 }
 
-fn setup_logger() {
-    Logger::with(LogSpecBuilder::new().default(LevelFilter::Info).build())
-        .log_to_stdout()
-        .start()
-        .expect("info logger should always start");
+fn setup_logger(to_stderr: bool) {
+    let logger = Logger::with(LogSpecBuilder::new().default(LevelFilter::Info).build());
+    let logger = if to_stderr {
+        logger.log_to_stderr()
+    } else {
+        logger.log_to_stdout()
+    };
+    logger.start().expect("info logger should always start");
 }
 
-fn run() -> anyhow::Result<()> {
-    let args: Args = argh::from_env();
-
+fn run(args: Args) -> anyhow::Result<()> {
     match args.command {
         Command::Decompile(opts) => Ok(decompile(opts)?),
         Command::Compile(opts) => Ok(compile(opts)?),
         Command::Lint(opts) => Ok(lint(opts)?),
+        Command::Test(opts) => Ok(test(opts)?),
+        Command::Fmt(opts) => Ok(fmt(opts)?),
+        Command::Diff(opts) => Ok(diff(opts)?),
+        Command::Hooks(opts) => Ok(hooks(opts)?),
+        Command::Verify(opts) => Ok(verify(opts)?),
+        Command::Xref(opts) => Ok(xref(opts)?),
+        Command::Cfg(opts) => Ok(cfg(opts)?),
+        Command::Graph(opts) => Ok(graph(opts)?),
     }
 }
 
@@ -108,25 +293,45 @@ fn compile(opts: CompileOpts) -> anyhow::Result<()> {
 
     let files = Files::from_dirs(&opts.src).map_err(|err| anyhow::anyhow!("Failed to load the source files: {err}"))?;
 
-    match CompilationUnit::new_with_defaults(&mut bundle.pool)
-        .map_err(|err| anyhow::anyhow!("Failed to create the compilation unit: {err}"))?
-        .compile_and_report(&files)
-    {
-        Ok(_) => {
-            let file = File::create(&opts.output).context("Failed to create a file at the specified output path")?;
-            bundle
-                .save(&mut io::BufWriter::new(file))
-                .context("Failed to write the script cache")?;
-
-            log::info!("Output successfully saved to {}", opts.output.display());
-        }
-        Err(_) => {
-            log::error!("Build failed");
-        }
+    if !compile_and_report(&mut bundle.pool, &files, opts.format)? {
+        anyhow::bail!("Build failed");
     }
+    if opts.compact {
+        let relocation = compact(&mut bundle.pool);
+        log::info!(
+            "Compaction removed {} definitions and {} strings",
+            relocation.removed_definitions(),
+            relocation.removed_strings()
+        );
+    }
+    let file = File::create(&opts.output).context("Failed to create a file at the specified output path")?;
+    bundle
+        .save(&mut io::BufWriter::new(file))
+        .context("Failed to write the script cache")?;
+
+    log::info!("Output successfully saved to {}", opts.output.display());
     Ok(())
 }
 
+/// Compiles the files into the pool and reports diagnostics in the given format.
+/// Returns whether the compilation succeeded.
+fn compile_and_report(pool: &mut ConstantPool, files: &Files, format: Format) -> anyhow::Result<bool> {
+    let unit = CompilationUnit::new_with_defaults(pool)
+        .map_err(|err| anyhow::anyhow!("Failed to create the compilation unit: {err}"))?;
+    if format == Format::Text {
+        return Ok(unit.compile_and_report(files).is_ok());
+    }
+
+    let diagnostics =
+        match unit.compile_files(files) {
+            Ok(output) => output.into_diagnostics(),
+            Err(err) => vec![Diagnostic::from_error(err)
+                .map_err(|err| anyhow::anyhow!("Unexpected error during compilation: {err}"))?],
+        };
+    report::write_report(&mut io::stdout().lock(), &diagnostics, files, format)?;
+    Ok(!diagnostics.iter().any(Diagnostic::is_fatal))
+}
+
 fn decompile(opts: DecompileOpts) -> anyhow::Result<()> {
     let bundle = load_bundle(&opts.input)?;
     let pool = &bundle.pool;
@@ -177,23 +382,218 @@ fn lint(opts: LintOpts) -> anyhow::Result<()> {
             let files =
                 Files::from_dirs(&opts.src).map_err(|err| anyhow::anyhow!("Failed to load the source files: {err}"))?;
 
-            if CompilationUnit::new_with_defaults(&mut bundle.pool)
-                .map_err(|err| anyhow::anyhow!("Failed to create the compilation unit: {err}"))?
-                .compile_and_report(&files)
-                .is_ok()
-            {
-                log::info!("Lint successful");
+            if !compile_and_report(&mut bundle.pool, &files, opts.format)? {
+                anyhow::bail!("Lint failed");
             }
+            log::info!("Lint successful");
             Ok(())
         }
         None => Ok(()),
     }
 }
 
+fn test(opts: TestOpts) -> anyhow::Result<()> {
+    let mut bundle = load_bundle(&opts.bundle)?;
+    let mut files =
+        Files::from_dirs(&opts.src).map_err(|err| anyhow::anyhow!("Failed to load the source files: {err}"))?;
+    files.add(PathBuf::from("<test prelude>"), test::prelude());
+
+    let output = CompilationUnit::new_with_defaults(&mut bundle.pool)
+        .map_err(|err| anyhow::anyhow!("Failed to create the compilation unit: {err}"))?
+        .compile_and_report(&files)
+        .map_err(|_| anyhow::anyhow!("Build failed"))?;
+
+    let summary = test::run_tests(&bundle.pool, &output, &files, opts.filter.as_deref())?;
+    log::info!("{} passed, {} failed", summary.passed, summary.failed);
+    if summary.failed > 0 {
+        anyhow::bail!("{} test(s) failed", summary.failed);
+    }
+    Ok(())
+}
+
+fn fmt(opts: FmtOpts) -> anyhow::Result<()> {
+    let files = Files::from_dirs(&opts.src).map_err(|err| anyhow::anyhow!("Failed to load the source files: {err}"))?;
+
+    let mut invalid = 0;
+    let mut changed = 0;
+    for file in files.files() {
+        match format_file(file) {
+            Ok(formatted) if formatted == file.source() => {}
+            Ok(_) if opts.check => {
+                println!("{}", file.path().display());
+                changed += 1;
+            }
+            Ok(formatted) => {
+                fs::write(file.path(), formatted)
+                    .with_context(|| format!("Failed to write {}", file.path().display()))?;
+                changed += 1;
+            }
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    diagnostic.log(&files);
+                }
+                invalid += 1;
+            }
+        }
+    }
+
+    if invalid > 0 {
+        anyhow::bail!("{invalid} file(s) could not be parsed");
+    }
+    if opts.check && changed > 0 {
+        anyhow::bail!("{changed} file(s) are not formatted");
+    }
+    if !opts.check {
+        log::info!("Formatted {changed} file(s)");
+    }
+    Ok(())
+}
+
+fn diff(opts: DiffOpts) -> anyhow::Result<()> {
+    if opts.format == Format::Sarif {
+        anyhow::bail!("SARIF output is not supported for diffs");
+    }
+    let old = load_bundle(&opts.old)?;
+    let new = load_bundle(&opts.new)?;
+    let changes = diff_pools(&old.pool, &new.pool).context("Failed to compare the script caches")?;
+
+    report::write_changes(&mut io::stdout().lock(), &changes, opts.format)?;
+    log::info!("Found {} change(s)", changes.len());
+    Ok(())
+}
+
+fn hooks(opts: HooksOpts) -> anyhow::Result<()> {
+    let mut old = load_bundle(&opts.old)?;
+    let mut new = load_bundle(&opts.new)?;
+    let files = Files::from_dirs(&opts.src).map_err(|err| anyhow::anyhow!("Failed to load the source files: {err}"))?;
+    let modules = hooks::parse_modules(&files)?;
+
+    let (broken, total) = hooks::check_hooks(&modules, &mut old.pool, &mut new.pool)?;
+    for hook in &broken {
+        if let Some(loc) = files.lookup(hook.hook.span) {
+            log::error!("{loc}: {hook}");
+        } else {
+            log::error!("{hook}");
+        }
+    }
+    if !broken.is_empty() {
+        anyhow::bail!("{} of {total} hook(s) are broken", broken.len());
+    }
+    log::info!("All {total} hook(s) resolved");
+    Ok(())
+}
+
+fn verify(opts: VerifyOpts) -> anyhow::Result<()> {
+    if opts.format == Format::Sarif {
+        anyhow::bail!("SARIF output is not supported for verification");
+    }
+    let bundle = load_bundle(&opts.bundle)?;
+    let errors = verify_pool(&bundle.pool);
+
+    report::write_verify_errors(&mut io::stdout().lock(), &errors, opts.format)?;
+    if !errors.is_empty() {
+        anyhow::bail!("Found {} error(s)", errors.len());
+    }
+    log::info!("No errors found");
+    Ok(())
+}
+
+fn xref(opts: XrefOpts) -> anyhow::Result<()> {
+    let bundle = load_bundle(&opts.bundle)?;
+    let definitions = find_definitions(&bundle.pool, &opts.name)?;
+    if definitions.is_empty() {
+        anyhow::bail!("No class, field or function named '{}' found", opts.name);
+    }
+    let index = XrefIndex::new(&bundle.pool)?;
+
+    let mut out = io::stdout().lock();
+    let mut total = 0;
+    for idx in definitions {
+        let refs = index.references(idx, &bundle.pool)?;
+        writeln!(out, "{}", declaration(bundle.pool.definition(idx)?, &bundle.pool)?)?;
+        for xref in &refs {
+            writeln!(out, "    {xref}")?;
+        }
+        total += refs.len();
+    }
+    log::info!("Found {total} reference(s)");
+    Ok(())
+}
+
+fn cfg(opts: CfgOpts) -> anyhow::Result<()> {
+    let bundle = load_bundle(&opts.bundle)?;
+    let pool = &bundle.pool;
+    let functions: Vec<_> = find_definitions(pool, &opts.function)?
+        .into_iter()
+        .filter(|&idx| pool.function(idx.cast()).is_ok())
+        .collect();
+    let index = match functions[..] {
+        [index] => index,
+        [] => anyhow::bail!("No function named '{}' found", opts.function),
+        _ => {
+            let names = functions
+                .iter()
+                .map(|&idx| full_name(pool, idx))
+                .collect::<Result<Vec<_>, _>>()?;
+            anyhow::bail!("'{}' is ambiguous, use one of: {}", opts.function, names.join(", "))
+        }
+    };
+
+    let fun = pool.function(index.cast())?;
+    let graph = ControlFlowGraph::new(&fun.code).context("Failed to build the control-flow graph")?;
+    let mut dot = String::new();
+    assembly::write_dot(&mut dot, &full_name(pool, index)?, fun, &graph, pool)?;
+    io::stdout().lock().write_all(dot.as_bytes())?;
+    log::info!("The graph has {} block(s)", graph.blocks().len());
+    Ok(())
+}
+
+fn graph(opts: GraphOpts) -> anyhow::Result<()> {
+    let bundle = load_bundle(&opts.bundle)?;
+    let pool = &bundle.pool;
+    let graph = match opts.kind {
+        GraphKind::Classes => {
+            let roots = find_roots(pool, &opts.root, "class", |idx| pool.class(idx.cast()).is_ok())?;
+            class_graph(pool, &roots, opts.depth)?
+        }
+        GraphKind::Calls => {
+            let roots = find_roots(pool, &opts.root, "function", |idx| pool.function(idx.cast()).is_ok())?;
+            call_graph(pool, &roots, opts.depth)?
+        }
+    };
+
+    report::write_graph(&mut io::stdout().lock(), &graph, opts.format)?;
+    log::info!(
+        "The graph has {} node(s) and {} edge(s)",
+        graph.nodes.len(),
+        graph.edges.len()
+    );
+    Ok(())
+}
+
+// every name has to match at least one definition of the expected kind
+fn find_roots<A>(
+    pool: &ConstantPool,
+    names: &[String],
+    kind: &str,
+    is_kind: impl Fn(PoolIndex<Definition>) -> bool,
+) -> anyhow::Result<Vec<PoolIndex<A>>> {
+    let mut roots = vec![];
+    for name in names {
+        let count = roots.len();
+        let matches = find_definitions(pool, name)?.into_iter().filter(|&idx| is_kind(idx));
+        roots.extend(matches.map(|idx| idx.cast()));
+        if roots.len() == count {
+            anyhow::bail!("No {kind} named '{name}' found");
+        }
+    }
+    Ok(roots)
+}
+
 fn load_bundle(path: &Path) -> anyhow::Result<ScriptBundle> {
     let (map, _) = Map::with_options()
         .open(path)
         .context("Failed to open the script cache")?;
     let mut reader = io::Cursor::new(map.as_ref());
-    ScriptBundle::load(&mut reader).context("Failed to load the script cache")
+    ScriptBundle::load(&mut reader).map_err(|err| anyhow::anyhow!("Failed to load the script cache: {err}"))
 }
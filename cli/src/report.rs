@@ -0,0 +1,457 @@
+use std::fmt::{self, Write as _};
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use redscript::verify::VerifyError;
+use redscript_compiler::diagnostics::Diagnostic;
+use redscript_compiler::source_map::{Files, SourceLoc};
+use redscript_decompiler::diff::{Change, ChangeKind};
+use redscript_decompiler::graph::{EdgeKind, Graph};
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const TOOL_URI: &str = "https://github.com/jac3km4/redscript";
+
+/// The format in which compilation diagnostics are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Sarif,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "sarif" => Ok(Self::Sarif),
+            other => Err(format!("invalid format '{other}', expected one of: text, json, sarif")),
+        }
+    }
+}
+
+/// The format in which graphs are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "dot" => Ok(Self::Dot),
+            "json" => Ok(Self::Json),
+            other => Err(format!("invalid format '{other}', expected one of: dot, json")),
+        }
+    }
+}
+
+/// Writes the diagnostics in the given format.
+///
+/// JSON output is an array with a record per diagnostic, SARIF output is a log with a single run.
+/// Line and column numbers are 1-based in both cases.
+pub fn write_report<W: io::Write>(
+    out: &mut W,
+    diagnostics: &[Diagnostic],
+    files: &Files,
+    format: Format,
+) -> io::Result<()> {
+    match format {
+        Format::Text => {
+            for diagnostic in diagnostics {
+                write!(out, "{}", diagnostic.display(files))?;
+            }
+            Ok(())
+        }
+        Format::Json => {
+            let records = diagnostics.iter().map(|diagnostic| json_record(diagnostic, files));
+            writeln!(out, "{}", Json::Array(records.collect()))
+        }
+        Format::Sarif => writeln!(out, "{}", sarif_log(diagnostics, files)),
+    }
+}
+
+/// Writes the changes between two bundles, SARIF is not supported.
+///
+/// JSON output is an array with a record per change, the `old` and `new` fields are only present
+/// for signature and flag changes.
+pub fn write_changes<W: io::Write>(out: &mut W, changes: &[Change], format: Format) -> io::Result<()> {
+    if format == Format::Text {
+        for change in changes {
+            writeln!(out, "{change}")?;
+        }
+        return Ok(());
+    }
+
+    let records = changes.iter().map(|change| {
+        let mut record = vec![
+            ("item", Json::from(change.item.name())),
+            ("name", Json::String(change.name.clone())),
+            ("signature", Json::String(change.signature.clone())),
+            ("change", Json::from(change.kind.name())),
+        ];
+        if let ChangeKind::Signature { old, new } | ChangeKind::Flags { old, new } = &change.kind {
+            record.push(("old", Json::String(old.clone())));
+            record.push(("new", Json::String(new.clone())));
+        }
+        Json::Object(record)
+    });
+    writeln!(out, "{}", Json::Array(records.collect()))
+}
+
+/// Writes a graph in the given format.
+///
+/// JSON output is a record with an array of nodes and an array of edges, which refer to nodes by their names.
+pub fn write_graph<W: io::Write>(out: &mut W, graph: &Graph, format: GraphFormat) -> io::Result<()> {
+    if format == GraphFormat::Dot {
+        writeln!(out, "digraph {{")?;
+        writeln!(out, "    node [shape=box];")?;
+        for (i, node) in graph.nodes.iter().enumerate() {
+            writeln!(out, "    n{i} [label={}];", Json::String(node.name.clone()))?;
+        }
+        for edge in &graph.edges {
+            match edge.kind {
+                EdgeKind::VirtualCall => writeln!(out, "    n{} -> n{} [style=dashed];", edge.from, edge.to)?,
+                EdgeKind::Subclass | EdgeKind::StaticCall => writeln!(out, "    n{} -> n{};", edge.from, edge.to)?,
+            }
+        }
+        return writeln!(out, "}}");
+    }
+
+    let nodes = graph.nodes.iter().map(|node| {
+        Json::Object(vec![
+            ("name", Json::String(node.name.clone())),
+            ("depth", Json::Number(node.depth)),
+        ])
+    });
+    let edges = graph.edges.iter().map(|edge| {
+        Json::Object(vec![
+            ("from", Json::String(graph.nodes[edge.from].name.clone())),
+            ("to", Json::String(graph.nodes[edge.to].name.clone())),
+            ("kind", Json::from(edge.kind.name())),
+        ])
+    });
+    let record = Json::Object(vec![
+        ("nodes", Json::Array(nodes.collect())),
+        ("edges", Json::Array(edges.collect())),
+    ]);
+    writeln!(out, "{record}")
+}
+
+/// Writes the errors found by the verifier, SARIF is not supported.
+///
+/// JSON output is an array with a record per error, identifying the definition by its pool index and name.
+pub fn write_verify_errors<W: io::Write>(out: &mut W, errors: &[VerifyError], format: Format) -> io::Result<()> {
+    if format == Format::Text {
+        for error in errors {
+            writeln!(out, "{error}")?;
+        }
+        return Ok(());
+    }
+
+    let records = errors.iter().map(|error| {
+        Json::Object(vec![
+            ("index", Json::Number(u32::from(error.definition) as usize)),
+            ("name", Json::String(error.name.clone())),
+            ("message", Json::String(error.kind.to_string())),
+        ])
+    });
+    writeln!(out, "{}", Json::Array(records.collect()))
+}
+
+fn json_record(diagnostic: &Diagnostic, files: &Files) -> Json {
+    let loc = files.lookup(diagnostic.span());
+    Json::Object(vec![
+        ("severity", Json::from(severity(diagnostic))),
+        ("code", Json::from(diagnostic.code())),
+        ("message", Json::String(diagnostic.message(files).to_string())),
+        ("file", loc.as_ref().map_or(Json::Null, |loc| Json::String(path(loc)))),
+        (
+            "start",
+            loc.as_ref()
+                .map_or(Json::Null, |loc| position(loc.start.line, loc.start.col)),
+        ),
+        (
+            "end",
+            loc.as_ref()
+                .map_or(Json::Null, |loc| position(loc.end.line, loc.end.col)),
+        ),
+    ])
+}
+
+fn sarif_log(diagnostics: &[Diagnostic], files: &Files) -> Json {
+    let mut codes: Vec<&str> = vec![];
+    for diagnostic in diagnostics {
+        if !codes.contains(&diagnostic.code()) {
+            codes.push(diagnostic.code());
+        }
+    }
+    let rules = codes
+        .into_iter()
+        .map(|code| Json::Object(vec![("id", Json::from(code))]))
+        .collect();
+
+    let results = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let mut result = vec![
+                ("ruleId", Json::from(diagnostic.code())),
+                ("level", Json::from(severity(diagnostic))),
+                (
+                    "message",
+                    Json::Object(vec![("text", Json::String(diagnostic.message(files).to_string()))]),
+                ),
+            ];
+            if let Some(loc) = files.lookup(diagnostic.span()) {
+                let region = Json::Object(vec![
+                    ("startLine", Json::Number(loc.start.line + 1)),
+                    ("startColumn", Json::Number(loc.start.col + 1)),
+                    ("endLine", Json::Number(loc.end.line + 1)),
+                    ("endColumn", Json::Number(loc.end.col + 1)),
+                ]);
+                let artifact = Json::Object(vec![("uri", Json::String(sarif_uri(loc.file.path())))]);
+                let physical = Json::Object(vec![("artifactLocation", artifact), ("region", region)]);
+                let location = Json::Object(vec![("physicalLocation", physical)]);
+                result.push(("locations", Json::Array(vec![location])));
+            }
+            Json::Object(result)
+        })
+        .collect();
+
+    let driver = Json::Object(vec![
+        ("name", Json::from("redscript")),
+        ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+        ("informationUri", Json::from(TOOL_URI)),
+        ("rules", Json::Array(rules)),
+    ]);
+    let run = Json::Object(vec![
+        ("tool", Json::Object(vec![("driver", driver)])),
+        ("results", Json::Array(results)),
+    ]);
+    Json::Object(vec![
+        ("$schema", Json::from(SARIF_SCHEMA)),
+        ("version", Json::from("2.1.0")),
+        ("runs", Json::Array(vec![run])),
+    ])
+}
+
+fn severity(diagnostic: &Diagnostic) -> &'static str {
+    if diagnostic.is_fatal() {
+        "error"
+    } else {
+        "warning"
+    }
+}
+
+fn path(loc: &SourceLoc<'_>) -> String {
+    loc.file.path().display().to_string()
+}
+
+// relative paths are kept as they are so that they can be resolved against the repository root,
+// drive letters are checked by hand because Windows paths are relative on other platforms
+fn sarif_uri(path: &Path) -> String {
+    let str = path.display().to_string().replace('\\', "/").replace(' ', "%20");
+    let has_drive = matches!(str.as_bytes(), [drive, b':', b'/', ..] if drive.is_ascii_alphabetic());
+    if path.is_absolute() || has_drive {
+        format!("file:///{}", str.trim_start_matches('/'))
+    } else {
+        str
+    }
+}
+
+fn position(line: usize, col: usize) -> Json {
+    Json::Object(vec![
+        ("line", Json::Number(line + 1)),
+        ("column", Json::Number(col + 1)),
+    ])
+}
+
+/// A minimal JSON document, only covers what's needed to write reports.
+enum Json {
+    Null,
+    Number(usize),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl From<&str> for Json {
+    fn from(str: &str) -> Self {
+        Self::String(str.to_owned())
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Number(num) => write!(f, "{num}"),
+            Self::String(str) => write_json_str(f, str),
+            Self::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_char(']')
+            }
+            Self::Object(entries) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_json_str(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_json_str(f: &mut fmt::Formatter<'_>, str: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for char in str.chars() {
+        match char {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            char if char.is_control() => write!(f, "\\u{:04x}", char as u32)?,
+            char => f.write_char(char)?,
+        }
+    }
+    f.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use redscript::ast::{Ident, Pos, Span};
+    use redscript_compiler::error::Cause;
+
+    use super::*;
+
+    const SOURCE: &str = "func Test() {\n  let x = Say();\n}\n";
+
+    fn files() -> Files {
+        let mut files = Files::new();
+        files.add(PathBuf::from("scripts/test.reds"), SOURCE.to_owned());
+        files
+    }
+
+    fn diagnostics() -> Vec<Diagnostic> {
+        let name = Ident::from_heap("Say\"Hi\"\t\u{1}".into());
+        vec![
+            Diagnostic::CompileError(Cause::FunctionNotFound(name), Span::new(Pos::new(24), Pos::new(29))),
+            Diagnostic::UnusedLocal(Span::new(Pos::new(16), Pos::new(21))),
+            Diagnostic::UnusedLocal(Span::new(Pos::new(16), Pos::new(21))),
+        ]
+    }
+
+    fn report(format: Format) -> String {
+        let mut out = vec![];
+        write_report(&mut out, &diagnostics(), &files(), format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_report() {
+        let expected = concat!(
+            r#"[{"severity":"error","code":"UNRESOLVED_FN","message":"function Say\"Hi\"\t\u0001 not found","#,
+            r#""file":"scripts/test.reds","start":{"line":2,"column":11},"end":{"line":2,"column":16}},"#,
+            r#"{"severity":"warning","code":"UNUSED_LOCAL","message":"this variable is never used","#,
+            r#""file":"scripts/test.reds","start":{"line":2,"column":3},"end":{"line":2,"column":8}},"#,
+            r#"{"severity":"warning","code":"UNUSED_LOCAL","message":"this variable is never used","#,
+            r#""file":"scripts/test.reds","start":{"line":2,"column":3},"end":{"line":2,"column":8}}]"#,
+            "\n"
+        );
+        assert_eq!(report(Format::Json), expected);
+    }
+
+    #[test]
+    fn sarif_report() {
+        let location = |start: usize, end: usize| {
+            format!(
+                r#""locations":[{{"physicalLocation":{{"artifactLocation":{{"uri":"scripts/test.reds"}},"region":{{"startLine":2,"startColumn":{start},"endLine":2,"endColumn":{end}}}}}}}]"#
+            )
+        };
+        let unused = format!(
+            r#"{{"ruleId":"UNUSED_LOCAL","level":"warning","message":{{"text":"this variable is never used"}},{}}}"#,
+            location(3, 8)
+        );
+        let expected = format!(
+            concat!(
+                r#"{{"$schema":"https://json.schemastore.org/sarif-2.1.0.json","version":"2.1.0","runs":[{{"tool":{{"#,
+                r#""driver":{{"name":"redscript","version":"{}","informationUri":"https://github.com/jac3km4/redscript","#,
+                r#""rules":[{{"id":"UNRESOLVED_FN"}},{{"id":"UNUSED_LOCAL"}}]}}}},"results":["#,
+                r#"{{"ruleId":"UNRESOLVED_FN","level":"error","message":{{"text":"function Say\"Hi\"\t\u0001 not found"}},{}}},"#,
+                r#"{},{}]}}]}}"#,
+                "\n"
+            ),
+            env!("CARGO_PKG_VERSION"),
+            location(11, 16),
+            unused,
+            unused
+        );
+        assert_eq!(report(Format::Sarif), expected);
+    }
+
+    #[test]
+    fn write_json() {
+        let json = Json::Object(vec![
+            ("null", Json::Null),
+            ("number", Json::Number(42)),
+            (
+                "array",
+                Json::Array(vec![Json::from("a"), Json::Array(vec![]), Json::Object(vec![])]),
+            ),
+        ]);
+        assert_eq!(json.to_string(), r#"{"null":null,"number":42,"array":["a",[],{}]}"#);
+    }
+
+    #[test]
+    fn escape_json_strings() {
+        let json = Json::from("\"quoted\" back\\slash\nline\rreturn\ttab\u{0}\u{1f}\u{7f}é");
+        assert_eq!(
+            json.to_string(),
+            r#""\"quoted\" back\\slash\nline\rreturn\ttab\u0000\u001f\u007fé""#
+        );
+    }
+
+    #[test]
+    fn one_based_positions() {
+        assert_eq!(position(0, 0).to_string(), r#"{"line":1,"column":1}"#);
+        assert_eq!(position(9, 4).to_string(), r#"{"line":10,"column":5}"#);
+    }
+
+    #[test]
+    fn sarif_uris() {
+        let uri = |path: &str| sarif_uri(Path::new(path));
+        assert_eq!(uri("scripts/my mod/test.reds"), "scripts/my%20mod/test.reds");
+        assert_eq!(uri("/home/user/test.reds"), "file:///home/user/test.reds");
+        assert_eq!(
+            uri("C:\\Games\\Cyberpunk 2077\\test.reds"),
+            "file:///C:/Games/Cyberpunk%202077/test.reds"
+        );
+        assert_eq!(uri("scripts\\test.reds"), "scripts/test.reds");
+    }
+
+    #[test]
+    fn deduplicate_sarif_rules() {
+        let log = sarif_log(&diagnostics(), &files()).to_string();
+        assert_eq!(log.matches(r#"{"id":"UNUSED_LOCAL"}"#).count(), 1);
+        assert_eq!(log.matches(r#""ruleId":"UNUSED_LOCAL""#).count(), 2);
+    }
+}
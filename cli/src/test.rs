@@ -0,0 +1,132 @@
+use std::fmt::Write;
+
+use redscript::ast::Span;
+use redscript::bundle::{ConstantPool, PoolIndex};
+use redscript::definition::Function;
+use redscript::interpreter::{Interpreter, NativeCall, Natives, RuntimeError, Value};
+use redscript_compiler::source_map::{Files, SourceLoc};
+use redscript_compiler::unit::CompilationOutput;
+
+const ASSERTABLE_TYPES: &[&str] = &[
+    "Bool", "Int8", "Int16", "Int32", "Int64", "Uint8", "Uint16", "Uint32", "Uint64", "Float", "Double", "String",
+    "CName",
+];
+
+/// Returns the source of the native assertion functions available to tests.
+pub fn prelude() -> String {
+    let mut source = String::new();
+    source.push_str("native func Assert(condition: Bool, opt message: String)\n");
+    source.push_str("native func Fail(opt message: String)\n");
+    for typ in ASSERTABLE_TYPES {
+        writeln!(
+            source,
+            "native func AssertEqual(actual: {typ}, expected: {typ}, opt message: String)\n\
+             native func AssertNotEqual(actual: {typ}, expected: {typ}, opt message: String)"
+        )
+        .unwrap();
+    }
+    source
+}
+
+#[derive(Debug, Default)]
+pub struct TestSummary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// Runs every test function in the compiled output and logs the result of each.
+pub fn run_tests(
+    pool: &ConstantPool,
+    output: &CompilationOutput,
+    files: &Files,
+    filter: Option<&str>,
+) -> anyhow::Result<TestSummary> {
+    let mut interpreter = Interpreter::new(pool, natives());
+    let mut summary = TestSummary::default();
+
+    for &test in output.tests() {
+        let full_name = pool.def_name(test)?;
+        let name = full_name.split(';').next().unwrap_or_default();
+        if filter.map_or(false, |filter| !name.contains(filter)) {
+            continue;
+        }
+
+        match interpreter.call(test, None, &mut []) {
+            Ok(_) => {
+                log::info!("PASS {name}");
+                summary.passed += 1;
+            }
+            Err(RuntimeError::AssertionFailed {
+                message,
+                function,
+                line,
+            }) => {
+                if let Some(loc) = lookup(function, output, files) {
+                    log::error!("FAIL {name} at {}:{}: {message}", loc.file.path().display(), line + 1);
+                } else {
+                    log::error!("FAIL {name}: {message}");
+                }
+                summary.failed += 1;
+            }
+            Err(err) => {
+                if let Some(loc) = lookup(test, output, files) {
+                    log::error!("FAIL {name} at {loc}: {err}");
+                } else {
+                    log::error!("FAIL {name}: {err}");
+                }
+                summary.failed += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+fn lookup<'a>(index: PoolIndex<Function>, output: &CompilationOutput, files: &'a Files) -> Option<SourceLoc<'a>> {
+    let pos = output
+        .source_refs()
+        .iter()
+        .find(|source_ref| source_ref.index() == index.cast())?
+        .pos();
+    files.lookup(Span::new(pos, pos))
+}
+
+fn natives() -> Natives {
+    let mut natives = Natives::with_defaults();
+    natives.register("Assert", |call| match call.arg(0)? {
+        Value::Bool(true) => Ok(Value::Void),
+        _ => Err(failure(call, 1, "condition is false")),
+    });
+    natives.register("Fail", |call| Err(failure(call, 0, "")));
+    natives.register("AssertEqual", |call| {
+        let (actual, expected) = (call.arg(0)?, call.arg(1)?);
+        if actual == expected {
+            Ok(Value::Void)
+        } else {
+            Err(failure(call, 2, &format!("expected {expected}, got {actual}")))
+        }
+    });
+    natives.register("AssertNotEqual", |call| {
+        let (actual, expected) = (call.arg(0)?, call.arg(1)?);
+        if actual == expected {
+            Err(failure(call, 2, &format!("expected a value other than {expected}")))
+        } else {
+            Ok(Value::Void)
+        }
+    });
+    natives
+}
+
+fn failure(call: &NativeCall<'_>, message_arg: usize, details: &str) -> RuntimeError {
+    let message = call.arg(message_arg).ok().and_then(Value::as_str).unwrap_or_default();
+    let message = match (message, details) {
+        ("", "") => "explicit failure".to_owned(),
+        ("", details) => details.to_owned(),
+        (message, "") => message.to_owned(),
+        (message, details) => format!("{message} ({details})"),
+    };
+    RuntimeError::AssertionFailed {
+        message,
+        function: call.caller,
+        line: call.line,
+    }
+}
@@ -1,7 +1,7 @@
 use itertools::Itertools;
 use redscript::ast::{Constant, Expr, Ident, Intrinsic, Literal, Seq, Span, TypeName};
 use redscript::bundle::{ConstantPool, PoolIndex};
-use redscript::bytecode::{Code, Instr, Label, Location, Offset};
+use redscript::bytecode::{Code, Instr, Label, Offset};
 use redscript::definition::{Definition, Function, Local, Type};
 
 use crate::error::{Cause, Error, ResultSpan};
@@ -10,10 +10,13 @@ use crate::source_map::Files;
 use crate::symbol::Symbol;
 use crate::typechecker::{type_of, Callable, Member, TypedAst, TypedExpr, TypedExprExt};
 
+pub mod optimizer;
+
 pub struct Assembler<'a> {
     files: &'a Files,
     instructions: Vec<Instr<Label>>,
     labels: usize,
+    loop_label: Option<Label>,
 }
 
 impl<'a> Assembler<'a> {
@@ -22,6 +25,7 @@ impl<'a> Assembler<'a> {
             files,
             instructions: Vec::new(),
             labels: 0,
+            loop_label: None,
         }
     }
 
@@ -224,7 +228,9 @@ impl<'a> Assembler<'a> {
                 self.emit_label(loop_label);
                 self.emit(Instr::JumpIfFalse(exit_label));
                 self.assemble(*cond, scope, pool, None)?;
+                let outer_loop = self.loop_label.replace(loop_label);
                 self.assemble_seq(body, scope, pool, Some(exit_label))?;
+                self.loop_label = outer_loop;
                 self.emit(Instr::Jump(loop_label));
                 self.emit_label(exit_label);
             }
@@ -275,14 +281,19 @@ impl<'a> Assembler<'a> {
             Expr::Break(_) if exit.is_some() => {
                 self.emit(Instr::Jump(exit.unwrap()));
             }
+            Expr::Continue(_) if self.loop_label.is_some() => {
+                self.emit(Instr::Jump(self.loop_label.unwrap()));
+            }
             Expr::ArrayLit(_, _, span) => return Err(Cause::UnsupportedFeature("ArrayLit").with_span(span)),
             Expr::InterpolatedString(_, _, span) => {
                 return Err(Cause::UnsupportedFeature("InterpolatedString").with_span(span))
             }
             Expr::ForIn(_, _, _, span) => return Err(Cause::UnsupportedFeature("For-in").with_span(span)),
+            Expr::Lambda(_, _, span) => return Err(Cause::UnsupportedFeature("Lambda").with_span(span)),
             Expr::BinOp(_, _, _, span) => return Err(Cause::UnsupportedFeature("BinOp").with_span(span)),
             Expr::UnOp(_, _, span) => return Err(Cause::UnsupportedFeature("UnOp").with_span(span)),
             Expr::Break(span) => return Err(Cause::UnsupportedFeature("Break").with_span(span)),
+            Expr::Continue(span) => return Err(Cause::UnsupportedFeature("Continue").with_span(span)),
             Expr::Goto(_, span) => return Err(Cause::UnsupportedFeature("Goto").with_span(span)),
         };
         Ok(())
@@ -611,22 +622,14 @@ impl<'a> Assembler<'a> {
         Ok(())
     }
 
-    fn into_code(self) -> Code<Offset> {
-        let mut locations = Vec::with_capacity(self.labels);
-        locations.resize(self.labels, Location::new(0));
-
+    fn into_code(self, pool: &ConstantPool, optimize: bool) -> Code<Offset> {
         let code = Code::new(self.instructions);
-        for (loc, instr) in code.iter() {
-            if let Instr::Target(label) = instr {
-                locations[label.index] = loc;
-            }
-        }
-
-        let mut resolved = Vec::with_capacity(code.len());
-        for (loc, instr) in code.iter().filter(|(_, instr)| !matches!(instr, Instr::Target(_))) {
-            resolved.push(instr.resolve_labels(loc, &locations));
-        }
-        Code::new(resolved)
+        let code = if optimize {
+            optimizer::optimize(code, pool)
+        } else {
+            code
+        };
+        code.resolve_labels(self.labels)
     }
 
     pub fn from_body(
@@ -634,10 +637,11 @@ impl<'a> Assembler<'a> {
         files: &'a Files,
         scope: &mut Scope,
         pool: &mut ConstantPool,
+        optimize: bool,
     ) -> Result<Code<Offset>, Error> {
         let mut assembler = Self::new(files);
         assembler.assemble_seq(seq, scope, pool, None)?;
         assembler.emit(Instr::Nop);
-        Ok(assembler.into_code())
+        Ok(assembler.into_code(pool, optimize))
     }
 }
@@ -0,0 +1,368 @@
+use std::str::FromStr;
+
+use hashbrown::HashMap;
+use redscript::ast::BinOp;
+use redscript::bundle::ConstantPool;
+use redscript::bytecode::{Code, Instr, Label};
+
+/// Optimizes the code of a function body before its labels are resolved.
+/// The passes below are repeated for as long as any of them makes a change:
+/// - calls to native binary operators with two literal arguments are folded into a literal
+/// - conditional jumps on constant conditions are made unconditional or removed
+/// - jumps leading to other jumps are redirected to the final target, and jumps to the instruction
+///   that follows them are removed
+/// - code after a `Return` or a `Jump` that can't be reached through any label is removed
+/// - `Nop` statements are removed, except for the one that terminates the function
+pub fn optimize(code: Code<Label>, pool: &ConstantPool) -> Code<Label> {
+    let mut code = code.into_vec();
+    loop {
+        let mut changed = fold_constants(&mut code, pool);
+        changed |= simplify_branches(&mut code);
+        changed |= thread_jumps(&mut code);
+        changed |= remove_unreachable(&mut code);
+        changed |= remove_nops(&mut code);
+        if !changed {
+            break;
+        }
+    }
+    Code::new(code)
+}
+
+fn fold_constants(code: &mut Vec<Instr<Label>>, pool: &ConstantPool) -> bool {
+    let mut changed = false;
+    // going backwards folds nested calls before the calls that take them as arguments
+    for i in (0..code.len()).rev() {
+        if let Some(folded) = fold_call(&code[i..], pool) {
+            code.splice(i..i + 5, [folded]);
+            changed = true;
+        }
+    }
+    changed
+}
+
+// folds a call at the start of the code if it's a native operator applied to two literals
+fn fold_call(code: &[Instr<Label>], pool: &ConstantPool) -> Option<Instr<Label>> {
+    let [Instr::InvokeStatic(exit, _, fun_idx, _), lhs, rhs, Instr::ParamEnd, Instr::Target(label), ..] = code else {
+        return None;
+    };
+    if exit.index != label.index {
+        return None;
+    }
+    let def = pool.definition(*fun_idx).ok()?;
+    let fun = pool.function(*fun_idx).ok()?;
+    if !fun.flags.is_native() || !def.parent.is_undefined() {
+        return None;
+    }
+    let name = pool.names.get(def.name).ok()?;
+    let op = BinOp::from_str(name.split(';').next()?).ok()?;
+    let result = Const::from_instr(lhs)?.apply(op, Const::from_instr(rhs)?)?;
+
+    // the declared return type has to agree with the folded value for the fold to be sound
+    let return_type = pool.definition(fun.return_type?).ok()?;
+    let return_type = pool.names.get(return_type.name).ok()?;
+    (return_type.as_ref() == result.type_name()).then(|| result.into_instr())
+}
+
+fn simplify_branches(code: &mut Vec<Instr<Label>>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i + 1 < code.len() {
+        match (&code[i], &code[i + 1]) {
+            (Instr::JumpIfFalse(_), Instr::TrueConst) => {
+                code.drain(i..i + 2);
+                changed = true;
+                continue;
+            }
+            (Instr::JumpIfFalse(target), Instr::FalseConst) => {
+                let jump = Instr::Jump(*target);
+                code.splice(i..i + 2, [jump]);
+                changed = true;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    changed
+}
+
+fn thread_jumps(code: &mut Vec<Instr<Label>>) -> bool {
+    let mut positions = HashMap::new();
+    for (i, instr) in code.iter().enumerate() {
+        if let Instr::Target(label) = instr {
+            positions.insert(label.index, i);
+        }
+    }
+    // the label a chain of jumps starting at a label ends at, cycles end at the first repeated label
+    let final_target = |label: Label| {
+        let mut current = label;
+        let mut visited = vec![label.index];
+        while let Some(Instr::Jump(next)) = positions.get(&current.index).and_then(|&pos| next_instr(code, pos)) {
+            if visited.contains(&next.index) {
+                break;
+            }
+            visited.push(next.index);
+            current = *next;
+        }
+        current
+    };
+
+    let mut redirects = vec![];
+    for (i, instr) in code.iter().enumerate() {
+        if let Instr::Jump(target) | Instr::JumpIfFalse(target) = instr {
+            let dest = final_target(*target);
+            if dest.index != target.index {
+                redirects.push((i, dest));
+            }
+        }
+    }
+    let mut changed = !redirects.is_empty();
+    for (i, dest) in redirects {
+        match &mut code[i] {
+            Instr::Jump(target) | Instr::JumpIfFalse(target) => *target = dest,
+            _ => unreachable!(),
+        }
+    }
+
+    let mut i = 0;
+    while i < code.len() {
+        if let Instr::Jump(target) = code[i] {
+            let is_next = code[i + 1..]
+                .iter()
+                .map_while(|instr| match instr {
+                    Instr::Target(label) => Some(label.index),
+                    _ => None,
+                })
+                .any(|label| label == target.index);
+            if is_next {
+                code.remove(i);
+                changed = true;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    changed
+}
+
+fn remove_unreachable(code: &mut Vec<Instr<Label>>) -> bool {
+    let refs = label_refs(code);
+    let last = code.len().saturating_sub(1);
+    let mut keep = vec![true; code.len()];
+    let mut i = 0;
+    while i < last {
+        let start = i;
+        i = match code[i] {
+            Instr::Return => match skip_expr(code, i + 1) {
+                Some(end) => end,
+                None => break,
+            },
+            Instr::Jump(_) => i + 1,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+
+        // a label is a way into the code that follows, unless every reference to it comes from
+        // the returned expression or from the code that's already been found unreachable
+        let mut seen = HashMap::new();
+        if matches!(code[start], Instr::Return) {
+            for label in code[start..i].iter().flat_map(labels) {
+                *seen.entry(label.index).or_insert(0) += 1;
+            }
+        }
+        while i < last {
+            match &code[i] {
+                Instr::Target(label) => {
+                    if seen.get(&label.index).copied().unwrap_or(0) < refs.get(&label.index).copied().unwrap_or(0) {
+                        break;
+                    }
+                    // the markers have no size, so they are left in place
+                }
+                instr => {
+                    for label in labels(instr) {
+                        *seen.entry(label.index).or_insert(0) += 1;
+                    }
+                    keep[i] = false;
+                }
+            }
+            i += 1;
+        }
+    }
+    retain(code, &keep)
+}
+
+fn remove_nops(code: &mut Vec<Instr<Label>>) -> bool {
+    let last = code.len().saturating_sub(1);
+    let mut keep = vec![true; code.len()];
+    let mut i = 0;
+    // walk the statements, so that the Nops used as operands are left alone
+    while i < last {
+        let next = match &code[i] {
+            Instr::Nop => {
+                keep[i] = false;
+                Some(i + 1)
+            }
+            Instr::Target(_)
+            | Instr::Jump(_)
+            | Instr::SwitchDefault
+            | Instr::Breakpoint(_)
+            | Instr::StartProfiling(_) => Some(i + 1),
+            Instr::JumpIfFalse(_) | Instr::Switch(_, _) | Instr::SwitchLabel(_, _) | Instr::Return => {
+                skip_expr(code, i + 1)
+            }
+            _ => skip_expr(code, i),
+        };
+        match next {
+            Some(next) => i = next,
+            None => return false,
+        }
+    }
+    retain(code, &keep)
+}
+
+fn retain(code: &mut Vec<Instr<Label>>, keep: &[bool]) -> bool {
+    let len = code.len();
+    let mut keep = keep.iter();
+    code.retain(|_| keep.next().copied().unwrap_or(true));
+    code.len() != len
+}
+
+fn label_refs(code: &[Instr<Label>]) -> HashMap<usize, usize> {
+    let mut refs = HashMap::new();
+    for label in code.iter().flat_map(labels) {
+        *refs.entry(label.index).or_insert(0) += 1;
+    }
+    refs
+}
+
+fn labels(instr: &Instr<Label>) -> impl Iterator<Item = Label> {
+    let labels = match instr {
+        Instr::Switch(_, label)
+        | Instr::Jump(label)
+        | Instr::JumpIfFalse(label)
+        | Instr::Skip(label)
+        | Instr::Context(label)
+        | Instr::InvokeStatic(label, _, _, _)
+        | Instr::InvokeVirtual(label, _, _, _) => [Some(*label), None],
+        Instr::SwitchLabel(first, second) | Instr::Conditional(first, second) => [Some(*first), Some(*second)],
+        _ => [None, None],
+    };
+    labels.into_iter().flatten()
+}
+
+fn next_instr(code: &[Instr<Label>], start: usize) -> Option<&Instr<Label>> {
+    code[start..].iter().find(|instr| !matches!(instr, Instr::Target(_)))
+}
+
+// returns the index that follows the expression starting at an index, labels placed inside of
+// expressions are skipped over
+fn skip_expr(code: &[Instr<Label>], start: usize) -> Option<usize> {
+    let start = start
+        + code[start..]
+            .iter()
+            .take_while(|instr| matches!(instr, Instr::Target(_)))
+            .count();
+    match code.get(start)? {
+        Instr::InvokeStatic(_, _, _, _) | Instr::InvokeVirtual(_, _, _, _) => {
+            let mut index = start + 1;
+            loop {
+                index += code[index..]
+                    .iter()
+                    .take_while(|instr| matches!(instr, Instr::Target(_)))
+                    .count();
+                index = match code.get(index)? {
+                    Instr::ParamEnd => return Some(index + 1),
+                    Instr::Skip(_) => skip_expr(code, index + 1)?,
+                    _ => skip_expr(code, index)?,
+                };
+            }
+        }
+        instr => (0..instr.operand_count()?).try_fold(start + 1, |index, _| skip_expr(code, index)),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Const {
+    I32(i32),
+    I64(i64),
+    U32(u32),
+    U64(u64),
+    Bool(bool),
+}
+
+macro_rules! fold_int {
+    ($op:expr, $lhs:expr, $rhs:expr, $variant:path) => {
+        match $op {
+            BinOp::Add => Some($variant($lhs.wrapping_add($rhs))),
+            BinOp::Subtract => Some($variant($lhs.wrapping_sub($rhs))),
+            BinOp::Multiply => Some($variant($lhs.wrapping_mul($rhs))),
+            BinOp::Divide => $lhs.checked_div($rhs).map($variant),
+            BinOp::Modulo => $lhs.checked_rem($rhs).map($variant),
+            BinOp::And => Some($variant($lhs & $rhs)),
+            BinOp::Or => Some($variant($lhs | $rhs)),
+            BinOp::Xor => Some($variant($lhs ^ $rhs)),
+            BinOp::Equal => Some(Const::Bool($lhs == $rhs)),
+            BinOp::NotEqual => Some(Const::Bool($lhs != $rhs)),
+            BinOp::Less => Some(Const::Bool($lhs < $rhs)),
+            BinOp::LessEqual => Some(Const::Bool($lhs <= $rhs)),
+            BinOp::Greater => Some(Const::Bool($lhs > $rhs)),
+            BinOp::GreaterEqual => Some(Const::Bool($lhs >= $rhs)),
+            _ => None,
+        }
+    };
+}
+
+impl Const {
+    fn from_instr(instr: &Instr<Label>) -> Option<Self> {
+        match *instr {
+            Instr::I32Const(val) => Some(Const::I32(val)),
+            Instr::I32One => Some(Const::I32(1)),
+            Instr::I32Zero => Some(Const::I32(0)),
+            Instr::I64Const(val) => Some(Const::I64(val)),
+            Instr::U32Const(val) => Some(Const::U32(val)),
+            Instr::U64Const(val) => Some(Const::U64(val)),
+            Instr::TrueConst => Some(Const::Bool(true)),
+            Instr::FalseConst => Some(Const::Bool(false)),
+            _ => None,
+        }
+    }
+
+    fn into_instr(self) -> Instr<Label> {
+        match self {
+            Const::I32(val) => Instr::I32Const(val),
+            Const::I64(val) => Instr::I64Const(val),
+            Const::U32(val) => Instr::U32Const(val),
+            Const::U64(val) => Instr::U64Const(val),
+            Const::Bool(true) => Instr::TrueConst,
+            Const::Bool(false) => Instr::FalseConst,
+        }
+    }
+
+    fn type_name(self) -> &'static str {
+        match self {
+            Const::I32(_) => "Int32",
+            Const::I64(_) => "Int64",
+            Const::U32(_) => "Uint32",
+            Const::U64(_) => "Uint64",
+            Const::Bool(_) => "Bool",
+        }
+    }
+
+    fn apply(self, op: BinOp, rhs: Self) -> Option<Self> {
+        match (self, rhs) {
+            (Const::I32(lhs), Const::I32(rhs)) => fold_int!(op, lhs, rhs, Const::I32),
+            (Const::I64(lhs), Const::I64(rhs)) => fold_int!(op, lhs, rhs, Const::I64),
+            (Const::U32(lhs), Const::U32(rhs)) => fold_int!(op, lhs, rhs, Const::U32),
+            (Const::U64(lhs), Const::U64(rhs)) => fold_int!(op, lhs, rhs, Const::U64),
+            (Const::Bool(lhs), Const::Bool(rhs)) => match op {
+                BinOp::LogicAnd | BinOp::And => Some(Const::Bool(lhs && rhs)),
+                BinOp::LogicOr | BinOp::Or => Some(Const::Bool(lhs || rhs)),
+                BinOp::Xor | BinOp::NotEqual => Some(Const::Bool(lhs != rhs)),
+                BinOp::Equal => Some(Const::Bool(lhs == rhs)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use std::fmt;
 
 use peg::error::ExpectedSet;
-use redscript::ast::{Seq, Span};
+use redscript::ast::{Ident, Seq, Span};
 use redscript::bundle::PoolIndex;
-use redscript::definition::{Function, FunctionFlags};
+use redscript::definition::{Function, FunctionFlags, Visibility};
 use thiserror::Error;
 
 use crate::error::{Cause, Error};
@@ -82,18 +82,21 @@ impl Diagnostic {
                 3
             };
 
-            if let Self::CompileError(cause, _) = self {
-                write!(f, "[{}] ", cause.code())?;
-            }
-
+            write!(f, "[{}] ", self.code())?;
             writeln!(f, "At {loc}:",)?;
             writeln!(f, "{line}")?;
             writeln!(f, "{:w$}{:^<underline_len$}", "", "", w = loc.start.col)?;
+            writeln!(f, "{}", self.message(files))
+        })
+    }
 
+    /// Returns the message of this diagnostic without the location and the code.
+    pub fn message<'a>(&'a self, files: &'a Files) -> impl fmt::Display + 'a {
+        DisplayFn::new(move |f: &mut fmt::Formatter<'_>| {
             if let Self::CompileError(cause, _) = self {
-                writeln!(f, "{}", cause.display(files))
+                write!(f, "{}", cause.display(files))
             } else {
-                writeln!(f, "{self}")
+                write!(f, "{self}")
             }
         })
     }
@@ -141,10 +144,23 @@ impl Diagnostic {
         }
     }
 
+    /// Returns a stable code identifying the kind of this diagnostic,
+    /// warnings can be suppressed by passing it to the `@allow` annotation.
     pub fn code(&self) -> &'static str {
         match self {
+            Self::ReplaceMethodConflict(_, _) => "REPLACE_METHOD_CONFLICT",
+            Self::FieldConflict(_) => "FIELD_CONFLICT",
+            Self::Deprecation(deprecation, _) => deprecation.code(),
+            Self::UnusedLocal(_) => "UNUSED_LOCAL",
+            Self::MissingReturn(_) => "MISSING_RETURN",
+            Self::StatementFallthrough(_) => "STMT_FALLTHROUGH",
+            Self::InvalidUseOfTemporary(_) => "INVALID_TEMP_USE",
+            Self::AddMethodConflict(_) => "ADD_METHOD_CONFLICT",
+            Self::NonClassRefDeprecation(_) => "NON_CLASS_REF",
+            Self::ClassWithNoIndirectionDeprecation(_) => "CLASS_WITHOUT_INDIRECTION",
+            Self::SyntaxError(_, _) => "SYNTAX_ERR",
             Self::CompileError(cause, _) => cause.code(),
-            _ => "OTHER",
+            Self::CteError(_, _) => "CTE_ERR",
         }
     }
 }
@@ -152,6 +168,16 @@ impl Diagnostic {
 #[derive(Debug)]
 pub enum Deprecation {
     UnrelatedTypeEquals,
+    InaccessibleMember(Ident, Visibility),
+}
+
+impl Deprecation {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnrelatedTypeEquals => "UNRELATED_TYPE_EQUALS",
+            Self::InaccessibleMember(_, _) => "INACCESSIBLE_MEMBER",
+        }
+    }
 }
 
 impl fmt::Display for Deprecation {
@@ -160,6 +186,12 @@ impl fmt::Display for Deprecation {
             Self::UnrelatedTypeEquals => {
                 f.write_str("comparing unrelated types, this is will not be allowed in the future")
             }
+            Self::InaccessibleMember(name, visibility) => {
+                write!(
+                    f,
+                    "{name} is {visibility} and should not be accessed here, this will not be allowed in the future"
+                )
+            }
         }
     }
 }
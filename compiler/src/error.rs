@@ -4,6 +4,7 @@ use itertools::Itertools;
 use peg::error::ExpectedSet;
 use redscript::ast::{Ident, Intrinsic, Pos, Span};
 use redscript::bundle::PoolError;
+use redscript::definition::Visibility;
 use thiserror::Error;
 
 use crate::diagnostics::DisplayFn;
@@ -55,6 +56,10 @@ pub enum Cause {
     InvalidAnnotationArgs,
     #[error("type cannot be inferred here, try annotating the variable")]
     TypeAnnotationRequired,
+    #[error("expected {1} type arguments for {0}")]
+    InvalidTypeArgCount(Ident, usize),
+    #[error("type arguments of {0} cannot be inferred here, try specifying them explicitly")]
+    TypeArgsRequired(Ident),
     #[error("{0} has no members")]
     InvalidMemberAccess(Ident),
     #[error("{0} is not supported on {1}")]
@@ -77,6 +82,10 @@ pub enum Cause {
     InvalidNonStaticMethodCall,
     #[error("no 'this' available in a static context")]
     UnexpectedThis,
+    #[error("continue can only be used inside of a loop")]
+    UnexpectedContinue,
+    #[error("{0} is {1} and cannot be accessed here")]
+    InaccessibleMember(Ident, Visibility),
     #[error("{0} is not supported")]
     UnsupportedFeature(&'static str),
     #[error("symbol with this name is already defined")]
@@ -145,13 +154,16 @@ impl Cause {
             Self::UnresolvedImport(_) | Self::UnresolvedModule(_) => "UNRESOLVED_IMPORT",
             Self::InvalidArgCount(_, _) | Self::NoMatchingOverload(_, _) => "NO_MATCHING_OVERLOAD",
             Self::InstantiatingAbstract(_) => "NEW_ABSTRACT",
-            Self::TypeAnnotationRequired => "TYPE_ANN_REQUIRED",
+            Self::TypeAnnotationRequired | Self::TypeArgsRequired(_) => "TYPE_ANN_REQUIRED",
+            Self::InvalidTypeArgCount(_, _) => "INVALID_TYPE_ARGS",
             Self::InvalidAnnotationArgs => "INVALID_ANN_USE",
             Self::InvalidMemberAccess(_) => "INVALID_MEMBER_ACCESS",
             Self::VoidCannotBeUsed => "INVALID_VOID_USE",
             Self::InvalidStaticMethodCall => "INVALID_STATIC_USE",
             Self::InvalidNonStaticMethodCall => "INVALID_NONSTATIC_USE",
             Self::UnexpectedThis => "UNEXPECTED_THIS",
+            Self::UnexpectedContinue => "UNEXPECTED_CONTINUE",
+            Self::InaccessibleMember(_, _) => "INACCESSIBLE_MEMBER",
             Self::SymbolRedefinition(_) => "SYM_REDEFINITION",
             Self::FieldRedefinition => "FIELD_REDEFINITION",
             Self::MissingBody => "MISSING_BODY",
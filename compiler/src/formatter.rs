@@ -0,0 +1,987 @@
+use std::collections::VecDeque;
+
+use itertools::Itertools;
+use redscript::ast::{BinOp, Expr, Ident, Kind, LambdaParam, Pos, Seq, SourceAst, Span, TypeName, UnOp};
+use redscript::Ref;
+
+use crate::diagnostics::Diagnostic;
+use crate::parser::{
+    self, Annotation, ClassSource, Declaration, EnumSource, FieldSource, FunctionSource, MemberSource, ParameterSource,
+    SourceEntry, SourceModule, SyntaxTree,
+};
+use crate::source_map::File;
+use crate::symbol::Import;
+
+const INDENT: &str = "  ";
+
+// binding strength of expressions, an operand is parenthesized when it binds weaker than its position requires
+const PREC_ANY: usize = 0;
+const PREC_ASSIGN: usize = 1;
+const PREC_UNARY: usize = 11;
+const PREC_POSTFIX: usize = 12;
+const PREC_ATOM: usize = 13;
+
+/// Formats a source file, returns the syntax errors instead if the file cannot be parsed.
+///
+/// Comments are preserved and consecutive blank lines are collapsed into one.
+/// Expressions and declaration headers with comments inside of them are kept as they were written.
+pub fn format_file(file: &File) -> Result<String, Vec<Diagnostic>> {
+    let tree = parser::parse_syntax_tree(file)?;
+    let mut formatter = Formatter::new(file, &tree);
+    formatter.module(&tree.module);
+    Ok(formatter.finish())
+}
+
+struct Formatter<'a> {
+    file: &'a File,
+    tree: &'a SyntaxTree,
+    out: String,
+    depth: usize,
+    // comments that have not been written yet
+    pending: VecDeque<Span>,
+    // the end of the last piece of source that has been written
+    last_end: Pos,
+    // blank lines are not carried over to the beginning of a block
+    block_start: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(file: &'a File, tree: &'a SyntaxTree) -> Self {
+        Self {
+            file,
+            tree,
+            out: String::new(),
+            depth: 0,
+            pending: tree.comments.iter().copied().collect(),
+            last_end: file.byte_offset(),
+            block_start: true,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.comments_before(self.file.span().high);
+        let len = self.out.trim_end().len();
+        self.out.truncate(len);
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    fn module(&mut self, module: &SourceModule) {
+        if let Some(path) = &module.path {
+            let pos = self.next_token(self.file.byte_offset());
+            self.item(pos);
+            self.write("module ");
+            self.write(&path.parts.iter().join("."));
+            let text = &self.source_from(pos)["module".len()..];
+            let len = text
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | ' ' | '\t')))
+                .unwrap_or(text.len());
+            self.end_item(pos + "module".len() + text[..len].trim_end().len());
+        }
+        for import in &module.imports {
+            self.import(import);
+        }
+        for entry in &module.entries {
+            self.entry(entry);
+        }
+    }
+
+    fn import(&mut self, import: &Import) {
+        let (Import::Exact(annotations, path, span)
+        | Import::Selected(annotations, path, _, span)
+        | Import::All(annotations, path, span)) = import;
+        self.item(span.low);
+        self.annotations(annotations);
+        self.write("import ");
+        self.write(&path.parts.iter().join("."));
+        match import {
+            Import::Selected(_, _, names, _) => self.write(&format!(".{{{}}}", names.iter().join(", "))),
+            Import::All(_, _, _) => self.write(".*"),
+            Import::Exact(_, _, _) => {}
+        }
+        self.end_item(span.high);
+    }
+
+    fn entry(&mut self, entry: &SourceEntry) {
+        match entry {
+            SourceEntry::Class(class) => self.class(class, "class"),
+            SourceEntry::Struct(struct_) => self.class(struct_, "struct"),
+            SourceEntry::Function(fun) => self.function(fun),
+            SourceEntry::GlobalLet(field) => self.field(field),
+            SourceEntry::Enum(enum_) => self.enum_(enum_),
+        }
+    }
+
+    fn class(&mut self, class: &ClassSource, keyword: &str) {
+        self.item(class.span.low);
+        let open = self.find('{', class.declaration.span.high);
+        if !self.verbatim_header(&class.declaration, open) {
+            self.declaration(&class.declaration, keyword);
+            self.type_params(&class.type_params);
+            if let Some(base) = &class.base {
+                self.write(" extends ");
+                self.write(base);
+            }
+        }
+        self.write(" ");
+        self.open(open);
+        for member in &class.members {
+            match member {
+                MemberSource::Function(fun) => self.function(fun),
+                MemberSource::Field(field) => self.field(field),
+            }
+        }
+        self.close(class.span.high - 1);
+        self.end_item(class.span.high);
+    }
+
+    fn function(&mut self, fun: &FunctionSource) {
+        self.item(fun.span.low);
+        let header_end = match &fun.body {
+            // a function without a body can be followed by comments that are not a part of its header
+            None => self.token_end(fun.span.high),
+            Some(body) => match &body.exprs[..] {
+                [Expr::Return(Some(_), span)] if self.source_from(span.low).starts_with('=') => span.low,
+                _ => self.find('{', fun.declaration.span.high),
+            },
+        };
+        if !self.verbatim_header(&fun.declaration, header_end) {
+            self.function_header(fun);
+        }
+        self.function_body(fun);
+        self.end_item(fun.span.high);
+    }
+
+    fn function_header(&mut self, fun: &FunctionSource) {
+        self.declaration(&fun.declaration, "func");
+        self.type_params(&fun.type_params);
+        self.write("(");
+        for (i, param) in fun.parameters.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.parameter(param);
+        }
+        self.write(")");
+        if let Some(type_) = &fun.type_ {
+            self.write(" -> ");
+            self.write(&type_name(type_));
+        }
+    }
+
+    fn function_body(&mut self, fun: &FunctionSource) {
+        match &fun.body {
+            None => self.write(";"),
+            Some(body) => match &body.exprs[..] {
+                // a function with an expression body is parsed as a single return statement
+                [Expr::Return(Some(val), span)] if self.source_from(span.low).starts_with('=') => {
+                    self.write(" = ");
+                    self.expr(val, PREC_ANY);
+                }
+                _ => {
+                    self.write(" ");
+                    self.block(self.find('{', fun.declaration.span.high), body);
+                }
+            },
+        }
+    }
+
+    fn parameter(&mut self, param: &ParameterSource) {
+        for qualifier in param.qualifiers.iter() {
+            self.write(qualifier.into());
+            self.write(" ");
+        }
+        self.write(&param.name);
+        self.write(": ");
+        self.write(&type_name(&param.type_));
+    }
+
+    fn field(&mut self, field: &FieldSource) {
+        self.item(field.span.low);
+        if self.has_comments(field.span) {
+            self.verbatim(field.span);
+            if !self.slice(field.span).ends_with(';') {
+                self.write(";");
+            }
+            self.end_item(field.span.high);
+            return;
+        }
+        self.declaration(&field.declaration, "let");
+        self.write(": ");
+        self.write(&type_name(&field.type_));
+        if let Some(default) = &field.default {
+            self.write(" = ");
+            self.expr(default, PREC_ANY);
+        }
+        self.write(";");
+        self.end_item(field.span.high);
+    }
+
+    fn enum_(&mut self, enum_: &EnumSource) {
+        self.item(enum_.span.low);
+        let open = self.find('{', enum_.declaration.span.high);
+        if !self.verbatim_header(&enum_.declaration, open) {
+            self.declaration(&enum_.declaration, "enum");
+        }
+        self.write(" ");
+        self.open(open);
+        for member in &enum_.members {
+            self.item(member.span.low);
+            if self.has_comments(member.span) {
+                self.verbatim(member.span);
+            } else {
+                // the value is written as it is to keep the literal suffix
+                let (_, value) = self.slice(member.span).split_once('=').unwrap_or_default();
+                self.write(&format!("{} = {}", member.name, value.trim()));
+            }
+            self.write(",");
+            self.end_item(member.span.high);
+        }
+        self.close(enum_.span.high - 1);
+        self.end_item(enum_.span.high);
+    }
+
+    fn declaration(&mut self, decl: &Declaration, keyword: &str) {
+        self.annotations(&decl.annotations);
+        for qualifier in decl.qualifiers.iter() {
+            self.write(qualifier.into());
+            self.write(" ");
+        }
+        self.write(keyword);
+        self.write(" ");
+        self.write(&decl.name);
+    }
+
+    /// Writes the header of a declaration up to the position as it was written if there are comments inside of it.
+    /// Returns whether the header was written.
+    fn verbatim_header(&mut self, decl: &Declaration, end: Pos) -> bool {
+        let span = Span::new(decl.span.low, end);
+        if !self.has_comments(span) {
+            return false;
+        }
+        self.write(self.slice(span).trim_end());
+        self.pending
+            .retain(|comment| comment.low < span.low || comment.low >= span.high);
+        true
+    }
+
+    fn annotations(&mut self, annotations: &[Annotation]) {
+        for annotation in annotations {
+            self.write("@");
+            self.write((&annotation.kind).into());
+            if !annotation.args.is_empty() {
+                self.args(&annotation.args);
+            }
+            self.newline();
+        }
+    }
+
+    fn type_params(&mut self, params: &[Ident]) {
+        if !params.is_empty() {
+            self.write(&format!("<{}>", params.iter().join(", ")));
+        }
+    }
+
+    fn stmts(&mut self, seq: &Seq<SourceAst>) {
+        for stmt in &seq.exprs {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Expr<SourceAst>) {
+        let span = stmt.span();
+        let annotated = self
+            .tree
+            .module
+            .annotated_stmts
+            .iter()
+            .find(|annotated| annotated.span.high == span.high && annotated.span.low < span.low);
+        self.item(annotated.map_or(span.low, |annotated| annotated.span.low));
+        if let Some(annotated) = annotated {
+            self.annotations(&annotated.annotations);
+        }
+
+        match stmt {
+            Expr::If(cond, then, else_, _) => self.if_(cond, then, else_.as_ref()),
+            Expr::While(cond, body, _) => {
+                self.write("while ");
+                self.expr(cond, PREC_ANY);
+                self.write(" ");
+                self.block(self.find('{', cond.span().high), body);
+            }
+            Expr::ForIn(name, array, body, _) => {
+                self.write(&format!("for {name} in "));
+                self.expr(array, PREC_ANY);
+                self.write(" ");
+                self.block(self.find('{', array.span().high), body);
+            }
+            Expr::Switch(matcher, cases, default, _) => {
+                self.write("switch ");
+                self.expr(matcher, PREC_ANY);
+                self.write(" ");
+                let open = self.find('{', matcher.span().high);
+                self.open(open);
+                let mut last = open + 1;
+                for case in cases {
+                    self.item(self.next_token(last));
+                    self.write("case ");
+                    self.expr(&case.matcher, PREC_ANY);
+                    last = self.label(self.find(':', case.matcher.span().high), &case.body);
+                }
+                if let Some(default) = default {
+                    let pos = self.next_token(last);
+                    self.item(pos);
+                    self.write("default");
+                    last = self.label(self.find(':', pos), default);
+                }
+                self.close(self.find('}', last));
+            }
+            // declarations and returns include the semicolon in their span unlike other expressions
+            _ if self.has_comments(span) => {
+                self.verbatim(span);
+                if !self.slice(span).ends_with(';') {
+                    self.write(";");
+                }
+            }
+            _ => {
+                self.expr(stmt, PREC_ANY);
+                self.write(";");
+            }
+        }
+        self.end_item(span.high);
+    }
+
+    fn if_(&mut self, cond: &Expr<SourceAst>, then: &Seq<SourceAst>, else_: Option<&Seq<SourceAst>>) {
+        self.write("if ");
+        self.expr(cond, PREC_ANY);
+        self.write(" ");
+        let end = self.block(self.find('{', cond.span().high), then);
+        if let Some(else_) = else_ {
+            self.write(" else ");
+            match &else_.exprs[..] {
+                // an else block with a single if statement is indistinguishable from an else if in the AST
+                [Expr::If(cond, then, else_, span)] if self.find('{', end) >= span.low => {
+                    self.if_(cond, then, else_.as_ref());
+                }
+                _ => {
+                    self.block(self.find('{', end), else_);
+                }
+            }
+        }
+    }
+
+    /// Writes the label of a switch case ending at the colon and the body that follows it.
+    /// Returns the end of the body.
+    fn label(&mut self, colon: Pos, body: &Seq<SourceAst>) -> Pos {
+        self.write(":");
+        self.end_item(colon + 1);
+        self.block_start = true;
+        self.depth += 1;
+        self.stmts(body);
+        self.depth -= 1;
+        body.exprs.last().map_or(colon + 1, |stmt| stmt.span().high)
+    }
+
+    /// Writes a block of statements starting at the opening brace. Returns the position after the closing brace.
+    fn block(&mut self, open: Pos, seq: &Seq<SourceAst>) -> Pos {
+        let close = self.find('}', seq.exprs.last().map_or(open + 1, |stmt| stmt.span().high));
+        if seq.exprs.is_empty() && !self.has_comments(Span::new(open, close)) {
+            self.write("{}");
+            self.last_end = close + 1;
+        } else {
+            self.open(open);
+            self.stmts(seq);
+            self.close(close);
+        }
+        close + 1
+    }
+
+    fn expr(&mut self, expr: &Expr<SourceAst>, min_prec: usize) {
+        let span = expr.span();
+        if self.has_comments(span) {
+            self.verbatim(span);
+            return;
+        }
+        let parens = precedence(expr) < min_prec || self.is_parenthesized(span);
+        if parens {
+            self.write("(");
+        }
+        match expr {
+            Expr::Ident(name, _) => self.write(name),
+            Expr::Constant(_, span) => self.verbatim(*span),
+            Expr::ArrayLit(items, _, _) => {
+                self.write("[");
+                self.exprs(items);
+                self.write("]");
+            }
+            Expr::InterpolatedString(_, parts, span) => self.interpolated_string(parts, *span),
+            Expr::Declare(name, type_, val, _) => {
+                self.write("let ");
+                self.write(name);
+                if let Some(type_) = type_ {
+                    self.write(": ");
+                    self.write(&type_name(type_));
+                }
+                if let Some(val) = val {
+                    self.write(" = ");
+                    self.expr(val, PREC_ANY);
+                }
+            }
+            Expr::Cast(type_, expr, _) => {
+                self.expr(expr, PREC_POSTFIX);
+                self.write(" as ");
+                self.write(&type_name(type_));
+            }
+            Expr::Assign(lhs, rhs, _) => {
+                self.expr(lhs, PREC_ASSIGN + 1);
+                self.write(" = ");
+                self.expr(rhs, PREC_ASSIGN);
+            }
+            Expr::Call(name, type_args, args, _) => {
+                self.write(name);
+                if !type_args.is_empty() {
+                    self.write(&format!("<{}>", type_args.iter().map(type_name).join(", ")));
+                }
+                self.args(args);
+            }
+            Expr::MethodCall(receiver, name, args, _) => {
+                self.expr(receiver, PREC_POSTFIX);
+                self.write(".");
+                self.write(name);
+                self.args(args);
+            }
+            Expr::Member(receiver, name, _) => {
+                self.expr(receiver, PREC_POSTFIX);
+                self.write(".");
+                self.write(name);
+            }
+            Expr::ArrayElem(array, index, _) => {
+                self.expr(array, PREC_POSTFIX);
+                self.write("[");
+                self.expr(index, PREC_ANY);
+                self.write("]");
+            }
+            Expr::New(type_, args, _) => {
+                self.write("new ");
+                self.write(&type_name(type_));
+                self.args(args);
+            }
+            Expr::Return(val, _) => {
+                self.write("return");
+                if let Some(val) = val {
+                    self.write(" ");
+                    self.expr(val, PREC_ANY);
+                }
+            }
+            Expr::Conditional(cond, then, else_, _) => {
+                self.expr(cond, PREC_ASSIGN + 1);
+                self.write(" ? ");
+                self.expr(then, PREC_ASSIGN);
+                self.write(" : ");
+                self.expr(else_, PREC_ASSIGN);
+            }
+            Expr::BinOp(lhs, rhs, op, _) => {
+                let prec = binop_precedence(*op);
+                // compound assignments associate to the right, other operators to the left
+                let (lhs_prec, rhs_prec) = if prec == PREC_ASSIGN {
+                    (prec + 1, prec)
+                } else {
+                    (prec, prec + 1)
+                };
+                self.expr(lhs, lhs_prec);
+                self.write(&format!(" {} ", binop_symbol(*op)));
+                self.expr(rhs, rhs_prec);
+            }
+            Expr::UnOp(expr, op, _) => {
+                self.write(unop_symbol(*op));
+                self.expr(expr, PREC_UNARY);
+            }
+            Expr::Lambda(params, body, _) => self.lambda(params, body, span),
+            Expr::This(_) => self.write("this"),
+            Expr::Super(_) => self.write("super"),
+            Expr::Break(_) => self.write("break"),
+            Expr::Continue(_) => self.write("continue"),
+            Expr::Null(_) => self.write("null"),
+            // these only occur as statements or cannot be written in source
+            Expr::Seq(_)
+            | Expr::Switch(_, _, _, _)
+            | Expr::Goto(_, _)
+            | Expr::If(_, _, _, _)
+            | Expr::While(_, _, _)
+            | Expr::ForIn(_, _, _, _) => self.verbatim(span),
+        }
+        if parens {
+            self.write(")");
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr<SourceAst>]) {
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.expr(expr, PREC_ANY);
+        }
+    }
+
+    fn args(&mut self, args: &[Expr<SourceAst>]) {
+        self.write("(");
+        self.exprs(args);
+        self.write(")");
+    }
+
+    fn lambda(&mut self, params: &[LambdaParam], body: &Expr<SourceAst>, span: Span) {
+        let params = params.iter().format_with(", ", |param, f| match &param.type_ {
+            Some(type_) => f(&format_args!("{}: {}", param.name, type_name(type_))),
+            None => f(&param.name),
+        });
+        self.write(&format!("({params}) -> "));
+        match body {
+            Expr::Seq(seq) => {
+                self.block(self.find('{', span.low), seq);
+            }
+            _ => self.expr(body, PREC_ASSIGN),
+        }
+    }
+
+    fn interpolated_string(&mut self, parts: &[(Expr<SourceAst>, Ref<str>)], span: Span) {
+        // the literal parts are written as they are to keep escape sequences intact
+        let mut cursor = span.low;
+        for (expr, _) in parts {
+            let expr_span = expr.span();
+            let head = self.slice(Span::new(cursor, expr_span.low));
+            let start = head.rfind("\\(").unwrap_or_default();
+            self.write(&head[..start]);
+            self.write("\\(");
+            self.expr(expr, PREC_ANY);
+            self.write(")");
+            // the expression itself can be wrapped in parentheses
+            let mut end = expr_span.high;
+            for _ in 0..=head[start + 2..].matches('(').count() {
+                end = self.find(')', end) + 1;
+            }
+            cursor = end;
+        }
+        self.write(self.slice(Span::new(cursor, span.high)));
+    }
+
+    /// Starts a new source element at the position, writing the comments that precede it first.
+    fn item(&mut self, pos: Pos) {
+        self.comments_before(pos);
+        self.separate(pos);
+    }
+
+    /// Finishes a source element ending at the position, along with comments that follow it on the same line.
+    fn end_item(&mut self, pos: Pos) {
+        self.last_end = pos;
+        while let Some(&comment) = self.pending.front() {
+            let gap = self.slice(Span::new(self.last_end.min(comment.low), comment.low));
+            if gap.contains('\n')
+                || !gap
+                    .trim_matches(|c: char| c.is_whitespace() || c == ';' || c == ',')
+                    .is_empty()
+            {
+                break;
+            }
+            self.pending.pop_front();
+            self.write(" ");
+            let text = self.slice(comment).trim_end();
+            self.write(text);
+            self.last_end = comment.high;
+            // nothing can follow a line comment, the remaining comments go on their own lines
+            if text.starts_with("//") {
+                break;
+            }
+        }
+        self.newline();
+    }
+
+    fn open(&mut self, pos: Pos) {
+        self.write("{");
+        self.end_item(pos + 1);
+        self.depth += 1;
+        self.block_start = true;
+    }
+
+    fn close(&mut self, pos: Pos) {
+        self.comments_before(pos);
+        if !self.at_line_start() {
+            self.newline();
+        }
+        self.depth -= 1;
+        self.write("}");
+        self.last_end = pos + 1;
+    }
+
+    fn comments_before(&mut self, pos: Pos) {
+        while let Some(&comment) = self.pending.front().filter(|comment| comment.low < pos) {
+            self.pending.pop_front();
+            self.separate(comment.low);
+            self.write(self.slice(comment).trim_end());
+            self.last_end = comment.high;
+            self.newline();
+        }
+    }
+
+    /// Makes sure that the next element starts on a new line, preceded by a blank line if there was one in the source.
+    fn separate(&mut self, pos: Pos) {
+        if !self.at_line_start() {
+            self.newline();
+        }
+        let gap = self.slice(Span::new(self.last_end.min(pos), pos));
+        // the lines between the first and the last one are whole lines that are not part of any element
+        let lines = gap.split('\n').collect::<Vec<_>>();
+        let inner = lines.get(1..lines.len().saturating_sub(1)).unwrap_or_default();
+        if !self.block_start && inner.iter().any(|line| line.trim().is_empty()) {
+            self.out.push('\n');
+        }
+    }
+
+    fn verbatim(&mut self, span: Span) {
+        self.write(self.slice(span));
+        self.pending
+            .retain(|comment| comment.low < span.low || comment.low >= span.high);
+    }
+
+    fn write(&mut self, str: &str) {
+        if str.is_empty() {
+            return;
+        }
+        if self.at_line_start() {
+            for _ in 0..self.depth {
+                self.out.push_str(INDENT);
+            }
+        }
+        self.out.push_str(str);
+        self.block_start = false;
+    }
+
+    fn newline(&mut self) {
+        let len = self.out.trim_end_matches(' ').len();
+        self.out.truncate(len);
+        self.out.push('\n');
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn has_comments(&self, span: Span) -> bool {
+        let comments = &self.tree.comments;
+        let idx = comments.partition_point(|comment| comment.low < span.low);
+        comments.get(idx).map_or(false, |comment| comment.low < span.high)
+    }
+
+    fn is_parenthesized(&self, span: Span) -> bool {
+        self.tree
+            .parenthesized
+            .binary_search_by_key(&(span.low, span.high), |span| (span.low, span.high))
+            .is_ok()
+    }
+
+    fn slice(&self, span: Span) -> &'a str {
+        self.file.source_slice(span)
+    }
+
+    fn source_from(&self, pos: Pos) -> &'a str {
+        self.slice(Span::new(pos, self.file.span().high))
+    }
+
+    /// Finds the first occurrence of a character at or after the position, skipping over comments.
+    fn find(&self, char: char, from: Pos) -> Pos {
+        self.scan(from, |c| c == char)
+    }
+
+    /// Finds the first token at or after the position, skipping over comments and semicolons.
+    fn next_token(&self, from: Pos) -> Pos {
+        self.scan(from, |c| !c.is_whitespace() && c != ';')
+    }
+
+    /// Finds the end of the last token before the position, skipping over comments and semicolons.
+    fn token_end(&self, to: Pos) -> Pos {
+        let comments = &self.tree.comments;
+        let mut end = to;
+        loop {
+            let text = self.slice(Span::new(self.file.byte_offset(), end));
+            end = end - (text.len() - text.trim_end_matches(|c: char| c.is_whitespace() || c == ';').len());
+            let idx = comments.partition_point(|comment| comment.low < end);
+            match comments[..idx].last().filter(|comment| comment.high >= end) {
+                Some(comment) => end = comment.low,
+                None => return end,
+            }
+        }
+    }
+
+    fn scan(&self, from: Pos, mut pred: impl FnMut(char) -> bool) -> Pos {
+        let comments = &self.tree.comments;
+        let mut next_comment = comments.partition_point(|comment| comment.high <= from);
+        let mut skip_until = from;
+        for (offset, char) in self.source_from(from).char_indices() {
+            let pos = from + offset;
+            if pos < skip_until {
+                continue;
+            }
+            if let Some(comment) = comments.get(next_comment).filter(|comment| comment.low <= pos) {
+                skip_until = comment.high;
+                next_comment += 1;
+            } else if pred(char) {
+                return pos;
+            }
+        }
+        self.file.span().high
+    }
+}
+
+fn type_name(type_: &TypeName) -> String {
+    match type_ {
+        TypeName::Named { args: Some(args), .. } if matches!(type_.kind(), Kind::Function) => {
+            let (ret, params) = args.split_last().expect("function types have a return type");
+            format!("({}) -> {}", params.iter().map(type_name).join(", "), type_name(ret))
+        }
+        TypeName::Named { name, args: Some(args) } => format!("{}<{}>", name, args.iter().map(type_name).join(", ")),
+        TypeName::Named { name, args: None } => name.to_string(),
+        TypeName::Array(elem) => format!("[{}]", type_name(elem)),
+        TypeName::StaticArray(elem, size) => format!("[{}; {}]", type_name(elem), size),
+    }
+}
+
+fn precedence(expr: &Expr<SourceAst>) -> usize {
+    match expr {
+        Expr::Assign(_, _, _) | Expr::Conditional(_, _, _, _) | Expr::Lambda(_, _, _) => PREC_ASSIGN,
+        Expr::BinOp(_, _, op, _) => binop_precedence(*op),
+        Expr::UnOp(_, _, _) => PREC_UNARY,
+        Expr::Cast(_, _, _) | Expr::MethodCall(_, _, _, _) | Expr::Member(_, _, _) | Expr::ArrayElem(_, _, _) => {
+            PREC_POSTFIX
+        }
+        _ => PREC_ATOM,
+    }
+}
+
+fn binop_precedence(op: BinOp) -> usize {
+    // compound assignments end up at the same level as regular assignments
+    PREC_UNARY - op.precedence()
+}
+
+fn binop_symbol(op: BinOp) -> &'static str {
+    match op {
+        BinOp::AssignAdd => "+=",
+        BinOp::AssignSubtract => "-=",
+        BinOp::AssignMultiply => "*=",
+        BinOp::AssignDivide => "/=",
+        BinOp::AssignOr => "|=",
+        BinOp::AssignAnd => "&=",
+        BinOp::LogicOr => "||",
+        BinOp::LogicAnd => "&&",
+        BinOp::Or => "|",
+        BinOp::Xor => "^",
+        BinOp::And => "&",
+        BinOp::Equal => "==",
+        BinOp::NotEqual => "!=",
+        BinOp::Less => "<",
+        BinOp::LessEqual => "<=",
+        BinOp::Greater => ">",
+        BinOp::GreaterEqual => ">=",
+        BinOp::Add => "+",
+        BinOp::Subtract => "-",
+        BinOp::Multiply => "*",
+        BinOp::Divide => "/",
+        BinOp::Modulo => "%",
+    }
+}
+
+fn unop_symbol(op: UnOp) -> &'static str {
+    match op {
+        UnOp::BitNot => "~",
+        UnOp::LogicNot => "!",
+        UnOp::Neg => "-",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::source_map::Files;
+
+    fn format(source: &str) -> String {
+        let mut files = Files::new();
+        files.add(PathBuf::from("test.reds"), source.to_owned());
+        let file = files.files().next().unwrap();
+        format_file(file).unwrap()
+    }
+
+    // the debug representation of the module without positions, which are expected to change
+    fn structure(source: &str) -> String {
+        let module = parser::parse_str(source).unwrap();
+        let mut debug = format!("{:?}{:?}", module.entries, module.annotated_stmts);
+        while let Some(start) = debug.find("Span {") {
+            let end = start + debug[start..].find('}').unwrap() + 1;
+            debug.replace_range(start..end, "");
+        }
+        debug
+    }
+
+    const UNFORMATTED: &str = r#"
+module   Test.Module
+import Other.{A,B}
+/* block
+   comment */
+@wrapMethod(PlayerPuppet)
+public   final func OnGameAttached( ) ->Void{
+  wrappedMethod( );   // trailing
+
+
+    let x:Int32=1+2*3;
+  if x>2{ return;}else if x<0 { x=(x+1)*2; } else {
+      // inside else
+      x -= 1;
+  }
+  switch x {
+    case 1:
+      case 2 :
+      Log(s"value: \(x+1) and \( (x) )");
+      break;
+    default:
+      Log("other");
+  }
+  let f = (a: Int32, b) -> a+b;
+  let y = -(x + 1) * (x - 1 - (1 - x));
+  let z = !(x > 1 && y < 2) || (x == 3);
+  @allow("UNUSED_LOCAL") let w = this.Get( ).Other() as Foo;
+  let v = Call(1, /* kept as it is */   2);
+}
+
+enum Kind { A = 0, B = 1, // last
+ C = 10l }
+"#;
+
+    #[test]
+    fn format_module() {
+        let expected = r#"module Test.Module
+import Other.{A, B}
+/* block
+   comment */
+@wrapMethod(PlayerPuppet)
+public final func OnGameAttached() -> Void {
+  wrappedMethod(); // trailing
+
+  let x: Int32 = 1 + 2 * 3;
+  if x > 2 {
+    return;
+  } else if x < 0 {
+    x = (x + 1) * 2;
+  } else {
+    // inside else
+    x -= 1;
+  }
+  switch x {
+    case 1:
+    case 2:
+      Log(s"value: \(x + 1) and \((x))");
+      break;
+    default:
+      Log("other");
+  }
+  let f = (a: Int32, b) -> a + b;
+  let y = -(x + 1) * (x - 1 - (1 - x));
+  let z = !(x > 1 && y < 2) || (x == 3);
+  @allow("UNUSED_LOCAL")
+  let w = this.Get().Other() as Foo;
+  let v = Call(1, /* kept as it is */   2);
+}
+
+enum Kind {
+  A = 0,
+  B = 1, // last
+  C = 10l,
+}
+"#;
+        assert_eq!(format(UNFORMATTED), expected);
+    }
+
+    #[test]
+    fn format_class() {
+        let source = "
+            class Thing extends Base {
+                private let field: array<ref<Foo>>;
+                let other: [Int32; 4] = [1,2,3,4];
+
+                public static func Make<T>(opt x: T, out y: script_ref<String>) -> (Int32, Bool) -> Void
+                func Short() -> Int32 = 42
+                func Empty() {}
+                func Lambda() -> (Int32) -> Int32 { return (a) -> { return a; }; }
+            }
+        ";
+        let expected = "class Thing extends Base {
+  private let field: array<ref<Foo>>;
+  let other: [Int32; 4] = [1, 2, 3, 4];
+
+  public static func Make<T>(opt x: T, out y: script_ref<String>) -> (Int32, Bool) -> Void;
+  func Short() -> Int32 = 42
+  func Empty() {}
+  func Lambda() -> (Int32) -> Int32 {
+    return (a) -> {
+      return a;
+    };
+  }
+}
+";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn format_idempotently() {
+        let formatted = format(UNFORMATTED);
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn keep_comments_apart() {
+        let source = "
+            class A /* a */ { // b
+              func F(/* c */ x: Int32) { // d
+              } // e
+              func G() // f
+              // g
+              let h /* h */: Int32;
+              func I(/* i */) -> Int32 = 1
+              func J(x: Int32 /* j */);
+            }
+            enum E /* k */ { A = 0 }
+        ";
+        let expected = "class A /* a */ { // b
+  func F(/* c */ x: Int32) { // d
+  } // e
+  func G(); // f
+  // g
+  let h /* h */: Int32;
+  func I(/* i */) -> Int32 = 1
+  func J(x: Int32 /* j */);
+}
+enum E /* k */ {
+  A = 0,
+}
+";
+        let formatted = format(source);
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn preserve_structure() {
+        let source = "
+            func Test() {
+              let a = (1 + 2) * -(3 - (4 - 5)) / (6 % 7);
+              let b = (a ? b : c) ? (d = e) : f;
+              let c = -a.b + (-a).b;
+              let d = (x) -> (x as Foo).y;
+              a += b -= (c ? d : e);
+              if !(a || b) && (c | d) == e { return; }
+            }
+        ";
+        assert_eq!(structure(&format(source)), structure(source));
+    }
+}
@@ -0,0 +1,557 @@
+use std::fmt;
+
+use hashbrown::HashMap;
+use itertools::Itertools;
+use redscript::ast::{Ident, Kind, Seq, SourceAst, Span, TypeName};
+use redscript::bundle::{CName, ConstantPool, PoolIndex};
+use redscript::bytecode::Code;
+use redscript::definition::{
+    Class, ClassFlags, Definition, Field, FieldFlags, Function, FunctionFlags, Parameter, ParameterFlags,
+    SourceReference, Visibility,
+};
+use redscript::{str_fmt, Ref};
+
+use crate::error::Cause;
+use crate::parser::{FieldSource, FunctionSource, Qualifier};
+use crate::scope::{Scope, TypeId};
+use crate::symbol::{FunctionSignature, Symbol};
+
+// guards against types like `Node<T>` referring to `Node<array<T>>` which would expand forever
+const MAX_INSTANCE_DEPTH: usize = 32;
+
+/// A registry of generic classes and functions and of their instances.
+/// Generics are compiled by monomorphization, each distinct set of type arguments
+/// produces a separate class or function in the pool, named after the template and its arguments.
+#[derive(Default)]
+pub struct Generics {
+    classes: HashMap<Ident, Ref<ClassTemplate>>,
+    functions: HashMap<Ident, Ref<FunctionTemplate>>,
+    methods: HashMap<(PoolIndex<Class>, Ident), Ref<FunctionTemplate>>,
+    class_instances: HashMap<Ident, TypeId>,
+    function_instances: HashMap<Ident, PoolIndex<Function>>,
+    instance_args: HashMap<PoolIndex<Class>, Vec<TypeId>>,
+    bodies: Vec<InstanceBody>,
+    depth: usize,
+}
+
+impl Generics {
+    pub fn add_class_template(&mut self, template: ClassTemplate) {
+        self.classes.insert(template.name.clone(), Ref::new(template));
+    }
+
+    pub fn add_function_template(&mut self, template: FunctionTemplate) {
+        self.functions.insert(template.name.clone(), Ref::new(template));
+    }
+
+    /// Registers a generic method of a class, the template is named after the method.
+    pub fn add_method_template(&mut self, template: FunctionTemplate) {
+        self.methods
+            .insert((template.class, template.name.clone()), Ref::new(template));
+    }
+
+    pub fn class_template(&self, name: &str) -> Option<Ref<ClassTemplate>> {
+        self.classes.get(name).cloned()
+    }
+
+    pub fn function_template(&self, name: &str) -> Option<Ref<FunctionTemplate>> {
+        self.functions.get(name).cloned()
+    }
+
+    pub fn method_template(&self, class: PoolIndex<Class>, name: &Ident) -> Option<Ref<FunctionTemplate>> {
+        self.methods.get(&(class, name.clone())).cloned()
+    }
+
+    pub fn class_instance(&self, name: &str) -> Option<&TypeId> {
+        self.class_instances.get(name)
+    }
+
+    /// Returns the type arguments a class was instantiated with.
+    pub fn instance_args(&self, class: PoolIndex<Class>) -> Option<&[TypeId]> {
+        self.instance_args.get(&class).map(Vec::as_slice)
+    }
+
+    /// Takes the bodies of the instance methods declared since the last call.
+    pub fn take_bodies(&mut self) -> Vec<InstanceBody> {
+        std::mem::take(&mut self.bodies)
+    }
+
+    /// Drops the templates, they hold scopes which refer back to this registry.
+    pub fn clear_templates(&mut self) {
+        self.classes.clear();
+        self.functions.clear();
+        self.methods.clear();
+    }
+
+    /// Returns a copy of the instances, templates are left out because their scopes refer back to this registry.
+    #[cfg(feature = "arc")]
+    pub(crate) fn fork(&self) -> Self {
+        Self {
+            classes: HashMap::new(),
+            functions: HashMap::new(),
+            methods: HashMap::new(),
+            class_instances: self.class_instances.clone(),
+            function_instances: self.function_instances.clone(),
+            instance_args: self.instance_args.clone(),
+            bodies: vec![],
+            depth: self.depth,
+        }
+    }
+
+    #[cfg(feature = "arc")]
+    pub(crate) fn instance_count(&self) -> usize {
+        self.class_instances.len() + self.function_instances.len() + self.bodies.len()
+    }
+}
+
+impl fmt::Debug for Generics {
+    // templates are omitted because their scopes refer back to the registry
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Generics")
+            .field("classes", &self.classes.keys())
+            .field("functions", &self.functions.keys())
+            .field("methods", &self.methods.keys())
+            .field("class_instances", &self.class_instances)
+            .field("function_instances", &self.function_instances)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A generic class or struct, its members are declared for each set of type arguments it's used with.
+pub struct ClassTemplate {
+    pub name: Ident,
+    pub type_params: Vec<Ident>,
+    pub is_struct: bool,
+    pub visibility: Visibility,
+    pub flags: ClassFlags,
+    pub base: Option<Ident>,
+    pub fields: Vec<FieldSource>,
+    pub methods: Vec<Ref<MethodTemplate>>,
+    pub scope: Scope,
+}
+
+impl ClassTemplate {
+    /// Returns the class instantiated with the given type arguments, declaring it on first use.
+    pub fn instantiate(&self, args: &[TypeId], pool: &mut ConstantPool) -> Result<TypeId, Cause> {
+        check_arg_count(&self.name, &self.type_params, args)?;
+
+        let name = self.instance_name(args, pool)?;
+        if let Some(type_) = self.scope.generics().borrow().class_instance(&name) {
+            return Ok(type_.clone());
+        }
+
+        let name_idx = pool.names.add(name.to_heap());
+        let class_idx = pool.stub_definition(name_idx);
+        let type_ = if self.is_struct {
+            TypeId::Struct(class_idx)
+        } else {
+            TypeId::Class(class_idx)
+        };
+        {
+            // the instance is registered before its members so that they can refer to it
+            let mut generics = self.scope.generics().borrow_mut();
+            generics.class_instances.insert(name, type_.clone());
+            generics.instance_args.insert(class_idx, args.to_vec());
+        }
+
+        let mut scope = bind_type_args(&self.scope, &self.type_params, args, pool)?;
+        let base = match &self.base {
+            _ if self.is_struct => PoolIndex::UNDEFINED,
+            Some(base) => match scope.resolve_symbol(base.clone()) {
+                Ok(Symbol::Class(idx, _)) => idx,
+                _ => return Err(Cause::ClassNotFound(base.clone())),
+            },
+            None => match scope.resolve_symbol(Ident::from_static("IScriptable")) {
+                Ok(Symbol::Class(idx, _)) => idx,
+                _ => PoolIndex::UNDEFINED,
+            },
+        };
+        enter_instance(&scope)?;
+        let members = self.declare_members(class_idx, base, &mut scope, pool);
+        scope.generics().borrow_mut().depth -= 1;
+        let (fields, functions) = members?;
+
+        let class = Class {
+            visibility: self.visibility,
+            flags: self.flags,
+            base,
+            functions,
+            fields,
+            overrides: vec![],
+        };
+        pool.put_definition(class_idx, Definition::class(name_idx, class));
+        Ok(type_)
+    }
+
+    /// Returns the name of the instance of this class with the given type arguments, e.g. `Box<Int32>`.
+    pub fn instance_name(&self, args: &[TypeId], pool: &ConstantPool) -> Result<Ident, Cause> {
+        instance_name(&self.name, args, pool)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn declare_members(
+        &self,
+        class_idx: PoolIndex<Class>,
+        base: PoolIndex<Class>,
+        scope: &mut Scope,
+        pool: &mut ConstantPool,
+    ) -> Result<(Vec<PoolIndex<Field>>, Vec<PoolIndex<Function>>), Cause> {
+        let mut fields = Vec::with_capacity(self.fields.len());
+        for source in &self.fields {
+            let decl = &source.declaration;
+            let type_ = scope.declare_type(&source.type_, pool)?;
+            let field = Field {
+                visibility: decl.qualifiers.visibility().unwrap_or(self.visibility),
+                type_: scope.get_type_index(&type_, pool)?,
+                flags: FieldFlags::new()
+                    .with_is_browsable(true)
+                    .with_is_persistent(decl.qualifiers.contain(Qualifier::Persistent)),
+                hint: None,
+                attributes: vec![],
+                defaults: vec![],
+            };
+            let name_idx = pool.names.add(decl.name.to_heap());
+            let field_idx = pool.add_definition(Definition::field(name_idx, class_idx.cast(), field));
+            if decl.qualifiers.visibility().is_none() {
+                scope.add_implicit_visibility(field_idx);
+            }
+            fields.push(field_idx);
+        }
+
+        let mut functions = Vec::with_capacity(self.methods.len());
+        for method in &self.methods {
+            let visibility = method
+                .source
+                .declaration
+                .qualifiers
+                .visibility()
+                .unwrap_or(self.visibility);
+            // generic methods are instantiated on use, in the scope of this instance
+            if !method.source.type_params.is_empty() {
+                let template = FunctionTemplate {
+                    name: method.source.declaration.name.clone(),
+                    type_params: method.source.type_params.clone(),
+                    method: method.clone(),
+                    visibility,
+                    class: class_idx,
+                    scope: scope.clone(),
+                };
+                scope.generics().borrow_mut().add_method_template(template);
+                continue;
+            }
+            let sig = FunctionSignature::from_source(&method.source);
+            let name_idx = pool.names.add(Ref::from(sig.as_ref()));
+            let fun_idx = pool.stub_definition(name_idx);
+            method.declare(fun_idx, class_idx, base, visibility, scope, pool)?;
+            functions.push(fun_idx);
+        }
+        Ok((fields, functions))
+    }
+}
+
+/// A generic function or method, it's declared for each set of type arguments it's called with.
+pub struct FunctionTemplate {
+    pub name: Ident,
+    pub type_params: Vec<Ident>,
+    pub method: Ref<MethodTemplate>,
+    pub visibility: Visibility,
+    /// The class the instances are added to, undefined for free functions.
+    pub class: PoolIndex<Class>,
+    pub scope: Scope,
+}
+
+impl FunctionTemplate {
+    /// Returns the function instantiated with the given type arguments, declaring it on first use.
+    pub fn instantiate(&self, args: &[TypeId], pool: &mut ConstantPool) -> Result<PoolIndex<Function>, Cause> {
+        check_arg_count(&self.name, &self.type_params, args)?;
+
+        let name = instance_name(&self.name, args, pool)?;
+        // instances of methods are registered under the names of their classes
+        let key = if self.class.is_undefined() {
+            name.clone()
+        } else {
+            str_fmt!("{}::{}", pool.def_name(self.class)?, name)
+        };
+        if let Some(idx) = self.scope.generics().borrow().function_instances.get(&key) {
+            return Ok(*idx);
+        }
+
+        let name_idx = pool.names.add(name.to_heap());
+        let fun_idx = pool.stub_definition(name_idx);
+        self.scope
+            .generics()
+            .borrow_mut()
+            .function_instances
+            .insert(key, fun_idx);
+
+        let mut scope = bind_type_args(&self.scope, &self.type_params, args, pool)?;
+        let base = if self.class.is_undefined() {
+            PoolIndex::UNDEFINED
+        } else {
+            pool.class(self.class)?.base
+        };
+        enter_instance(&scope)?;
+        let res = self
+            .method
+            .declare(fun_idx, self.class, base, self.visibility, &mut scope, pool);
+        scope.generics().borrow_mut().depth -= 1;
+        res?;
+
+        if !self.class.is_undefined() {
+            // instances can't be overridden, a subclass would declare its own set of them
+            let fun = pool.function_mut(fun_idx)?;
+            fun.flags = fun.flags.with_is_final(true);
+            pool.class_mut(self.class)?.functions.push(fun_idx);
+        }
+        Ok(fun_idx)
+    }
+
+    /// Returns the type a parameter would have given the type arguments or `None` if it depends on an unknown argument.
+    pub fn substitute(
+        &self,
+        type_: &TypeName,
+        args: &[Option<TypeId>],
+        pool: &mut ConstantPool,
+    ) -> Result<Option<TypeId>, Cause> {
+        if !self.mentions_unbound(type_, args) {
+            let mut scope = self.scope.clone();
+            for (param, arg) in self.type_params.iter().zip(args) {
+                if let Some(arg) = arg {
+                    scope.add_type_arg(param.clone(), arg.clone());
+                }
+            }
+            Ok(Some(scope.declare_type(type_, pool)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn mentions_unbound(&self, type_: &TypeName, args: &[Option<TypeId>]) -> bool {
+        let is_unbound = type_.arguments().is_empty()
+            && self
+                .type_params
+                .iter()
+                .zip(args)
+                .any(|(param, arg)| *param == type_.name() && arg.is_none());
+        is_unbound || type_.arguments().iter().any(|arg| self.mentions_unbound(arg, args))
+    }
+}
+
+/// A function or a method of a generic class, the body is shared by all instances.
+pub struct MethodTemplate {
+    pub source: FunctionSource,
+    pub body: Option<Ref<Seq<SourceAst>>>,
+    pub source_ref: SourceReference,
+}
+
+impl MethodTemplate {
+    fn declare(
+        &self,
+        fun_idx: PoolIndex<Function>,
+        class_idx: PoolIndex<Class>,
+        base: PoolIndex<Class>,
+        visibility: Visibility,
+        scope: &mut Scope,
+        pool: &mut ConstantPool,
+    ) -> Result<(), Cause> {
+        let decl = &self.source.declaration;
+        if decl.qualifiers.visibility().is_none() {
+            scope.add_implicit_visibility(fun_idx);
+        }
+        let return_type = match &self.source.type_ {
+            Some(type_) if *type_ != TypeName::VOID => {
+                let type_ = scope.declare_type(type_, pool)?;
+                Some(scope.get_type_index(&type_, pool)?)
+            }
+            _ => None,
+        };
+
+        let mut parameters = Vec::with_capacity(self.source.parameters.len());
+        for param in &self.source.parameters {
+            let type_ = scope.declare_type(&param.type_, pool)?;
+            let flags = ParameterFlags::new()
+                .with_is_optional(param.qualifiers.contain(Qualifier::Optional))
+                .with_is_out(param.qualifiers.contain(Qualifier::Out))
+                .with_is_const(param.qualifiers.contain(Qualifier::Const));
+            let param_def = Parameter {
+                type_: scope.get_type_index(&type_, pool)?,
+                flags,
+            };
+            let name_idx = pool.names.add(param.name.to_heap());
+            parameters.push(pool.add_definition(Definition::param(name_idx, fun_idx, param_def)));
+        }
+
+        let flags = FunctionFlags::new()
+            .with_is_static(decl.qualifiers.contain(Qualifier::Static) || class_idx.is_undefined())
+            .with_is_final(decl.qualifiers.contain(Qualifier::Final))
+            .with_is_const(decl.qualifiers.contain(Qualifier::Const))
+            .with_has_return_value(return_type.is_some())
+            .with_has_parameters(!parameters.is_empty());
+        let name_idx = pool.definition(fun_idx)?.name;
+        let base_method = if flags.is_static() {
+            None
+        } else {
+            find_base_method(base, name_idx, pool)?
+        };
+        let function = Function {
+            visibility,
+            flags,
+            source: Some(self.source_ref.clone()),
+            return_type,
+            unk1: false,
+            base_method,
+            parameters,
+            locals: vec![],
+            operator: None,
+            cast: 0,
+            code: Code::EMPTY,
+            unk2: vec![],
+        };
+        pool.put_definition(fun_idx, Definition::function(name_idx, class_idx.cast(), function));
+
+        if let Some(code) = &self.body {
+            let body = InstanceBody {
+                class: class_idx,
+                index: fun_idx,
+                code: code.clone(),
+                scope: scope.clone(),
+                span: self.source.span,
+            };
+            scope.generics().borrow_mut().bodies.push(body);
+        }
+        Ok(())
+    }
+}
+
+/// The body of a function declared for a generic instance, it's compiled like any other function.
+#[derive(Debug)]
+pub struct InstanceBody {
+    pub class: PoolIndex<Class>,
+    pub index: PoolIndex<Function>,
+    pub code: Ref<Seq<SourceAst>>,
+    pub scope: Scope,
+    pub span: Span,
+}
+
+/// Infers type arguments by matching a type written in terms of type parameters against a concrete type.
+/// Arguments that have already been inferred are left unchanged.
+pub fn unify(
+    pattern: &TypeName,
+    actual: &TypeId,
+    type_params: &[Ident],
+    args: &mut [Option<TypeId>],
+    scope: &Scope,
+    pool: &ConstantPool,
+) -> Result<(), Cause> {
+    match (pattern.kind(), pattern.arguments(), actual) {
+        (_, _, TypeId::Null | TypeId::Void) => {}
+        (Kind::Prim, [], _) => {
+            let name = pattern.name();
+            if let Some(i) = type_params.iter().position(|param| *param == name) {
+                args[i].get_or_insert_with(|| actual.clone());
+            }
+        }
+        (Kind::Ref | Kind::WRef, [inner], TypeId::Ref(actual) | TypeId::WeakRef(actual))
+        | (Kind::ScriptRef, [inner], TypeId::ScriptRef(actual))
+        | (Kind::Array | Kind::StaticArray(_), [inner], TypeId::Array(actual) | TypeId::StaticArray(actual, _)) => {
+            unify(inner, actual, type_params, args, scope, pool)?;
+        }
+        (Kind::ScriptRef, [inner], actual) => {
+            unify(inner, actual, type_params, args, scope, pool)?;
+        }
+        (Kind::Function, [params @ .., ret], _) => {
+            if let Some(fn_type) = scope.resolve_function_type(actual, pool)? {
+                if fn_type.params.len() == params.len() {
+                    for (param, actual) in params.iter().zip(&fn_type.params) {
+                        unify(param, actual, type_params, args, scope, pool)?;
+                    }
+                    unify(ret, &fn_type.return_type, type_params, args, scope, pool)?;
+                }
+            }
+        }
+        (Kind::Prim, patterns, TypeId::Class(class) | TypeId::Struct(class)) => {
+            let instance_args = scope.generics().borrow().instance_args(*class).map(<[_]>::to_vec);
+            if let Some(instance_args) = instance_args.filter(|instance_args| instance_args.len() == patterns.len()) {
+                for (pattern, actual) in patterns.iter().zip(&instance_args) {
+                    unify(pattern, actual, type_params, args, scope, pool)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Finds the method with the same signature in the closest base class that declares it.
+fn find_base_method(
+    base: PoolIndex<Class>,
+    name: PoolIndex<CName>,
+    pool: &ConstantPool,
+) -> Result<Option<PoolIndex<Function>>, Cause> {
+    let mut current = base;
+    while !current.is_undefined() {
+        let class = pool.class(current)?;
+        for fun in &class.functions {
+            if pool.definition(*fun)?.name == name {
+                return Ok(Some(*fun));
+            }
+        }
+        current = class.base;
+    }
+    Ok(None)
+}
+
+fn check_arg_count(name: &Ident, params: &[Ident], args: &[TypeId]) -> Result<(), Cause> {
+    if params.len() == args.len() {
+        Ok(())
+    } else {
+        Err(Cause::InvalidTypeArgCount(name.clone(), params.len()))
+    }
+}
+
+fn instance_name(name: &Ident, args: &[TypeId], pool: &ConstantPool) -> Result<Ident, Cause> {
+    let args: Vec<_> = args.iter().map(|arg| arg.pretty(pool)).try_collect()?;
+    Ok(str_fmt!("{}<{}>", name, args.iter().format(",")))
+}
+
+fn bind_type_args(scope: &Scope, params: &[Ident], args: &[TypeId], pool: &ConstantPool) -> Result<Scope, Cause> {
+    let mut scope = scope.clone();
+    for (param, arg) in params.iter().zip(args) {
+        scope.add_type_arg(param.clone(), arg.clone());
+        import_type_symbols(arg, &mut scope, pool)?;
+    }
+    Ok(scope)
+}
+
+// types in the pool are resolved by their short names, so the types used as arguments
+// have to be visible in the scope of the instance even when the template module doesn't import them
+fn import_type_symbols(type_: &TypeId, scope: &mut Scope, pool: &ConstantPool) -> Result<(), Cause> {
+    let (name, symbol) = match type_ {
+        TypeId::Ref(inner)
+        | TypeId::WeakRef(inner)
+        | TypeId::ScriptRef(inner)
+        | TypeId::Array(inner)
+        | TypeId::StaticArray(inner, _) => return import_type_symbols(inner, scope, pool),
+        // instances are resolved by their full names
+        &TypeId::Class(idx) | &TypeId::Struct(idx) if scope.generics().borrow().instance_args(idx).is_some() => {
+            return Ok(());
+        }
+        &TypeId::Class(idx) => (pool.def_name(idx)?, Symbol::Class(idx, pool.class(idx)?.visibility)),
+        &TypeId::Struct(idx) => (pool.def_name(idx)?, Symbol::Struct(idx, pool.class(idx)?.visibility)),
+        &TypeId::Enum(idx) => (pool.def_name(idx)?, Symbol::Enum(idx)),
+        _ => return Ok(()),
+    };
+    let ident = Ident::from_ref(name.split('.').last().unwrap());
+    if scope.resolve_symbol(ident.clone()).is_err() {
+        scope.add_symbol(ident, symbol);
+    }
+    Ok(())
+}
+
+fn enter_instance(scope: &Scope) -> Result<(), Cause> {
+    let mut generics = scope.generics().borrow_mut();
+    if generics.depth >= MAX_INSTANCE_DEPTH {
+        return Err(Cause::UnsupportedFeature("generic types nested this deeply"));
+    }
+    generics.depth += 1;
+    Ok(())
+}
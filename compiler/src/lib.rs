@@ -2,6 +2,8 @@ pub mod assembler;
 pub mod cte;
 pub mod diagnostics;
 pub mod error;
+pub mod formatter;
+pub mod generics;
 #[allow(clippy::redundant_closure_call)]
 pub mod parser;
 pub mod scope;
@@ -1,12 +1,18 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::ops::Range;
 use std::str::FromStr;
 
 use peg::error::ParseError;
 use peg::str::LineCol;
-use redscript::ast::{BinOp, Constant, Expr, Ident, Literal, Pos, Seq, SourceAst, Span, SwitchCase, TypeName, UnOp};
+use redscript::ast::{
+    BinOp, Constant, Expr, Ident, LambdaParam, Literal, Pos, Seq, SourceAst, Span, SwitchCase, TypeName, UnOp,
+};
 use redscript::definition::Visibility;
 use redscript::Ref;
-use strum::EnumString;
+use strum::{EnumString, IntoStaticStr};
 
+use crate::diagnostics::Diagnostic;
 use crate::source_map::File;
 use crate::symbol::{Import, ModulePath};
 
@@ -15,6 +21,14 @@ pub struct SourceModule {
     pub path: Option<ModulePath>,
     pub imports: Vec<Import>,
     pub entries: Vec<SourceEntry>,
+    pub annotated_stmts: Vec<AnnotatedStmt>,
+}
+
+/// A statement in a function body preceded by annotations, only `@allow` is accepted on statements.
+#[derive(Debug)]
+pub struct AnnotatedStmt {
+    pub annotations: Vec<Annotation>,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -40,6 +54,7 @@ impl SourceEntry {
 #[derive(Debug)]
 pub struct ClassSource {
     pub declaration: Declaration,
+    pub type_params: Vec<Ident>,
     pub base: Option<Ident>,
     pub members: Vec<MemberSource>,
     pub span: Span,
@@ -56,11 +71,13 @@ pub struct FieldSource {
     pub declaration: Declaration,
     pub type_: TypeName,
     pub default: Option<Expr<SourceAst>>,
+    pub span: Span,
 }
 
 #[derive(Debug)]
 pub struct FunctionSource {
     pub declaration: Declaration,
+    pub type_params: Vec<Ident>,
     pub type_: Option<TypeName>,
     pub parameters: Vec<ParameterSource>,
     pub body: Option<Seq<SourceAst>>,
@@ -85,9 +102,11 @@ pub struct EnumSource {
 pub struct EnumMember {
     pub name: Ident,
     pub value: i64,
+    pub span: Span,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
 pub enum Qualifier {
     Public,
     Protected,
@@ -98,8 +117,10 @@ pub enum Qualifier {
     Const,
     Native,
     Exec,
+    #[strum(serialize = "cb")]
     Callback,
     Out,
+    #[strum(serialize = "opt")]
     Optional,
     Quest,
     ImportOnly,
@@ -122,6 +143,10 @@ impl Qualifiers {
     pub fn contain(&self, qualifier: Qualifier) -> bool {
         self.0.contains(&qualifier)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Qualifier> {
+        self.0.iter()
+    }
 }
 
 #[derive(Debug)]
@@ -139,7 +164,7 @@ pub struct Annotation {
     pub span: Span,
 }
 
-#[derive(Debug, PartialEq, Eq, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, IntoStaticStr)]
 #[strum(serialize_all = "camelCase")]
 pub enum AnnotationKind {
     ReplaceMethod,
@@ -149,22 +174,248 @@ pub enum AnnotationKind {
     AddField,
     If,
     RuntimeProperty,
+    Test,
+    Allow,
+}
+
+/// A module along with the comments and the parenthesized expressions found in its source,
+/// which the AST does not retain. It's used to print the source back without losing anything.
+#[derive(Debug)]
+pub struct SyntaxTree {
+    pub module: SourceModule,
+    /// The spans of comments, in the order of appearance.
+    pub comments: Vec<Span>,
+    /// The spans of expressions wrapped in parentheses, sorted.
+    pub parenthesized: Vec<Span>,
+}
+
+/// Details collected on the side while parsing, so that the AST does not have to carry them.
+#[derive(Debug, Default)]
+struct ParseContext {
+    annotated_stmts: RefCell<Vec<AnnotatedStmt>>,
+    comments: RefCell<Vec<Span>>,
+    parenthesized: RefCell<Vec<Span>>,
 }
 
-pub fn parse_file(file: &File) -> Result<SourceModule, ParseError<LineCol>> {
-    lang::module(file.source(), file.byte_offset())
+impl ParseContext {
+    // the same input can be parsed more than once when the parser backtracks, so spans are deduplicated
+    fn add_span(spans: &RefCell<Vec<Span>>, span: Span) {
+        let mut spans = spans.borrow_mut();
+        if let Err(idx) = spans.binary_search_by_key(&(span.low, span.high), |span| (span.low, span.high)) {
+            spans.insert(idx, span);
+        }
+    }
+}
+
+/// Parses a file, recovering from syntax errors by skipping the statement, member or declaration
+/// in which they occur. Returns the module with everything that could be parsed along with the
+/// syntax errors that were encountered.
+pub fn parse_file(file: &File) -> (SourceModule, Vec<Diagnostic>) {
+    let mut source = Cow::Borrowed(file.source());
+    let mut errors = vec![];
+    loop {
+        match lang::module(&source, file.byte_offset(), &ParseContext::default()) {
+            Ok(module) => return (module, errors),
+            Err(err) => {
+                let pos = file.byte_offset() + err.location.offset;
+                errors.push(Diagnostic::SyntaxError(err.expected, Span::new(pos, pos)));
+                // blanking out preserves the positions of everything else in the file,
+                // every iteration erases some non-whitespace characters so this always terminates
+                let range = recovery_range(&source, err.location.offset);
+                let blanked = source[range.clone()].replace(|c: char| !c.is_whitespace(), " ");
+                source.to_mut().replace_range(range, &blanked);
+            }
+        }
+    }
 }
 
 pub fn parse_str(str: &str) -> Result<SourceModule, ParseError<LineCol>> {
-    lang::module(str, Pos::ZERO)
+    lang::module(str, Pos::ZERO, &ParseContext::default())
+}
+
+/// Parses a file into a syntax tree, returns all syntax errors in the file if it's not valid.
+pub fn parse_syntax_tree(file: &File) -> Result<SyntaxTree, Vec<Diagnostic>> {
+    let state = ParseContext::default();
+    match lang::module(file.source(), file.byte_offset(), &state) {
+        Ok(module) => Ok(SyntaxTree {
+            module,
+            comments: state.comments.into_inner(),
+            parenthesized: state.parenthesized.into_inner(),
+        }),
+        Err(_) => Err(parse_file(file).1),
+    }
+}
+
+/// Returns the range of the source to skip in order to recover from a syntax error at the given offset.
+fn recovery_range(source: &str, offset: usize) -> Range<usize> {
+    let marks = scan_marks(source);
+    let unit_start = |idx: usize| match idx.checked_sub(1).map(|prev| marks[prev]) {
+        Some((pos, Mark::Item)) => pos,
+        Some((pos, _)) => pos + 1,
+        None => 0,
+    };
+    let is_blank = |range: &Range<usize>| source[range.clone()].trim().is_empty();
+
+    // the unit in which the error occurred starts after the last boundary preceding the error
+    let first = marks.partition_point(|&(pos, _)| pos < offset);
+    let start = unit_start(first);
+    let mut end = source.len();
+    let mut depth = 0usize;
+    for &(pos, mark) in &marks[first..] {
+        match mark {
+            Mark::Open => depth += 1,
+            Mark::Close if depth == 0 => {
+                end = pos;
+                break;
+            }
+            Mark::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    end = pos + 1;
+                    break;
+                }
+            }
+            Mark::Semicolon if depth == 0 => {
+                end = pos + 1;
+                break;
+            }
+            Mark::Item if depth == 0 && pos > start => {
+                end = pos;
+                break;
+            }
+            Mark::Semicolon | Mark::Item => {}
+        }
+    }
+    if !is_blank(&(start..end)) {
+        return start..end;
+    }
+
+    // the error is on a stray token, only that character is skipped
+    if let Some(char) = source[offset..].chars().next().filter(|c| !c.is_whitespace()) {
+        return offset..offset + char.len_utf8();
+    }
+
+    // the error is at the end of the file, the outermost block that was never closed is skipped
+    let mut open = vec![];
+    for (idx, &(_, mark)) in marks.iter().enumerate() {
+        match mark {
+            Mark::Open => open.push(idx),
+            Mark::Close => {
+                open.pop();
+            }
+            Mark::Semicolon | Mark::Item => {}
+        }
+    }
+    match open.first() {
+        Some(&idx) if !is_blank(&(unit_start(idx)..source.len())) => unit_start(idx)..source.len(),
+        _ => 0..source.len(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Semicolon,
+    Open,
+    Close,
+    /// A keyword or an annotation that can start a declaration at the beginning of a line.
+    Item,
+}
+
+/// Finds the positions of tokens delimiting statements, members and declarations,
+/// skipping over comments and string literals.
+fn scan_marks(source: &str) -> Vec<(usize, Mark)> {
+    const ITEM_KEYWORDS: &[&str] = &[
+        "func",
+        "class",
+        "struct",
+        "enum",
+        "import",
+        "module",
+        "native",
+        "public",
+        "protected",
+        "private",
+        "abstract",
+        "final",
+        "static",
+        "importonly",
+    ];
+
+    let bytes = source.as_bytes();
+    let mut marks = vec![];
+    let mut line_start = true;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let mut depth = 0;
+                while i < bytes.len() {
+                    if bytes[i..].starts_with(b"/*") {
+                        depth += 1;
+                        i += 2;
+                    } else if bytes[i..].starts_with(b"*/") {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                continue;
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b';' => marks.push((i, Mark::Semicolon)),
+            b'{' => marks.push((i, Mark::Open)),
+            b'}' => marks.push((i, Mark::Close)),
+            b'\n' => {
+                line_start = true;
+                i += 1;
+                continue;
+            }
+            b' ' | b'\t' | b'\r' => {
+                i += 1;
+                continue;
+            }
+            b'@' if line_start => marks.push((i, Mark::Item)),
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                let len = bytes[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+                    .count();
+                if line_start && ITEM_KEYWORDS.contains(&&source[i..i + len]) {
+                    marks.push((i, Mark::Item));
+                }
+                line_start = false;
+                i += len;
+                continue;
+            }
+            _ => {}
+        }
+        line_start = false;
+        i += 1;
+    }
+    marks
 }
 
 peg::parser! {
-    grammar lang(offset: Pos) for str {
+    grammar lang<'a>(offset: Pos, state: &'a ParseContext) for str {
         use peg::ParseLiteral;
 
         rule pos() -> Pos = pos:position!() { offset + pos }
-        rule _() = quiet!{ ([' ' | '\n' | '\r' | '\t'] / comment() / line_comment())* }
+        rule _() = quiet!{ ([' ' | '\n' | '\r' | '\t'] / any_comment())* }
         rule commasep<T>(x: rule<T>) -> Vec<T> = v:(x() ** ("," _)) {v}
         rule dotsep<T>(x: rule<T>) -> Vec<T> = v:(x() ** ("." _)) {v}
 
@@ -177,6 +428,10 @@ peg::parser! {
 
         rule line_comment() = "//" $(!['\n'] [_])*
 
+        rule any_comment()
+            = low:pos() (comment() / line_comment()) high:pos()
+            { ParseContext::add_span(&state.comments, Span::new(low, high)) }
+
         rule qualifier() -> Qualifier
             = keyword("public") { Qualifier::Public }
             / keyword("protected") { Qualifier::Protected }
@@ -200,9 +455,9 @@ peg::parser! {
             / "t" { Literal::TweakDbId }
 
         rule annotation() -> Annotation
-            = pos:pos() "@" ident:ident() _ "(" _ args:commasep(<expr()>) _ ")" end:pos() {?
+            = pos:pos() "@" ident:ident() args:(_ "(" _ args:commasep(<expr()>) _ ")" { args })? end:pos() {?
                 AnnotationKind::from_str(ident.as_ref()).map(|kind| {
-                    Annotation { kind, args, span: Span::new(pos, end) }
+                    Annotation { kind, args: args.unwrap_or_default(), span: Span::new(pos, end) }
                 }).map_err(|_| "valid annotation")
             }
 
@@ -272,6 +527,7 @@ peg::parser! {
             / "[" _ type_:type_() _ ";" _ size:number_str() "]" {?
                 Ok(TypeName::StaticArray(type_.into(), size.parse().or(Err("valid 32-bit integer"))?))
             }
+            / "(" _ params:commasep(<type_()>) _ ")" _ "->" _ ret:type_() { TypeName::function(params, ret) }
         rule type_args() -> Vec<TypeName> = "<" _ args:commasep(<type_()>) _ ">" { args }
 
         rule let_type() -> TypeName = ":" _ type_:type_() { type_ }
@@ -288,12 +544,15 @@ peg::parser! {
             { Declaration { annotations, qualifiers, name, span: Span::new(pos, end) } }
 
         rule field() -> FieldSource
-            = declaration:decl(<keyword("let")>) _ type_:let_type() _ default:initializer()? _ ";"
-            { FieldSource { declaration, type_, default }}
+            = pos:pos() declaration:decl(<keyword("let")>) _ type_:let_type() _ default:initializer()? _ ";" end:pos()
+            { FieldSource { declaration, type_, default, span: Span::new(pos, end) }}
 
         pub rule function() -> FunctionSource
-            = pos:pos() declaration:decl(<keyword("func")>) _ "(" _ parameters:commasep(<param()>) _ ")" _ type_:func_type()? _ body:function_body()? ";"? end:pos()
-            { FunctionSource { declaration, type_, parameters, body, span: Span::new(pos, end) } }
+            = pos:pos() declaration:decl(<keyword("func")>) _ type_params:type_params()? _ "(" _ parameters:commasep(<param()>) _ ")" _ type_:func_type()? _ body:function_body()? ";"? end:pos()
+            {
+                let type_params = type_params.unwrap_or_default();
+                FunctionSource { declaration, type_params, type_, parameters, body, span: Span::new(pos, end) }
+            }
         rule function_body() -> Seq<SourceAst>
             = "{" _ body:seq() _ "}" { body }
             / pos:pos() "=" _ expr:expr() _ end:pos() { Seq::new(vec![Expr::Return(Some(Box::new(expr)), Span::new(pos, end))]) }
@@ -302,15 +561,30 @@ peg::parser! {
             = qualifiers:qualifiers() _ name:ident() _ type_:let_type()
             { ParameterSource { qualifiers, name, type_ } }
 
+        rule lambda_param() -> LambdaParam
+            = name:ident() _ type_:let_type()? { LambdaParam { name, type_ } }
+
+        rule lambda_body() -> Expr<SourceAst>
+            = "{" _ body:seq() _ "}" { Expr::Seq(body) }
+            / expr()
+
+        rule type_params() -> Vec<Ident> = "<" _ params:commasep(<ident()>) _ ">" { params }
+
         rule extends() -> Ident = keyword("extends") _ name:ident() { name }
 
         pub rule class() -> ClassSource
-            = pos:pos() declaration:decl(<keyword("class")>) _ base:extends()? _ "{" _ members:member()**_ _ "}" end:pos()
-            { ClassSource { declaration, base, members, span: Span::new(pos, end) } }
+            = pos:pos() declaration:decl(<keyword("class")>) _ type_params:type_params()? _ base:extends()? _ "{" _ members:member()**_ _ "}" end:pos()
+            {
+                let type_params = type_params.unwrap_or_default();
+                ClassSource { declaration, type_params, base, members, span: Span::new(pos, end) }
+            }
 
         pub rule struct_() -> ClassSource
-            = pos:pos() declaration:decl(<keyword("struct")>) _ "{" _ members:member()**_ _ "}" end:pos()
-            { ClassSource { declaration, base: None, members, span: Span::new(pos, end) } }
+            = pos:pos() declaration:decl(<keyword("struct")>) _ type_params:type_params()? _ "{" _ members:member()**_ _ "}" end:pos()
+            {
+                let type_params = type_params.unwrap_or_default();
+                ClassSource { declaration, type_params, base: None, members, span: Span::new(pos, end) }
+            }
 
         rule member() -> MemberSource
             = fun:function() { MemberSource::Function(fun) }
@@ -322,10 +596,10 @@ peg::parser! {
             { EnumSource { declaration, members, span: Span::new(pos, end) } }
 
         rule enum_member() -> EnumMember
-            = name:ident() _ "=" _ value:number()
+            = pos:pos() name:ident() _ "=" _ value:number() end:pos()
             {? match value {
-                 Constant::I32(value) => Ok(EnumMember { name, value: value.into() }),
-                 Constant::I64(value) => Ok(EnumMember { name, value }),
+                 Constant::I32(value) => Ok(EnumMember { name, value: value.into(), span: Span::new(pos, end) }),
+                 Constant::I64(value) => Ok(EnumMember { name, value, span: Span::new(pos, end) }),
                  _ => Err("signed 64-bit int")
                }
             }
@@ -351,7 +625,7 @@ peg::parser! {
 
         pub rule module() -> SourceModule =
             _ path:module_path()? _ imports:(import() ** _) _ entries:(source_entry() ** _) _
-            { SourceModule { path, imports, entries } }
+            { SourceModule { path, imports, entries, annotated_stmts: state.annotated_stmts.take() } }
 
         rule switch() -> Expr<SourceAst>
             = pos:pos() keyword("switch") _ matcher:expr() _ "{" _ cases:(case() ** _) _ default:default()? _ "}" _ ";"? end:pos()
@@ -379,13 +653,26 @@ peg::parser! {
             = keyword("else") _ "{" _ body:seq() _ "}" { body }
             / keyword("else") _ body:if_() { Seq::new(vec![body]) }
 
+        rule stmt_annotation() -> Annotation
+            = ann:annotation() {? if ann.kind == AnnotationKind::Allow { Ok(ann) } else { Err("@allow") } }
+
         pub rule stmt() -> Expr<SourceAst>
-            = while_: while_() { while_ }
+            = annotations:(stmt_annotation() ++ _) _ stmt:stmt() {
+                let span = annotations[0].span.merge(stmt.span());
+                let mut stmts = state.annotated_stmts.borrow_mut();
+                // the same statement can be parsed more than once when the parser backtracks
+                if !stmts.iter().any(|existing| existing.span == span) {
+                    stmts.push(AnnotatedStmt { annotations, span });
+                }
+                stmt
+            }
+            / while_: while_() { while_ }
             / for_: for_() { for_ }
             / if_: if_() { if_ }
             / switch: switch() { switch }
             / pos:pos() keyword("return") _ val:expr()? _ end_of_stmt() end:pos() { Expr::Return(val.map(Box::new), Span::new(pos, end)) }
             / pos:pos() keyword("break") _ end_of_stmt() end:pos() { Expr::Break(Span::new(pos, end)) }
+            / pos:pos() keyword("continue") _ end_of_stmt() end:pos() { Expr::Continue(Span::new(pos, end)) }
             / let_:let() { let_ }
             / expr:expr() _ end_of_stmt() { expr }
             / expected!("a statement")
@@ -435,12 +722,12 @@ peg::parser! {
             x:(@) _ pos:pos() "/" end:pos() _ y:@ { binop(x, y, BinOp::Divide) }
             x:(@) _ pos:pos() "%" end:pos() _ y:@ { binop(x, y, BinOp::Modulo) }
             --
-            "!" _ expr:@ { unop(expr, UnOp::LogicNot) }
-            "~" _ expr:@ { unop(expr, UnOp::BitNot) }
-            "-" _ expr:@ { unop(expr, UnOp::Neg) }
+            pos:pos() "!" _ expr:@ { unop(pos, expr, UnOp::LogicNot) }
+            pos:pos() "~" _ expr:@ { unop(pos, expr, UnOp::BitNot) }
+            pos:pos() "-" _ expr:@ { unop(pos, expr, UnOp::Neg) }
 
-            pos:pos() keyword("new") _ id:ident() _ "(" _ params:commasep(<expr()>) _ ")" end:pos() {
-                Expr::New(TypeName::new(id, vec![]), params.into_boxed_slice(), Span::new(pos, end))
+            pos:pos() keyword("new") _ id:ident() _ type_args:type_args()? _ "(" _ params:commasep(<expr()>) _ ")" end:pos() {
+                Expr::New(TypeName::new(id, type_args.unwrap_or_default()), params.into_boxed_slice(), Span::new(pos, end))
             }
             --
             expr:(@) _ "[" _ idx:expr() _ "]" high:pos() {
@@ -462,7 +749,13 @@ peg::parser! {
             pos:pos() "[" _ exprs:commasep(<expr()>)_ "]" end:pos() {
                 Expr::ArrayLit(exprs.into_boxed_slice(), None, Span::new(pos, end))
             }
-            "(" _ v:expr() _ ")" { v }
+            pos:pos() "(" _ params:commasep(<lambda_param()>) _ ")" _ "->" _ body:lambda_body() end:pos() {
+                Expr::Lambda(params.into_boxed_slice(), Box::new(body), Span::new(pos, end))
+            }
+            "(" _ v:expr() _ ")" {
+                ParseContext::add_span(&state.parenthesized, v.span());
+                v
+            }
             pos:pos() keyword("null") end:pos() {
                 Expr::Null(Span::new(pos, end))
             }
@@ -494,18 +787,21 @@ fn binop(lhs: Expr<SourceAst>, rhs: Expr<SourceAst>, op: BinOp) -> Expr<SourceAs
 }
 
 #[inline]
-fn unop(expr: Expr<SourceAst>, op: UnOp) -> Expr<SourceAst> {
-    let span = expr.span();
+fn unop(pos: Pos, expr: Expr<SourceAst>, op: UnOp) -> Expr<SourceAst> {
+    let span = Span::new(pos, expr.span().high);
     Expr::UnOp(Box::new(expr), op, span)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
     use super::*;
+    use crate::source_map::Files;
 
     #[test]
     fn parse_ternary_op() {
-        let expr = lang::expr("3.0 ? 5.0 : 5 + 4", Pos::ZERO).unwrap();
+        let expr = lang::expr("3.0 ? 5.0 : 5 + 4", Pos::ZERO, &ParseContext::default()).unwrap();
         assert_eq!(
             format!("{:?}", expr),
             "Conditional(Constant(F32(3.0), Span { low: Pos(0), high: Pos(3) }), Constant(F32(5.0), Span { low: Pos(6), high: Pos(9) }), BinOp(Constant(I32(5), Span { low: Pos(12), high: Pos(13) }), Constant(I32(4), Span { low: Pos(16), high: Pos(17) }), Add, Span { low: Pos(12), high: Pos(17) }), Span { low: Pos(0), high: Pos(17) })"
@@ -523,11 +819,12 @@ mod tests {
                 }
              }",
             Pos::ZERO,
+            &ParseContext::default(),
         )
         .unwrap();
         assert_eq!(
             format!("{:?}", module.entries),
-            r#"[Class(ClassSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Public]), name: "A", span: Span { low: Pos(0), high: Pos(14) } }, base: Some("IScriptable"), members: [Field(FieldSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Private, Const]), name: "m_field", span: Span { low: Pos(53), high: Pos(78) } }, type_: Named { name: "Int32", args: None }, default: None }), Function(FunctionSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Public]), name: "GetField", span: Span { low: Pos(104), high: Pos(124) } }, type_: Some(Named { name: "Int32", args: None }), parameters: [], body: Some(Seq { exprs: [Return(Some(Member(This(Span { low: Pos(165), high: Pos(169) }), "m_field", Span { low: Pos(165), high: Pos(177) })), Span { low: Pos(158), high: Pos(178) })] }), span: Span { low: Pos(104), high: Pos(196) } })], span: Span { low: Pos(0), high: Pos(211) } })]"#
+            r#"[Class(ClassSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Public]), name: "A", span: Span { low: Pos(0), high: Pos(14) } }, type_params: [], base: Some("IScriptable"), members: [Field(FieldSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Private, Const]), name: "m_field", span: Span { low: Pos(53), high: Pos(78) } }, type_: Named { name: "Int32", args: None }, default: None, span: Span { low: Pos(53), high: Pos(86) } }), Function(FunctionSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Public]), name: "GetField", span: Span { low: Pos(104), high: Pos(124) } }, type_params: [], type_: Some(Named { name: "Int32", args: None }), parameters: [], body: Some(Seq { exprs: [Return(Some(Member(This(Span { low: Pos(165), high: Pos(169) }), "m_field", Span { low: Pos(165), high: Pos(177) })), Span { low: Pos(158), high: Pos(178) })] }), span: Span { low: Pos(104), high: Pos(196) } })], span: Span { low: Pos(0), high: Pos(211) } })]"#
         );
     }
 
@@ -538,11 +835,12 @@ mod tests {
                 return this.m_field > optimum ? this.m_field : optimum;
              }",
             Pos::ZERO,
+            &ParseContext::default(),
         )
         .unwrap();
         assert_eq!(
             format!("{:?}", module.entries),
-            r#"[Function(FunctionSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Public, Static]), name: "GetField", span: Span { low: Pos(0), high: Pos(27) } }, type_: Some(Named { name: "Uint64", args: None }), parameters: [ParameterSource { qualifiers: Qualifiers([]), name: "optimum", type_: Named { name: "Uint64", args: None } }], body: Some(Seq { exprs: [Return(Some(Conditional(BinOp(Member(This(Span { low: Pos(80), high: Pos(84) }), "m_field", Span { low: Pos(80), high: Pos(92) }), Ident("optimum", Span { low: Pos(95), high: Pos(102) }), Greater, Span { low: Pos(80), high: Pos(102) }), Member(This(Span { low: Pos(105), high: Pos(109) }), "m_field", Span { low: Pos(105), high: Pos(117) }), Ident("optimum", Span { low: Pos(120), high: Pos(127) }), Span { low: Pos(80), high: Pos(127) })), Span { low: Pos(73), high: Pos(128) })] }), span: Span { low: Pos(0), high: Pos(143) } })]"#
+            r#"[Function(FunctionSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Public, Static]), name: "GetField", span: Span { low: Pos(0), high: Pos(27) } }, type_params: [], type_: Some(Named { name: "Uint64", args: None }), parameters: [ParameterSource { qualifiers: Qualifiers([]), name: "optimum", type_: Named { name: "Uint64", args: None } }], body: Some(Seq { exprs: [Return(Some(Conditional(BinOp(Member(This(Span { low: Pos(80), high: Pos(84) }), "m_field", Span { low: Pos(80), high: Pos(92) }), Ident("optimum", Span { low: Pos(95), high: Pos(102) }), Greater, Span { low: Pos(80), high: Pos(102) }), Member(This(Span { low: Pos(105), high: Pos(109) }), "m_field", Span { low: Pos(105), high: Pos(117) }), Ident("optimum", Span { low: Pos(120), high: Pos(127) }), Span { low: Pos(80), high: Pos(127) })), Span { low: Pos(73), high: Pos(128) })] }), span: Span { low: Pos(0), high: Pos(143) } })]"#
         );
     }
 
@@ -554,6 +852,7 @@ mod tests {
                 i += 1;
              }",
             Pos::ZERO,
+            &ParseContext::default(),
         )
         .unwrap();
         assert_eq!(
@@ -571,6 +870,7 @@ mod tests {
                 this.Bugs();
              }",
             Pos::ZERO,
+            &ParseContext::default(),
         )
         .unwrap();
         assert_eq!(
@@ -592,6 +892,7 @@ mod tests {
                 return 3;
              }",
             Pos::ZERO,
+            &ParseContext::default(),
         )
         .unwrap();
         assert_eq!(
@@ -613,6 +914,7 @@ mod tests {
                     Log("default");
             }"#,
             Pos::ZERO,
+            &ParseContext::default(),
         )
         .unwrap();
         assert_eq!(
@@ -633,11 +935,12 @@ mod tests {
             }
             "#,
             Pos::ZERO,
+            &ParseContext::default(),
         )
         .unwrap();
         assert_eq!(
             format!("{:?}", module.entries),
-            r#"[Class(ClassSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([]), name: "Test", span: Span { low: Pos(101), high: Pos(111) } }, base: None, members: [Field(FieldSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Private]), name: "m_field", span: Span { low: Pos(130), high: Pos(149) } }, type_: Named { name: "String", args: None }, default: None })], span: Span { low: Pos(101), high: Pos(189) } })]"#
+            r#"[Class(ClassSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([]), name: "Test", span: Span { low: Pos(101), high: Pos(111) } }, type_params: [], base: None, members: [Field(FieldSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Private]), name: "m_field", span: Span { low: Pos(130), high: Pos(149) } }, type_: Named { name: "String", args: None }, default: None, span: Span { low: Pos(130), high: Pos(175) } })], span: Span { low: Pos(101), high: Pos(189) } })]"#
         );
     }
 
@@ -651,11 +954,12 @@ mod tests {
             }
             "#,
             Pos::ZERO,
+            &ParseContext::default(),
         )
         .unwrap();
         assert_eq!(
             format!("{:?}", module.entries),
-            r#"[Class(ClassSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([]), name: "Test", span: Span { low: Pos(13), high: Pos(23) } }, base: None, members: [Field(FieldSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Private]), name: "m_field", span: Span { low: Pos(114), high: Pos(133) } }, type_: Named { name: "String", args: None }, default: None })], span: Span { low: Pos(13), high: Pos(156) } })]"#
+            r#"[Class(ClassSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([]), name: "Test", span: Span { low: Pos(13), high: Pos(23) } }, type_params: [], base: None, members: [Field(FieldSource { declaration: Declaration { annotations: [], qualifiers: Qualifiers([Private]), name: "m_field", span: Span { low: Pos(114), high: Pos(133) } }, type_: Named { name: "String", args: None }, default: None, span: Span { low: Pos(114), high: Pos(142) } })], span: Span { low: Pos(13), high: Pos(156) } })]"#
         );
     }
 
@@ -664,6 +968,7 @@ mod tests {
         let escaped = lang::escaped_string(
             r#""This is a backslash \'\\\' \"escaped\" string \t\u{03BB}\r\n""#,
             Pos::ZERO,
+            &ParseContext::default(),
         );
 
         assert_eq!(
@@ -679,6 +984,7 @@ mod tests {
         let mangled = lang::escaped_string(
             r#""These are invalid escape characters: \a \\" \u{1234567}""#,
             Pos::ZERO,
+            &ParseContext::default(),
         );
 
         assert!(mangled.is_err());
@@ -689,6 +995,7 @@ mod tests {
         let str = lang::interpolated_string(
             r#"s"My name is \(name) and I am \(currentYear - birthYear) years old""#,
             Pos::ZERO,
+            &ParseContext::default(),
         )
         .unwrap();
         assert_eq!(
@@ -699,10 +1006,64 @@ mod tests {
 
     #[test]
     fn parse_complex_logic() {
-        let str = lang::expr(r#"(true || false && false) && ((true || false) && true)"#, Pos::ZERO).unwrap();
+        let str = lang::expr(
+            r#"(true || false && false) && ((true || false) && true)"#,
+            Pos::ZERO,
+            &ParseContext::default(),
+        )
+        .unwrap();
         assert_eq!(
             format!("{:?}", str),
             r#"BinOp(BinOp(Constant(Bool(true), Span { low: Pos(1), high: Pos(5) }), BinOp(Constant(Bool(false), Span { low: Pos(9), high: Pos(14) }), Constant(Bool(false), Span { low: Pos(18), high: Pos(23) }), LogicAnd, Span { low: Pos(9), high: Pos(23) }), LogicOr, Span { low: Pos(1), high: Pos(23) }), BinOp(BinOp(Constant(Bool(true), Span { low: Pos(30), high: Pos(34) }), Constant(Bool(false), Span { low: Pos(38), high: Pos(43) }), LogicOr, Span { low: Pos(30), high: Pos(43) }), Constant(Bool(true), Span { low: Pos(48), high: Pos(52) }), LogicAnd, Span { low: Pos(30), high: Pos(52) }), LogicAnd, Span { low: Pos(1), high: Pos(52) })"#
         );
     }
+
+    #[test]
+    fn parse_with_error_recovery() {
+        let source = "
+            func A() -> Int32 {
+                let x = ;
+                return 1;
+            }
+
+            native func B( -> Int32
+            native func C() -> Int32
+
+            class D {
+                let a: Int32
+                func E() {
+                    F(;
+                    G();
+                }
+            }
+
+            func H() {}
+            ";
+        let mut files = Files::new();
+        files.add(PathBuf::from("test.reds"), source.to_owned());
+        let (module, errors) = parse_file(files.files().next().unwrap());
+
+        let lines = errors
+            .iter()
+            .map(|err| files.lookup(err.span()).unwrap().start.line)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [2, 6, 11, 12]);
+
+        let names = module
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                SourceEntry::Function(fun) => fun.declaration.name.as_ref().to_owned(),
+                SourceEntry::Class(class) => {
+                    let members = class.members.iter().map(|member| match member {
+                        MemberSource::Function(fun) => fun.declaration.name.as_ref(),
+                        MemberSource::Field(field) => field.declaration.name.as_ref(),
+                    });
+                    format!("{}({})", class.declaration.name, members.collect::<Vec<_>>().join(", "))
+                }
+                other => panic!("unexpected entry: {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["A", "C", "D(E)", "H"]);
+    }
 }
@@ -1,29 +1,78 @@
 use hamt_sync::Map;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
 use redscript::ast::{Ident, Kind, TypeName};
 use redscript::bundle::{ConstantPool, PoolError, PoolIndex};
-use redscript::definition::{AnyDefinition, Class, Definition, Enum, Field, Function, Local, Parameter, Type};
-use redscript::str_fmt;
+use redscript::bytecode::Code;
+use redscript::definition::{
+    AnyDefinition, Class, ClassFlags, Definition, Enum, Field, Function, FunctionFlags, Local, Parameter,
+    ParameterFlags, SourceReference, Type, Visibility,
+};
+use redscript::{str_fmt, Ref};
 
 use crate::error::{Cause, Error};
+use crate::generics::{ClassTemplate, FunctionTemplate, Generics};
 use crate::symbol::{FunctionSignature, Symbol};
 
+#[cfg(not(feature = "arc"))]
+pub type SharedCell<A> = std::cell::RefCell<A>;
+#[cfg(feature = "arc")]
+pub type SharedCell<A> = SyncCell<A>;
+
+/// A replacement for `RefCell` that can be shared between threads, it's used with the `arc` feature.
+#[cfg(feature = "arc")]
+#[derive(Debug, Default)]
+pub struct SyncCell<A>(std::sync::RwLock<A>);
+
+#[cfg(feature = "arc")]
+impl<A> SyncCell<A> {
+    pub fn new(value: A) -> Self {
+        Self(std::sync::RwLock::new(value))
+    }
+
+    pub fn borrow(&self) -> std::sync::RwLockReadGuard<'_, A> {
+        self.0.read().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    pub fn borrow_mut(&self) -> std::sync::RwLockWriteGuard<'_, A> {
+        self.0.write().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Scope {
     symbols: Map<Ident, Symbol>,
     references: Map<Ident, Value>,
     types: Map<Ident, PoolIndex<Type>>,
+    type_args: Map<Ident, TypeId>,
+    // classes backing function types are shared by all scopes, they can be declared while compiling any function
+    function_types: Ref<SharedCell<HashMap<Ident, PoolIndex<Class>>>>,
+    // generic templates and their instances are shared for the same reason
+    generics: Ref<SharedCell<Generics>>,
+    // members declared without a visibility qualifier, they take the visibility of their class but can be
+    // accessed from anywhere
+    implicit_visibility: Ref<SharedCell<HashSet<PoolIndex<Definition>>>>,
 
     pub this: Option<PoolIndex<Class>>,
+    // the class that encloses the current function, unlike `this` it's also set for static methods
+    pub class: Option<PoolIndex<Class>>,
     pub function: Option<PoolIndex<Function>>,
 }
 
 impl Scope {
     pub fn new(pool: &ConstantPool) -> Result<Self, Error> {
         let mut types = Map::new();
+        let mut function_types = HashMap::new();
         for (idx, def) in pool.roots() {
-            if let AnyDefinition::Type(_) = def.value {
-                let ident = Ident::from_heap(pool.def_name(idx)?);
-                types = types.insert(ident, idx.cast());
+            match def.value {
+                AnyDefinition::Type(_) => {
+                    let ident = Ident::from_heap(pool.def_name(idx)?);
+                    types = types.insert(ident, idx.cast());
+                }
+                AnyDefinition::Class(_) if pool.def_name(idx)?.starts_with(FUNCTION_TYPE_PREFIX) => {
+                    function_types.insert(Ident::from_heap(pool.def_name(idx)?), idx.cast());
+                }
+                _ => {}
             }
         }
 
@@ -31,7 +80,12 @@ impl Scope {
             symbols: Map::new(),
             references: Map::new(),
             types,
+            type_args: Map::new(),
+            function_types: Ref::new(SharedCell::new(function_types)),
+            generics: Ref::default(),
+            implicit_visibility: Ref::default(),
             this: None,
+            class: None,
             function: None,
         };
 
@@ -58,6 +112,11 @@ impl Scope {
         self.types = self.types.insert(name, typ);
     }
 
+    /// Binds a type parameter of a generic template to the type it's instantiated with.
+    pub fn add_type_arg(&mut self, name: Ident, type_: TypeId) {
+        self.type_args = self.type_args.insert(name, type_);
+    }
+
     pub fn add_symbol(&mut self, name: Ident, symbol: Symbol) {
         match symbol {
             Symbol::Functions(funs) => match self.symbols.find(&name) {
@@ -192,6 +251,79 @@ impl Scope {
             .or_else(|_| self.resolve_symbol(name).map(Reference::Symbol))
     }
 
+    pub fn generics(&self) -> &SharedCell<Generics> {
+        &self.generics
+    }
+
+    /// Marks a member as declared without a visibility qualifier, accesses to it are not checked.
+    pub fn add_implicit_visibility<A>(&self, member: PoolIndex<A>) {
+        self.implicit_visibility.borrow_mut().insert(member.cast());
+    }
+
+    pub fn has_implicit_visibility<A>(&self, member: PoolIndex<A>) -> bool {
+        self.implicit_visibility.borrow().contains(&member.cast())
+    }
+
+    /// Returns a copy of this scope with its own copy of the function types and generic instances
+    /// shared by all scopes, generic templates are left out since they refer back to the original.
+    #[cfg(feature = "arc")]
+    pub(crate) fn fork_shared(&self) -> Self {
+        Self {
+            function_types: Ref::new(SharedCell::new(self.function_types.borrow().clone())),
+            generics: Ref::new(SharedCell::new(self.generics.borrow().fork())),
+            ..self.clone()
+        }
+    }
+
+    /// Makes this scope use the state shared by the scopes of another one.
+    #[cfg(feature = "arc")]
+    pub(crate) fn share_with(&mut self, other: &Scope) {
+        self.function_types = other.function_types.clone();
+        self.generics = other.generics.clone();
+    }
+
+    /// Returns the number of function types and generic instances shared by all scopes,
+    /// it grows whenever one of them is declared.
+    #[cfg(feature = "arc")]
+    pub(crate) fn shared_size(&self) -> usize {
+        self.function_types.borrow().len() + self.generics.borrow().instance_count()
+    }
+
+    pub fn resolve_class_template(&self, name: Ident) -> Result<Ref<ClassTemplate>, Cause> {
+        match self.symbols.find(&name) {
+            Some(Symbol::ClassTemplate(path, _)) => self
+                .generics
+                .borrow()
+                .class_template(path)
+                .ok_or(Cause::UnresolvedType(name)),
+            _ => Err(Cause::UnresolvedType(name)),
+        }
+    }
+
+    pub fn resolve_function_template(&self, name: Ident) -> Option<Ref<FunctionTemplate>> {
+        match self.symbols.find(&name) {
+            Some(Symbol::FunctionTemplate(path, _)) => self.generics.borrow().function_template(path),
+            _ => None,
+        }
+    }
+
+    /// Finds a generic method declared by a class or by any of its bases.
+    pub fn resolve_method_template(
+        &self,
+        name: &Ident,
+        class_idx: PoolIndex<Class>,
+        pool: &ConstantPool,
+    ) -> Result<Option<Ref<FunctionTemplate>>, Cause> {
+        let mut current_idx = class_idx;
+        while current_idx != PoolIndex::UNDEFINED {
+            if let Some(template) = self.generics.borrow().method_template(current_idx, name) {
+                return Ok(Some(template));
+            }
+            current_idx = pool.class(current_idx)?.base;
+        }
+        Ok(None)
+    }
+
     fn get_type_index_with(
         &mut self,
         type_: &TypeId,
@@ -244,6 +376,9 @@ impl Scope {
     }
 
     pub fn resolve_type(&self, name: &TypeName, pool: &ConstantPool) -> Result<TypeId, Cause> {
+        if let (Some(type_), []) = (self.type_args.find(&name.name()), name.arguments()) {
+            return Ok(type_.clone());
+        }
         let result = if let Some(res) = self.types.find(&name.repr()) {
             self.resolve_type_from_pool(*res, pool)?
         } else {
@@ -255,6 +390,24 @@ impl Scope {
                 (Kind::StaticArray(size), [nested]) => {
                     TypeId::StaticArray(Box::new(self.resolve_type(nested, pool)?), size)
                 }
+                (Kind::Function, [params @ .., ret]) => {
+                    let params: Vec<_> = params.iter().map(|p| self.resolve_type(p, pool)).try_collect()?;
+                    let ret = self.resolve_return_type(ret, pool)?;
+                    let type_name = function_type_name(&params, &ret, pool)?;
+                    match self.function_types.borrow().get(&type_name) {
+                        Some(idx) => TypeId::Ref(Box::new(TypeId::Class(*idx))),
+                        None => return Err(Cause::UnresolvedType(name.pretty())),
+                    }
+                }
+                (Kind::Prim, args @ [_, ..]) => {
+                    let template = self.resolve_class_template(name.name())?;
+                    let args: Vec<_> = args.iter().map(|arg| self.resolve_type(arg, pool)).try_collect()?;
+                    let instance = template.instance_name(&args, pool)?;
+                    match self.generics.borrow().class_instance(&instance) {
+                        Some(type_) => type_.clone(),
+                        None => return Err(Cause::UnresolvedType(name.pretty())),
+                    }
+                }
                 _ => match self.symbols.find(&name.repr()) {
                     Some(Symbol::Class(idx, _)) => TypeId::Class(*idx),
                     Some(Symbol::Struct(idx, _)) => TypeId::Struct(*idx),
@@ -266,6 +419,134 @@ impl Scope {
         Ok(result)
     }
 
+    /// Resolves a type name like [`Self::resolve_type`], declaring the classes backing any function types
+    /// and the instances of any generic classes it contains.
+    pub fn declare_type(&mut self, name: &TypeName, pool: &mut ConstantPool) -> Result<TypeId, Cause> {
+        match (name.kind(), name.arguments()) {
+            (Kind::Function, [params @ .., ret]) => {
+                let params: Vec<_> = params.iter().map(|p| self.declare_type(p, pool)).try_collect()?;
+                let ret = if *ret == TypeName::VOID {
+                    TypeId::Void
+                } else {
+                    self.declare_type(ret, pool)?
+                };
+                self.function_type(&params, &ret, pool)
+            }
+            (Kind::Prim, args @ [_, ..]) => {
+                let args: Vec<_> = args.iter().map(|arg| self.declare_type(arg, pool)).try_collect()?;
+                let template = self.resolve_class_template(name.name())?;
+                template.instantiate(&args, pool)
+            }
+            (_, args) => {
+                for arg in args {
+                    self.declare_type(arg, pool)?;
+                }
+                self.resolve_type(name, pool)
+            }
+        }
+    }
+
+    /// Returns the type of functions with the given signature.
+    /// Function types are backed by abstract classes with a single `Invoke` method, they're declared on first use.
+    pub fn function_type(&mut self, params: &[TypeId], ret: &TypeId, pool: &mut ConstantPool) -> Result<TypeId, Cause> {
+        let name = function_type_name(params, ret, pool)?;
+        if let Some(idx) = self.function_types.borrow().get(&name) {
+            return Ok(TypeId::Ref(Box::new(TypeId::Class(*idx))));
+        }
+
+        let class_idx = pool.reserve();
+        let invoke_idx = pool.reserve();
+
+        let mut parameters = Vec::with_capacity(params.len());
+        for (i, type_) in params.iter().enumerate() {
+            let param = Parameter {
+                type_: self.get_type_index(type_, pool)?,
+                flags: ParameterFlags::new(),
+            };
+            let name_idx = pool.names.add(Ref::from(format!("arg{i}")));
+            parameters.push(pool.add_definition(Definition::param(name_idx, invoke_idx, param)));
+        }
+        let return_type = match ret {
+            TypeId::Void => None,
+            type_ => Some(self.get_type_index(type_, pool)?),
+        };
+        let invoke = Function {
+            visibility: Visibility::Public,
+            flags: FunctionFlags::new()
+                .with_has_return_value(return_type.is_some())
+                .with_has_parameters(!parameters.is_empty()),
+            source: Some(SourceReference::default()),
+            return_type,
+            unk1: false,
+            base_method: None,
+            parameters,
+            locals: vec![],
+            operator: None,
+            cast: 0,
+            code: Code::EMPTY,
+            unk2: vec![],
+        };
+        let invoke_name = pool.names.add(Ref::from(FUNCTION_TYPE_METHOD));
+        pool.put_definition(invoke_idx, Definition::function(invoke_name, class_idx, invoke));
+
+        let base = match self.resolve_symbol(Ident::from_static("IScriptable")) {
+            Ok(Symbol::Class(idx, _)) => idx,
+            _ => PoolIndex::UNDEFINED,
+        };
+        let class = Class {
+            visibility: Visibility::Public,
+            flags: ClassFlags::new().with_is_abstract(true),
+            base,
+            functions: vec![invoke_idx],
+            fields: vec![],
+            overrides: vec![],
+        };
+        let class_name = pool.names.add(name.to_heap());
+        pool.put_definition(class_idx, Definition::class(class_name, class));
+
+        self.function_types.borrow_mut().insert(name, class_idx);
+        Ok(TypeId::Ref(Box::new(TypeId::Class(class_idx))))
+    }
+
+    /// Returns the signature of a function type or `None` if the type is not a function type.
+    pub fn resolve_function_type(&self, type_: &TypeId, pool: &ConstantPool) -> Result<Option<FunctionType>, Cause> {
+        let TypeId::Ref(inner) = type_ else {
+            return Ok(None);
+        };
+        let TypeId::Class(class_idx) = **inner else {
+            return Ok(None);
+        };
+        let name = pool.def_name(class_idx)?;
+        if self.function_types.borrow().get(name.as_ref()) != Some(&class_idx) {
+            return Ok(None);
+        }
+        let invoke = pool.class(class_idx)?.functions[0];
+        let fun = pool.function(invoke)?;
+        let params = fun
+            .parameters
+            .iter()
+            .map(|&idx| self.resolve_type_from_pool(pool.parameter(idx)?.type_, pool))
+            .try_collect()?;
+        let return_type = match fun.return_type {
+            Some(idx) => self.resolve_type_from_pool(idx, pool)?,
+            None => TypeId::Void,
+        };
+        Ok(Some(FunctionType {
+            class: class_idx,
+            invoke,
+            params,
+            return_type,
+        }))
+    }
+
+    fn resolve_return_type(&self, name: &TypeName, pool: &ConstantPool) -> Result<TypeId, Cause> {
+        if *name == TypeName::VOID {
+            Ok(TypeId::Void)
+        } else {
+            self.resolve_type(name, pool)
+        }
+    }
+
     pub fn resolve_type_from_pool(&self, index: PoolIndex<Type>, pool: &ConstantPool) -> Result<TypeId, Cause> {
         let result = match pool.type_(index)? {
             Type::Prim => match Ident::from_heap(pool.def_name(index)?) {
@@ -274,6 +555,12 @@ impl Scope {
             },
             Type::Class => {
                 let name = pool.def_name(index)?;
+                if let Some(class_idx) = self.function_types.borrow().get(name.as_ref()) {
+                    return Ok(TypeId::Class(*class_idx));
+                }
+                if let Some(type_) = self.generics.borrow().class_instance(&name) {
+                    return Ok(type_.clone());
+                }
                 let ident = Ident::from_ref(name.split('.').last().unwrap());
                 match self.symbols.find(&ident) {
                     Some(Symbol::Class(class_idx, _)) => TypeId::Class(*class_idx),
@@ -376,6 +663,22 @@ impl TypeId {
     }
 }
 
+#[derive(Debug)]
+pub struct FunctionType {
+    pub class: PoolIndex<Class>,
+    pub invoke: PoolIndex<Function>,
+    pub params: Vec<TypeId>,
+    pub return_type: TypeId,
+}
+
+const FUNCTION_TYPE_PREFIX: &str = "func<";
+pub const FUNCTION_TYPE_METHOD: &str = "Invoke";
+
+fn function_type_name(params: &[TypeId], ret: &TypeId, pool: &ConstantPool) -> Result<Ident, PoolError> {
+    let args: Vec<_> = params.iter().chain([ret]).map(|typ| typ.pretty(pool)).try_collect()?;
+    Ok(str_fmt!("{FUNCTION_TYPE_PREFIX}{}>", args.iter().format(",")))
+}
+
 #[derive(Debug, Clone)]
 pub struct FunctionCandidates {
     pub functions: Vec<PoolIndex<Function>>,
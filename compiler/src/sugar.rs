@@ -1,15 +1,19 @@
-use std::vec;
+use std::{mem, vec};
 
+use hashbrown::HashMap;
 use redscript::ast::{BinOp, Constant, Expr, Ident, Intrinsic, Literal, Seq, Span, TypeName};
 use redscript::bundle::{ConstantPool, PoolIndex};
-use redscript::definition::{Definition, Local, LocalFlags};
+use redscript::definition::{
+    Class, ClassFlags, Definition, Field, FieldFlags, Function, Local, LocalFlags, Parameter, Visibility,
+};
 use redscript::Ref;
 
 use crate::error::{Cause, Error, ResultSpan};
 use crate::scope::{Reference, Scope, TypeId, Value};
-use crate::symbol::{FunctionSignature, FunctionSignatureBuilder};
+use crate::symbol::{FunctionSignature, FunctionSignatureBuilder, Symbol};
 use crate::transform::ExprTransformer;
-use crate::typechecker::{type_of, Callable, TypedAst, TypedExpr};
+use crate::typechecker::{type_of, Callable, Member, TypedAst, TypedExpr};
+use crate::visit_expr;
 
 pub struct Desugar<'a> {
     pool: &'a mut ConstantPool,
@@ -17,6 +21,7 @@ pub struct Desugar<'a> {
     name_count: usize,
     prefix_exprs: Vec<TypedExpr>,
     locals: Vec<PoolIndex<Local>>,
+    lambdas: Vec<Lambda>,
 }
 
 impl<'a> Desugar<'a> {
@@ -26,12 +31,14 @@ impl<'a> Desugar<'a> {
             scope,
             prefix_exprs: vec![],
             locals: vec![],
+            lambdas: vec![],
             name_count: 0,
         }
     }
 
-    pub fn locals(self) -> Vec<PoolIndex<Local>> {
-        self.locals
+    /// Returns the synthetic locals of the function and the bodies of the lambdas lifted out of it.
+    pub fn into_inner(self) -> (Vec<PoolIndex<Local>>, Vec<Lambda>) {
+        (self.locals, self.lambdas)
     }
 
     fn add_prefix(&mut self, expr: TypedExpr) {
@@ -59,6 +66,169 @@ impl<'a> Desugar<'a> {
         self.name_count += 1;
         Ok(Reference::Value(Value::Local(idx)))
     }
+
+    fn add_lambda_class(
+        &mut self,
+        invoke: PoolIndex<Function>,
+        captures: &[Capture],
+    ) -> Result<PoolIndex<Class>, Cause> {
+        let class_idx = self.pool.reserve();
+
+        let mut fields = Vec::with_capacity(captures.len());
+        for capture in captures {
+            let field = Field {
+                visibility: Visibility::Private,
+                type_: self.scope.get_type_index(&capture.type_, self.pool)?,
+                flags: FieldFlags::new(),
+                hint: None,
+                attributes: vec![],
+                defaults: vec![],
+            };
+            let name_idx = self.pool.names.add(capture.name.clone());
+            fields.push(self.pool.add_definition(Definition::field(name_idx, class_idx, field)));
+        }
+
+        // the typechecker leaves the base method unset if the type of the lambda couldn't be resolved
+        let base_method = self
+            .pool
+            .function(invoke)?
+            .base_method
+            .ok_or(Cause::UnsupportedFeature("lambdas without a function type"))?;
+        let base = self.pool.definition(base_method)?.parent;
+        let class = Class {
+            visibility: Visibility::Private,
+            flags: ClassFlags::new().with_is_final(true),
+            base: base.cast(),
+            functions: vec![invoke],
+            fields,
+            overrides: vec![],
+        };
+        let name: Ref<str> = Ref::from(format!("lambda${invoke}"));
+        let name_idx = self.pool.names.add(name.clone());
+        self.pool.put_definition(class_idx, Definition::class(name_idx, class));
+        let mut invoke_def = self.pool.definition(invoke)?.clone();
+        invoke_def.parent = class_idx.cast();
+        self.pool.put_definition(invoke, invoke_def);
+
+        self.scope
+            .add_symbol(Ident::from_heap(name), Symbol::Class(class_idx, Visibility::Private));
+        Ok(class_idx)
+    }
+}
+
+/// Prepends an expression to every `continue` that targets the loop with the given body.
+fn prefix_continue(seq: &mut Seq<TypedAst>, make_prefix: &impl Fn() -> TypedExpr) {
+    for expr in &mut seq.exprs {
+        match expr {
+            Expr::Continue(span) => {
+                *expr = Expr::Seq(Seq::new(vec![make_prefix(), Expr::Continue(*span)]));
+            }
+            Expr::Seq(seq) => prefix_continue(seq, make_prefix),
+            Expr::If(_, if_, else_, _) => {
+                prefix_continue(if_, make_prefix);
+                if let Some(else_) = else_ {
+                    prefix_continue(else_, make_prefix);
+                }
+            }
+            Expr::Switch(_, cases, default, _) => {
+                for case in cases {
+                    prefix_continue(&mut case.body, make_prefix);
+                }
+                if let Some(default) = default {
+                    prefix_continue(default, make_prefix);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The body of a lambda lifted into the `Invoke` method of a synthesized class.
+#[derive(Debug)]
+pub struct Lambda {
+    pub class: PoolIndex<Class>,
+    pub function: PoolIndex<Function>,
+    pub code: Seq<TypedAst>,
+    pub locals: Vec<PoolIndex<Local>>,
+    pub span: Span,
+}
+
+/// A value captured by a lambda, it's copied into a field of the lambda object on creation.
+#[derive(Debug)]
+struct Capture {
+    name: Ref<str>,
+    type_: TypeId,
+    value: TypedExpr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Captured {
+    Local(PoolIndex<Local>),
+    Parameter(PoolIndex<Parameter>),
+    This,
+}
+
+/// Collects the values referenced by a lambda body that are not defined inside of it.
+struct CaptureCollector<'a> {
+    locals: &'a [PoolIndex<Local>],
+    params: &'a [PoolIndex<Parameter>],
+    captured: Vec<(Captured, Span)>,
+}
+
+impl<'a> CaptureCollector<'a> {
+    fn visit(&mut self, expr: &TypedExpr) {
+        let captured = match expr {
+            Expr::Ident(Reference::Value(Value::Local(local)), span) if !self.locals.contains(local) => {
+                Some((Captured::Local(*local), *span))
+            }
+            Expr::Ident(Reference::Value(Value::Parameter(param)), span) if !self.params.contains(param) => {
+                Some((Captured::Parameter(*param), *span))
+            }
+            Expr::This(span) | Expr::Super(span) => Some((Captured::This, *span)),
+            _ => None,
+        };
+        if let Some((captured, span)) = captured {
+            if !self.captured.iter().any(|(existing, _)| *existing == captured) {
+                self.captured.push((captured, span));
+            }
+        }
+        visit_expr!(self, visit, expr);
+    }
+}
+
+/// Replaces captured values with the fields of the lambda object.
+struct CaptureRewriter {
+    fields: HashMap<Captured, PoolIndex<Field>>,
+}
+
+impl CaptureRewriter {
+    fn field(&self, captured: Captured, span: Span) -> Option<TypedExpr> {
+        let field = self.fields.get(&captured)?;
+        Some(Expr::Member(
+            Box::new(Expr::This(span)),
+            Member::ClassField(*field),
+            span,
+        ))
+    }
+}
+
+impl ExprTransformer<TypedAst> for CaptureRewriter {
+    fn on_ident(&mut self, reference: Reference, pos: Span) -> Result<TypedExpr, Error> {
+        let captured = match &reference {
+            Reference::Value(Value::Local(local)) => self.field(Captured::Local(*local), pos),
+            Reference::Value(Value::Parameter(param)) => self.field(Captured::Parameter(*param), pos),
+            _ => None,
+        };
+        Ok(captured.unwrap_or(Expr::Ident(reference, pos)))
+    }
+
+    fn on_this(&mut self, pos: Span) -> Result<TypedExpr, Error> {
+        Ok(self.field(Captured::This, pos).unwrap_or(Expr::This(pos)))
+    }
+
+    fn on_super(&mut self, pos: Span) -> Result<TypedExpr, Error> {
+        Err(Cause::UnsupportedFeature("Using super in a lambda").with_span(pos))
+    }
 }
 
 impl<'a> ExprTransformer<TypedAst> for Desugar<'a> {
@@ -200,20 +370,118 @@ impl<'a> ExprTransformer<TypedAst> for Desugar<'a> {
             )),
             span,
         );
-        let increment_counter = Expr::Call(
-            assign_add,
-            [].into(),
-            [Expr::Ident(counter_local, span), Expr::Constant(Constant::I32(1), span)].into(),
-            span,
-        );
+        let increment_counter = || {
+            Expr::Call(
+                assign_add.clone(),
+                [].into(),
+                [
+                    Expr::Ident(counter_local.clone(), span),
+                    Expr::Constant(Constant::I32(1), span),
+                ]
+                .into(),
+                span,
+            )
+        };
+        // continue jumps to the loop condition, so the counter has to be incremented before it
+        prefix_continue(&mut seq, &increment_counter);
 
         let mut body = vec![assign_iter_value];
         body.append(&mut seq.exprs);
-        body.push(increment_counter);
+        body.push(increment_counter());
 
         Ok(Expr::While(Box::new(condition), Seq::new(body), span))
     }
 
+    fn on_lambda(&mut self, invoke: PoolIndex<Function>, body: TypedExpr, span: Span) -> Result<TypedExpr, Error> {
+        let fun = self.pool.function(invoke)?;
+        let params = fun.parameters.clone();
+
+        let outer_function = self.scope.function.replace(invoke);
+        let outer_locals = mem::replace(&mut self.locals, fun.locals.clone());
+        let outer_prefix = mem::take(&mut self.prefix_exprs);
+        let body = self.on_expr(body);
+        self.scope.function = outer_function;
+        let locals = mem::replace(&mut self.locals, outer_locals);
+        self.prefix_exprs = outer_prefix;
+        let body = body?;
+
+        let mut collector = CaptureCollector {
+            locals: &locals,
+            params: &params,
+            captured: vec![],
+        };
+        collector.visit(&body);
+
+        let mut captures = Vec::with_capacity(collector.captured.len());
+        for (captured, span) in &collector.captured {
+            let (name, type_, value) = match *captured {
+                Captured::Local(local) => {
+                    let value = Expr::Ident(Reference::Value(Value::Local(local)), *span);
+                    (
+                        self.pool.def_name(local)?,
+                        type_of(&value, self.scope, self.pool)?,
+                        value,
+                    )
+                }
+                Captured::Parameter(param) => {
+                    let value = Expr::Ident(Reference::Value(Value::Parameter(param)), *span);
+                    (
+                        self.pool.def_name(param)?,
+                        type_of(&value, self.scope, self.pool)?,
+                        value,
+                    )
+                }
+                Captured::This => {
+                    let this = self.scope.this.ok_or(Cause::UnexpectedThis).with_span(*span)?;
+                    let type_ = TypeId::Ref(Box::new(TypeId::Class(this)));
+                    (Ref::from("this"), type_, Expr::This(*span))
+                }
+            };
+            captures.push(Capture { name, type_, value });
+        }
+
+        let class = self.add_lambda_class(invoke, &captures).with_span(span)?;
+        let fields = self.pool.class(class)?.fields.clone();
+
+        let mut rewriter = CaptureRewriter {
+            fields: collector
+                .captured
+                .iter()
+                .map(|(captured, _)| *captured)
+                .zip(fields.iter().copied())
+                .collect(),
+        };
+        let code = match rewriter.on_expr(body)? {
+            Expr::Seq(seq) => seq,
+            other => Seq::new(vec![other]),
+        };
+        self.lambdas.push(Lambda {
+            class,
+            function: invoke,
+            code,
+            locals,
+            span,
+        });
+
+        let instance = self
+            .fresh_local(&TypeId::Ref(Box::new(TypeId::Class(class))))
+            .with_span(span)?;
+        self.add_prefix(Expr::Assign(
+            Box::new(Expr::Ident(instance.clone(), span)),
+            Box::new(Expr::New(TypeId::Class(class), [].into(), span)),
+            span,
+        ));
+        for (capture, field) in captures.into_iter().zip(fields) {
+            let member = Expr::Member(
+                Box::new(Expr::Ident(instance.clone(), span)),
+                Member::ClassField(field),
+                span,
+            );
+            self.add_prefix(Expr::Assign(Box::new(member), Box::new(capture.value), span));
+        }
+        Ok(Expr::Ident(instance, span))
+    }
+
     fn on_seq(&mut self, seq: Seq<TypedAst>) -> Result<Seq<TypedAst>, Error> {
         let mut processed = Vec::with_capacity(seq.exprs.len());
         for expr in seq.exprs {
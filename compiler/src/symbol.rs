@@ -57,6 +57,10 @@ impl SymbolMap {
         self.symbols.insert(path, Symbol::Enum(enum_));
     }
 
+    pub fn add_template(&mut self, path: &ModulePath, template: Symbol) {
+        self.symbols.insert(path, template);
+    }
+
     pub fn add_function(&mut self, path: &ModulePath, index: PoolIndex<Function>, visibility: Visibility) {
         match self.symbols.get_mut(path) {
             Some(Symbol::Functions(existing)) => {
@@ -121,6 +125,10 @@ pub enum Symbol {
     Struct(PoolIndex<Class>, Visibility),
     Enum(PoolIndex<Enum>),
     Functions(Vec<(PoolIndex<Function>, Visibility)>),
+    /// A generic class or struct, identified by its full path.
+    ClassTemplate(Ident, Visibility),
+    /// A generic function, identified by its full path.
+    FunctionTemplate(Ident, Visibility),
 }
 
 impl Symbol {
@@ -128,6 +136,7 @@ impl Symbol {
         match self {
             Self::Class(_, v) if v <= visibility => Some(self),
             Self::Struct(_, v) if v <= visibility => Some(self),
+            Self::ClassTemplate(_, v) | Self::FunctionTemplate(_, v) if v <= visibility => Some(self),
             Self::Enum(_) => Some(self),
             Self::Functions(funs) => {
                 let visible_funs: Vec<_> = funs.into_iter().filter(|(_, v)| *v <= visibility).collect();
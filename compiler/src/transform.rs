@@ -15,6 +15,7 @@ where
     N::Function: Debug,
     N::Member: Debug,
     N::Type: Debug,
+    N::Lambda: Debug,
 {
     fn on_ident(&mut self, reference: N::Reference, pos: Span) -> Result<Expr<N>, Error> {
         Ok(Expr::Ident(reference, pos))
@@ -188,6 +189,11 @@ where
         Ok(Expr::UnOp(Box::new(expr), op, pos))
     }
 
+    fn on_lambda(&mut self, lambda: N::Lambda, body: Expr<N>, pos: Span) -> Result<Expr<N>, Error> {
+        let body = self.on_expr(body)?;
+        Ok(Expr::Lambda(lambda, Box::new(body), pos))
+    }
+
     fn on_this(&mut self, pos: Span) -> Result<Expr<N>, Error> {
         Ok(Expr::This(pos))
     }
@@ -200,6 +206,10 @@ where
         Ok(Expr::Break(pos))
     }
 
+    fn on_continue(&mut self, pos: Span) -> Result<Expr<N>, Error> {
+        Ok(Expr::Continue(pos))
+    }
+
     fn on_null(&mut self, pos: Span) -> Result<Expr<N>, Error> {
         Ok(Expr::Null(pos))
     }
@@ -228,9 +238,11 @@ where
             Expr::ForIn(name, array, body, pos) => self.on_for_in(name, *array, body, pos),
             Expr::BinOp(lhs, rhs, op, pos) => self.on_binop(*lhs, *rhs, op, pos),
             Expr::UnOp(expr, op, pos) => self.on_unop(*expr, op, pos),
+            Expr::Lambda(lambda, body, pos) => self.on_lambda(lambda, *body, pos),
             Expr::This(pos) => self.on_this(pos),
             Expr::Super(pos) => self.on_super(pos),
             Expr::Break(pos) => self.on_break(pos),
+            Expr::Continue(pos) => self.on_continue(pos),
             Expr::Null(pos) => self.on_null(pos),
         }
     }
@@ -327,6 +339,9 @@ macro_rules! visit_expr {
                 $self.$fun(array);
                 $crate::transform::visit_seq(body, |e| $self.$fun(e));
             }
+            Expr::Lambda(_, body, _) => {
+                $self.$fun(body);
+            }
             Expr::BinOp(lhs, rhs, _, _) => {
                 $self.$fun(lhs);
                 $self.$fun(rhs);
@@ -1,33 +1,54 @@
-use std::iter;
 use std::ops::Not;
 use std::str::FromStr;
+use std::{iter, mem};
 
 use itertools::{izip, Itertools};
-use redscript::ast::{Constant, Expr, Ident, Intrinsic, Literal, NameKind, Seq, SourceAst, Span, SwitchCase, TypeName};
+use redscript::ast::{
+    Constant, Expr, Ident, Intrinsic, Kind, LambdaParam, Literal, NameKind, Seq, SourceAst, Span, SwitchCase, TypeName,
+};
 use redscript::bundle::{ConstantPool, PoolError, PoolIndex};
-use redscript::definition::{Class, Definition, Enum, Field, Function, Local, LocalFlags};
+use redscript::bytecode::Code;
+use redscript::definition::{
+    Class, Definition, Enum, Field, Function, FunctionFlags, Local, LocalFlags, Parameter, ParameterFlags, Visibility,
+};
 use redscript::Ref;
 use thiserror::Error;
 
 use crate::diagnostics::{Deprecation, Diagnostic};
 use crate::error::{Cause, Error, FunctionMatchError, ResultSpan};
-use crate::scope::{FunctionCandidates, Reference, Scope, TypeId, Value};
+use crate::generics::{unify, FunctionTemplate};
+use crate::parser::Qualifier;
+use crate::scope::{FunctionCandidates, FunctionType, Reference, Scope, TypeId, Value, FUNCTION_TYPE_METHOD};
 use crate::symbol::Symbol;
 
+/// Determines how accesses to members that are not visible in the current context are reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VisibilityCheck {
+    /// Inaccessible members are rejected with an error.
+    #[default]
+    Strict,
+    /// Inaccessible members are reported as deprecations, this gives existing code time to migrate.
+    Warn,
+}
+
 pub struct TypeChecker<'a> {
     pool: &'a mut ConstantPool,
     locals: Vec<PoolIndex<Local>>,
     diagnostics: Vec<Diagnostic>,
     permissive: bool,
+    visibility_check: VisibilityCheck,
+    in_loop: bool,
 }
 
 impl<'a> TypeChecker<'a> {
-    pub fn new(pool: &'a mut ConstantPool, permissive: bool) -> TypeChecker<'a> {
+    pub fn new(pool: &'a mut ConstantPool, permissive: bool, visibility_check: VisibilityCheck) -> TypeChecker<'a> {
         TypeChecker {
             pool,
             locals: vec![],
             diagnostics: vec![],
             permissive,
+            visibility_check,
+            in_loop: false,
         }
     }
 
@@ -108,9 +129,9 @@ impl<'a> TypeChecker<'a> {
                         let type_ = type_of(&checked, scope, self.pool)?;
                         (Some(checked), type_)
                     }
-                    (Some(type_name), None) => (None, scope.resolve_type(type_name, self.pool).with_span(*span)?),
+                    (Some(type_name), None) => (None, scope.declare_type(type_name, self.pool).with_span(*span)?),
                     (Some(type_name), Some(expr)) => {
-                        let type_ = scope.resolve_type(type_name, self.pool).with_span(*span)?;
+                        let type_ = scope.declare_type(type_name, self.pool).with_span(*span)?;
                         let checked = self.check_and_convert(expr, &type_, scope)?;
                         (Some(checked), type_)
                     }
@@ -119,7 +140,7 @@ impl<'a> TypeChecker<'a> {
                 Expr::Declare(local, Some(type_.into()), initializer.map(Box::new), *span)
             }
             Expr::Cast(type_name, expr, span) => {
-                let type_ = scope.resolve_type(type_name, self.pool).with_span(*span)?;
+                let type_ = scope.declare_type(type_name, self.pool).with_span(*span)?;
                 let checked = self.check(expr, None, scope)?;
                 if let TypeId::WeakRef(inner) = type_of(&checked, scope, self.pool)? {
                     let converted = insert_conversion(checked, &TypeId::Ref(inner), Conversion::WeakRefToRef);
@@ -135,12 +156,25 @@ impl<'a> TypeChecker<'a> {
                 Expr::Assign(Box::new(lhs_typed), Box::new(rhs_typed), *span)
             }
             Expr::Call(name, type_args, args, span) => {
+                if let Some(template) = scope.resolve_function_template(name.clone()) {
+                    return self.check_generic_call(name.clone(), &template, type_args, args, None, scope, *span);
+                }
                 let expected = match &type_args[..] {
-                    [target] => Some(scope.resolve_type(target, self.pool).with_span(*span)?),
+                    [target] => Some(scope.declare_type(target, self.pool).with_span(*span)?),
                     _ => expected.cloned(),
                 };
                 if let Ok(intrinsic) = Intrinsic::from_str(name.as_ref()) {
                     self.check_intrinsic(intrinsic, args, expected.as_ref(), scope, *span)?
+                } else if let Some((receiver, fn_type)) = self.resolve_function_value(name.clone(), scope, *span)? {
+                    self.check_invoke(
+                        name.clone(),
+                        receiver,
+                        fn_type,
+                        args.iter(),
+                        expected.as_ref(),
+                        scope,
+                        *span,
+                    )?
                 } else {
                     let candidates = scope.resolve_function(name.clone()).with_span(*span)?;
                     let match_ = self.resolve_overload(
@@ -170,10 +204,59 @@ impl<'a> TypeChecker<'a> {
                 let receiver = matches!(checked_receiver, Expr::Ident(Reference::Symbol(_), _))
                     .not()
                     .then_some(&receiver_type);
-                let candidates = Scope::resolve_method(name.clone(), class, self.pool).with_span(*span)?;
+                if let Some(template) = scope.resolve_method_template(name, class, self.pool).with_span(*span)? {
+                    let is_static = template.method.source.declaration.qualifiers.contain(Qualifier::Static);
+                    if receiver.is_some() && is_static {
+                        return Err(Cause::InvalidStaticMethodCall.with_span(*span));
+                    } else if receiver.is_none() && !is_static {
+                        return Err(Cause::InvalidNonStaticMethodCall.with_span(*span));
+                    }
+                    let converted_receiver = if let TypeId::WeakRef(inner) = receiver_type {
+                        insert_conversion(checked_receiver, &TypeId::Ref(inner), Conversion::WeakRefToRef)
+                    } else {
+                        checked_receiver
+                    };
+                    return self.check_generic_call(
+                        name.clone(),
+                        &template,
+                        &[],
+                        args,
+                        Some(converted_receiver),
+                        scope,
+                        *span,
+                    );
+                }
+                let candidates = match Scope::resolve_method(name.clone(), class, self.pool) {
+                    Ok(candidates) => candidates,
+                    Err(err) => {
+                        // fields of function types can be called like methods
+                        let Some((field, fn_type)) = self.resolve_function_field(name.clone(), class, scope, *span)?
+                        else {
+                            return Err(err.with_span(*span));
+                        };
+                        let visibility = self.pool.field(field)?.visibility;
+                        self.check_visibility(name, field, visibility, scope, *span)?;
+                        let member = if matches!(receiver_type.unwrapped(), TypeId::Struct(_)) {
+                            Member::StructField(field)
+                        } else {
+                            Member::ClassField(field)
+                        };
+                        let converted_receiver = if let TypeId::WeakRef(inner) = receiver_type {
+                            insert_conversion(checked_receiver, &TypeId::Ref(inner), Conversion::WeakRefToRef)
+                        } else {
+                            checked_receiver
+                        };
+                        let field = Expr::Member(Box::new(converted_receiver), member, *span);
+                        return self.check_invoke(name.clone(), field, fn_type, args.iter(), expected, scope, *span);
+                    }
+                };
 
                 let match_ =
                     self.resolve_overload(name.clone(), candidates, args.iter(), expected, receiver, scope, *span)?;
+                if !match_.insert_receiver {
+                    let visibility = self.pool.function(match_.index)?.visibility;
+                    self.check_visibility(name, match_.index, visibility, scope, *span)?;
+                }
 
                 if match_.insert_receiver {
                     Expr::Call(
@@ -229,10 +312,14 @@ impl<'a> TypeChecker<'a> {
                 let member = match type_.unwrapped() {
                     TypeId::Class(class) => {
                         let field = Scope::resolve_field(name.clone(), *class, self.pool).with_span(*span)?;
+                        let visibility = self.pool.field(field)?.visibility;
+                        self.check_visibility(name, field, visibility, scope, *span)?;
                         Member::ClassField(field)
                     }
                     TypeId::Struct(class) => {
                         let field = Scope::resolve_field(name.clone(), *class, self.pool).with_span(*span)?;
+                        let visibility = self.pool.field(field)?.visibility;
+                        self.check_visibility(name, field, visibility, scope, *span)?;
                         Member::StructField(field)
                     }
                     TypeId::Enum(enum_) => {
@@ -255,7 +342,7 @@ impl<'a> TypeChecker<'a> {
                 Expr::ArrayElem(Box::new(checked_expr), Box::new(checked_idx), *span)
             }
             Expr::New(type_name, args, span) => {
-                let type_ = scope.resolve_type(type_name, self.pool).with_span(*span)?;
+                let type_ = scope.declare_type(type_name, self.pool).with_span(*span)?;
                 match type_ {
                     TypeId::Class(class_idx) => {
                         if self.pool.class(class_idx)?.flags.is_abstract() {
@@ -368,7 +455,7 @@ impl<'a> TypeChecker<'a> {
             Expr::While(cond, body, span) => {
                 let cond_type = scope.resolve_type(&TypeName::BOOL, self.pool).with_span(*span)?;
                 let checked_cond = self.check_and_convert(cond, &cond_type, scope)?;
-                let checked_body = self.check_seq(body, &mut scope.clone())?;
+                let checked_body = self.check_loop_body(body, &mut scope.clone())?;
 
                 Expr::While(Box::new(checked_cond), checked_body, *span)
             }
@@ -380,7 +467,7 @@ impl<'a> TypeChecker<'a> {
                         let local = self
                             .add_local(name.clone(), &inner, &mut local_scope)
                             .with_span(*span)?;
-                        let body = self.check_seq(body, &mut local_scope)?;
+                        let body = self.check_loop_body(body, &mut local_scope)?;
                         Expr::ForIn(local, Box::new(array), body, *span)
                     }
                     other => {
@@ -389,14 +476,24 @@ impl<'a> TypeChecker<'a> {
                     }
                 }
             }
+            Expr::Lambda(params, body, span) => self.check_lambda(params, body, expected, scope, *span)?,
             Expr::This(span) => Expr::This(*span),
             Expr::Super(span) => Expr::Super(*span),
             Expr::Break(span) => Expr::Break(*span),
+            Expr::Continue(span) if self.in_loop => Expr::Continue(*span),
+            Expr::Continue(span) => return Err(Cause::UnexpectedContinue.with_span(*span)),
             Expr::Null(span) => Expr::Null(*span),
         };
         Ok(res)
     }
 
+    fn check_loop_body(&mut self, body: &Seq<SourceAst>, scope: &mut Scope) -> Result<Seq<TypedAst>, Error> {
+        let outer = mem::replace(&mut self.in_loop, true);
+        let checked = self.check_seq(body, scope);
+        self.in_loop = outer;
+        checked
+    }
+
     pub fn check_seq(&mut self, seq: &Seq<SourceAst>, scope: &mut Scope) -> Result<Seq<TypedAst>, Error> {
         let mut exprs = Vec::with_capacity(seq.exprs.len());
         for expr in &seq.exprs {
@@ -562,6 +659,355 @@ impl<'a> TypeChecker<'a> {
         ))
     }
 
+    fn check_lambda(
+        &mut self,
+        params: &[LambdaParam],
+        body: &Expr<SourceAst>,
+        expected: Option<&TypeId>,
+        scope: &mut Scope,
+        span: Span,
+    ) -> Result<TypedExpr, Error> {
+        let expected = match expected {
+            Some(type_) => scope
+                .resolve_function_type(type_, self.pool)
+                .with_span(span)?
+                .filter(|fn_type| fn_type.params.len() == params.len()),
+            None => None,
+        };
+        match expected {
+            Some(fn_type) => self.check_lambda_with(
+                params,
+                body,
+                Some(&fn_type.params),
+                Some(&fn_type.return_type),
+                scope,
+                span,
+            ),
+            None => self.check_lambda_with(params, body, None, None, scope, span),
+        }
+    }
+
+    /// Checks a lambda against the parameter and return types known from the context.
+    fn check_lambda_with(
+        &mut self,
+        params: &[LambdaParam],
+        body: &Expr<SourceAst>,
+        expected_params: Option<&[TypeId]>,
+        expected_return: Option<&TypeId>,
+        scope: &mut Scope,
+        span: Span,
+    ) -> Result<TypedExpr, Error> {
+        let fun_idx = self.pool.reserve();
+        let mut lambda_scope = scope.clone();
+        lambda_scope.function = Some(fun_idx);
+
+        let mut param_types = Vec::with_capacity(params.len());
+        let mut parameters = Vec::with_capacity(params.len());
+        for (i, param) in params.iter().enumerate() {
+            let type_ = match (&param.type_, expected_params) {
+                (Some(type_name), _) => scope.declare_type(type_name, self.pool).with_span(span)?,
+                (None, Some(types)) => types[i].clone(),
+                (None, None) => return Err(Cause::TypeAnnotationRequired.with_span(span)),
+            };
+            let param_def = Parameter {
+                type_: scope.get_type_index(&type_, self.pool).with_span(span)?,
+                flags: ParameterFlags::new(),
+            };
+            let name_idx = self.pool.names.add(param.name.to_heap());
+            let idx = self
+                .pool
+                .add_definition(Definition::param(name_idx, fun_idx, param_def));
+            lambda_scope.add_parameter(param.name.clone(), idx);
+            parameters.push(idx);
+            param_types.push(type_);
+        }
+
+        let return_type = match expected_return {
+            Some(type_) => Some(type_.clone()),
+            None if matches!(body, Expr::Seq(_)) => Some(TypeId::Void),
+            None => None,
+        };
+        let return_type_idx = match &return_type {
+            Some(TypeId::Void) | None => None,
+            Some(type_) => Some(scope.get_type_index(type_, self.pool).with_span(span)?),
+        };
+        let source = match scope.function {
+            Some(fun) => self.pool.function(fun)?.source.clone(),
+            None => None,
+        };
+        let invoke = Function {
+            visibility: Visibility::Public,
+            flags: FunctionFlags::new(),
+            source: Some(source.unwrap_or_default()),
+            return_type: return_type_idx,
+            unk1: false,
+            base_method: None,
+            parameters,
+            locals: vec![],
+            operator: None,
+            cast: 0,
+            code: Code::EMPTY,
+            unk2: vec![],
+        };
+        let name_idx = self.pool.names.add(Ref::from(FUNCTION_TYPE_METHOD));
+        self.pool
+            .put_definition(fun_idx, Definition::function(name_idx, PoolIndex::UNDEFINED, invoke));
+
+        let outer_locals = mem::take(&mut self.locals);
+        let outer_in_loop = mem::replace(&mut self.in_loop, false);
+        let checked = self.check_lambda_body(body, return_type.as_ref(), &mut lambda_scope);
+        let locals = mem::replace(&mut self.locals, outer_locals);
+        self.in_loop = outer_in_loop;
+        let (checked, return_type) = checked?;
+
+        let fn_type = scope
+            .function_type(&param_types, &return_type, self.pool)
+            .with_span(span)?;
+        let base = scope
+            .resolve_function_type(&fn_type, self.pool)
+            .with_span(span)?
+            .map(|fn_type| fn_type.invoke);
+        let return_type_idx = match return_type {
+            TypeId::Void => None,
+            type_ => Some(scope.get_type_index(&type_, self.pool).with_span(span)?),
+        };
+        let invoke = self.pool.function_mut(fun_idx)?;
+        invoke.flags = invoke
+            .flags
+            .with_has_return_value(return_type_idx.is_some())
+            .with_has_parameters(!invoke.parameters.is_empty());
+        invoke.return_type = return_type_idx;
+        invoke.base_method = base;
+        invoke.locals = locals;
+
+        Ok(Expr::Lambda(fun_idx, Box::new(Expr::Seq(checked)), span))
+    }
+
+    fn check_lambda_body(
+        &mut self,
+        body: &Expr<SourceAst>,
+        return_type: Option<&TypeId>,
+        scope: &mut Scope,
+    ) -> Result<(Seq<TypedAst>, TypeId), Error> {
+        if let Expr::Seq(seq) = body {
+            let checked = self.check_seq(seq, scope)?;
+            return Ok((checked, return_type.cloned().unwrap_or(TypeId::Void)));
+        }
+        let span = body.span();
+        let (checked, return_type) = match return_type {
+            Some(TypeId::Void) => (self.check(body, None, scope)?, TypeId::Void),
+            Some(type_) => (self.check_and_convert(body, type_, scope)?, type_.clone()),
+            None => {
+                let checked = self.check(body, None, scope)?;
+                let type_ = type_of(&checked, scope, self.pool)?;
+                (checked, type_)
+            }
+        };
+        let expr = match return_type {
+            TypeId::Void => checked,
+            _ => Expr::Return(Some(Box::new(checked)), span),
+        };
+        Ok((Seq::new(vec![expr]), return_type))
+    }
+
+    fn resolve_function_value(
+        &self,
+        name: Ident,
+        scope: &Scope,
+        span: Span,
+    ) -> Result<Option<(TypedExpr, FunctionType)>, Error> {
+        let Ok(value) = scope.resolve_value(name) else {
+            return Ok(None);
+        };
+        let receiver = Expr::Ident(Reference::Value(value), span);
+        let type_ = type_of(&receiver, scope, self.pool)?;
+        let fn_type = scope.resolve_function_type(&type_, self.pool).with_span(span)?;
+        Ok(fn_type.map(|fn_type| (receiver, fn_type)))
+    }
+
+    /// Checks whether a member of a class can be accessed from the class enclosing the current function.
+    /// Private members are only visible in the class that defines them, protected ones in its subclasses too.
+    /// Members declared without a visibility qualifier are visible everywhere.
+    fn check_visibility<A>(
+        &mut self,
+        name: &Ident,
+        member: PoolIndex<A>,
+        visibility: Visibility,
+        scope: &Scope,
+        span: Span,
+    ) -> Result<(), Error> {
+        if scope.has_implicit_visibility(member) {
+            return Ok(());
+        }
+        let owner: PoolIndex<Class> = self.pool.definition(member)?.parent.cast();
+        let is_accessible = match (visibility, scope.class) {
+            (Visibility::Public, _) => true,
+            (Visibility::Private, Some(class)) => class == owner,
+            (Visibility::Protected, Some(class)) => collect_supertypes(class, self.pool)?.contains(&owner),
+            (_, None) => false,
+        };
+        if is_accessible {
+            return Ok(());
+        }
+        match self.visibility_check {
+            VisibilityCheck::Strict => Err(Cause::InaccessibleMember(name.clone(), visibility).with_span(span)),
+            VisibilityCheck::Warn => {
+                let deprecation = Deprecation::InaccessibleMember(name.clone(), visibility);
+                self.diagnostics.push(Diagnostic::Deprecation(deprecation, span));
+                Ok(())
+            }
+        }
+    }
+
+    fn resolve_function_field(
+        &self,
+        name: Ident,
+        class: PoolIndex<Class>,
+        scope: &Scope,
+        span: Span,
+    ) -> Result<Option<(PoolIndex<Field>, FunctionType)>, Error> {
+        let Ok(field) = Scope::resolve_field(name, class, self.pool) else {
+            return Ok(None);
+        };
+        let type_ = scope
+            .resolve_type_from_pool(self.pool.field(field)?.type_, self.pool)
+            .with_span(span)?;
+        let fn_type = scope.resolve_function_type(&type_, self.pool).with_span(span)?;
+        Ok(fn_type.map(|fn_type| (field, fn_type)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn check_invoke<'b>(
+        &mut self,
+        name: Ident,
+        receiver: TypedExpr,
+        fn_type: FunctionType,
+        args: impl ExactSizeIterator<Item = &'b Expr<SourceAst>> + Clone,
+        expected: Option<&TypeId>,
+        scope: &mut Scope,
+        span: Span,
+    ) -> Result<TypedExpr, Error> {
+        let candidates = FunctionCandidates {
+            functions: vec![fn_type.invoke],
+        };
+        let match_ = self.resolve_overload(name, candidates, args, expected, None, scope, span)?;
+        Ok(Expr::MethodCall(Box::new(receiver), match_.index, match_.args, span))
+    }
+
+    /// Checks a call to a generic function or method, type arguments that aren't given explicitly
+    /// are inferred from the arguments.
+    #[allow(clippy::too_many_arguments)]
+    fn check_generic_call(
+        &mut self,
+        name: Ident,
+        template: &FunctionTemplate,
+        type_args: &[TypeName],
+        args: &[Expr<SourceAst>],
+        receiver: Option<TypedExpr>,
+        scope: &mut Scope,
+        span: Span,
+    ) -> Result<TypedExpr, Error> {
+        if !type_args.is_empty() {
+            let type_args: Vec<_> = type_args
+                .iter()
+                .map(|arg| scope.declare_type(arg, self.pool))
+                .try_collect()
+                .with_span(span)?;
+            let candidates = FunctionCandidates {
+                functions: vec![template.instantiate(&type_args, self.pool).with_span(span)?],
+            };
+            let match_ = self.resolve_overload(name, candidates, args.iter(), None, None, scope, span)?;
+            return Ok(generic_call(match_, receiver, span));
+        }
+
+        let params = &template.method.source.parameters;
+        let mut bindings = vec![None; template.type_params.len()];
+        let mut checked_args = Vec::with_capacity(args.len());
+
+        // lambdas are checked last because the types of their parameters can depend on the other arguments
+        for (arg, param) in args.iter().zip(params.iter().map(Some).chain(iter::repeat(None))) {
+            if matches!(arg, Expr::Lambda(_, _, _)) {
+                checked_args.push(None);
+                continue;
+            }
+            let checked = self.check(arg, None, scope)?;
+            if let Some(param) = param {
+                let type_ = type_of(&checked, scope, self.pool)?;
+                unify(
+                    &param.type_,
+                    &type_,
+                    &template.type_params,
+                    &mut bindings,
+                    scope,
+                    self.pool,
+                )
+                .with_span(span)?;
+            }
+            checked_args.push(Some(checked));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            let Expr::Lambda(lambda_params, body, lambda_span) = arg else {
+                continue;
+            };
+            let (param_types, return_type) = match params.get(i).map(|param| (&param.type_, param.type_.arguments())) {
+                Some((type_, [fn_params @ .., ret]))
+                    if matches!(type_.kind(), Kind::Function) && fn_params.len() == lambda_params.len() =>
+                {
+                    let param_types: Option<Vec<_>> = fn_params
+                        .iter()
+                        .map(|param| template.substitute(param, &bindings, self.pool))
+                        .collect::<Result<_, _>>()
+                        .with_span(span)?;
+                    let return_type = if *ret == TypeName::VOID {
+                        Some(TypeId::Void)
+                    } else {
+                        template.substitute(ret, &bindings, self.pool).with_span(span)?
+                    };
+                    (param_types, return_type)
+                }
+                _ => (None, None),
+            };
+            let checked = self.check_lambda_with(
+                lambda_params,
+                body,
+                param_types.as_deref(),
+                return_type.as_ref(),
+                scope,
+                *lambda_span,
+            )?;
+            if let Some(param) = params.get(i) {
+                let type_ = type_of(&checked, scope, self.pool)?;
+                unify(
+                    &param.type_,
+                    &type_,
+                    &template.type_params,
+                    &mut bindings,
+                    scope,
+                    self.pool,
+                )
+                .with_span(span)?;
+            }
+            checked_args[i] = Some(checked);
+        }
+
+        let Some(type_args) = bindings.into_iter().collect::<Option<Vec<_>>>() else {
+            return Err(Cause::TypeArgsRequired(name).with_span(span));
+        };
+        let fun_idx = template.instantiate(&type_args, self.pool).with_span(span)?;
+        let checked_args: Vec<_> = checked_args.into_iter().flatten().collect();
+
+        let into_error = |err| match err {
+            MatcherError::MatchError(err) => Cause::NoMatchingOverload(name.clone(), [err].into()).with_span(span),
+            MatcherError::Other(err) => err,
+        };
+        let (fun_idx, types, _) =
+            Self::validate_call(fun_idx, checked_args.len(), None, scope, self.pool, span).map_err(into_error)?;
+        let convs =
+            Self::validate_args(fun_idx, &checked_args, &types, None, scope, self.pool, span).map_err(into_error)?;
+        let match_ = FunctionMatch::new(fun_idx, checked_args, convs, false);
+        Ok(generic_call(match_, receiver, span))
+    }
+
     pub fn check_and_convert(
         &mut self,
         expr: &Expr<SourceAst>,
@@ -779,6 +1225,12 @@ pub fn type_of(expr: &TypedExpr, scope: &Scope, pool: &ConstantPool) -> Result<T
             Reference::Symbol(Symbol::Struct(idx, _)) => TypeId::Struct(*idx),
             Reference::Symbol(Symbol::Enum(idx)) => TypeId::Enum(*idx),
             Reference::Symbol(Symbol::Functions(_)) => return Err(Cause::UnexpectedToken("function").with_span(*span)),
+            Reference::Symbol(Symbol::ClassTemplate(_, _)) => {
+                return Err(Cause::UnexpectedToken("generic type").with_span(*span));
+            }
+            Reference::Symbol(Symbol::FunctionTemplate(_, _)) => {
+                return Err(Cause::UnexpectedToken("generic function").with_span(*span));
+            }
         },
         Expr::Constant(cons, span) => match cons {
             Constant::String(Literal::String, _) => scope.resolve_type(&TypeName::STRING, pool).with_span(*span)?,
@@ -843,6 +1295,13 @@ pub fn type_of(expr: &TypedExpr, scope: &Scope, pool: &ConstantPool) -> Result<T
             None => return Err(Cause::UnexpectedThis.with_span(*span)),
         },
         Expr::Null(_) => TypeId::Null,
+        Expr::Lambda(fun, _, span) => {
+            // lambdas override the Invoke method of their function type
+            let Some(base) = pool.function(*fun)?.base_method else {
+                return Err(Cause::UnsupportedFeature("Untyped lambda").with_span(*span));
+            };
+            TypeId::Ref(Box::new(TypeId::Class(pool.definition(base)?.parent.cast())))
+        }
         Expr::BinOp(_, _, _, span) => return Err(Cause::UnsupportedFeature("BinOp").with_span(*span)),
         Expr::UnOp(_, _, span) => return Err(Cause::UnsupportedFeature("UnOp").with_span(*span)),
         Expr::Declare(_, _, _, _)
@@ -854,7 +1313,8 @@ pub fn type_of(expr: &TypedExpr, scope: &Scope, pool: &ConstantPool) -> Result<T
         | Expr::ForIn(_, _, _, _)
         | Expr::Return(_, _)
         | Expr::Goto(_, _)
-        | Expr::Break(_) => TypeId::Void,
+        | Expr::Break(_)
+        | Expr::Continue(_) => TypeId::Void,
     };
     Ok(res)
 }
@@ -951,6 +1411,18 @@ fn find_conversion(from: &TypeId, to: &TypeId, pool: &ConstantPool) -> Result<Op
     Ok(result)
 }
 
+fn generic_call(match_: FunctionMatch, receiver: Option<TypedExpr>, span: Span) -> TypedExpr {
+    match receiver {
+        Some(receiver) => Expr::MethodCall(Box::new(receiver), match_.index, match_.args, span),
+        None => Expr::Call(
+            Callable::Function(match_.index),
+            [].into(),
+            match_.args.into_boxed_slice(),
+            span,
+        ),
+    }
+}
+
 fn insert_conversion(expr: TypedExpr, type_: &TypeId, conversion: Conversion) -> TypedExpr {
     let span = expr.span();
     match conversion {
@@ -1045,6 +1517,7 @@ pub struct TypedAst;
 impl NameKind for TypedAst {
     type Callable = Callable;
     type Function = PoolIndex<Function>;
+    type Lambda = PoolIndex<Function>;
     type Local = PoolIndex<Local>;
     type Member = Member;
     type Reference = Reference;
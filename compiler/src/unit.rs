@@ -1,8 +1,9 @@
 use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
 use std::path::PathBuf;
 
 use hashbrown::{HashMap, HashSet};
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 use redscript::ast::{Constant, Expr, Ident, Literal, Pos, Seq, SourceAst, Span, TypeName};
 use redscript::bundle::{ConstantPool, PoolIndex};
 use redscript::bytecode::{Code, Instr};
@@ -18,13 +19,17 @@ use crate::diagnostics::stmt_fallthrough::StatementFallthroughCheck;
 use crate::diagnostics::unused_local::UnusedLocalCheck;
 use crate::diagnostics::{Diagnostic, DiagnosticPass, FunctionMetadata};
 use crate::error::{Cause, Error, ResultSpan};
+use crate::generics::{ClassTemplate, FunctionTemplate, InstanceBody, MethodTemplate};
 use crate::parser::*;
 use crate::scope::{Reference, Scope, TypeId, Value};
 use crate::source_map::{Files, SourceLoc};
 use crate::sugar::Desugar;
 use crate::symbol::{FunctionSignature, Import, ModulePath, Symbol, SymbolMap};
 use crate::transform::ExprTransformer;
-use crate::typechecker::{collect_supertypes, Callable, TypeChecker, TypedAst};
+use crate::typechecker::{collect_supertypes, Callable, TypeChecker, TypedAst, VisibilityCheck};
+
+#[cfg(feature = "arc")]
+mod parallel;
 
 type ProxyMap = HashMap<PoolIndex<Function>, PoolIndex<Function>>;
 
@@ -38,14 +43,20 @@ pub struct CompilationUnit<'a> {
     wrappers: ProxyMap,
     proxies: ProxyMap,
     source_refs: BTreeMap<PoolIndex<Definition>, Pos>,
+    template_refs: HashMap<Ident, Pos>,
+    tests: Vec<PoolIndex<Function>>,
     diagnostics: Vec<Diagnostic>,
     file_map: HashMap<PathBuf, PoolIndex<SourceFile>>,
-    diagnostic_passes: Vec<Box<dyn DiagnosticPass + Send>>,
+    diagnostic_passes: Vec<Box<dyn DiagnosticPass + Send + Sync>>,
+    visibility_check: VisibilityCheck,
+    optimize: bool,
+    threads: usize,
+    allowed: Vec<(Span, Ref<str>)>,
 }
 
 impl<'a> CompilationUnit<'a> {
     pub fn new_with_defaults(pool: &'a mut ConstantPool) -> Result<Self, Error> {
-        let passes: Vec<Box<dyn DiagnosticPass + Send>> = vec![
+        let passes: Vec<Box<dyn DiagnosticPass + Send + Sync>> = vec![
             Box::new(UnusedLocalCheck),
             Box::new(MissingReturnCheck),
             Box::new(StatementFallthroughCheck),
@@ -54,7 +65,7 @@ impl<'a> CompilationUnit<'a> {
         Self::new(pool, passes)
     }
 
-    pub fn new(pool: &'a mut ConstantPool, passes: Vec<Box<dyn DiagnosticPass + Send>>) -> Result<Self, Error> {
+    pub fn new(pool: &'a mut ConstantPool, passes: Vec<Box<dyn DiagnosticPass + Send + Sync>>) -> Result<Self, Error> {
         let symbols = SymbolMap::new(pool)?;
         let mut scope = Scope::new(pool)?;
 
@@ -73,19 +84,46 @@ impl<'a> CompilationUnit<'a> {
             wrappers: HashMap::new(),
             proxies: HashMap::new(),
             source_refs: BTreeMap::new(),
+            template_refs: HashMap::new(),
+            tests: vec![],
             diagnostics: vec![],
             file_map: HashMap::new(),
             diagnostic_passes: passes,
+            visibility_check: VisibilityCheck::default(),
+            optimize: false,
+            threads: 1,
+            allowed: vec![],
         })
     }
 
+    /// Sets how accesses to private and protected members from outside of their classes are reported.
+    pub fn with_visibility_check(mut self, check: VisibilityCheck) -> Self {
+        self.visibility_check = check;
+        self
+    }
+
+    /// Enables the optimization of the bytecode of compiled functions,
+    /// see [`optimize`](crate::assembler::optimizer::optimize).
+    pub fn with_optimizations(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Sets the number of threads used to typecheck and assemble function bodies.
+    /// Bodies are only compiled in parallel with the `arc` feature, the output doesn't depend on the number of threads.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
     pub fn compile(mut self, modules: Vec<SourceModule>, files: &Files) -> Result<CompilationOutput, Error> {
-        let funcs = self.compile_modules(modules, files, true, false)?;
+        let funcs = self.compile_modules(modules, files, true, false, self.threads)?;
         self.finish(funcs, files)
     }
 
-    pub fn compile_files(self, files: &Files) -> Result<CompilationOutput, Error> {
-        self.compile(Self::parse(files)?, files)
+    pub fn compile_files(mut self, files: &Files) -> Result<CompilationOutput, Error> {
+        let modules = self.parse(files);
+        self.compile(modules, files)
     }
 
     pub fn typecheck(
@@ -95,7 +133,17 @@ impl<'a> CompilationUnit<'a> {
         desugar: bool,
         permissive: bool,
     ) -> Result<TypecheckOutput, Error> {
-        let functions = self.compile_modules(modules, files, desugar, permissive)?;
+        // typed bodies are only kept when compiling on a single thread
+        let functions = self
+            .compile_modules(modules, files, desugar, permissive, 1)?
+            .into_iter()
+            .map(|func| match func {
+                Compiled::Function(func) => func,
+                #[cfg(feature = "arc")]
+                Compiled::Staged(_) => unreachable!("bodies are only staged when compiled on multiple threads"),
+            })
+            .collect();
+        Self::remove_allowed(&mut self.diagnostics, &self.allowed);
         Ok(TypecheckOutput {
             functions,
             diagnostics: self.diagnostics,
@@ -107,8 +155,9 @@ impl<'a> CompilationUnit<'a> {
         })
     }
 
-    pub fn typecheck_files(self, files: &Files, desugar: bool, permissive: bool) -> Result<TypecheckOutput, Error> {
-        self.typecheck(Self::parse(files)?, files, desugar, permissive)
+    pub fn typecheck_files(mut self, files: &Files, desugar: bool, permissive: bool) -> Result<TypecheckOutput, Error> {
+        let modules = self.parse(files);
+        self.typecheck(modules, files, desugar, permissive)
     }
 
     pub fn compile_and_report(self, files: &Files) -> Result<CompilationOutput, Error> {
@@ -143,16 +192,57 @@ impl<'a> CompilationUnit<'a> {
         }
     }
 
-    fn parse(files: &Files) -> Result<Vec<SourceModule>, Error> {
+    /// Resolves the target of every `@wrapMethod`, `@replaceMethod` and `@replaceGlobal` function
+    /// in the modules against the pool without compiling anything.
+    /// Functions excluded by their `@if` conditions are skipped.
+    pub fn resolve_hooks(&self, modules: &[SourceModule]) -> Result<Vec<Hook>, Error> {
+        let cte = cte::Context::new(modules.iter().filter_map(|m| m.path.clone()).collect());
+        let mut hooks = vec![];
+
+        for module in modules {
+            for entry in &module.entries {
+                let source = match entry {
+                    SourceEntry::Function(source) => source,
+                    _ => continue,
+                };
+                if !eval_conditions(&cte, entry.annotations())? {
+                    continue;
+                }
+                let name = &source.declaration.name;
+                let sig = FunctionSignature::from_source(source);
+                let anns = source.declaration.annotations.iter().filter(|ann| {
+                    matches!(
+                        ann.kind,
+                        AnnotationKind::WrapMethod | AnnotationKind::ReplaceMethod | AnnotationKind::ReplaceGlobal
+                    )
+                });
+                for ann in anns {
+                    let class = match ann.kind {
+                        AnnotationKind::ReplaceGlobal => None,
+                        _ => ann.args.first().and_then(Expr::as_ident).map(|(name, _)| name.clone()),
+                    };
+                    hooks.push(Hook {
+                        kind: ann.kind,
+                        class,
+                        name: name.clone(),
+                        span: source.declaration.span,
+                        target: self.resolve_hook_target(ann, name, &sig).map(|(_, idx)| idx),
+                    });
+                }
+            }
+        }
+        Ok(hooks)
+    }
+
+    // syntax errors are reported as diagnostics, so that the rest of the code can still be checked
+    fn parse(&mut self, files: &Files) -> Vec<SourceModule> {
         let mut modules = vec![];
         for file in files.files() {
-            let parsed = parse_file(file).map_err(|err| {
-                let pos = file.byte_offset() + err.location.offset;
-                Error::SyntaxError(err.expected, Span::new(pos, pos))
-            })?;
-            modules.push(parsed);
+            let (module, errors) = parse_file(file);
+            self.diagnostics.extend(errors);
+            modules.push(module);
         }
-        Ok(modules)
+        modules
     }
 
     fn compile_modules(
@@ -161,7 +251,8 @@ impl<'a> CompilationUnit<'a> {
         files: &Files,
         desugar: bool,
         permissive: bool,
-    ) -> Result<Vec<CompiledFunction>, Error> {
+        threads: usize,
+    ) -> Result<Vec<Compiled>, Error> {
         let mut seen_funcs = HashSet::new();
         let mut queue = Vec::with_capacity(modules.len());
         let mut compiled_funcs = Vec::new();
@@ -169,6 +260,7 @@ impl<'a> CompilationUnit<'a> {
         let cte = cte::Context::new(modules.iter().filter_map(|m| m.path.clone()).collect());
 
         for module in modules {
+            self.collect_allowed(&module)?;
             let path = module.path.unwrap_or(ModulePath::EMPTY);
             let mut slots = Vec::with_capacity(module.entries.len());
 
@@ -183,6 +275,8 @@ impl<'a> CompilationUnit<'a> {
             queue.push((path, module.imports, slots));
         }
 
+        // templates of all modules are defined first, any other definition could instantiate them
+        let mut scopes = Vec::with_capacity(queue.len());
         for (path, imports, slots) in queue {
             let mut module_scope = self.scope.clone();
 
@@ -207,6 +301,19 @@ impl<'a> CompilationUnit<'a> {
                 }
             }
 
+            let (templates, slots): (Vec<_>, Vec<_>) = slots.into_iter().partition_map(|slot| match slot {
+                Slot::Template(template) => Either::Left(template),
+                other => Either::Right(other),
+            });
+            for template in templates {
+                if let Err(err) = self.define_template(template, files, &module_scope, &cte) {
+                    self.report(err)?;
+                }
+            }
+            scopes.push((module_scope, slots));
+        }
+
+        for (mut module_scope, slots) in scopes {
             for slot in slots {
                 let res = match slot {
                     Slot::Function {
@@ -260,6 +367,7 @@ impl<'a> CompilationUnit<'a> {
                         visibility,
                     } => self.define_global_let(index, visibility, source, &mut module_scope),
                     Slot::Enum { index, source } => self.define_enum(index, source),
+                    Slot::Template(_) => unreachable!("templates are defined before other symbols"),
                 };
                 if let Err(err) = res {
                     self.report(err)?;
@@ -268,7 +376,7 @@ impl<'a> CompilationUnit<'a> {
         }
 
         for default in self.field_defaults.drain(..) {
-            let diagnostics = Self::compile_default(default, self.pool)?;
+            let diagnostics = Self::compile_default(default, self.pool, self.visibility_check)?;
             self.diagnostics.extend(diagnostics);
         }
 
@@ -278,34 +386,121 @@ impl<'a> CompilationUnit<'a> {
             Self::construct_proxy(*proxy, wrapped, wrapper, files, &mut self.scope, self.pool)?;
         }
 
-        // compile function bodies
-        for item in self.function_bodies.drain(..) {
-            let was_callback = item.was_callback;
-            match Self::compile_function(item, self.pool, desugar, permissive) {
-                Ok((func, diagnostics)) => {
-                    self.diagnostics.extend(diagnostics);
+        // compile function bodies, generic instances requested by them are compiled in subsequent rounds
+        let mut pending = mem::take(&mut self.function_bodies);
+        pending.extend(self.take_instance_bodies());
+        while !pending.is_empty() {
+            match threads {
+                #[cfg(feature = "arc")]
+                2.. => self.compile_parallel(pending, files, desugar, permissive, threads, &mut compiled_funcs)?,
+                _ => {
+                    for item in pending {
+                        self.compile_body(item, desugar, permissive, &mut compiled_funcs)?;
+                    }
+                }
+            }
+            pending = self.take_instance_bodies();
+        }
+        self.scope.generics().borrow_mut().clear_templates();
+
+        Ok(compiled_funcs)
+    }
 
+    fn compile_body(
+        &mut self,
+        item: FunctionBody,
+        desugar: bool,
+        permissive: bool,
+        compiled: &mut Vec<Compiled>,
+    ) -> Result<(), Error> {
+        let was_callback = item.was_callback;
+        match Self::compile_function(item, self.pool, desugar, permissive, self.visibility_check) {
+            Ok((funcs, diagnostics)) => {
+                self.diagnostics.extend(diagnostics);
+
+                for func in funcs {
                     let flags = self.pool.function(func.index)?.flags;
                     let metadata = FunctionMetadata::new(flags, was_callback, func.span);
                     for pass in &self.diagnostic_passes {
                         self.diagnostics.extend(pass.diagnose(&func.code, &metadata));
                     }
 
-                    compiled_funcs.push(func);
+                    compiled.push(Compiled::Function(func));
                 }
-                Err(err) => self.diagnostics.push(Diagnostic::from_error(err)?),
             }
+            Err(err) => self.diagnostics.push(Diagnostic::from_error(err)?),
         }
+        Ok(())
+    }
 
-        Ok(compiled_funcs)
+    /// Removes the warnings with a code allowed in the region they were reported in.
+    fn remove_allowed(diagnostics: &mut Vec<Diagnostic>, allowed: &[(Span, Ref<str>)]) {
+        diagnostics.retain(|diagnostic| {
+            diagnostic.is_fatal()
+                || !allowed
+                    .iter()
+                    .any(|(span, code)| span.contains(diagnostic.span().low) && code.as_ref() == diagnostic.code())
+        });
+    }
+
+    /// Records the regions covered by `@allow` annotations, warnings with a matching code
+    /// reported in these regions are dropped once compilation is done.
+    fn collect_allowed(&mut self, module: &SourceModule) -> Result<(), Error> {
+        let mut annotated = vec![];
+        for entry in &module.entries {
+            match entry {
+                SourceEntry::Class(class) | SourceEntry::Struct(class) => {
+                    annotated.push((&class.declaration.annotations[..], class.span));
+                    for member in &class.members {
+                        match member {
+                            MemberSource::Function(fun) => annotated.push((&fun.declaration.annotations[..], fun.span)),
+                            MemberSource::Field(field) => {
+                                annotated.push((&field.declaration.annotations[..], field.declaration.span));
+                            }
+                        }
+                    }
+                }
+                SourceEntry::Function(fun) => annotated.push((&fun.declaration.annotations[..], fun.span)),
+                SourceEntry::GlobalLet(field) => {
+                    annotated.push((&field.declaration.annotations[..], field.declaration.span));
+                }
+                SourceEntry::Enum(enum_) => annotated.push((&enum_.declaration.annotations[..], enum_.span)),
+            }
+        }
+        for stmt in &module.annotated_stmts {
+            annotated.push((&stmt.annotations[..], stmt.span));
+        }
+
+        for (annotations, span) in annotated {
+            for ann in annotations.iter().filter(|ann| ann.kind == AnnotationKind::Allow) {
+                if ann.args.is_empty() {
+                    self.report(Cause::InvalidAnnotationArgs.with_span(ann.span))?;
+                }
+                for arg in &ann.args {
+                    match arg {
+                        Expr::Constant(Constant::String(Literal::String, code), _) => {
+                            self.allowed.push((span, code.clone()));
+                        }
+                        _ => self.report(Cause::InvalidAnnotationArgs.with_span(arg.span()))?,
+                    }
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn finish(self, functions: Vec<CompiledFunction>, files: &Files) -> Result<CompilationOutput, Error> {
-        for mut func in functions {
-            let code = Assembler::from_body(func.code, files, &mut func.scope, self.pool)?;
-            let function = self.pool.function_mut(func.index)?;
-            function.code = code;
-            function.locals = func.locals;
+    fn finish(self, functions: Vec<Compiled>, files: &Files) -> Result<CompilationOutput, Error> {
+        for func in functions {
+            match func {
+                Compiled::Function(mut func) => {
+                    let code = Assembler::from_body(func.code, files, &mut func.scope, self.pool, self.optimize)?;
+                    let function = self.pool.function_mut(func.index)?;
+                    function.code = code;
+                    function.locals = func.locals;
+                }
+                #[cfg(feature = "arc")]
+                Compiled::Staged(chunk) => chunk.merge_assembly(self.pool)?,
+            }
         }
 
         // swap proxies with the functions they wrap
@@ -324,6 +519,7 @@ impl<'a> CompilationUnit<'a> {
         }
 
         let mut diagnostics = self.diagnostics;
+        Self::remove_allowed(&mut diagnostics, &self.allowed);
         diagnostics.sort_by_key(Diagnostic::is_fatal);
         let mut source_refs = self
             .source_refs
@@ -336,19 +532,73 @@ impl<'a> CompilationUnit<'a> {
         Ok(CompilationOutput {
             diagnostics,
             source_refs,
+            tests: self.tests,
         })
     }
 
     fn define_symbol(&mut self, entry: SourceEntry, module: &ModulePath, permissive: bool) -> Result<Slot, Error> {
         match entry {
+            SourceEntry::Class(source) if !source.type_params.is_empty() => {
+                let (path, visibility) = self.declare_template(
+                    &source.declaration,
+                    source.span,
+                    module,
+                    permissive,
+                    Symbol::ClassTemplate,
+                )?;
+                let slot = TemplateSlot::Class {
+                    path,
+                    source,
+                    is_struct: false,
+                    visibility,
+                };
+                Ok(Slot::Template(slot))
+            }
+            SourceEntry::Struct(source) if !source.type_params.is_empty() => {
+                let (path, visibility) = self.declare_template(
+                    &source.declaration,
+                    source.span,
+                    module,
+                    permissive,
+                    Symbol::ClassTemplate,
+                )?;
+                let slot = TemplateSlot::Class {
+                    path,
+                    source,
+                    is_struct: true,
+                    visibility,
+                };
+                Ok(Slot::Template(slot))
+            }
+            SourceEntry::Function(source) if !source.type_params.is_empty() => {
+                let (path, visibility) = self.declare_template(
+                    &source.declaration,
+                    source.span,
+                    module,
+                    permissive,
+                    Symbol::FunctionTemplate,
+                )?;
+                let slot = TemplateSlot::Function {
+                    path,
+                    source,
+                    visibility,
+                };
+                Ok(Slot::Template(slot))
+            }
             SourceEntry::Class(source) => {
                 let decl = &source.declaration;
                 let path = module.with_child(decl.name.clone());
                 let visibility = decl.qualifiers.visibility().unwrap_or(Visibility::Private);
 
-                if let Some(Symbol::Class(idx, _) | Symbol::Struct(idx, _)) = self.symbols.get_symbol(&path) {
+                if let Some(
+                    existing @ (Symbol::Class(_, _)
+                    | Symbol::Struct(_, _)
+                    | Symbol::ClassTemplate(_, _)
+                    | Symbol::FunctionTemplate(_, _)),
+                ) = self.symbols.get_symbol(&path)
+                {
                     if !permissive {
-                        let pos = self.source_refs.get(&idx.cast()).copied();
+                        let pos = self.symbol_pos(&existing);
                         return Err(Cause::SymbolRedefinition(pos).with_span(source.span));
                     }
                 }
@@ -376,9 +626,15 @@ impl<'a> CompilationUnit<'a> {
                 let path = module.with_child(decl.name.clone());
                 let visibility = decl.qualifiers.visibility().unwrap_or(Visibility::Private);
 
-                if let Some(Symbol::Class(idx, _) | Symbol::Struct(idx, _)) = self.symbols.get_symbol(&path) {
+                if let Some(
+                    existing @ (Symbol::Class(_, _)
+                    | Symbol::Struct(_, _)
+                    | Symbol::ClassTemplate(_, _)
+                    | Symbol::FunctionTemplate(_, _)),
+                ) = self.symbols.get_symbol(&path)
+                {
                     if !permissive {
-                        let pos = self.source_refs.get(&idx.cast()).copied();
+                        let pos = self.symbol_pos(&existing);
                         return Err(Cause::SymbolRedefinition(pos).with_span(source.span));
                     }
                 }
@@ -440,6 +696,227 @@ impl<'a> CompilationUnit<'a> {
         }
     }
 
+    /// Finds the function targeted by a `@wrapMethod`, `@replaceMethod` or `@replaceGlobal` annotation,
+    /// along with the class it belongs to, which is undefined for global functions.
+    fn resolve_hook_target(
+        &self,
+        ann: &Annotation,
+        name: &Ident,
+        sig: &FunctionSignature<'_>,
+    ) -> Result<(PoolIndex<Class>, PoolIndex<Function>), Error> {
+        if ann.kind == AnnotationKind::ReplaceGlobal {
+            let fun_idx = self
+                .scope
+                .resolve_function(name.clone())
+                .with_span(ann.span)?
+                .by_id(sig, self.pool)
+                .ok_or_else(|| Cause::FunctionNotFound(name.clone()).with_span(ann.span))?;
+            return Ok((PoolIndex::UNDEFINED, fun_idx));
+        }
+
+        let (class_name, _) = ann
+            .args
+            .first()
+            .and_then(Expr::as_ident)
+            .ok_or_else(|| Cause::InvalidAnnotationArgs.with_span(ann.span))?;
+        let target_class_idx = match self.scope.resolve_symbol(class_name.clone()).with_span(ann.span)? {
+            Symbol::Struct(idx, _) | Symbol::Class(idx, _) => idx,
+            _ => return Err(Cause::ClassNotFound(class_name.clone()).with_span(ann.span)),
+        };
+        let candidates = Scope::resolve_direct_method(name.clone(), target_class_idx, self.pool).ok();
+        let fun_idx = candidates
+            .as_ref()
+            .and_then(|cd| cd.by_id(sig, self.pool))
+            .ok_or_else(|| {
+                if candidates.is_some() {
+                    Cause::NoMethodWithMatchingSignature.with_span(ann.span)
+                } else {
+                    Cause::NoMethodWithMatchingName.with_span(ann.span)
+                }
+            })?;
+        Ok((target_class_idx, fun_idx))
+    }
+
+    fn declare_template(
+        &mut self,
+        decl: &Declaration,
+        span: Span,
+        module: &ModulePath,
+        permissive: bool,
+        make_symbol: fn(Ident, Visibility) -> Symbol,
+    ) -> Result<(ModulePath, Visibility), Error> {
+        let path = module.with_child(decl.name.clone());
+        let visibility = decl.qualifiers.visibility().unwrap_or(Visibility::Private);
+
+        if let Some(existing) = self.symbols.get_symbol(&path) {
+            if !permissive {
+                let pos = self.symbol_pos(&existing);
+                return Err(Cause::SymbolRedefinition(pos).with_span(span));
+            }
+        }
+
+        let symbol = make_symbol(path.render(), visibility);
+        self.symbols.add_template(&path, symbol.clone());
+        self.template_refs.insert(path.render(), span.low);
+
+        // add to globals when no module
+        if module.is_empty() {
+            self.scope.add_symbol(decl.name.clone(), symbol);
+        }
+        Ok((path, visibility))
+    }
+
+    fn define_template(
+        &mut self,
+        slot: TemplateSlot,
+        files: &Files,
+        scope: &Scope,
+        ctx: &cte::Context,
+    ) -> Result<(), Error> {
+        match slot {
+            TemplateSlot::Class {
+                path,
+                source,
+                is_struct,
+                visibility,
+            } => {
+                let qualifiers = &source.declaration.qualifiers;
+                if qualifiers.contain(Qualifier::Native) || qualifiers.contain(Qualifier::ImportOnly) {
+                    return Err(Cause::UnsupportedFeature("native generic types").with_span(source.declaration.span));
+                }
+                let flags = ClassFlags::new()
+                    .with_is_abstract(!is_struct && qualifiers.contain(Qualifier::Abstract))
+                    .with_is_final(qualifiers.contain(Qualifier::Final))
+                    .with_is_struct(is_struct);
+
+                let mut fields = vec![];
+                let mut methods = vec![];
+                let mut field_names = HashSet::new();
+
+                for member in source.members {
+                    match member {
+                        MemberSource::Function(fun) => {
+                            if !eval_conditions(ctx, &fun.declaration.annotations)? {
+                                continue;
+                            }
+                            let decl = &fun.declaration;
+                            if decl.qualifiers.contain(Qualifier::Native) {
+                                self.report(Cause::UnexpectedNative.with_span(decl.span))?;
+                            }
+                            if is_struct && !decl.qualifiers.contain(Qualifier::Static) {
+                                let err = Cause::UnsupportedFeature("defining non-static struct methods");
+                                self.report(err.with_span(decl.span))?;
+                            }
+                            if !flags.is_abstract() && fun.body.is_none() {
+                                self.report(Cause::MissingBody.with_span(decl.span))?;
+                            }
+                            methods.push(Ref::new(self.method_template(fun, files)));
+                        }
+                        MemberSource::Field(field) => {
+                            let decl = &field.declaration;
+                            if !eval_conditions(ctx, &decl.annotations)? {
+                                continue;
+                            }
+                            if !field_names.insert(decl.name.clone()) {
+                                self.report(Cause::FieldRedefinition.with_span(decl.span))?;
+                            }
+                            if decl.qualifiers.contain(Qualifier::Native) {
+                                self.report(Cause::UnexpectedNative.with_span(decl.span))?;
+                            }
+                            if field.default.is_some() {
+                                let err = Cause::UnsupportedFeature("default values in generic types");
+                                self.report(err.with_span(decl.span))?;
+                            }
+                            fields.push(field);
+                        }
+                    }
+                }
+
+                let template = ClassTemplate {
+                    name: path.render(),
+                    type_params: source.type_params,
+                    is_struct,
+                    visibility,
+                    flags,
+                    base: source.base,
+                    fields,
+                    methods,
+                    scope: scope.clone(),
+                };
+                scope.generics().borrow_mut().add_class_template(template);
+            }
+            TemplateSlot::Function {
+                path,
+                mut source,
+                visibility,
+            } => {
+                let decl = &source.declaration;
+                if decl.qualifiers.contain(Qualifier::Native) {
+                    return Err(Cause::UnsupportedFeature("native generic functions").with_span(decl.span));
+                }
+                if let Some(ann) = decl.annotations.iter().find(|ann| ann.kind != AnnotationKind::If) {
+                    return Err(Cause::UnsupportedFeature("annotations on generic functions").with_span(ann.span));
+                }
+                if source.body.is_none() {
+                    return Err(Cause::MissingBody.with_span(decl.span));
+                }
+
+                let template = FunctionTemplate {
+                    name: path.render(),
+                    type_params: mem::take(&mut source.type_params),
+                    method: Ref::new(self.method_template(source, files)),
+                    visibility,
+                    class: PoolIndex::UNDEFINED,
+                    scope: scope.clone(),
+                };
+                scope.generics().borrow_mut().add_function_template(template);
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers a generic method of a class, its instances are added to the class as they're used.
+    fn define_method_template(
+        &mut self,
+        class_idx: PoolIndex<Class>,
+        class_visibility: Visibility,
+        mut source: FunctionSource,
+        files: &Files,
+        scope: &Scope,
+    ) -> Result<(), Error> {
+        let decl = &source.declaration;
+        if decl.qualifiers.contain(Qualifier::Native) {
+            return Err(Cause::UnsupportedFeature("native generic functions").with_span(decl.span));
+        }
+        if let Some(ann) = decl.annotations.iter().find(|ann| ann.kind != AnnotationKind::If) {
+            return Err(Cause::UnsupportedFeature("annotations on generic functions").with_span(ann.span));
+        }
+        if source.body.is_none() {
+            return Err(Cause::MissingBody.with_span(decl.span));
+        }
+
+        let template = FunctionTemplate {
+            name: decl.name.clone(),
+            type_params: mem::take(&mut source.type_params),
+            visibility: decl.qualifiers.visibility().unwrap_or(class_visibility),
+            method: Ref::new(self.method_template(source, files)),
+            class: class_idx,
+            scope: scope.clone(),
+        };
+        scope.generics().borrow_mut().add_method_template(template);
+        Ok(())
+    }
+
+    fn method_template(&mut self, mut source: FunctionSource, files: &Files) -> MethodTemplate {
+        let opt_loc = files.lookup(source.declaration.span);
+        let source_ref = opt_loc.map(|loc| self.define_source_ref(loc)).unwrap_or_default();
+        MethodTemplate {
+            body: source.body.take().map(Ref::new),
+            source,
+            source_ref,
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn define_class(
         &mut self,
@@ -480,11 +957,19 @@ impl<'a> CompilationUnit<'a> {
                         self.report(err)?;
                     }
 
+                    if !fun.type_params.is_empty() {
+                        if let Err(err) = self.define_method_template(class_idx, visibility, fun, files, scope) {
+                            self.report(err)?;
+                        }
+                        continue;
+                    }
+
                     let fun_sig = FunctionSignature::from_source(&fun);
                     let name_idx = self.pool.names.add(Ref::from(fun_sig.as_ref()));
                     let fun_idx = self.pool.stub_definition(name_idx);
                     let opt_loc = files.lookup(fun.declaration.span);
                     let source_ref = opt_loc.map(|loc| self.define_source_ref(loc)).unwrap_or_default();
+                    let visibility = fun.declaration.qualifiers.visibility().unwrap_or(visibility);
 
                     let spec = FunctionSpec {
                         fun_idx,
@@ -512,6 +997,7 @@ impl<'a> CompilationUnit<'a> {
                     }
 
                     let field_idx = self.pool.stub_definition(name_idx);
+                    let visibility = let_.declaration.qualifiers.visibility().unwrap_or(visibility);
 
                     self.define_field(field_idx, class_idx, flags, visibility, let_, scope)?;
                     fields.push(field_idx);
@@ -567,6 +1053,18 @@ impl<'a> CompilationUnit<'a> {
         if is_native && spec.source.body.is_some() {
             self.report(Cause::UnexpectedBody.with_span(spec.source.declaration.span))?;
         }
+        if !spec.is_replacement && decl.qualifiers.visibility().is_none() {
+            scope.add_implicit_visibility(spec.fun_idx);
+        }
+        if let Some(ann) = decl.annotations.iter().find(|ann| ann.kind == AnnotationKind::Test) {
+            if !spec.class_idx.is_undefined() {
+                self.report(Cause::UnsupportedFeature("test methods").with_span(ann.span))?;
+            } else if !spec.source.parameters.is_empty() {
+                self.report(Cause::UnsupportedFeature("test functions with parameters").with_span(ann.span))?;
+            } else {
+                self.tests.push(spec.fun_idx);
+            }
+        }
 
         let return_type = match spec.source.type_ {
             None => None,
@@ -640,7 +1138,7 @@ impl<'a> CompilationUnit<'a> {
                 class: spec.class_idx,
                 index: spec.fun_idx,
                 wrapped: spec.wrapped,
-                code,
+                code: Ref::new(code),
                 scope: scope.clone(),
                 was_callback: is_callback,
                 span: spec.source.span,
@@ -665,6 +1163,9 @@ impl<'a> CompilationUnit<'a> {
         let decl = source.declaration;
         let is_native = decl.qualifiers.contain(Qualifier::Native);
         let is_persistent = decl.qualifiers.contain(Qualifier::Persistent);
+        if decl.qualifiers.visibility().is_none() {
+            scope.add_implicit_visibility(field_idx);
+        }
 
         if is_native && !class_flags.is_native() {
             self.report(Cause::UnexpectedNative.with_span(decl.span))?;
@@ -812,11 +1313,8 @@ impl<'a> CompilationUnit<'a> {
     fn determine_function_location(&mut self, source: FunctionSource, module: &ModulePath) -> Result<Slot, Error> {
         let name = source.declaration.name.clone();
         let sig = FunctionSignature::from_source(&source);
-        let visibility = source
-            .declaration
-            .qualifiers
-            .visibility()
-            .unwrap_or(Visibility::Private);
+        let qualified_visibility = source.declaration.qualifiers.visibility();
+        let visibility = qualified_visibility.unwrap_or(Visibility::Private);
 
         for ann in &source.declaration.annotations {
             match ann.kind {
@@ -824,27 +1322,7 @@ impl<'a> CompilationUnit<'a> {
                     if source.declaration.qualifiers.contain(Qualifier::Native) {
                         return Err(Cause::UnsupportedFeature("wrapping natives").with_span(ann.span));
                     }
-                    let (class_name, _) = ann
-                        .args
-                        .first()
-                        .and_then(Expr::as_ident)
-                        .ok_or_else(|| Cause::InvalidAnnotationArgs.with_span(ann.span))?;
-
-                    let target_class_idx = match self.scope.resolve_symbol(class_name.clone()).with_span(ann.span)? {
-                        Symbol::Struct(idx, _) | Symbol::Class(idx, _) => idx,
-                        _ => return Err(Cause::ClassNotFound(class_name.clone()).with_span(ann.span)),
-                    };
-                    let candidates = Scope::resolve_direct_method(name.clone(), target_class_idx, self.pool).ok();
-                    let fun_idx = candidates
-                        .as_ref()
-                        .and_then(|cd| cd.by_id(&sig, self.pool))
-                        .ok_or_else(|| {
-                            if candidates.is_some() {
-                                Cause::NoMethodWithMatchingSignature.with_span(ann.span)
-                            } else {
-                                Cause::NoMethodWithMatchingName.with_span(ann.span)
-                            }
-                        })?;
+                    let (target_class_idx, fun_idx) = self.resolve_hook_target(ann, &name, &sig)?;
 
                     let wrapped_idx = if let Some(wrapped) = self.wrappers.get(&fun_idx) {
                         *wrapped
@@ -856,7 +1334,11 @@ impl<'a> CompilationUnit<'a> {
 
                     let name_idx = self.pool.names.add(Ref::from(format!("wrapper${wrapped_idx}")));
                     let wrapper_idx = self.pool.stub_definition(name_idx);
-                    let base = self.pool.function(fun_idx).ok().and_then(|fun| fun.base_method);
+                    let wrapped_fun = self.pool.function(fun_idx).ok();
+                    let base = wrapped_fun.and_then(|fun| fun.base_method);
+                    let visibility = qualified_visibility
+                        .or_else(|| wrapped_fun.map(|fun| fun.visibility))
+                        .unwrap_or(Visibility::Private);
 
                     self.wrappers.insert(fun_idx, wrapper_idx);
                     self.pool.class_mut(target_class_idx)?.functions.push(wrapper_idx);
@@ -873,28 +1355,13 @@ impl<'a> CompilationUnit<'a> {
                     return Ok(slot);
                 }
                 AnnotationKind::ReplaceMethod => {
-                    let (class_name, _) = ann
-                        .args
-                        .first()
-                        .and_then(Expr::as_ident)
-                        .ok_or_else(|| Cause::InvalidAnnotationArgs.with_span(ann.span))?;
-                    let target_class_idx = match self.scope.resolve_symbol(class_name.clone()).with_span(ann.span)? {
-                        Symbol::Struct(idx, _) | Symbol::Class(idx, _) => idx,
-                        _ => return Err(Cause::ClassNotFound(class_name.clone()).with_span(ann.span)),
-                    };
-                    let candidates = Scope::resolve_direct_method(name.clone(), target_class_idx, self.pool).ok();
-                    let fun_idx = candidates
-                        .as_ref()
-                        .and_then(|cd| cd.by_id(&sig, self.pool))
-                        .ok_or_else(|| {
-                            if candidates.is_some() {
-                                Cause::NoMethodWithMatchingSignature.with_span(ann.span)
-                            } else {
-                                Cause::NoMethodWithMatchingName.with_span(ann.span)
-                            }
-                        })?;
+                    let (target_class_idx, fun_idx) = self.resolve_hook_target(ann, &name, &sig)?;
 
-                    let base = self.pool.function(fun_idx).ok().and_then(|fun| fun.base_method);
+                    let replaced_fun = self.pool.function(fun_idx).ok();
+                    let base = replaced_fun.and_then(|fun| fun.base_method);
+                    let visibility = qualified_visibility
+                        .or_else(|| replaced_fun.map(|fun| fun.visibility))
+                        .unwrap_or(Visibility::Private);
                     let slot = Slot::Function {
                         index: fun_idx,
                         parent: target_class_idx,
@@ -907,12 +1374,7 @@ impl<'a> CompilationUnit<'a> {
                     return Ok(slot);
                 }
                 AnnotationKind::ReplaceGlobal => {
-                    let fun_idx = self
-                        .scope
-                        .resolve_function(name.clone())
-                        .with_span(ann.span)?
-                        .by_id(&sig, self.pool)
-                        .ok_or_else(|| Cause::FunctionNotFound(name).with_span(ann.span))?;
+                    let (_, fun_idx) = self.resolve_hook_target(ann, &name, &sig)?;
 
                     let slot = Slot::Function {
                         index: fun_idx,
@@ -969,7 +1431,11 @@ impl<'a> CompilationUnit<'a> {
                     };
                     return Ok(slot);
                 }
-                AnnotationKind::AddField | AnnotationKind::If | AnnotationKind::RuntimeProperty => {}
+                AnnotationKind::AddField
+                | AnnotationKind::If
+                | AnnotationKind::RuntimeProperty
+                | AnnotationKind::Test
+                | AnnotationKind::Allow => {}
             }
         }
 
@@ -977,6 +1443,10 @@ impl<'a> CompilationUnit<'a> {
         let fun_idx = self.pool.stub_definition(name_idx);
 
         let path = module.with_child(name.clone());
+        if let Some(Symbol::FunctionTemplate(template, _)) = self.symbols.get_symbol(&path) {
+            let pos = self.template_refs.get(&template).copied();
+            return Err(Cause::SymbolRedefinition(pos).with_span(source.span));
+        }
         self.symbols.add_function(&path, fun_idx, visibility);
 
         // add to globals when no module
@@ -1002,7 +1472,8 @@ impl<'a> CompilationUnit<'a> {
         pool: &mut ConstantPool,
         desugar: bool,
         permissive: bool,
-    ) -> Result<(CompiledFunction, Vec<Diagnostic>), Error> {
+        visibility_check: VisibilityCheck,
+    ) -> Result<(Vec<CompiledFunction>, Vec<Diagnostic>), Error> {
         let fun = pool.function(item.index)?;
 
         let mut local_scope = if fun.flags.is_static() {
@@ -1010,6 +1481,7 @@ impl<'a> CompilationUnit<'a> {
         } else {
             item.scope.with_context(Some(item.class), item.index)
         };
+        local_scope.class = Some(item.class).filter(|class| !class.is_undefined());
 
         for param in &fun.parameters {
             let ident = Ident::from_heap(pool.def_name(*param)?);
@@ -1021,30 +1493,46 @@ impl<'a> CompilationUnit<'a> {
             local_scope.add_symbol(wrapped_ident, Symbol::Functions(vec![(wrapped, Visibility::Public)]));
         }
 
-        let mut checker = TypeChecker::new(pool, permissive);
+        let mut checker = TypeChecker::new(pool, permissive, visibility_check);
         let checked = checker.check_seq(&item.code, &mut local_scope)?;
         let (diagnostics, mut locals) = checker.into_inner();
 
-        let ast = if desugar {
+        let (ast, lambdas) = if desugar {
             let mut desugar = Desugar::new(&mut local_scope, pool);
             let desugared = desugar.on_seq(checked)?;
-            locals.extend(desugar.locals());
-            desugared
+            let (synthetic, lambdas) = desugar.into_inner();
+            locals.extend(synthetic);
+            (desugared, lambdas)
         } else {
-            checked
+            (checked, vec![])
         };
 
-        let compiled = CompiledFunction {
+        // lambdas are compiled as methods of their own classes
+        let mut compiled = Vec::with_capacity(lambdas.len() + 1);
+        for lambda in lambdas {
+            compiled.push(CompiledFunction {
+                index: lambda.function,
+                code: lambda.code,
+                locals: lambda.locals,
+                scope: local_scope.with_context(Some(lambda.class), lambda.function),
+                span: lambda.span,
+            });
+        }
+        compiled.push(CompiledFunction {
             index: item.index,
             code: ast,
             locals,
             scope: local_scope,
             span: item.span,
-        };
+        });
         Ok((compiled, diagnostics))
     }
 
-    fn compile_default(mut default: FieldDefault, pool: &mut ConstantPool) -> Result<Vec<Diagnostic>, Error> {
+    fn compile_default(
+        mut default: FieldDefault,
+        pool: &mut ConstantPool,
+        visibility_check: VisibilityCheck,
+    ) -> Result<Vec<Diagnostic>, Error> {
         fn stringify_default(expr: &Expr<SourceAst>) -> Result<String, Error> {
             match expr {
                 Expr::Constant(constant, _) => match constant {
@@ -1079,7 +1567,9 @@ impl<'a> CompilationUnit<'a> {
         };
         pool.field_mut(default.index)?.defaults = vec![property];
 
-        let mut typeck = TypeChecker::new(pool, false);
+        // defaults are evaluated in the context of the class, so they can refer to its private members
+        default.scope.class = Some(default.class);
+        let mut typeck = TypeChecker::new(pool, false, visibility_check);
         typeck.check_and_convert(&default.value, &type_id, &mut default.scope)?;
         Ok(typeck.into_diagnostics())
     }
@@ -1115,7 +1605,7 @@ impl<'a> CompilationUnit<'a> {
         } else {
             call
         };
-        let code = Assembler::from_body(Seq::new(vec![expr]), files, scope, pool)?;
+        let code = Assembler::from_body(Seq::new(vec![expr]), files, scope, pool, false)?;
 
         let compiled = Function {
             code,
@@ -1238,8 +1728,38 @@ impl<'a> CompilationUnit<'a> {
         }
     }
 
-    fn try_resolve_type(&mut self, name: &TypeName, scope: &Scope, span: Span) -> Result<TypeId, Error> {
-        match scope.resolve_type(name, self.pool) {
+    fn take_instance_bodies(&mut self) -> Vec<FunctionBody> {
+        let bodies = self.scope.generics().borrow_mut().take_bodies();
+        bodies
+            .into_iter()
+            .map(|body: InstanceBody| {
+                self.source_refs.insert(body.index.cast(), body.span.low);
+                FunctionBody {
+                    class: body.class,
+                    index: body.index,
+                    wrapped: None,
+                    code: body.code,
+                    scope: body.scope,
+                    was_callback: false,
+                    span: body.span,
+                }
+            })
+            .collect()
+    }
+
+    fn symbol_pos(&self, symbol: &Symbol) -> Option<Pos> {
+        match symbol {
+            Symbol::Class(idx, _) | Symbol::Struct(idx, _) => self.source_refs.get(&idx.cast()).copied(),
+            Symbol::Enum(idx) => self.source_refs.get(&idx.cast()).copied(),
+            Symbol::Functions(funs) => funs
+                .first()
+                .and_then(|(idx, _)| self.source_refs.get(&idx.cast()).copied()),
+            Symbol::ClassTemplate(path, _) | Symbol::FunctionTemplate(path, _) => self.template_refs.get(path).copied(),
+        }
+    }
+
+    fn try_resolve_type(&mut self, name: &TypeName, scope: &mut Scope, span: Span) -> Result<TypeId, Error> {
+        match scope.declare_type(name, self.pool) {
             Ok(ty) => Ok(ty),
             Err(err) => {
                 // report the fatal error and return a dummy type
@@ -1255,12 +1775,12 @@ impl<'a> CompilationUnit<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct FunctionBody {
     class: PoolIndex<Class>,
     index: PoolIndex<Function>,
     wrapped: Option<PoolIndex<Function>>,
-    code: Seq<SourceAst>,
+    code: Ref<Seq<SourceAst>>,
     scope: Scope,
     was_callback: bool,
     span: Span,
@@ -1283,6 +1803,14 @@ pub struct CompiledFunction {
     pub span: Span,
 }
 
+/// A function compiled on this thread, or a chunk of functions compiled on another one.
+#[derive(Debug)]
+enum Compiled {
+    Function(CompiledFunction),
+    #[cfg(feature = "arc")]
+    Staged(Box<parallel::StagedChunk>),
+}
+
 #[derive(Debug)]
 enum Slot {
     Function {
@@ -1313,6 +1841,22 @@ enum Slot {
         index: PoolIndex<Enum>,
         source: EnumSource,
     },
+    Template(TemplateSlot),
+}
+
+#[derive(Debug)]
+enum TemplateSlot {
+    Class {
+        path: ModulePath,
+        source: ClassSource,
+        is_struct: bool,
+        visibility: Visibility,
+    },
+    Function {
+        path: ModulePath,
+        source: FunctionSource,
+        visibility: Visibility,
+    },
 }
 
 #[derive(Debug)]
@@ -1341,10 +1885,22 @@ fn eval_conditions(cte: &cte::Context, anns: &[Annotation]) -> Result<bool, Erro
         })
 }
 
+/// A function that wraps or replaces an existing one, along with the result of resolving its target.
+#[derive(Debug)]
+pub struct Hook {
+    pub kind: AnnotationKind,
+    /// The class named in the annotation, absent for `@replaceGlobal`.
+    pub class: Option<Ident>,
+    pub name: Ident,
+    pub span: Span,
+    pub target: Result<PoolIndex<Function>, Error>,
+}
+
 #[derive(Debug, Default)]
 pub struct CompilationOutput {
     diagnostics: Vec<Diagnostic>,
     source_refs: Vec<SourceRef>,
+    tests: Vec<PoolIndex<Function>>,
 }
 
 impl CompilationOutput {
@@ -1356,6 +1912,11 @@ impl CompilationOutput {
         &self.source_refs
     }
 
+    /// Returns the functions annotated with `@test`.
+    pub fn tests(&self) -> &[PoolIndex<Function>] {
+        &self.tests
+    }
+
     pub fn into_diagnostics(self) -> Vec<Diagnostic> {
         self.diagnostics
     }
@@ -0,0 +1,201 @@
+//! Compilation of function bodies on multiple threads.
+//!
+//! Bodies are split into contiguous chunks, and every chunk is typechecked and assembled on its own thread
+//! against an overlay over the pool, which the threads share without copying it. The entries added to the
+//! overlays are merged back in the order in which a single thread would have added them: the ones added by
+//! typechecking right after the chunk is compiled, the ones added by assembly when the unit is finished.
+//! The output doesn't depend on the number of threads.
+//!
+//! Chunks with a body that fails to compile, declares lambdas, declares function types or generic instances,
+//! or modifies a definition that was already in the pool are compiled again on the calling thread, because
+//! the entries they add depend on what was compiled before.
+use std::{mem, panic, thread};
+
+use itertools::Itertools;
+use redscript::bundle::{ConstantPool, PoolIndex, PoolMark, SharedPool};
+use redscript::bytecode::{Code, Offset};
+use redscript::definition::{Function, Local};
+use redscript::mapper::PoolStage;
+
+use super::{CompilationUnit, Compiled, FunctionBody};
+use crate::assembler::Assembler;
+use crate::diagnostics::{Diagnostic, DiagnosticPass, FunctionMetadata};
+use crate::error::Error;
+use crate::scope::Scope;
+use crate::source_map::Files;
+use crate::typechecker::VisibilityCheck;
+
+impl CompilationUnit<'_> {
+    pub(super) fn compile_parallel(
+        &mut self,
+        bodies: Vec<FunctionBody>,
+        files: &Files,
+        desugar: bool,
+        permissive: bool,
+        threads: usize,
+        compiled: &mut Vec<Compiled>,
+    ) -> Result<(), Error> {
+        let chunk_size = bodies.len().div_ceil(threads);
+        let chunks = bodies
+            .into_iter()
+            .chunks(chunk_size)
+            .into_iter()
+            .map(Itertools::collect_vec)
+            .collect_vec();
+
+        let settings = Settings {
+            desugar,
+            permissive,
+            visibility_check: self.visibility_check,
+            optimize: self.optimize,
+        };
+        let pool = mem::take(self.pool).share();
+        let passes = &self.diagnostic_passes[..];
+        let results = thread::scope(|s| {
+            let handles = chunks
+                .iter()
+                .map(|chunk| {
+                    let shared = chunk[0].scope.fork_shared();
+                    let bodies = chunk
+                        .iter()
+                        .cloned()
+                        .map(|mut body| {
+                            body.scope.share_with(&shared);
+                            body
+                        })
+                        .collect_vec();
+                    let pool = &pool;
+                    s.spawn(move || compile_chunk(bodies, &shared, pool, files, passes, settings))
+                })
+                .collect_vec();
+            handles.into_iter().map(thread::ScopedJoinHandle::join).collect_vec()
+        });
+        // the overlays are dropped by the time the threads finish, even if they panic
+        *self.pool = pool.into_inner().expect("no overlays should be left");
+        let results = results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|err| panic::resume_unwind(err)));
+
+        for (chunk, result) in chunks.into_iter().zip(results) {
+            if let Some(mut staged) = result {
+                for func in &mut staged.functions {
+                    staged.stage.merge(func.checked, self.pool);
+                    self.diagnostics.append(&mut func.diagnostics);
+                }
+                compiled.push(Compiled::Staged(Box::new(staged)));
+            } else {
+                for body in chunk {
+                    self.compile_body(body, desugar, permissive, compiled)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A chunk of functions compiled on another thread, along with the entries they added to its overlay.
+#[derive(Debug)]
+pub struct StagedChunk {
+    stage: PoolStage,
+    functions: Vec<StagedFunction>,
+}
+
+impl StagedChunk {
+    /// Merges the entries added by assembling the functions and sets their code.
+    pub fn merge_assembly(self, pool: &mut ConstantPool) -> Result<(), Error> {
+        let Self { mut stage, functions } = self;
+        for func in functions {
+            stage.merge(func.assembled, pool);
+
+            let mut code = func.code;
+            let mapper = stage.mapper();
+            for instr in code.as_mut() {
+                mapper.map_instr(instr);
+            }
+            let function = pool.function_mut(stage.definition(func.index))?;
+            function.code = code;
+            function.locals = func.locals.into_iter().map(|local| stage.definition(local)).collect();
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct StagedFunction {
+    index: PoolIndex<Function>,
+    code: Code<Offset>,
+    locals: Vec<PoolIndex<Local>>,
+    diagnostics: Vec<Diagnostic>,
+    // the size of the overlay after the function was typechecked and after it was assembled
+    checked: PoolMark,
+    assembled: PoolMark,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    desugar: bool,
+    permissive: bool,
+    visibility_check: VisibilityCheck,
+    optimize: bool,
+}
+
+/// Compiles a chunk against an overlay, or returns `None` if it has to be compiled on the calling thread.
+fn compile_chunk(
+    bodies: Vec<FunctionBody>,
+    shared: &Scope,
+    pool: &SharedPool,
+    files: &Files,
+    passes: &[Box<dyn DiagnosticPass + Send + Sync>],
+    settings: Settings,
+) -> Option<StagedChunk> {
+    let mut overlay = pool.overlay();
+    let base = overlay.mark();
+    let shared_size = shared.shared_size();
+
+    let mut checked = Vec::with_capacity(bodies.len());
+    for body in bodies {
+        let was_callback = body.was_callback;
+        let (mut funcs, mut diagnostics) = CompilationUnit::compile_function(
+            body,
+            &mut overlay,
+            settings.desugar,
+            settings.permissive,
+            settings.visibility_check,
+        )
+        .ok()?;
+        // lambdas are named after the position of their functions in the pool
+        if funcs.len() != 1
+            || shared.shared_size() != shared_size
+            || overlay.has_overrides()
+            || diagnostics.iter().any(Diagnostic::is_fatal)
+        {
+            return None;
+        }
+        let func = funcs.pop()?;
+
+        let flags = overlay.function(func.index).ok()?.flags;
+        let metadata = FunctionMetadata::new(flags, was_callback, func.span);
+        for pass in passes {
+            diagnostics.extend(pass.diagnose(&func.code, &metadata));
+        }
+        checked.push((func, diagnostics, overlay.mark()));
+    }
+
+    let mut functions = Vec::with_capacity(checked.len());
+    for (mut func, diagnostics, checked) in checked {
+        let code = Assembler::from_body(func.code, files, &mut func.scope, &mut overlay, settings.optimize).ok()?;
+        if shared.shared_size() != shared_size || overlay.has_overrides() {
+            return None;
+        }
+        functions.push(StagedFunction {
+            index: func.index,
+            code,
+            locals: func.locals,
+            diagnostics,
+            checked,
+            assembled: overlay.mark(),
+        });
+    }
+    let stage = PoolStage::new(overlay, base);
+    Some(StagedChunk { stage, functions })
+}
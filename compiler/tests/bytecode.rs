@@ -1,5 +1,9 @@
 #![allow(clippy::redundant_closure_call)]
-use redscript::bytecode::{Code, Instr, Offset};
+use redscript::assembly;
+use redscript::bundle::{ConstantPool, PoolIndex};
+use redscript::bytecode::{Code, Instr, Label, Offset};
+use redscript::definition::AnyDefinition;
+use redscript_compiler::assembler::optimizer;
 
 #[allow(unused)]
 mod utils;
@@ -998,3 +1002,244 @@ fn compile_static_arrays() {
     ];
     TestContext::compiled(vec![sources]).unwrap().run("Testing", check);
 }
+
+#[test]
+fn reassemble_disassembled_code() {
+    let sources = r#"
+        enum Enum {
+            Zero = 0,
+            One = 1
+        }
+
+        class Class {
+            let a: Int32 = 10;
+
+            func Method(x: Int32, flag: Bool) -> Int32 {
+                switch x {
+                    case 0:
+                        return 1;
+                    default:
+                        break;
+                }
+                let name = n"name";
+                let str = "\"quoted\"\n";
+                let e = Enum.One;
+                while flag {
+                    flag = Global();
+                }
+                return flag ? this.a : x;
+            }
+        }
+
+        func Global() -> Bool {
+            new Class().Method(1, false);
+            return true;
+        }
+    "#;
+
+    let (mut pool, _) = utils::compiled(vec![sources]).unwrap();
+    let functions: Vec<_> = pool
+        .definitions()
+        .filter(|(_, def)| matches!(&def.value, AnyDefinition::Function(fun) if !fun.code.is_empty()))
+        .map(|(idx, _)| idx.cast())
+        .collect();
+    assert_eq!(functions.len(), 2);
+
+    for idx in functions {
+        let fun = pool.function(idx).unwrap();
+        let expected = fun.code.clone();
+        let mut text = String::new();
+        assembly::disassemble(&mut text, fun, &pool).unwrap();
+        let code = assembly::assemble(&text, idx, &mut pool).unwrap();
+        assert_eq!(code, expected, "{text}");
+    }
+}
+
+#[test]
+fn assemble_hand_written_code() {
+    let sources = r#"
+        func Testing(flag: Bool) -> CName = n"Original"
+    "#;
+
+    let (mut pool, _) = utils::compiled(vec![sources]).unwrap();
+    let (idx, _) = pool
+        .definitions()
+        .find(|(_, def)| pool.names.get(def.name).unwrap().as_ref() == "Testing;Bool")
+        .unwrap();
+    let idx = idx.cast();
+    let flag = pool.function(idx).unwrap().parameters[0];
+
+    let code = assembly::assemble(
+        r#"
+            jump_if_false L0 // skipped when the flag is set
+            param flag
+            return
+            name_const "Patched"
+        L0:
+            return
+            name_const "Default"
+            nop
+        "#,
+        idx,
+        &mut pool,
+    )
+    .unwrap();
+
+    let locations: Vec<_> = code.iter().map(|(loc, _)| loc).collect();
+    let default = pool.names.get_index("Default").unwrap();
+    let expected = [
+        Instr::JumpIfFalse(locations[4].relative(locations[0])),
+        Instr::Param(flag),
+        Instr::Return,
+        Instr::NameConst(pool.names.get_index("Patched").unwrap()),
+        Instr::Return,
+        Instr::NameConst(default),
+        Instr::Nop,
+    ];
+    assert_eq!(code.as_ref(), &expected[..]);
+    pool.function_mut(idx).unwrap().code = code;
+
+    let err = assembly::assemble("jump L0\nparam other\n", idx, &mut pool).unwrap_err();
+    assert_eq!(err.to_string(), "line 2: parameter 'other' not found");
+    let err = assembly::assemble("jump L0\nnop\n", idx, &mut pool).unwrap_err();
+    assert_eq!(err.to_string(), "line 1: label 'L0' is never placed");
+    let err = assembly::assemble("new Missing", idx, &mut pool).unwrap_err();
+    assert_eq!(err.to_string(), "line 1: unknown class 'Missing'");
+    let err = assembly::assemble("nop", PoolIndex::new(u32::MAX), &mut pool).unwrap_err();
+    assert!(matches!(err, assembly::AssemblyError::Pool(_)));
+}
+
+#[test]
+fn optimize_constant_operators() {
+    let sources = "
+        func Testing() -> Int32 {
+            let x = 2 + 3 * 4;
+            let y = x - 1;
+            return 1 - 2;
+        }
+
+        native func OperatorAdd(l: Int32, r: Int32) -> Int32
+        native func OperatorMultiply(l: Int32, r: Int32) -> Int32
+        func OperatorSubtract(l: Int32, r: Int32) -> Int32 = 0
+        ";
+
+    let check = check_code![
+        pat!(Assign),
+        mem!(Local(x)),
+        pat!(I32Const(14)),
+        pat!(Assign),
+        mem!(Local(y)),
+        mem!(InvokeStatic(_0, _1, subtract, _2)),
+        mem!(Local(x)),
+        pat!(I32Const(1)),
+        pat!(ParamEnd),
+        pat!(Return),
+        mem!(InvokeStatic(_0, _1, subtract, _2)),
+        pat!(I32Const(1)),
+        pat!(I32Const(2)),
+        pat!(ParamEnd),
+        pat!(Nop)
+    ];
+    TestContext::optimized(vec![sources]).unwrap().run("Testing", check);
+}
+
+#[test]
+fn optimize_constant_conditions() {
+    let sources = "
+        func Testing() -> Int32 {
+            if 2 < 1 {
+                Log();
+            }
+            if 1 < 2 {
+                return 1;
+            } else {
+                return 2;
+            }
+        }
+
+        native func Log()
+        native func OperatorLess(l: Int32, r: Int32) -> Bool
+        ";
+
+    let check = check_code![pat!(Return), pat!(I32Const(1)), pat!(Nop)];
+    TestContext::optimized(vec![sources]).unwrap().run("Testing", check);
+}
+
+#[test]
+fn optimize_jump_chains() {
+    let sources = "
+        func Testing(a: Bool, b: Bool) {
+            if a {
+                if b {
+                    Log();
+                }
+            } else {
+                Log();
+            }
+        }
+
+        native func Log()
+        ";
+
+    let check = check_code![
+        pat!(JumpIfFalse(Offset { value: 43 })),
+        mem!(Param(a)),
+        pat!(JumpIfFalse(Offset { value: 47 })),
+        mem!(Param(b)),
+        mem!(InvokeStatic(_0, _1, log, _2)),
+        pat!(ParamEnd),
+        pat!(Jump(Offset { value: 19 })),
+        mem!(InvokeStatic(_0, _1, log, _2)),
+        pat!(ParamEnd),
+        pat!(Nop)
+    ];
+    TestContext::optimized(vec![sources]).unwrap().run("Testing", check);
+}
+
+#[test]
+fn optimize_unreachable_code() {
+    let sources = "
+        func Testing() {
+            Log();
+            return;
+            Log();
+        }
+
+        native func Log()
+        ";
+
+    let check = check_code![
+        mem!(InvokeStatic(_0, _1, log, _2)),
+        pat!(ParamEnd),
+        pat!(Return),
+        pat!(Nop),
+        pat!(Nop)
+    ];
+    TestContext::optimized(vec![sources]).unwrap().run("Testing", check);
+}
+
+#[test]
+fn optimize_nop_statements() {
+    let label = Label { index: 0 };
+    let code = Code::new(vec![
+        Instr::Nop,
+        Instr::InvokeStatic(label, 0, PoolIndex::UNDEFINED, 0),
+        Instr::Nop,
+        Instr::ParamEnd,
+        Instr::Target(label),
+        Instr::Nop,
+        Instr::Return,
+        Instr::Nop,
+        Instr::Nop,
+    ]);
+    let code = optimizer::optimize(code, &ConstantPool::default()).resolve_labels(1);
+    let expected = [
+        Instr::InvokeStatic(Offset::new(17), 0, PoolIndex::UNDEFINED, 0),
+        Instr::Nop,
+        Instr::ParamEnd,
+        Instr::Return,
+        Instr::Nop,
+        Instr::Nop,
+    ];
+    assert_eq!(code.as_ref(), &expected[..]);
+}
@@ -1,11 +1,19 @@
+use std::io::Cursor;
+
 use itertools::Itertools;
-use redscript::definition::{ClassFlags, Property};
+use redscript::bundle::{ConstantPool, PoolIndex, ScriptBundle};
+use redscript::definition::{AnyDefinition, ClassFlags, Definition, Local, LocalFlags, Property, Visibility};
+use redscript::mapper::{MultiMapper, PoolMapper};
+use redscript::{assembly, compact, text, verify};
 
 #[allow(unused)]
 mod utils;
 
 use redscript_compiler::diagnostics::Diagnostic;
-use redscript_compiler::error::Cause;
+use redscript_compiler::error::{Cause, Error};
+use redscript_compiler::parser;
+use redscript_compiler::source_map::Files;
+use redscript_compiler::unit::CompilationUnit;
 use utils::{check_class_flags, compiled};
 
 #[test]
@@ -369,3 +377,774 @@ fn fail_with_extra_arg_on_static_receiver() {
         errs
     );
 }
+
+#[test]
+fn compile_test_annotations() {
+    let sources = "
+        @test
+        func Passing() {}
+
+        @test()
+        func AlsoPassing() {}
+        ";
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(matches!(&errs[..], &[]), "{:?}", errs);
+}
+
+#[test]
+fn fail_on_test_with_parameters() {
+    let sources = "
+        @test
+        func Parameterized(x: Int32) {}
+        ";
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(
+        matches!(&errs[..], &[Diagnostic::CompileError(Cause::UnsupportedFeature(_), _)]),
+        "{:?}",
+        errs
+    );
+}
+
+#[test]
+fn compile_lambdas_and_function_types() {
+    let sources = "
+        func Compose(f: (Int32) -> Int32, g: (Int32) -> Int32) -> (Int32) -> Int32 {
+            return (x) -> g(f(x));
+        }
+
+        func Testing() {
+            let inc = (x: Int32) -> x;
+            let noop: () -> Void = () -> {};
+            let composed: func<Int32, Int32> = Compose(inc, inc);
+            composed(1);
+            noop();
+        }
+        ";
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(matches!(&errs[..], &[]), "{:?}", errs);
+}
+
+#[test]
+fn fail_on_lambda_without_param_types() {
+    let sources = "
+        func Testing() {
+            let f = (x) -> x;
+        }
+        ";
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(
+        matches!(&errs[..], &[Diagnostic::CompileError(Cause::TypeAnnotationRequired, _)]),
+        "{:?}",
+        errs
+    );
+}
+
+#[test]
+fn encode_lambda_invoke_flags() {
+    let sources = "
+        func Testing() {
+            let id = (x: Int32) -> x;
+            let noop = () -> {};
+            id(1);
+            noop();
+        }
+        ";
+
+    let (pool, errs) = compiled(vec![sources]).unwrap();
+    assert!(matches!(&errs[..], &[]), "{:?}", errs);
+
+    let bundle = ScriptBundle::load(&mut Cursor::new(encoded(pool))).unwrap();
+    let pool = &bundle.pool;
+    let flags = pool
+        .definitions()
+        .filter_map(|(_, def)| match &def.value {
+            AnyDefinition::Class(class) if pool.names.get(def.name).unwrap().starts_with("lambda$") => Some(class),
+            _ => None,
+        })
+        .map(|class| {
+            let invoke = class.functions[0];
+            let flags = pool.function(invoke).unwrap().flags;
+            (flags.has_parameters(), flags.has_return_value())
+        })
+        .collect_vec();
+    assert_eq!(flags, vec![(true, true), (false, false)]);
+}
+
+#[test]
+fn compile_generic_classes_and_functions() {
+    let sources = "
+        class Box<T> {
+            let value: T;
+
+            func Get() -> T {
+                return this.value;
+            }
+        }
+
+        struct Pair<A, B> {
+            let first: A;
+            let second: B;
+        }
+
+        func Wrap<T>(value: T) -> ref<Box<T>> {
+            let box = new Box<T>();
+            box.value = value;
+            return box;
+        }
+
+        func Apply<T, R>(value: T, f: (T) -> R) -> R {
+            return f(value);
+        }
+
+        func Testing() -> String {
+            let box: ref<Box<String>> = Wrap(\"a\");
+            let pair = new Pair<Int32, ref<Box<String>>>(1, box);
+            Wrap<Float>(1.0).Get();
+            return Apply(pair.second, (b) -> b.Get());
+        }
+        ";
+
+    let (pool, errs) = compiled(vec![sources]).unwrap();
+    assert!(matches!(&errs[..], &[]), "{:?}", errs);
+
+    let names: Vec<_> = pool.roots().filter_map(|(idx, _)| pool.def_name(idx).ok()).collect();
+    assert!(names.iter().any(|name| name.as_ref() == "Box<String>"));
+    assert!(names.iter().any(|name| name.as_ref() == "Wrap<Float>"));
+}
+
+#[test]
+fn compile_generic_methods() {
+    let sources = "
+        class Named {
+            func Name() -> String {
+                return \"named\";
+            }
+        }
+
+        class Box<T> extends Named {
+            let value: T;
+
+            func Name() -> String {
+                return \"box\";
+            }
+
+            func Map<R>(f: (T) -> R) -> ref<Box<R>> {
+                let box = new Box<R>();
+                box.value = f(this.value);
+                return box;
+            }
+        }
+
+        class Registry {
+            func Pick<T>(first: T, second: T) -> T {
+                return second;
+            }
+        }
+
+        func Testing() -> String {
+            let box = new Box<Int32>();
+            let registry = new Registry();
+            registry.Pick(1, 2);
+            return box.Map((x) -> registry.Pick(\"a\", \"b\")).value;
+        }
+        ";
+
+    let (pool, errs) = compiled(vec![sources]).unwrap();
+    assert!(matches!(&errs[..], &[]), "{:?}", errs);
+
+    let find_class = |name: &str| {
+        pool.roots()
+            .find(|(_, def)| pool.names.get(def.name).unwrap().as_ref() == name)
+            .map(|(idx, _)| idx.cast())
+            .unwrap()
+    };
+    let method_names = |class| {
+        pool.class(class)
+            .unwrap()
+            .functions
+            .iter()
+            .map(|fun| pool.def_name(*fun).unwrap().as_ref().to_owned())
+            .collect_vec()
+    };
+    assert!(method_names(find_class("Box<Int32>")).contains(&"Map<String>".to_owned()));
+    assert_eq!(
+        method_names(find_class("Registry")),
+        vec!["Pick<Int32>".to_owned(), "Pick<String>".to_owned()]
+    );
+
+    // methods of generic instances override the methods of their bases
+    let base = pool.class(find_class("Named")).unwrap().functions[0];
+    for class in ["Box<Int32>", "Box<String>"] {
+        let name = pool.class(find_class(class)).unwrap().functions[0];
+        assert_eq!(pool.function(name).unwrap().base_method, Some(base));
+    }
+}
+
+#[test]
+fn allow_access_to_members_in_their_classes() {
+    let sources = "
+        class A {
+            private let secret: Int32;
+
+            private static func Make() -> ref<A> = new A()
+            protected func Reveal() -> Int32 = this.secret
+
+            public static func Create() -> ref<A> = A.Make()
+        }
+
+        class B extends A {
+            public func RevealTwice() -> Int32 = this.Reveal() + this.Reveal()
+        }
+
+        native func OperatorAdd(a: Int32, b: Int32) -> Int32
+        ";
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(matches!(&errs[..], &[]), "{:?}", errs);
+}
+
+#[test]
+fn inherit_class_visibility_in_unqualified_members() {
+    let sources = "
+        public class A {
+            private let secret: Int32;
+            let value: Int32 = A.secret;
+
+            func Get() -> Int32 = this.value
+        }
+
+        class B {
+            let value: Int32;
+        }
+
+        func Read(a: ref<A>, b: ref<B>) -> Int32 = a.Get() + b.value
+
+        native func OperatorAdd(a: Int32, b: Int32) -> Int32
+        ";
+
+    let (pool, errs) = compiled(vec![sources]).unwrap();
+    assert!(matches!(&errs[..], &[]), "{:?}", errs);
+
+    let visibility = |class: &str, member: &str| {
+        let (_, def) = pool
+            .definitions()
+            .find(|(_, def)| {
+                !def.parent.is_undefined()
+                    && pool.def_name(def.parent).unwrap().as_ref() == class
+                    && pool.names.get(def.name).unwrap().split(';').next() == Some(member)
+            })
+            .unwrap();
+        match &def.value {
+            AnyDefinition::Field(field) => field.visibility,
+            AnyDefinition::Function(fun) => fun.visibility,
+            _ => panic!("{member} is not a member"),
+        }
+    };
+    assert_eq!(visibility("A", "secret"), Visibility::Private);
+    assert_eq!(visibility("A", "value"), Visibility::Public);
+    assert_eq!(visibility("A", "Get"), Visibility::Public);
+    assert_eq!(visibility("B", "value"), Visibility::Private);
+}
+
+#[test]
+fn fail_on_inaccessible_member() {
+    let sources = "
+        class A {
+            private let secret: Int32;
+            protected func Reveal() -> Int32 = this.secret
+        }
+
+        class B {
+            func Steal(a: ref<A>) -> Int32 = a.secret
+        }
+
+        func Reveal(a: ref<A>) -> Int32 = a.Reveal()
+        ";
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(
+        matches!(
+            &errs[..],
+            &[
+                Diagnostic::CompileError(Cause::InaccessibleMember(_, Visibility::Private), _),
+                Diagnostic::CompileError(Cause::InaccessibleMember(_, Visibility::Protected), _)
+            ]
+        ),
+        "{:?}",
+        errs
+    );
+}
+
+#[test]
+fn fail_on_continue_outside_of_loop() {
+    let sources = "
+        func Testing() {
+            let f = () -> {
+                continue;
+            };
+            while true {
+                f();
+            }
+        }
+        ";
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(
+        matches!(&errs[..], &[Diagnostic::CompileError(Cause::UnexpectedContinue, _)]),
+        "{:?}",
+        errs
+    );
+}
+
+#[test]
+fn fail_on_uninferrable_type_args() {
+    let sources = "
+        func Default<T>() -> T {
+            let value: T;
+            return value;
+        }
+
+        func Testing() {
+            let value = Default();
+        }
+        ";
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(
+        matches!(&errs[..], &[Diagnostic::CompileError(Cause::TypeArgsRequired(_), _)]),
+        "{:?}",
+        errs
+    );
+}
+
+#[test]
+fn fail_on_invalid_type_arg_count() {
+    let sources = "
+        class Box<T> {
+            let value: T;
+        }
+
+        func Testing() {
+            let box = new Box<Int32, Int32>();
+        }
+        ";
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(
+        matches!(
+            &errs[..],
+            &[Diagnostic::CompileError(Cause::InvalidTypeArgCount(_, 1), _)]
+        ),
+        "{:?}",
+        errs
+    );
+}
+
+#[test]
+fn suppress_allowed_warnings() {
+    let sources = r#"
+        @allow("UNUSED_LOCAL")
+        func Unused() {
+            let x = 1;
+        }
+
+        @allow("MISSING_RETURN", "UNUSED_LOCAL")
+        class Holder {
+            func Get() -> Int32 {
+                let y = 2;
+            }
+        }
+
+        func Partial() -> Int32 {
+            @allow("UNUSED_LOCAL")
+            let a = 1;
+            let b = 2;
+            return 0;
+        }
+
+        @allow("UNUSED_LOCAL")
+        func Other() -> Int32 {}
+        "#;
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(
+        matches!(&errs[..], &[Diagnostic::UnusedLocal(_), Diagnostic::MissingReturn(_)]),
+        "{:?}",
+        errs
+    );
+    assert_eq!(
+        errs.iter().map(Diagnostic::code).collect_vec(),
+        ["UNUSED_LOCAL", "MISSING_RETURN"]
+    );
+}
+
+#[test]
+fn fail_on_invalid_allow_annotation() {
+    let sources = r#"
+        @allow(Unused, "UNUSED_LOCAL")
+        func Testing() {
+            let x = 1;
+        }
+        "#;
+
+    let (_, errs) = compiled(vec![sources]).unwrap();
+    assert!(
+        matches!(&errs[..], &[Diagnostic::CompileError(Cause::InvalidAnnotationArgs, _)]),
+        "{:?}",
+        errs
+    );
+}
+
+#[test]
+fn resolve_hook_targets() {
+    let base = "
+        class A {
+            func Wrapped(a: Int32) -> Int32 { return a; }
+            func Replaced() {}
+        }
+
+        func Global() -> Bool { return true; }
+        ";
+    let mod_ = "
+        @wrapMethod(A)
+        func Wrapped(a: Int32) -> Int32 { return wrappedMethod(a); }
+
+        @replaceMethod(A)
+        func Replaced(b: Bool) {}
+
+        @replaceGlobal()
+        func Global() -> Bool { return false; }
+
+        @replaceMethod(B)
+        func Missing() {}
+
+        @if(false)
+        @replaceMethod(A)
+        func Excluded() {}
+        ";
+
+    let (mut pool, _) = compiled(vec![base]).unwrap();
+    let module = parser::parse_str(mod_).unwrap();
+    let hooks = CompilationUnit::new_with_defaults(&mut pool)
+        .unwrap()
+        .resolve_hooks(&[module])
+        .unwrap();
+
+    let names = hooks.iter().map(|hook| hook.name.as_ref()).collect_vec();
+    assert_eq!(names, ["Wrapped", "Replaced", "Global", "Missing"]);
+    assert_eq!(
+        pool.def_name(*hooks[0].target.as_ref().unwrap()).unwrap().as_ref(),
+        "Wrapped;Int32"
+    );
+    assert!(matches!(
+        hooks[1].target,
+        Err(Error::CompileError(Cause::NoMethodWithMatchingSignature, _))
+    ));
+    assert_eq!(
+        pool.def_name(*hooks[2].target.as_ref().unwrap()).unwrap().as_ref(),
+        "Global;"
+    );
+    assert!(matches!(
+        hooks[3].target,
+        Err(Error::CompileError(Cause::UnresolvedReference(_), _))
+    ));
+}
+
+#[test]
+fn round_trip_pool_through_text() {
+    let sources = r#"
+        enum Enum {
+            Zero = 0,
+            One = 1
+        }
+
+        class Class {
+            let a: Int32 = 10;
+            let b: array<String>;
+
+            func Method(x: Int32, opt y: Float, flag: Bool) -> Int32 {
+                switch x {
+                    case 0:
+                        return 1;
+                    default:
+                        break;
+                }
+                let name = n"name";
+                let str = "\"quoted\"\n";
+                let float = 1.5;
+                let e = Enum.One;
+                while flag {
+                    flag = Global();
+                }
+                return flag ? this.a : x;
+            }
+        }
+
+        func Global() -> Bool = true
+    "#;
+
+    let (pool, errs) = compiled(vec![sources]).unwrap();
+    let errs = errs.into_iter().filter(Diagnostic::is_fatal).collect_vec();
+    assert!(matches!(&errs[..], &[]));
+
+    let mut str = String::new();
+    text::write_pool(&mut str, &pool).unwrap();
+    let imported = text::read_pool(&str).unwrap();
+    assert_eq!(encoded(pool), encoded(imported));
+}
+
+#[cfg(feature = "arc")]
+#[test]
+fn compile_on_multiple_threads() {
+    let mut sources = r#"
+        class Box<T> {
+            let value: T;
+        }
+
+        func Boxed() -> ref<Box<Int32>> = new Box<Int32>()
+
+        func Describe(name: CName) -> String = "described"
+
+        func Lambda() -> Int32 {
+            let f = (x: Int32) -> x;
+            return f(1);
+        }
+    "#
+    .to_owned();
+    for i in 0..24 {
+        sources.push_str(&format!(
+            r#"
+            func Function{i}(flag: Bool) -> array<String> {{
+                let unused: Float;
+                let name = n"name{i}";
+                let array: array<String>;
+                ArrayPush(array, "string{i}");
+                ArrayPush(array, "shared");
+                if flag {{
+                    ArrayPush(array, Describe(name));
+                }}
+                return array;
+            }}
+            "#
+        ));
+    }
+
+    let (pool, diagnostics) = utils::compiled_with(vec![&sources], true, 1).unwrap();
+    let expected = encoded(pool);
+    assert!(!diagnostics.iter().any(Diagnostic::is_fatal), "{diagnostics:?}");
+
+    for threads in [2, 5, 32] {
+        let (pool, other) = utils::compiled_with(vec![&sources], true, threads).unwrap();
+        assert_eq!(encoded(pool), expected);
+        assert_eq!(format!("{other:?}"), format!("{diagnostics:?}"));
+    }
+}
+
+#[cfg(feature = "arc")]
+#[test]
+fn compile_modified_definitions_on_multiple_threads() {
+    // instantiating a generic method adds the instance to its existing class
+    let mut sources = r#"
+        class Picker {
+            func Pick<T>(first: T, second: T) -> T = first
+        }
+    "#
+    .to_owned();
+    for i in 0..12 {
+        sources.push_str(&format!(
+            r#"
+            func Function{i}(picker: ref<Picker>) -> Int32 {{
+                let str = picker.Pick("a{i}", "b");
+                return picker.Pick({i}, 1);
+            }}
+            "#
+        ));
+    }
+
+    let (pool, diagnostics) = utils::compiled_with(vec![&sources], true, 1).unwrap();
+    let expected = encoded(pool);
+    assert!(!diagnostics.iter().any(Diagnostic::is_fatal), "{diagnostics:?}");
+
+    for threads in [2, 5] {
+        let (pool, other) = utils::compiled_with(vec![&sources], true, threads).unwrap();
+        assert_eq!(encoded(pool), expected);
+        assert_eq!(format!("{other:?}"), format!("{diagnostics:?}"));
+    }
+}
+
+#[test]
+fn compact_compiled_pool() {
+    let sources = r#"
+        class Class {
+            let a: Int32 = 10;
+
+            func Method(x: Int32, flag: Bool) -> Int32 {
+                let name = n"name";
+                let str = "string";
+                while flag {
+                    flag = Global();
+                }
+                return flag ? this.a : x;
+            }
+        }
+
+        func Global() -> Bool = true
+    "#;
+
+    let (mut pool, errs) = compiled(vec![sources]).unwrap();
+    let errs = errs.into_iter().filter(Diagnostic::is_fatal).collect_vec();
+    assert!(matches!(&errs[..], &[]));
+
+    let orphan_name = pool.names.add("orphan".into());
+    let (type_idx, _) = pool
+        .definitions()
+        .find(|(_, def)| def.value.as_type().is_some())
+        .unwrap();
+    let orphan = pool.add_definition::<Local>(Definition::local(
+        orphan_name,
+        PoolIndex::UNDEFINED,
+        Local::new(type_idx.cast(), LocalFlags::new()),
+    ));
+    let before = disassembled(&pool);
+    let errors = verify_errors(&pool);
+    let definitions = pool.definitions().count();
+
+    let relocation = compact::compact(&mut pool);
+    assert_eq!(relocation.definition(orphan), None);
+    assert!(pool.definitions().count() < definitions);
+    assert_eq!(pool.names.get_index("orphan"), None);
+    assert_eq!(verify_errors(&pool), errors);
+    assert_eq!(disassembled(&pool), before);
+
+    let bundle = ScriptBundle::load(&mut Cursor::new(encoded(pool))).unwrap();
+    assert_eq!(disassembled(&bundle.pool), before);
+}
+
+#[test]
+fn compile_against_compacted_pool() {
+    let sources = r#"
+        class Class {
+            public func Method(x: Int32) -> Int32 = x
+        }
+    "#;
+    let (mut pool, errs) = compiled(vec![sources]).unwrap();
+    assert!(!errs.iter().any(Diagnostic::is_fatal), "{errs:?}");
+    compact::compact(&mut pool);
+    let mut pool = ScriptBundle::load(&mut Cursor::new(encoded(pool))).unwrap().pool;
+
+    let sources = r#"
+        func Test(instance: ref<Class>, flag: Bool, values: array<Float>) -> String {
+            let name = n"name";
+            let variant = ToVariant(instance.Method(ArraySize(values)));
+            return flag ? "some" : "none";
+        }
+    "#;
+    let module = parser::parse_str(sources).unwrap();
+    let output = CompilationUnit::new_with_defaults(&mut pool)
+        .unwrap()
+        .compile(vec![module], &Files::default())
+        .unwrap();
+    let errs = output.into_diagnostics();
+    assert!(!errs.iter().any(Diagnostic::is_fatal), "{errs:?}");
+}
+
+#[test]
+fn remap_every_index_kind() {
+    let sources = r#"
+        enum Enum {
+            Zero = 0,
+            One = 1
+        }
+
+        class Class {
+            let a: Int32 = 10;
+            let b: array<String>;
+
+            func Method(x: Int32, flag: Bool) -> Int32 {
+                let name = n"name";
+                let e = Enum.One;
+                ArrayPush(this.b, "string");
+                while flag {
+                    flag = Global();
+                }
+                return flag ? this.a : x;
+            }
+        }
+
+        func Global() -> Bool = true
+    "#;
+
+    let (mut pool, errs) = compiled(vec![sources]).unwrap();
+    let errs = errs.into_iter().filter(Diagnostic::is_fatal).collect_vec();
+    assert!(matches!(&errs[..], &[]));
+
+    let mut before = disassembled(&pool);
+    before.sort();
+    let renamed = pool.names.add("renamed".into());
+    let name = pool.names.get_index("name").unwrap();
+
+    // reverse the order of all definitions and fix up the references
+    let last = u32::from(pool.definitions().last().unwrap().0);
+    for i in 1..=last / 2 {
+        pool.swap_definition(PoolIndex::<Definition>::new(i), PoolIndex::new(last + 1 - i));
+    }
+    PoolMapper::default()
+        .with_class_mapper(reversed(last))
+        .with_function_mapper(reversed(last))
+        .with_field_mapper(reversed(last))
+        .with_type_mapper(reversed(last))
+        .with_local_mapper(reversed(last))
+        .with_parameter_mapper(reversed(last))
+        .with_enum_mapper(reversed(last))
+        .with_enum_value_mapper(reversed(last))
+        .with_source_file_mapper(reversed(last))
+        .with_name_mapper(MultiMapper::new([(name, renamed)].into_iter().collect()))
+        .map(&mut pool);
+
+    let bundle = ScriptBundle::load(&mut Cursor::new(encoded(pool))).unwrap();
+    let mut after = disassembled(&bundle.pool);
+    after.sort();
+    let expected = before
+        .iter()
+        .map(|str| str.replace(r#"name_const "name""#, r#"name_const "renamed""#))
+        .collect_vec();
+    assert_ne!(before, expected);
+    assert_eq!(after, expected);
+}
+
+fn reversed<A>(last: u32) -> MultiMapper<PoolIndex<A>> {
+    let mappings = (1..=last)
+        .map(|i| (PoolIndex::new(i), PoolIndex::new(last + 1 - i)))
+        .collect();
+    MultiMapper::new(mappings)
+}
+
+fn verify_errors(pool: &ConstantPool) -> Vec<String> {
+    verify::verify_pool(pool).iter().map(ToString::to_string).collect()
+}
+
+// the disassembly refers to entries by name, so it does not depend on their positions
+fn disassembled(pool: &ConstantPool) -> Vec<String> {
+    pool.definitions()
+        .filter_map(|(_, def)| match &def.value {
+            AnyDefinition::Function(fun) => {
+                let mut str = format!("{}:\n", pool.names.get(def.name).unwrap());
+                assembly::disassemble(&mut str, fun, pool).unwrap();
+                Some(str)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn encoded(pool: ConstantPool) -> Vec<u8> {
+    let mut bundle = ScriptBundle::load(&mut Cursor::new(utils::PREDEF)).unwrap();
+    bundle.pool = pool;
+    let mut output = Cursor::new(vec![]);
+    bundle.save(&mut output).unwrap();
+    output.into_inner()
+}
@@ -0,0 +1,474 @@
+use std::io::Cursor;
+
+use redscript::bundle::{ConstantPool, PoolIndex, ScriptBundle};
+use redscript::bytecode::{Code, Instr, Offset};
+use redscript::definition::{AnyDefinition, Function};
+use redscript::interpreter::{Interpreter, Natives, RuntimeError, Value};
+use redscript_compiler::parser;
+use redscript_compiler::source_map::Files;
+use redscript_compiler::unit::CompilationUnit;
+
+#[allow(unused)]
+mod utils;
+
+use utils::{compiled, PREDEF};
+
+const OPERATORS: &str = "
+    native func OperatorAdd(x: Int32, y: Int32) -> Int32
+    native func OperatorSubtract(x: Int32, y: Int32) -> Int32
+    native func OperatorLess(x: Int32, y: Int32) -> Bool
+    native func OperatorEqual(x: Int32, y: Int32) -> Bool
+    native func OperatorAssignAdd(out x: Int32, y: Int32) -> Int32
+    native func OperatorLogicAnd(x: Bool, y: Bool) -> Bool
+    native func OperatorLogicOr(x: Bool, y: Bool) -> Bool
+";
+
+fn run(source: &str, name: &str, args: &mut [Value]) -> Result<Value, RuntimeError> {
+    let (pool, _) = compiled(vec![OPERATORS, source]).unwrap();
+    run_in(&pool, name, args)
+}
+
+fn run_in(pool: &ConstantPool, name: &str, args: &mut [Value]) -> Result<Value, RuntimeError> {
+    let mut interpreter = Interpreter::new(pool, Natives::with_defaults());
+    let fun = interpreter.find_function(name).expect("Function not found in the pool");
+    interpreter.call(fun, None, args)
+}
+
+fn find_function(pool: &ConstantPool, name: &str) -> PoolIndex<Function> {
+    pool.definitions()
+        .find(|(_, def)| {
+            matches!(def.value, AnyDefinition::Function(_))
+                && pool.names.get(def.name).unwrap().split(';').next() == Some(name)
+        })
+        .expect("Function not found in the pool")
+        .0
+        .cast()
+}
+
+/// Replaces the code of a function, for instructions the compiler doesn't emit.
+fn patch_code<F>(pool: &mut ConstantPool, name: &str, patch: F)
+where
+    F: FnOnce(&Function) -> Vec<Instr<Offset>>,
+{
+    let fun = pool.function_mut(find_function(pool, name)).unwrap();
+    fun.code = Code::new(patch(fun));
+}
+
+#[test]
+fn run_loops_and_recursion() {
+    let source = "
+        func Fib(n: Int32) -> Int32 {
+            if n < 2 {
+                return n;
+            }
+            return Fib(n - 1) + Fib(n - 2);
+        }
+
+        func Test() -> Int32 {
+            let sum = 0;
+            let i = 0;
+            while i < 10 && sum < 1000 {
+                sum += Fib(i);
+                i += 1;
+            }
+            return sum;
+        }
+        ";
+
+    assert_eq!(run(source, "Test", &mut []).unwrap(), Value::I32(88));
+    assert_eq!(run(source, "Fib", &mut [Value::I32(12)]).unwrap(), Value::I32(144));
+}
+
+#[test]
+fn run_virtual_dispatch() {
+    let source = "
+        class A {
+            func Get() -> Int32 { return 1; }
+            func GetTwice() -> Int32 { return this.Get() + this.Get(); }
+        }
+
+        class B extends A {
+            func Get() -> Int32 { return 2; }
+        }
+
+        func Test() -> Int32 {
+            let a: ref<A> = new A();
+            let b: ref<A> = new B();
+            return a.GetTwice() + b.GetTwice();
+        }
+        ";
+
+    assert_eq!(run(source, "Test", &mut []).unwrap(), Value::I32(6));
+}
+
+#[test]
+fn run_struct_and_array_updates() {
+    let source = "
+        struct Point {
+            let x: Int32;
+            let y: Int32;
+        }
+
+        func Test() -> Int32 {
+            let points: array<Point>;
+            ArrayPush(points, new Point(1, 2));
+            ArrayPush(points, new Point(3, 4));
+            points[1].x = 10;
+            let total = 0;
+            for point in points {
+                total += point.x + point.y;
+            }
+            return total + ArraySize(points);
+        }
+        ";
+
+    assert_eq!(run(source, "Test", &mut []).unwrap(), Value::I32(19));
+}
+
+#[test]
+fn run_out_params_and_switch() {
+    let source = "
+        func Set(out x: Int32) {
+            x = 42;
+        }
+
+        func Test() -> Int32 {
+            let v: Int32;
+            Set(v);
+            switch v {
+                case 1:
+                case 42:
+                    return 1;
+                default:
+                    return 0;
+            }
+        }
+        ";
+
+    assert_eq!(run(source, "Test", &mut []).unwrap(), Value::I32(1));
+
+    let mut args = [Value::I32(0)];
+    run(source, "Set", &mut args).unwrap();
+    assert_eq!(args[0], Value::I32(42));
+}
+
+#[test]
+fn report_missing_native() {
+    let source = "
+        native func Unknown() -> Int32
+
+        func Test() -> Int32 {
+            return Unknown();
+        }
+        ";
+
+    assert!(matches!(
+        run(source, "Test", &mut []),
+        Err(RuntimeError::NativeNotFound(name)) if name.starts_with("Unknown")
+    ));
+}
+
+#[test]
+fn run_lambdas_with_captures() {
+    let source = "
+        class Counter {
+            let step: Int32;
+            let onChange: (Int32) -> Int32;
+
+            func Update(value: Int32) -> Int32 {
+                return this.onChange(value);
+            }
+        }
+
+        func Apply(f: (Int32, Int32) -> Int32, x: Int32, y: Int32) -> Int32 {
+            return f(x, y);
+        }
+
+        func Test() -> Int32 {
+            let offset = 10;
+            let add = (x: Int32, y: Int32) -> x + y + offset;
+            offset = 100;
+
+            let counter = new Counter();
+            counter.step = 5;
+            counter.onChange = (value) -> {
+                let result = value + counter.step;
+                return result;
+            };
+            return Apply(add, 1, 2) + Apply((x, y) -> x - y, 10, 4) + counter.Update(1);
+        }
+        ";
+
+    assert_eq!(run(source, "Test", &mut []).unwrap(), Value::I32(25));
+}
+
+#[test]
+fn run_generic_instances() {
+    let source = "
+        class Node<T> {
+            let value: T;
+            let next: ref<Node<T>>;
+        }
+
+        func Push<T>(head: ref<Node<T>>, value: T) -> ref<Node<T>> {
+            let node = new Node<T>();
+            node.value = value;
+            node.next = head;
+            return node;
+        }
+
+        func Fold<T, R>(node: ref<Node<T>>, init: R, f: (R, T) -> R) -> R {
+            let acc = init;
+            while IsDefined(node) {
+                acc = f(acc, node.value);
+                node = node.next;
+            }
+            return acc;
+        }
+
+        func Test() -> Int32 {
+            let list = Push(Push(Push(null, 1), 2), 3);
+            return Fold(list, 0, (acc, x) -> acc + x) + Fold(list, 10, (acc, x) -> acc - x);
+        }
+        ";
+
+    assert_eq!(run(source, "Test", &mut []).unwrap(), Value::I32(10));
+}
+
+#[test]
+fn run_generic_methods() {
+    let source = "
+        class Counter {
+            func Get() -> Int32 {
+                return 1;
+            }
+        }
+
+        class Box<T> extends Counter {
+            let value: T;
+
+            func Get() -> Int32 {
+                return 10;
+            }
+
+            func Map<R>(f: (T) -> R) -> ref<Box<R>> {
+                let box = new Box<R>();
+                box.value = f(this.value);
+                return box;
+            }
+        }
+
+        class Math {
+            static func Twice<T>(value: T, f: (T, T) -> T) -> T {
+                return f(value, value);
+            }
+        }
+
+        func Count(counter: ref<Counter>) -> Int32 {
+            return counter.Get();
+        }
+
+        func Test() -> Int32 {
+            let box = new Box<Int32>();
+            box.value = 4;
+            let mapped = box.Map((x) -> x + 1).Map((x) -> Math.Twice(x, (a, b) -> a + b));
+            return mapped.value + Count(mapped);
+        }
+        ";
+
+    assert_eq!(run(source, "Test", &mut []).unwrap(), Value::I32(20));
+}
+
+#[test]
+fn run_loops_with_continue() {
+    let source = "
+        func Test() -> Int32 {
+            let values = [1, 2, 3, 4, 5];
+            let sum = 0;
+            for value in values {
+                if value == 2 {
+                    continue;
+                }
+                let i = 0;
+                while i < value {
+                    i += 1;
+                    if i == 3 {
+                        continue;
+                    }
+                    sum += 1;
+                }
+            }
+            return sum;
+        }
+        ";
+
+    assert_eq!(run(source, "Test", &mut []).unwrap(), Value::I32(10));
+}
+
+#[test]
+fn run_short_circuit_operators() {
+    let source = "
+        native func Fail() -> Bool
+
+        func And(x: Bool) -> Bool {
+            return x && Fail();
+        }
+
+        func Or(x: Bool) -> Bool {
+            return x || Fail();
+        }
+        ";
+
+    // the game marks the right operands of the logic operators as short-circuit parameters
+    let mut scripts = ScriptBundle::load(&mut Cursor::new(PREDEF)).unwrap();
+    CompilationUnit::new_with_defaults(&mut scripts.pool)
+        .unwrap()
+        .compile(vec![parser::parse_str(OPERATORS).unwrap()], &Files::default())
+        .unwrap();
+    for (_, def) in scripts.pool.clone().definitions() {
+        let name = scripts.pool.names.get(def.name).unwrap();
+        if let AnyDefinition::Function(fun) = &def.value {
+            if name.starts_with("OperatorLogic") {
+                let mut param = scripts.pool.definition(fun.parameters[1]).unwrap().clone();
+                if let AnyDefinition::Parameter(param) = &mut param.value {
+                    param.flags.set_is_short_circuit(true);
+                }
+                scripts.pool.put_definition(fun.parameters[1], param);
+            }
+        }
+    }
+    CompilationUnit::new_with_defaults(&mut scripts.pool)
+        .unwrap()
+        .compile(vec![parser::parse_str(source).unwrap()], &Files::default())
+        .unwrap();
+    let pool = &scripts.pool;
+
+    assert_eq!(
+        run_in(pool, "And", &mut [Value::Bool(false)]).unwrap(),
+        Value::Bool(false)
+    );
+    assert_eq!(run_in(pool, "Or", &mut [Value::Bool(true)]).unwrap(), Value::Bool(true));
+    assert!(matches!(
+        run_in(pool, "And", &mut [Value::Bool(true)]),
+        Err(RuntimeError::NativeNotFound(name)) if name.starts_with("Fail")
+    ));
+    assert!(matches!(
+        run_in(pool, "Or", &mut [Value::Bool(false)]),
+        Err(RuntimeError::NativeNotFound(name)) if name.starts_with("Fail")
+    ));
+}
+
+#[test]
+fn run_array_sort_by_predicate() {
+    let source = "
+        func Less(x: Int32, y: Int32) -> Bool {
+            return x < y;
+        }
+
+        func SortByName(items: array<Int32>, predicate: CName) -> array<Int32> {
+            return items;
+        }
+
+        func SortByLambda(items: array<Int32>, predicate: (Int32, Int32) -> Bool) -> array<Int32> {
+            return items;
+        }
+
+        func TestName() -> array<Int32> {
+            return SortByName([3, 1, 2, 1], n\"Less\");
+        }
+
+        func TestLambda() -> array<Int32> {
+            return SortByLambda([3, 1, 2, 1], (x, y) -> y < x);
+        }
+        ";
+
+    let (mut pool, _) = compiled(vec![OPERATORS, source]).unwrap();
+    for name in ["SortByName", "SortByLambda"] {
+        let array_type = pool.function(find_function(&pool, name)).unwrap().parameters[0];
+        let array_type = pool.parameter(array_type).unwrap().type_;
+        patch_code(&mut pool, name, |fun| {
+            let [items, predicate] = fun.parameters[..] else {
+                panic!("Unexpected parameters")
+            };
+            vec![
+                Instr::ArraySortByPredicate(array_type),
+                Instr::Param(items),
+                Instr::Param(predicate),
+                Instr::Return,
+                Instr::Param(items),
+            ]
+        });
+    }
+
+    let ints = |items: &[i32]| Value::Array(items.iter().copied().map(Value::I32).collect());
+    assert_eq!(run_in(&pool, "TestName", &mut []).unwrap(), ints(&[1, 1, 2, 3]));
+    assert_eq!(run_in(&pool, "TestLambda", &mut []).unwrap(), ints(&[3, 2, 1, 1]));
+}
+
+#[test]
+fn run_external_var() {
+    let source = "
+        class Box {
+            let value: Int32;
+
+            func Get() -> Int32 {
+                return this.value;
+            }
+        }
+
+        func Test() -> Int32 {
+            let box = new Box();
+            box.value = 7;
+            return box.Get();
+        }
+        ";
+
+    let (mut pool, _) = compiled(vec![OPERATORS, source]).unwrap();
+    patch_code(&mut pool, "Get", |fun| {
+        assert!(fun.code.iter().any(|(_, instr)| instr == Instr::This));
+        fun.code
+            .iter()
+            .map(|(_, instr)| match instr {
+                Instr::This => Instr::ExternalVar,
+                other => other,
+            })
+            .collect()
+    });
+
+    assert_eq!(run_in(&pool, "Test", &mut []).unwrap(), Value::I32(7));
+}
+
+#[test]
+fn run_wrapping_arithmetic() {
+    let source = "
+        native func OperatorMultiply(x: Uint64, y: Uint64) -> Uint64
+        native func OperatorSubtract(x: Int64, y: Int64) -> Int64
+        native func OperatorDivide(x: Int64, y: Int64) -> Int64
+
+        func Square(x: Uint64) -> Uint64 {
+            return x * x;
+        }
+
+        func Decrement(x: Int64) -> Int64 {
+            return x - 1l;
+        }
+
+        func Divide(x: Int64, y: Int64) -> Int64 {
+            return x / y;
+        }
+        ";
+
+    let (pool, _) = compiled(vec![OPERATORS, source]).unwrap();
+    assert_eq!(
+        run_in(&pool, "Square", &mut [Value::U64(u64::MAX)]).unwrap(),
+        Value::U64(1)
+    );
+    assert_eq!(
+        run_in(&pool, "Decrement", &mut [Value::I64(i64::MIN)]).unwrap(),
+        Value::I64(i64::MAX)
+    );
+    assert!(matches!(
+        run_in(&pool, "Divide", &mut [Value::I64(1), Value::I64(0)]),
+        Err(RuntimeError::ArithmeticError)
+    ));
+}
@@ -1,4 +1,4 @@
-use std::io::Cursor;
+use std::io::{self, Cursor};
 
 use hashbrown::HashMap;
 use redscript::bundle::{ConstantPool, PoolIndex, ScriptBundle};
@@ -20,7 +20,14 @@ pub struct TestContext {
 
 impl TestContext {
     pub fn compiled(sources: Vec<&str>) -> Result<Self, Error> {
-        let (pool, diagnostics) = compiled(sources)?;
+        Self::new(compiled(sources)?)
+    }
+
+    pub fn optimized(sources: Vec<&str>) -> Result<Self, Error> {
+        Self::new(compiled_with(sources, true, 1)?)
+    }
+
+    fn new((pool, diagnostics): (ConstantPool, Vec<Diagnostic>)) -> Result<Self, Error> {
         assert!(
             !diagnostics.iter().any(Diagnostic::is_fatal),
             "Fatal errors: {:?}",
@@ -118,12 +125,23 @@ macro_rules! check_code {
 }
 
 pub fn compiled(sources: Vec<&str>) -> Result<(ConstantPool, Vec<Diagnostic>), Error> {
+    compiled_with(sources, false, 1)
+}
+
+pub fn compiled_with(
+    sources: Vec<&str>,
+    optimize: bool,
+    threads: usize,
+) -> Result<(ConstantPool, Vec<Diagnostic>), Error> {
     let modules = sources
         .iter()
         .map(|source| parser::parse_str(source).unwrap())
         .collect();
-    let mut scripts = ScriptBundle::load(&mut Cursor::new(PREDEF))?;
-    let res = CompilationUnit::new_with_defaults(&mut scripts.pool)?.compile(modules, &Files::default())?;
+    let mut scripts = ScriptBundle::load(&mut Cursor::new(PREDEF)).map_err(io::Error::from)?;
+    let res = CompilationUnit::new_with_defaults(&mut scripts.pool)?
+        .with_optimizations(optimize)
+        .with_threads(threads)
+        .compile(modules, &Files::default())?;
 
     Ok((scripts.pool, res.into_diagnostics()))
 }
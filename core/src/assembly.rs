@@ -0,0 +1,447 @@
+//! A textual assembly language for function bodies.
+//!
+//! Instructions use the mnemonics of the [text format](crate::text), but jump targets are symbolic
+//! labels and pool entries are referred to by name:
+//!
+//! ```text
+//! L0:
+//!     jump_if_false L1
+//!     local counter
+//!     invoke_static L1 12 Math.Abs;Int32 0
+//!     ...
+//! L1:
+//!     name_const "Default"
+//! ```
+//!
+//! Classes, enums, types and global functions are referred to by their name, methods, fields and enum
+//! members are qualified with the name of their owner (`Class.Method;Sig`, `Class.field`, `Enum.Member`),
+//! and locals and parameters by their short name within the function being assembled. Names, strings,
+//! TweakDB IDs and resources are written as string literals and are added to the pool when missing.
+//! Any entry can also be referred to by its index, e.g. `#42`.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Write};
+
+use thiserror::Error;
+
+use crate::bundle::{ConstantPool, DefaultString, DefinitionType, PoolError, PoolIndex, Strings};
+use crate::bytecode::{Code, ControlFlowGraph, EdgeKind, Instr, Label, Offset, PoolRef};
+use crate::definition::{AnyDefinition, Definition, Function, Local, Parameter};
+use crate::text::{parse_instr, write_instr, ParseError, Parser, PrintOperand, Printer, ReadOperand, Reader};
+use crate::Ref;
+
+/// Writes the code of a function, with a line per instruction and label.
+///
+/// References that cannot be written as an unambiguous name are written as pool indices,
+/// so that the output can always be assembled back into the same code.
+pub fn disassemble<W: fmt::Write>(out: &mut W, fun: &Function, pool: &ConstantPool) -> fmt::Result {
+    let labels = code_labels(fun);
+    let mut printer = Disassembler {
+        pool,
+        fun,
+        labels: &labels,
+        position: 0,
+    };
+    for (location, instr) in fun.code.iter() {
+        if let Some(label) = labels.get(&i32::from(location.value)) {
+            writeln!(out, "L{label}:")?;
+        }
+        printer.position = location.value;
+        write!(out, "    ")?;
+        write_instr(out, &instr, &printer)?;
+        writeln!(out)?;
+    }
+    let end = fun
+        .code
+        .iter()
+        .last()
+        .map_or(0, |(loc, instr)| i32::from(loc.value) + i32::from(instr.size()));
+    if let Some(label) = labels.get(&end) {
+        writeln!(out, "L{label}:")?;
+    }
+    Ok(())
+}
+
+/// Writes the control-flow graph of a function in the Graphviz DOT format. Blocks are labeled with
+/// their instructions written like in [`disassemble`], edges with the way control is transferred.
+/// The graph is expected to be built from the code of the function.
+pub fn write_dot<W: fmt::Write>(
+    out: &mut W,
+    name: &str,
+    fun: &Function,
+    graph: &ControlFlowGraph,
+    pool: &ConstantPool,
+) -> fmt::Result {
+    let labels = code_labels(fun);
+    let mut printer = Disassembler {
+        pool,
+        fun,
+        labels: &labels,
+        position: 0,
+    };
+    let instrs: Vec<_> = fun.code.iter().collect();
+
+    writeln!(out, "digraph \"{}\" {{", dot_escape(name))?;
+    writeln!(out, "    node [shape=box, fontname=monospace];")?;
+    for (n, block) in graph.blocks().iter().enumerate() {
+        let mut text = String::new();
+        if let Some(label) = labels.get(&i32::from(block.start.value)) {
+            writeln!(text, "L{label}:")?;
+        }
+        for (location, instr) in &instrs[block.instructions.clone()] {
+            printer.position = location.value;
+            text.push_str("    ");
+            write_instr(&mut text, instr, &printer)?;
+            text.push('\n');
+        }
+        // the escaped line breaks align each line to the left
+        writeln!(out, "    b{n} [label=\"{}\"];", dot_escape(&text).replace('\n', "\\l"))?;
+    }
+    for (n, block) in graph.blocks().iter().enumerate() {
+        for edge in &block.successors {
+            match edge.kind {
+                EdgeKind::Next => writeln!(out, "    b{n} -> b{};", edge.target)?,
+                kind => writeln!(out, "    b{n} -> b{} [label=\"{}\"];", edge.target, kind.name())?,
+            }
+        }
+    }
+    writeln!(out, "}}")
+}
+
+/// Assembles the code for a function of the pool. The result can be injected into the function
+/// by assigning it to [`Function::code`].
+pub fn assemble(
+    input: &str,
+    function: PoolIndex<Function>,
+    pool: &mut ConstantPool,
+) -> Result<Code<Offset>, AssemblyError> {
+    let mut parser = Parser::new(input)?;
+    let mut assembler = Assembler::new(function, pool)?;
+
+    let mut code = vec![];
+    let mut size = 0u32;
+    while !parser.is_at_end() {
+        let instr = if let Some(name) = parser.eat_label() {
+            Instr::Target(assembler.label(name, parser.line()))
+        } else {
+            let mnemonic = parser.word()?;
+            parse_instr(&mut parser, mnemonic, &mut assembler)?
+        };
+        if let Instr::Target(label) = instr {
+            if !assembler.placed.insert(label.index) {
+                return Err(parser.error("the label is placed more than once").into());
+            }
+        }
+        size += u32::from(instr.size());
+        if size > u32::from(u16::MAX) {
+            return Err(parser.error("the code is too large").into());
+        }
+        code.push(instr);
+    }
+
+    let mut unplaced = assembler
+        .labels
+        .iter()
+        .filter(|(_, (label, _))| !assembler.placed.contains(&label.index));
+    if let Some((name, &(_, line))) = unplaced.next() {
+        let message = format!("label '{name}' is never placed");
+        return Err(ParseError { line, message }.into());
+    }
+    Ok(Code::new(code).resolve_labels(assembler.labels.len()))
+}
+
+/// An error produced when the code cannot be assembled.
+#[derive(Debug, Error)]
+pub enum AssemblyError {
+    #[error("{0}")]
+    Parse(#[from] ParseError),
+    #[error("{0}")]
+    Pool(#[from] PoolError),
+}
+
+struct Disassembler<'a> {
+    pool: &'a ConstantPool,
+    fun: &'a Function,
+    labels: &'a HashMap<i32, usize>,
+    position: u16,
+}
+
+impl Disassembler<'_> {
+    fn name(&self, ref_: PoolRef) -> Option<String> {
+        let pool = self.pool;
+        let name = match ref_ {
+            PoolRef::Name(idx) => string_literal(&pool.names, idx)?,
+            PoolRef::String(idx) => string_literal(&pool.strings, idx)?,
+            PoolRef::TweakDbId(idx) => string_literal(&pool.tweakdb_ids, idx)?,
+            PoolRef::Resource(idx) => string_literal(&pool.resources, idx)?,
+            PoolRef::Local(idx) => self.local_name(idx, &self.fun.locals)?,
+            PoolRef::Param(idx) => self.local_name(idx, &self.fun.parameters)?,
+            PoolRef::Enum(_) | PoolRef::Type(_) | PoolRef::Class(_) => self.root_name(ref_.index())?,
+            PoolRef::Function(idx) => {
+                let def = pool.definition(idx).ok()?;
+                if def.parent.is_undefined() {
+                    self.root_name(idx.cast())?
+                } else {
+                    let class = pool.class(def.parent.cast()).ok()?;
+                    self.member_name(idx.cast(), &class.functions)?
+                }
+            }
+            PoolRef::Field(idx) => {
+                let class = pool.class(pool.definition(idx).ok()?.parent.cast()).ok()?;
+                self.member_name(idx.cast(), &class.fields)?
+            }
+            PoolRef::EnumValue(idx) => {
+                let enum_ = pool.enum_(pool.definition(idx).ok()?.parent.cast()).ok()?;
+                self.member_name(idx.cast(), &enum_.members)?
+            }
+        };
+        Some(name)
+    }
+
+    // root names are expected to be unique for each kind of definition
+    fn root_name(&self, index: PoolIndex<Definition>) -> Option<String> {
+        let name = self.pool.def_name(index).ok()?;
+        is_symbol(&name).then(|| name.to_string())
+    }
+
+    fn member_name<A>(&self, index: PoolIndex<Definition>, siblings: &[PoolIndex<A>]) -> Option<String> {
+        let parent = self.root_name(self.pool.definition(index).ok()?.parent)?;
+        let name = self.unique_name(index, siblings)?;
+        Some(format!("{parent}.{name}"))
+    }
+
+    fn local_name<A>(&self, index: PoolIndex<A>, locals: &[PoolIndex<A>]) -> Option<String> {
+        self.unique_name(index.cast(), locals).map(|name| name.to_string())
+    }
+
+    fn unique_name<A>(&self, index: PoolIndex<Definition>, siblings: &[PoolIndex<A>]) -> Option<Ref<str>> {
+        let name = self.pool.def_name(index).ok()?;
+        let mut matching = siblings
+            .iter()
+            .filter(|&&idx| self.pool.def_name(idx).map_or(false, |other| other == name));
+        let is_unique = matching.next()?.cast() == index && matching.next().is_none();
+        (is_unique && is_symbol(&name)).then_some(name)
+    }
+}
+
+impl Printer for Disassembler<'_> {
+    type Loc = Offset;
+
+    fn print_ref<W: fmt::Write>(&self, out: &mut W, ref_: PoolRef) -> fmt::Result {
+        match self.name(ref_) {
+            Some(name) => out.write_str(&name),
+            None => write!(out, "#{}", ref_.index::<()>()),
+        }
+    }
+}
+
+impl PrintOperand<Disassembler<'_>> for Offset {
+    fn print<W: fmt::Write>(&self, out: &mut W, printer: &Disassembler<'_>) -> fmt::Result {
+        let target = i32::from(printer.position) + i32::from(self.value);
+        write!(out, "L{}", printer.labels[&target])
+    }
+}
+
+struct Assembler<'a> {
+    pool: &'a mut ConstantPool,
+    roots: HashMap<(DefinitionType, Ref<str>), Option<PoolIndex<Definition>>>,
+    locals: Vec<PoolIndex<Local>>,
+    parameters: Vec<PoolIndex<Parameter>>,
+    /// Labels by name, along with the line where they first appear.
+    labels: HashMap<String, (Label, usize)>,
+    placed: HashSet<usize>,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(function: PoolIndex<Function>, pool: &'a mut ConstantPool) -> Result<Self, PoolError> {
+        let fun = pool.function(function)?;
+        let locals = fun.locals.clone();
+        let parameters = fun.parameters.clone();
+
+        // ambiguous names are kept so that they can be reported
+        let mut roots = HashMap::new();
+        for (idx, def) in pool.roots() {
+            if let Ok(name) = pool.names.get(def.name) {
+                roots
+                    .entry((def.value.type_(), name))
+                    .and_modify(|entry| *entry = None)
+                    .or_insert(Some(idx));
+            }
+        }
+
+        Ok(Self {
+            pool,
+            roots,
+            locals,
+            parameters,
+            labels: HashMap::new(),
+            placed: HashSet::new(),
+        })
+    }
+
+    fn label(&mut self, name: &str, line: usize) -> Label {
+        let index = self.labels.len();
+        self.labels.entry(name.to_owned()).or_insert((Label { index }, line)).0
+    }
+
+    fn resolve(&self, kind: PoolRef, name: &str) -> Result<PoolIndex<Definition>, String> {
+        let (type_, root, member) = match kind {
+            PoolRef::Class(_) => (DefinitionType::Class, name, None),
+            PoolRef::Enum(_) => (DefinitionType::Enum, name, None),
+            PoolRef::Type(_) => (DefinitionType::Type, name, None),
+            PoolRef::Function(_) => match name.split_once('.') {
+                Some((class, method)) => (DefinitionType::Class, class, Some(method)),
+                None => (DefinitionType::Function, name, None),
+            },
+            PoolRef::Field(_) | PoolRef::EnumValue(_) => {
+                let (owner, member) = name
+                    .split_once('.')
+                    .ok_or_else(|| format!("expected a qualified {} name, found '{name}'", kind_name(kind)))?;
+                let type_ = if matches!(kind, PoolRef::Field(_)) {
+                    DefinitionType::Class
+                } else {
+                    DefinitionType::Enum
+                };
+                (type_, owner, Some(member))
+            }
+            _ => unreachable!("{} is not a definition", kind_name(kind)),
+        };
+
+        let index = match self.roots.get(&(type_, Ref::from(root))) {
+            Some(Some(index)) => *index,
+            Some(None) => return Err(format!("'{root}' is ambiguous")),
+            None if member.is_some() => return Err(format!("unknown owner '{root}'")),
+            None => return Err(format!("unknown {} '{root}'", kind_name(kind))),
+        };
+        let Some(member) = member else {
+            return Ok(index);
+        };
+
+        let members: Vec<PoolIndex<Definition>> =
+            match (&self.pool.definition(index).map_err(|err| err.to_string())?.value, kind) {
+                (AnyDefinition::Class(class), PoolRef::Function(_)) => {
+                    class.functions.iter().map(PoolIndex::cast).collect()
+                }
+                (AnyDefinition::Class(class), _) => class.fields.iter().map(PoolIndex::cast).collect(),
+                (AnyDefinition::Enum(enum_), _) => enum_.members.iter().map(PoolIndex::cast).collect(),
+                _ => vec![],
+            };
+        self.find_by_name(&members, member)
+            .map_err(|err| format!("{err} in '{root}'"))
+    }
+
+    fn find_by_name<A>(&self, candidates: &[PoolIndex<A>], name: &str) -> Result<PoolIndex<Definition>, String> {
+        let mut matching = candidates
+            .iter()
+            .filter(|&&idx| self.pool.def_name(idx).map_or(false, |other| &*other == name));
+        match (matching.next(), matching.next()) {
+            (Some(idx), None) => Ok(idx.cast()),
+            (Some(_), Some(_)) => Err(format!("'{name}' is ambiguous")),
+            (None, _) => Err(format!("'{name}' not found")),
+        }
+    }
+}
+
+impl Reader for Assembler<'_> {
+    type Loc = Label;
+
+    fn read_ref(&mut self, parser: &mut Parser<'_>, kind: PoolRef) -> Result<PoolRef, ParseError> {
+        if parser.peek_word().map_or(false, |word| word.starts_with('#')) {
+            return Ok(kind.with_index::<()>(parser.index()?));
+        }
+        let ref_ = match kind {
+            PoolRef::Name(_) => PoolRef::Name(self.pool.names.add(parser.string()?.into())),
+            PoolRef::String(_) => PoolRef::String(self.pool.strings.add(parser.string()?.into())),
+            PoolRef::TweakDbId(_) => PoolRef::TweakDbId(self.pool.tweakdb_ids.add(parser.string()?.into())),
+            PoolRef::Resource(_) => PoolRef::Resource(self.pool.resources.add(parser.string()?.into())),
+            PoolRef::Local(_) => {
+                let name = parser.word()?;
+                let index = self.find_by_name(&self.locals, name);
+                PoolRef::Local(index.map_err(|err| parser.error(format!("local {err}")))?.cast())
+            }
+            PoolRef::Param(_) => {
+                let name = parser.word()?;
+                let index = self.find_by_name(&self.parameters, name);
+                PoolRef::Param(index.map_err(|err| parser.error(format!("parameter {err}")))?.cast())
+            }
+            _ => {
+                let name = parser.word()?;
+                kind.with_index(self.resolve(kind, name).map_err(|err| parser.error(err))?)
+            }
+        };
+        Ok(ref_)
+    }
+}
+
+impl ReadOperand<Assembler<'_>> for Label {
+    fn read(parser: &mut Parser<'_>, reader: &mut Assembler<'_>) -> Result<Self, ParseError> {
+        let name = parser.word()?;
+        Ok(reader.label(name, parser.line()))
+    }
+}
+
+// labels are numbered in the order of their locations
+fn code_labels(fun: &Function) -> HashMap<i32, usize> {
+    let mut targets = BTreeSet::new();
+    for (location, instr) in fun.code.iter() {
+        for offset in jump_targets(&instr) {
+            targets.insert(i32::from(location.value) + i32::from(offset.value));
+        }
+    }
+    targets.into_iter().enumerate().map(|(i, target)| (target, i)).collect()
+}
+
+fn dot_escape(str: &str) -> String {
+    str.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn jump_targets(instr: &Instr<Offset>) -> Vec<Offset> {
+    match *instr {
+        Instr::Target(offset)
+        | Instr::Switch(_, offset)
+        | Instr::Jump(offset)
+        | Instr::JumpIfFalse(offset)
+        | Instr::Skip(offset)
+        | Instr::InvokeStatic(offset, _, _, _)
+        | Instr::InvokeVirtual(offset, _, _, _)
+        | Instr::Context(offset) => vec![offset],
+        Instr::SwitchLabel(first, second) | Instr::Conditional(first, second) => vec![first, second],
+        _ => vec![],
+    }
+}
+
+// strings are only written as literals if they read back to the same index
+fn string_literal<K: DefaultString>(strings: &Strings<K>, index: PoolIndex<K>) -> Option<String> {
+    let str = strings.get_ref(index).ok()?;
+    let resolved = if K::DEFAULT == Some(str) {
+        Some(PoolIndex::UNDEFINED)
+    } else {
+        strings.get_index(str)
+    };
+    (resolved == Some(index)).then(|| format!("{str:?}"))
+}
+
+// names that cannot be read back as a single word are written as indices
+fn is_symbol(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('#')
+        && !name.ends_with(':')
+        && !name.contains("//")
+        && !name.contains(|c: char| c.is_whitespace() || "\"{}[]=.".contains(c))
+}
+
+fn kind_name(kind: PoolRef) -> &'static str {
+    match kind {
+        PoolRef::Name(_) => "name",
+        PoolRef::String(_) => "string",
+        PoolRef::TweakDbId(_) => "TweakDB ID",
+        PoolRef::Resource(_) => "resource",
+        PoolRef::Enum(_) => "enum",
+        PoolRef::EnumValue(_) => "enum member",
+        PoolRef::Local(_) => "local",
+        PoolRef::Param(_) => "parameter",
+        PoolRef::Field(_) => "field",
+        PoolRef::Type(_) => "type",
+        PoolRef::Class(_) => "class",
+        PoolRef::Function(_) => "function",
+    }
+}
@@ -20,6 +20,7 @@ where
     Name::Function: fmt::Debug,
     Name::Member: fmt::Debug,
     Name::Type: fmt::Debug,
+    Name::Lambda: fmt::Debug,
 {
     Ident(Name::Reference, Span),
     Constant(Constant, Span),
@@ -43,9 +44,11 @@ where
     ForIn(Name::Local, Box<Self>, Seq<Name>, Span),
     BinOp(Box<Self>, Box<Self>, BinOp, Span),
     UnOp(Box<Self>, UnOp, Span),
+    Lambda(Name::Lambda, Box<Self>, Span),
     This(Span),
     Super(Span),
     Break(Span),
+    Continue(Span),
     Null(Span),
 }
 
@@ -56,6 +59,7 @@ pub trait NameKind {
     type Function;
     type Member;
     type Type;
+    type Lambda;
 }
 
 #[derive(Debug)]
@@ -64,6 +68,7 @@ pub struct SourceAst;
 impl NameKind for SourceAst {
     type Callable = Ident;
     type Function = Ident;
+    type Lambda = Box<[LambdaParam]>;
     type Local = Ident;
     type Member = Ident;
     type Reference = Ident;
@@ -79,6 +84,7 @@ where
     N::Function: fmt::Debug,
     N::Member: fmt::Debug,
     N::Type: fmt::Debug,
+    N::Lambda: fmt::Debug,
 {
     pub const EMPTY: Self = Expr::Seq(Seq { exprs: vec![] });
 
@@ -112,9 +118,11 @@ where
             | Expr::ForIn(_, _, _, span)
             | Expr::BinOp(_, _, _, span)
             | Expr::UnOp(_, _, span)
+            | Expr::Lambda(_, _, span)
             | Expr::This(span)
             | Expr::Super(span)
             | Expr::Break(span)
+            | Expr::Continue(span)
             | Expr::Null(span) => *span,
             Expr::Seq(seq) => {
                 let start = seq.exprs.first().map(Self::span).unwrap_or_default();
@@ -250,6 +258,12 @@ pub enum UnOp {
     Neg,
 }
 
+#[derive(Debug)]
+pub struct LambdaParam {
+    pub name: Ident,
+    pub type_: Option<TypeName>,
+}
+
 #[derive(Debug)]
 pub struct SwitchCase<N>
 where
@@ -260,6 +274,7 @@ where
     N::Function: fmt::Debug,
     N::Member: fmt::Debug,
     N::Type: fmt::Debug,
+    N::Lambda: fmt::Debug,
 {
     pub matcher: Expr<N>,
     pub body: Seq<N>,
@@ -275,6 +290,7 @@ where
     N::Function: fmt::Debug,
     N::Member: fmt::Debug,
     N::Type: fmt::Debug,
+    N::Lambda: fmt::Debug,
 {
     pub exprs: Vec<Expr<N>>,
 }
@@ -288,6 +304,7 @@ where
     N::Function: fmt::Debug,
     N::Member: fmt::Debug,
     N::Type: fmt::Debug,
+    N::Lambda: fmt::Debug,
 {
     pub fn new(exprs: Vec<Expr<N>>) -> Seq<N> {
         Seq { exprs }
@@ -422,6 +439,15 @@ impl TypeName {
         }
     }
 
+    /// Creates a function type, represented as `func` applied to the parameter types followed by the return type.
+    pub fn function(mut params: Vec<TypeName>, return_type: TypeName) -> Self {
+        params.push(return_type);
+        TypeName::Named {
+            name: Ident::from_static("func"),
+            args: Some(params.into()),
+        }
+    }
+
     #[inline]
     pub const fn basic(name: &'static str) -> Self {
         TypeName::Named {
@@ -459,6 +485,7 @@ impl TypeName {
                 "wref" => Kind::WRef,
                 "script_ref" => Kind::ScriptRef,
                 "array" => Kind::Array,
+                "func" => Kind::Function,
                 _ => Kind::Prim,
             },
             TypeName::Array(_) => Kind::Array,
@@ -488,6 +515,11 @@ impl TypeName {
 
     pub fn pretty(&self) -> Ident {
         match self {
+            Self::Named { args: Some(args), .. } if matches!(self.kind(), Kind::Function) => {
+                let (ret, params) = args.split_last().unwrap();
+                let params = params.iter().map(Self::pretty).format(", ");
+                str_fmt!("({}) -> {}", params, ret.pretty())
+            }
             Self::Named { name, args } => match args {
                 Some(args) => {
                     let args = args.iter().map(Self::pretty).format(",");
@@ -553,6 +585,7 @@ pub enum Kind {
     ScriptRef,
     Array,
     StaticArray(u32),
+    Function,
 }
 
 #[derive(Debug, Clone, Copy, EnumString, Display, IntoStaticStr)]
@@ -1,14 +1,14 @@
 use std::hash::Hash;
 use std::io::Seek;
 use std::marker::PhantomData;
-use std::{fmt, io};
+use std::{fmt, io, mem};
 
 use hashbrown::hash_map::{self, HashMap};
 use itertools::chain;
 use modular_bitfield::prelude::*;
 use thiserror::Error;
 
-use crate::decode::{Decode, DecodeExt};
+use crate::decode::{Decode, DecodeError, DecodeExt, InvalidValue, Table, MAX_PREALLOCATED};
 use crate::definition::{AnyDefinition, Class, Definition, Enum, Field, Function, Local, Parameter, Type};
 use crate::encode::{Encode, EncodeExt};
 use crate::io::StreamOffset;
@@ -16,36 +16,105 @@ use crate::Ref;
 
 #[derive(Debug)]
 pub struct ScriptBundle {
-    header: Header,
+    pub(crate) header: Header,
     pub pool: ConstantPool,
 }
 
 impl ScriptBundle {
-    pub fn load<I: io::Read + io::Seek>(input: &mut I) -> io::Result<Self> {
-        let header: Header = input.decode()?;
-        let pool = ConstantPool::decode(input, &header)?;
+    /// Loads a bundle, the input is expected to start at the beginning of the bundle.
+    pub fn load<I: io::Read + io::Seek>(input: &mut I) -> Result<Self, DecodeError> {
+        let mut input =
+            StreamOffset::new_seekable(input).map_err(|err| DecodeError::new(Table::Header, None, 0, err))?;
+        let header: Header = decode_entry(&mut input, Table::Header, None, DecodeExt::decode)?;
+        let pool = ConstantPool::decode(&mut input, &header)?;
         let cache = ScriptBundle { header, pool };
         Ok(cache)
     }
 
+    /// Saves the bundle in the version it was loaded from.
     pub fn save<O: io::Write + io::Seek>(&self, output: &mut O) -> io::Result<()> {
+        self.save_as(output, self.header.version)
+    }
+
+    /// Saves the bundle with the given version recorded in the header.
+    pub fn save_as<O: io::Write + io::Seek>(&self, output: &mut O, version: Version) -> io::Result<()> {
         output.seek(io::SeekFrom::Start(Header::SIZE as u64))?;
-        let header = self.pool.encode(output, &self.header)?;
+        let header = Header {
+            version,
+            ..self.header.clone()
+        };
+        let header = self.pool.encode(output, &header)?;
 
         output.seek(io::SeekFrom::Start(0))?;
         output.encode(&header)?;
         Ok(())
     }
+
+    /// Returns the version of the format the bundle was loaded from.
+    #[inline]
+    pub fn version(&self) -> Version {
+        self.header.version
+    }
+}
+
+/// A version of the bundle format.
+///
+/// All known versions share the definition layouts and instruction encodings,
+/// so the version only determines the number recorded in the header.
+/// Unknown versions are decoded like the latest one, but they keep their number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    V13,
+    V14,
+    Unknown(u32),
+}
+
+impl Version {
+    pub const LATEST: Version = Version::V14;
+
+    pub fn from_number(number: u32) -> Option<Self> {
+        match number {
+            13 => Some(Version::V13),
+            14 => Some(Version::V14),
+            _ => None,
+        }
+    }
+
+    /// Returns the version with the given number, or an unknown version if the number is not supported.
+    pub fn from_number_or_unknown(number: u32) -> Self {
+        Self::from_number(number).unwrap_or_else(|| {
+            log::warn!(
+                "Loading an unsupported version of the script cache (v{number}), it will be decoded like {}. \
+                 You might be running the wrong version of redscript.",
+                Self::LATEST
+            );
+            Version::Unknown(number)
+        })
+    }
+
+    pub fn number(self) -> u32 {
+        match self {
+            Version::V13 => 13,
+            Version::V14 => 14,
+            Version::Unknown(number) => number,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.number())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Header {
-    version: u32,
-    flags: u32,
-    timestamp: Timestamp,
-    unk3: u32,
+    pub(crate) version: Version,
+    pub(crate) flags: u32,
+    pub(crate) timestamp: Timestamp,
+    pub(crate) unk3: u32,
     hash: u32,
-    chunks: u32,
+    pub(crate) chunks: u32,
     data: TableHeader,
     names: TableHeader,
     tweakdb_indexes: TableHeader,
@@ -57,25 +126,36 @@ pub struct Header {
 impl Header {
     const MAGIC: u32 = 0x5344_4552;
     const SIZE: usize = 104;
-    const SUPPORTED_VERSION: u32 = 14;
+
+    /// Creates a header for a bundle that has not been encoded yet, the tables and the hash are filled in on save.
+    pub(crate) fn new(version: Version, flags: u32, timestamp: Timestamp, unk3: u32, chunks: u32) -> Self {
+        Header {
+            version,
+            flags,
+            timestamp,
+            unk3,
+            hash: 0,
+            chunks,
+            data: TableHeader::default(),
+            names: TableHeader::default(),
+            tweakdb_indexes: TableHeader::default(),
+            resources: TableHeader::default(),
+            strings: TableHeader::default(),
+            definitions: TableHeader::default(),
+        }
+    }
 }
 
 impl Decode for Header {
     fn decode<I: io::Read>(input: &mut I) -> io::Result<Self> {
         let magic: u32 = input.decode()?;
         if magic != Header::MAGIC {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid file header"));
+            return Err(InvalidValue::error("the bundle magic", format!("{magic:#x}")));
         }
 
-        let version: u32 = input.decode()?;
+        let version = Version::from_number_or_unknown(input.decode()?);
         let flags: u32 = input.decode()?;
         let timestamp: Timestamp = input.decode()?;
-        if version != Self::SUPPORTED_VERSION {
-            log::warn!(
-                "Loading an unsupported version of the script cache (v{version}) built at {timestamp}. \
-                 You might be running the wrong version of redscript."
-            );
-        }
         let unk3: u32 = input.decode()?;
         let hash: u32 = input.decode()?;
         let chunks: u32 = input.decode()?;
@@ -107,7 +187,7 @@ impl Decode for Header {
 impl Encode for Header {
     fn encode<O: io::Write>(&self, output: &mut O) -> io::Result<()> {
         output.encode(&Header::MAGIC)?;
-        output.encode(&self.version)?;
+        output.encode(&self.version.number())?;
         output.encode(&self.flags)?;
         output.encode(&self.timestamp)?;
         output.encode(&self.unk3)?;
@@ -129,25 +209,39 @@ pub struct ConstantPool {
     pub resources: Strings<Resource>,
     pub strings: Strings<String>,
     pub(crate) definitions: Vec<Definition>,
+    // the definitions of the pool this one is an overlay over, the ones above are added on top of them
+    pub(crate) shared: Option<SharedDefinitions>,
 }
 
 impl ConstantPool {
-    pub fn decode<I: io::Read + io::Seek>(input: &mut I, header: &Header) -> io::Result<Self> {
-        let buffer = input.decode_bytes(header.data.count)?;
-
-        let mut cursor = io::Cursor::new(buffer);
+    pub fn decode<I: io::Read + io::Seek>(input: &mut StreamOffset<I>, header: &Header) -> Result<Self, DecodeError> {
+        let data_offset = input.offset() as u64;
+        let buffer = decode_entry(input, Table::StringData, None, |input| {
+            input.decode_bytes(header.data.count)
+        })?;
+
+        let mut data = StringData {
+            cursor: io::Cursor::new(buffer),
+            offset: data_offset,
+        };
 
-        let names = Strings::decode_from(&mut cursor, &input.decode_vec(header.names.count)?)?;
-        let tweakdb_ids = Strings::decode_from(&mut cursor, &input.decode_vec(header.tweakdb_indexes.count)?)?;
-        let resources = Strings::decode_from(&mut cursor, &input.decode_vec(header.resources.count)?)?;
-        let headers: Vec<DefinitionHeader> = input.decode_vec(header.definitions.count)?;
-        let strings = Strings::decode_from(&mut cursor, &input.decode_vec(header.strings.count)?)?;
+        let offsets = decode_table(input, Table::Names, header.names.count)?;
+        let names = Strings::decode_from(&mut data, Table::Names, &offsets)?;
+        let offsets = decode_table(input, Table::TweakDbIds, header.tweakdb_indexes.count)?;
+        let tweakdb_ids = Strings::decode_from(&mut data, Table::TweakDbIds, &offsets)?;
+        let offsets = decode_table(input, Table::Resources, header.resources.count)?;
+        let resources = Strings::decode_from(&mut data, Table::Resources, &offsets)?;
+        let headers: Vec<DefinitionHeader> = decode_table(input, Table::DefinitionHeaders, header.definitions.count)?;
+        let offsets = decode_table(input, Table::Strings, header.strings.count)?;
+        let strings = Strings::decode_from(&mut data, Table::Strings, &offsets)?;
 
         let mut definitions = Vec::with_capacity(headers.len());
         definitions.push(Definition::DEFAULT);
 
-        for header in headers.iter().skip(1) {
-            let definition = Definition::decode(input, header)?;
+        for (idx, header) in headers.iter().enumerate().skip(1) {
+            let definition = decode_entry(input, Table::Definitions, Some(idx as u32), |input| {
+                Definition::decode(input, header)
+            })?;
             definitions.push(definition);
         }
 
@@ -157,6 +251,7 @@ impl ConstantPool {
             resources,
             strings,
             definitions,
+            shared: None,
         };
         Ok(result)
     }
@@ -246,15 +341,57 @@ impl ConstantPool {
         index: PoolIndex<A>,
         get: F,
     ) -> Result<&A, PoolError> {
-        self.definitions
-            .get(index.value as usize)
+        self.entry(index.value as usize)
             .and_then(|def| get(&def.value))
             .ok_or_else(|| PoolError::DefinitionNotFound(index.cast()))
     }
 
+    fn entry(&self, index: usize) -> Option<&Definition> {
+        match &self.shared {
+            Some(shared) if index < shared.definitions.len() => {
+                shared.overrides.get(&index).or_else(|| shared.definitions.get(index))
+            }
+            _ => self.definitions.get(index - self.shared_len()),
+        }
+    }
+
+    // shared definitions are copied into the overlay before they're modified
+    fn entry_mut(&mut self, index: usize) -> Option<&mut Definition> {
+        let Some(shared) = &mut self.shared else {
+            return self.definitions.get_mut(index);
+        };
+        match index.checked_sub(shared.definitions.len()) {
+            Some(added) => self.definitions.get_mut(added),
+            None => Some(
+                shared
+                    .overrides
+                    .entry(index)
+                    .or_insert_with(|| shared.definitions[index].clone()),
+            ),
+        }
+    }
+
+    fn shared_len(&self) -> usize {
+        self.shared.as_ref().map_or(0, |shared| shared.definitions.len())
+    }
+
+    /// Returns whether this is an [overlay](SharedPool::overlay), whose entries are spread over the shared pool.
+    pub(crate) fn is_overlay(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    /// Returns whether any of the shared definitions of an overlay has been modified.
+    pub fn has_overrides(&self) -> bool {
+        self.shared.as_ref().is_some_and(|shared| !shared.overrides.is_empty())
+    }
+
+    /// Removes the definitions starting at a position, which can't be below the shared definitions of an overlay.
+    pub(crate) fn split_definitions_off(&mut self, position: usize) -> Vec<Definition> {
+        self.definitions.split_off(position - self.shared_len())
+    }
+
     pub fn definition<A>(&self, index: PoolIndex<A>) -> Result<&Definition, PoolError> {
-        self.definitions
-            .get(index.value as usize)
+        self.entry(index.value as usize)
             .ok_or_else(|| PoolError::DefinitionNotFound(index.cast()))
     }
 
@@ -263,8 +400,7 @@ impl ConstantPool {
     }
 
     pub fn function_mut(&mut self, index: PoolIndex<Function>) -> Result<&mut Function, PoolError> {
-        self.definitions
-            .get_mut(index.value as usize)
+        self.entry_mut(index.value as usize)
             .and_then(|def| def.value.as_function_mut())
             .ok_or_else(|| PoolError::DefinitionNotFound(index.cast()))
     }
@@ -274,8 +410,7 @@ impl ConstantPool {
     }
 
     pub fn field_mut(&mut self, index: PoolIndex<Field>) -> Result<&mut Field, PoolError> {
-        self.definitions
-            .get_mut(index.value as usize)
+        self.entry_mut(index.value as usize)
             .and_then(|def| def.value.as_field_mut())
             .ok_or_else(|| PoolError::DefinitionNotFound(index.cast()))
     }
@@ -297,8 +432,7 @@ impl ConstantPool {
     }
 
     pub fn class_mut(&mut self, index: PoolIndex<Class>) -> Result<&mut Class, PoolError> {
-        self.definitions
-            .get_mut(index.value as usize)
+        self.entry_mut(index.value as usize)
             .and_then(|def| def.value.as_class_mut())
             .ok_or_else(|| PoolError::DefinitionNotFound(index.cast()))
     }
@@ -316,10 +450,22 @@ impl ConstantPool {
     }
 
     pub fn definitions(&self) -> impl DoubleEndedIterator<Item = (PoolIndex<Definition>, &Definition)> {
-        self.definitions
+        let shared = self.shared.iter().flat_map(|shared| {
+            shared
+                .definitions
+                .iter()
+                .enumerate()
+                .map(|(index, def)| (index, shared.overrides.get(&index).unwrap_or(def)))
+        });
+        let offset = self.shared_len();
+        let added = self
+            .definitions
             .iter()
             .enumerate()
-            .skip(1)
+            .map(move |(index, def)| (offset + index, def));
+        shared
+            .chain(added)
+            .filter(|(index, _)| *index != 0)
             .map(|(index, def)| (PoolIndex::new(index as u32), def))
     }
 
@@ -328,15 +474,22 @@ impl ConstantPool {
     }
 
     pub fn put_definition<A>(&mut self, index: PoolIndex<A>, definition: Definition) {
-        self.definitions[index.value as usize] = definition;
+        *self.definition_at(index) = definition;
     }
 
     pub fn swap_definition<A>(&mut self, lhs: PoolIndex<A>, rhs: PoolIndex<A>) {
-        self.definitions.swap(lhs.value as usize, rhs.value as usize);
+        let lhs_def = mem::replace(self.definition_at(lhs), Definition::DEFAULT);
+        let rhs_def = mem::replace(self.definition_at(rhs), lhs_def);
+        *self.definition_at(lhs) = rhs_def;
+    }
+
+    fn definition_at<A>(&mut self, index: PoolIndex<A>) -> &mut Definition {
+        self.entry_mut(index.value as usize)
+            .unwrap_or_else(|| panic!("definition #{index} out of bounds"))
     }
 
     pub fn add_definition<A>(&mut self, definition: Definition) -> PoolIndex<A> {
-        let position = self.definitions.len();
+        let position = self.shared_len() + self.definitions.len();
         self.definitions.push(definition);
         PoolIndex::new(position as u32)
     }
@@ -346,37 +499,194 @@ impl ConstantPool {
     }
 
     pub fn rename<A>(&mut self, index: PoolIndex<A>, name: PoolIndex<CName>) {
-        self.definitions[index.value as usize].name = name;
+        self.definition_at(index).name = name;
     }
 
     pub fn roots(&self) -> impl Iterator<Item = (PoolIndex<Definition>, &Definition)> {
         self.definitions().filter(|(_, def)| def.parent.is_undefined())
     }
+
+    /// Returns the current size of every table, entries added after this point
+    /// can be staged with [`PoolStage`](crate::mapper::PoolStage).
+    pub fn mark(&self) -> PoolMark {
+        PoolMark {
+            definitions: self.shared_len() + self.definitions.len(),
+            names: self.names.len(),
+            tweakdb_ids: self.tweakdb_ids.len(),
+            resources: self.resources.len(),
+            strings: self.strings.len(),
+        }
+    }
+
+    /// Moves the entries of the pool behind shared references, so that several
+    /// [overlays](SharedPool::overlay) can be stacked on top of it without copying it.
+    ///
+    /// # Panics
+    /// Panics if the pool is an overlay itself.
+    pub fn share(self) -> SharedPool {
+        assert!(self.shared.is_none(), "an overlay can't be shared");
+        SharedPool {
+            names: Ref::new(self.names),
+            tweakdb_ids: Ref::new(self.tweakdb_ids),
+            resources: Ref::new(self.resources),
+            strings: Ref::new(self.strings),
+            definitions: Ref::new(self.definitions),
+        }
+    }
+}
+
+/// A pool with its entries behind shared references, see [`ConstantPool::share`].
+#[derive(Debug)]
+pub struct SharedPool {
+    names: Ref<Strings<CName>>,
+    tweakdb_ids: Ref<Strings<TweakDbId>>,
+    resources: Ref<Strings<Resource>>,
+    strings: Ref<Strings<String>>,
+    definitions: Ref<Vec<Definition>>,
+}
+
+impl SharedPool {
+    /// Creates a pool that reads the entries of this one and keeps the ones added to it apart,
+    /// shared definitions are copied into it before they're modified.
+    /// Overlays are meant to be staged with [`PoolStage`](crate::mapper::PoolStage),
+    /// they can't be encoded on their own.
+    pub fn overlay(&self) -> ConstantPool {
+        ConstantPool {
+            names: Strings::overlay(&self.names),
+            tweakdb_ids: Strings::overlay(&self.tweakdb_ids),
+            resources: Strings::overlay(&self.resources),
+            strings: Strings::overlay(&self.strings),
+            definitions: vec![],
+            shared: Some(SharedDefinitions {
+                definitions: self.definitions.clone(),
+                overrides: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Returns the pool, or `None` if there are overlays over it left, in which case it's dropped with them.
+    pub fn into_inner(self) -> Option<ConstantPool> {
+        Some(ConstantPool {
+            names: Ref::into_inner(self.names)?,
+            tweakdb_ids: Ref::into_inner(self.tweakdb_ids)?,
+            resources: Ref::into_inner(self.resources)?,
+            strings: Ref::into_inner(self.strings)?,
+            definitions: Ref::into_inner(self.definitions)?,
+            shared: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SharedDefinitions {
+    definitions: Ref<Vec<Definition>>,
+    overrides: HashMap<usize, Definition>,
+}
+
+/// The number of entries in every table of a pool at some point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolMark {
+    pub(crate) definitions: usize,
+    pub(crate) names: usize,
+    pub(crate) tweakdb_ids: usize,
+    pub(crate) resources: usize,
+    pub(crate) strings: usize,
+}
+
+/// Runs a decoder on the input, attaching the location to the error if it fails.
+fn decode_entry<I, A, F>(
+    input: &mut StreamOffset<I>,
+    table: Table,
+    index: Option<u32>,
+    decode: F,
+) -> Result<A, DecodeError>
+where
+    F: FnOnce(&mut StreamOffset<I>) -> io::Result<A>,
+{
+    decode(input).map_err(|err| DecodeError::new(table, index, input.offset() as u64, err))
+}
+
+fn decode_table<I: io::Read, A: Decode>(
+    input: &mut StreamOffset<I>,
+    table: Table,
+    count: u32,
+) -> Result<Vec<A>, DecodeError> {
+    let mut entries = Vec::with_capacity((count as usize).min(MAX_PREALLOCATED));
+    for idx in 0..count {
+        entries.push(decode_entry(input, table, Some(idx), DecodeExt::decode)?);
+    }
+    Ok(entries)
+}
+
+/// The buffer all strings of a bundle are stored in.
+struct StringData {
+    cursor: io::Cursor<Vec<u8>>,
+    /// The offset of the buffer in the bundle.
+    offset: u64,
 }
 
 #[derive(Debug, Clone)]
 pub struct Strings<K> {
     strings: Vec<Ref<str>>,
     mappings: HashMap<Ref<str>, PoolIndex<K>>,
+    // the table of the pool this one is an overlay over, the entries above are added on top of it
+    shared: Option<Ref<Strings<K>>>,
     phantom: PhantomData<K>,
 }
 
 impl<K: DefaultString> Strings<K> {
-    fn decode_from<I: io::Read + io::Seek>(input: &mut I, offsets: &[u32]) -> io::Result<Strings<K>> {
+    fn decode_from(data: &mut StringData, table: Table, offsets: &[u32]) -> Result<Strings<K>, DecodeError> {
         let mut strings = Vec::with_capacity(offsets.len());
-        let mut mappings = HashMap::new();
         for (idx, offset) in offsets.iter().enumerate() {
-            input.seek(io::SeekFrom::Start((*offset).into()))?;
-            let str: Ref<str> = Ref::from(input.decode::<String>()?);
-            strings.push(str.clone());
-            mappings.insert(str, PoolIndex::new(idx as u32));
+            data.cursor.set_position((*offset).into());
+            let str = data
+                .cursor
+                .decode::<String>()
+                .map_err(|err| DecodeError::new(table, Some(idx as u32), data.offset + data.cursor.position(), err))?;
+            strings.push(Ref::from(str));
         }
-        let result = Strings {
+        Ok(Strings::from_entries(strings))
+    }
+
+    /// Creates a table with the given entries at their positions, duplicates are preserved.
+    pub(crate) fn from_entries(strings: Vec<Ref<str>>) -> Self {
+        let mappings = strings
+            .iter()
+            .enumerate()
+            .map(|(idx, str)| (str.clone(), PoolIndex::new(idx as u32)))
+            .collect();
+        Strings {
             strings,
             mappings,
+            shared: None,
             phantom: PhantomData,
-        };
-        Ok(result)
+        }
+    }
+
+    fn overlay(shared: &Ref<Self>) -> Self {
+        Strings {
+            shared: Some(shared.clone()),
+            ..Strings::default()
+        }
+    }
+
+    /// Returns all of the entries, which can't be called on an overlay.
+    pub(crate) fn entries(&self) -> &[Ref<str>] {
+        assert!(self.shared.is_none(), "the entries of an overlay are not contiguous");
+        &self.strings
+    }
+
+    /// Returns the entries starting at a position, which can't be below the shared entries of an overlay.
+    pub(crate) fn entries_from(&self, position: usize) -> &[Ref<str>] {
+        &self.strings[position - self.shared_len()..]
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.shared_len() + self.strings.len()
+    }
+
+    fn shared_len(&self) -> usize {
+        self.shared.as_ref().map_or(0, |shared| shared.len())
     }
 
     fn encoded_offsets(&self, str_map: &HashMap<Ref<str>, u32>) -> io::Result<Vec<u8>> {
@@ -391,8 +701,7 @@ impl<K: DefaultString> Strings<K> {
         match K::DEFAULT {
             Some(default) if index.is_undefined() => Ok(Ref::from(default)),
             _ => self
-                .strings
-                .get(index.value as usize)
+                .entry(index)
                 .cloned()
                 .ok_or_else(|| PoolError::StringNotFound(index.cast())),
         }
@@ -402,22 +711,33 @@ impl<K: DefaultString> Strings<K> {
         match K::DEFAULT {
             Some(default) if index.is_undefined() => Ok(default),
             _ => self
-                .strings
-                .get(index.value as usize)
+                .entry(index)
                 .map(Ref::as_ref)
                 .ok_or_else(|| PoolError::StringNotFound(index.cast())),
         }
     }
 
+    fn entry(&self, index: PoolIndex<K>) -> Option<&Ref<str>> {
+        match &self.shared {
+            Some(shared) if (index.value as usize) < shared.len() => shared.entry(index),
+            _ => self.strings.get(index.value as usize - self.shared_len()),
+        }
+    }
+
     pub fn get_index(&self, name: &str) -> Option<PoolIndex<K>> {
-        self.mappings.get(name).copied()
+        self.shared
+            .as_ref()
+            .and_then(|shared| shared.get_index(name))
+            .or_else(|| self.mappings.get(name).copied())
     }
 
     pub fn add(&mut self, str: Ref<str>) -> PoolIndex<K> {
         if K::DEFAULT == Some(&str) {
             PoolIndex::UNDEFINED
+        } else if let Some(idx) = self.shared.as_ref().and_then(|shared| shared.get_index(&str)) {
+            idx
         } else {
-            let idx = PoolIndex::new(self.strings.len() as u32);
+            let idx = PoolIndex::new(self.len() as u32);
             match self.mappings.entry(str.clone()) {
                 hash_map::Entry::Occupied(entry) => *entry.get(),
                 hash_map::Entry::Vacant(slot) => {
@@ -434,12 +754,13 @@ impl<K> Default for Strings<K> {
         Self {
             strings: vec![],
             mappings: HashMap::new(),
+            shared: None,
             phantom: PhantomData,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 struct TableHeader {
     offset: u32,
     count: u32,
@@ -559,7 +880,7 @@ impl Encode for DefinitionHeader {
 
 #[derive(BitfieldSpecifier)]
 #[bits = 8]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DefinitionType {
     Type = 0,
     Class = 1,
@@ -575,7 +896,8 @@ pub enum DefinitionType {
 
 impl Decode for DefinitionType {
     fn decode<I: io::Read>(input: &mut I) -> io::Result<Self> {
-        Ok(DefinitionType::from_bytes(input.decode()?).expect("Invalid DefinitionType enum value"))
+        let byte: u8 = input.decode()?;
+        DefinitionType::from_bytes(byte).map_err(|_| InvalidValue::error("a definition type", byte))
     }
 }
 
@@ -769,7 +1091,10 @@ pub enum PoolError {
 mod tests {
     use std::io::{self, Cursor};
 
-    use super::ScriptBundle;
+    use super::{DefinitionHeader, DefinitionType, Header, PoolIndex, ScriptBundle, Version};
+    use crate::decode::{DecodeExt, Table};
+    use crate::definition::{Definition, Type};
+    use crate::Ref;
 
     const PREDEF: &[u8] = include_bytes!("../../resources/predef.redscripts");
 
@@ -783,4 +1108,105 @@ mod tests {
         assert_eq!(scripts.pool.definitions.len(), scripts2.pool.definitions.len());
         Ok(())
     }
+
+    #[test]
+    fn save_in_target_version() -> io::Result<()> {
+        let scripts = ScriptBundle::load(&mut Cursor::new(PREDEF))?;
+        assert_eq!(scripts.version(), Version::V13);
+
+        let mut tmp = Cursor::new(Vec::new());
+        scripts.save_as(&mut tmp, Version::V14)?;
+        tmp.set_position(0);
+        let scripts2 = ScriptBundle::load(&mut tmp)?;
+        assert_eq!(scripts2.version(), Version::V14);
+        assert_eq!(scripts.pool.definitions.len(), scripts2.pool.definitions.len());
+        Ok(())
+    }
+
+    #[test]
+    fn save_unknown_version_as_loaded() -> io::Result<()> {
+        let mut bytes = PREDEF.to_vec();
+        bytes[4..8].copy_from_slice(&99u32.to_le_bytes());
+
+        let scripts = ScriptBundle::load(&mut Cursor::new(bytes))?;
+        let expected = ScriptBundle::load(&mut Cursor::new(PREDEF))?;
+        assert_eq!(scripts.version(), Version::Unknown(99));
+        assert_eq!(scripts.pool.definitions.len(), expected.pool.definitions.len());
+
+        let mut tmp = Cursor::new(Vec::new());
+        scripts.save(&mut tmp)?;
+        tmp.set_position(0);
+        let scripts2 = ScriptBundle::load(&mut tmp)?;
+        assert_eq!(scripts2.version(), Version::Unknown(99));
+        assert_eq!(scripts2.pool.definitions.len(), expected.pool.definitions.len());
+        Ok(())
+    }
+
+    #[test]
+    fn report_invalid_type_tag() -> io::Result<()> {
+        let mut cursor = Cursor::new(PREDEF);
+        let header: Header = cursor.decode()?;
+        cursor.set_position(header.definitions.offset.into());
+        let headers: Vec<DefinitionHeader> = cursor.decode_vec(header.definitions.count)?;
+        let (index, type_) = headers
+            .iter()
+            .enumerate()
+            .skip(1)
+            .find(|(_, header)| header.type_ == DefinitionType::Type)
+            .expect("no types in the bundle");
+
+        let mut bytes = PREDEF.to_vec();
+        // the tag is the first byte of a type
+        bytes[type_.offset as usize] = 0xFF;
+
+        let err = ScriptBundle::load(&mut Cursor::new(bytes)).unwrap_err();
+        assert_eq!(err.table, Table::Definitions);
+        assert_eq!(err.index, Some(index as u32));
+        assert_eq!(err.offset, u64::from(type_.offset) + 1);
+        assert_eq!(err.expected(), Some("a type tag"));
+        Ok(())
+    }
+
+    #[test]
+    fn add_entries_to_overlay() -> io::Result<()> {
+        let scripts = ScriptBundle::load(&mut Cursor::new(PREDEF))?;
+        let mark = scripts.pool.mark();
+        let (type_, _) = scripts.pool.definitions().next().unwrap();
+        let existing = scripts.pool.names.get(PoolIndex::new(1)).unwrap();
+
+        let pool = scripts.pool.share();
+        let mut overlay = pool.overlay();
+        assert_eq!(overlay.mark(), mark);
+        assert_eq!(overlay.names.add(existing), PoolIndex::new(1));
+        let name = overlay.names.add(Ref::from("OverlayName"));
+        assert_eq!(name, PoolIndex::new(mark.names as u32));
+        assert_eq!(&*overlay.names.get(name).unwrap(), "OverlayName");
+
+        let added = overlay.add_definition(Definition::type_(name, Type::Prim));
+        assert_eq!(added, PoolIndex::<Definition>::new(mark.definitions as u32));
+        assert_eq!(overlay.definitions().last().map(|(idx, _)| idx), Some(added));
+        overlay.rename(type_, name);
+        assert_eq!(overlay.definition(type_).unwrap().name, name);
+
+        assert!(pool.overlay().names.get_index("OverlayName").is_none());
+        drop(overlay);
+        let pool = pool.into_inner().unwrap();
+        assert_eq!(pool.mark(), mark);
+        assert_ne!(pool.definition(type_).unwrap().name, name);
+        Ok(())
+    }
+
+    #[test]
+    fn load_corrupted_scripts_without_panicking() {
+        for len in 0..PREDEF.len() {
+            assert!(ScriptBundle::load(&mut Cursor::new(&PREDEF[..len])).is_err());
+        }
+        for pos in 0..PREDEF.len() {
+            for value in [0x00, 0xFF, PREDEF[pos] ^ 0x80] {
+                let mut bytes = PREDEF.to_vec();
+                bytes[pos] = value;
+                ScriptBundle::load(&mut Cursor::new(bytes)).ok();
+            }
+        }
+    }
 }
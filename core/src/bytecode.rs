@@ -4,10 +4,14 @@ use std::{io, usize};
 use thiserror::Error;
 
 use crate::bundle::{CName, PoolIndex, Resource, TweakDbId};
-use crate::decode::{Decode, DecodeExt};
+use crate::decode::{Decode, DecodeExt, InvalidValue};
 use crate::definition::{Class, Enum, Field, Function, Local, Parameter, Type};
 use crate::encode::{Encode, EncodeExt};
 
+mod cfg;
+
+pub use cfg::{BasicBlock, ControlFlowGraph, Dominators, Edge, EdgeKind};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Instr<Loc> {
     Nop,
@@ -223,6 +227,98 @@ impl<L> Instr<L> {
         };
         1 + op_size
     }
+
+    /// Returns the number of expressions this instruction takes as operands. Calls take a variable
+    /// number of arguments terminated with `ParamEnd`, so like instructions that aren't expressions
+    /// they have no operand count.
+    #[inline]
+    pub fn operand_count(&self) -> Option<usize> {
+        operand_count(self)
+    }
+
+    /// Returns the pool entries referenced by the operands of this instruction.
+    pub fn pool_refs(&self) -> Vec<PoolRef>
+    where
+        L: Clone,
+    {
+        let mut refs = vec![];
+        self.clone().map_pool_refs(|ref_| {
+            refs.push(ref_);
+            ref_
+        });
+        refs
+    }
+
+    /// Replaces every pool entry referenced by the operands of this instruction with the result of the function.
+    /// The function is expected to return a reference of the same kind as the one it was given.
+    pub fn map_pool_refs<F: FnMut(PoolRef) -> PoolRef>(&mut self, mut f: F) {
+        match self {
+            Instr::NameConst(idx) | Instr::InvokeVirtual(_, _, idx, _) => *idx = f(PoolRef::Name(*idx)).index(),
+            Instr::StringConst(idx) => *idx = f(PoolRef::String(*idx)).index(),
+            Instr::TweakDbIdConst(idx) => *idx = f(PoolRef::TweakDbId(*idx)).index(),
+            Instr::ResourceConst(idx) => *idx = f(PoolRef::Resource(*idx)).index(),
+            Instr::EnumConst(enum_, member) => {
+                *enum_ = f(PoolRef::Enum(*enum_)).index();
+                *member = f(PoolRef::EnumValue(*member)).index();
+            }
+            Instr::Local(idx) => *idx = f(PoolRef::Local(*idx)).index(),
+            Instr::Param(idx) => *idx = f(PoolRef::Param(*idx)).index(),
+            Instr::ObjectField(idx) | Instr::StructField(idx) => *idx = f(PoolRef::Field(*idx)).index(),
+            Instr::Construct(_, idx) | Instr::New(idx) | Instr::DynamicCast(idx, _) => {
+                *idx = f(PoolRef::Class(*idx)).index();
+            }
+            Instr::InvokeStatic(_, _, idx, _) => *idx = f(PoolRef::Function(*idx)).index(),
+            Instr::Switch(idx, _)
+            | Instr::Equals(idx)
+            | Instr::RefStringEqualsString(idx)
+            | Instr::StringEqualsRefString(idx)
+            | Instr::NotEquals(idx)
+            | Instr::RefStringNotEqualsString(idx)
+            | Instr::StringNotEqualsRefString(idx)
+            | Instr::ArrayClear(idx)
+            | Instr::ArraySize(idx)
+            | Instr::ArrayResize(idx)
+            | Instr::ArrayFindFirst(idx)
+            | Instr::ArrayFindFirstFast(idx)
+            | Instr::ArrayFindLast(idx)
+            | Instr::ArrayFindLastFast(idx)
+            | Instr::ArrayContains(idx)
+            | Instr::ArrayContainsFast(idx)
+            | Instr::ArrayCount(idx)
+            | Instr::ArrayCountFast(idx)
+            | Instr::ArrayPush(idx)
+            | Instr::ArrayPop(idx)
+            | Instr::ArrayInsert(idx)
+            | Instr::ArrayRemove(idx)
+            | Instr::ArrayRemoveFast(idx)
+            | Instr::ArrayGrow(idx)
+            | Instr::ArrayErase(idx)
+            | Instr::ArrayEraseFast(idx)
+            | Instr::ArrayLast(idx)
+            | Instr::ArrayElement(idx)
+            | Instr::ArraySort(idx)
+            | Instr::ArraySortByPredicate(idx)
+            | Instr::StaticArraySize(idx)
+            | Instr::StaticArrayFindFirst(idx)
+            | Instr::StaticArrayFindFirstFast(idx)
+            | Instr::StaticArrayFindLast(idx)
+            | Instr::StaticArrayFindLastFast(idx)
+            | Instr::StaticArrayContains(idx)
+            | Instr::StaticArrayContainsFast(idx)
+            | Instr::StaticArrayCount(idx)
+            | Instr::StaticArrayCountFast(idx)
+            | Instr::StaticArrayLast(idx)
+            | Instr::StaticArrayElement(idx)
+            | Instr::EnumToI32(idx, _)
+            | Instr::I32ToEnum(idx, _)
+            | Instr::ToString(idx)
+            | Instr::ToVariant(idx)
+            | Instr::FromVariant(idx)
+            | Instr::AsRef(idx)
+            | Instr::Deref(idx) => *idx = f(PoolRef::Type(*idx)).index(),
+            _ => {}
+        }
+    }
 }
 
 impl Instr<Label> {
@@ -477,10 +573,7 @@ impl Decode for Instr<Offset> {
             101 => Ok(Instr::WeakRefNull),
             102 => Ok(Instr::AsRef(input.decode()?)),
             103 => Ok(Instr::Deref(input.decode()?)),
-            other => {
-                let msg = format!("Invalid instruction code: {other}");
-                Err(io::Error::new(io::ErrorKind::InvalidData, msg))
-            }
+            other => Err(InvalidValue::error("an instruction code", other)),
         }
     }
 }
@@ -899,14 +992,69 @@ impl Encode for Instr<Offset> {
     }
 }
 
+/// A pool entry referenced by an instruction operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PoolRef {
+    Name(PoolIndex<CName>),
+    String(PoolIndex<String>),
+    TweakDbId(PoolIndex<TweakDbId>),
+    Resource(PoolIndex<Resource>),
+    Enum(PoolIndex<Enum>),
+    EnumValue(PoolIndex<i64>),
+    Local(PoolIndex<Local>),
+    Param(PoolIndex<Parameter>),
+    Field(PoolIndex<Field>),
+    Type(PoolIndex<Type>),
+    Class(PoolIndex<Class>),
+    Function(PoolIndex<Function>),
+}
+
+impl PoolRef {
+    /// Returns the index regardless of the kind of the entry.
+    pub fn index<A>(self) -> PoolIndex<A> {
+        match self {
+            PoolRef::Name(idx) => idx.cast(),
+            PoolRef::String(idx) => idx.cast(),
+            PoolRef::TweakDbId(idx) => idx.cast(),
+            PoolRef::Resource(idx) => idx.cast(),
+            PoolRef::Enum(idx) => idx.cast(),
+            PoolRef::EnumValue(idx) => idx.cast(),
+            PoolRef::Local(idx) => idx.cast(),
+            PoolRef::Param(idx) => idx.cast(),
+            PoolRef::Field(idx) => idx.cast(),
+            PoolRef::Type(idx) => idx.cast(),
+            PoolRef::Class(idx) => idx.cast(),
+            PoolRef::Function(idx) => idx.cast(),
+        }
+    }
+
+    /// Returns a reference to an entry of the same kind at a different index.
+    pub fn with_index<A>(self, index: PoolIndex<A>) -> Self {
+        match self {
+            PoolRef::Name(_) => PoolRef::Name(index.cast()),
+            PoolRef::String(_) => PoolRef::String(index.cast()),
+            PoolRef::TweakDbId(_) => PoolRef::TweakDbId(index.cast()),
+            PoolRef::Resource(_) => PoolRef::Resource(index.cast()),
+            PoolRef::Enum(_) => PoolRef::Enum(index.cast()),
+            PoolRef::EnumValue(_) => PoolRef::EnumValue(index.cast()),
+            PoolRef::Local(_) => PoolRef::Local(index.cast()),
+            PoolRef::Param(_) => PoolRef::Param(index.cast()),
+            PoolRef::Field(_) => PoolRef::Field(index.cast()),
+            PoolRef::Type(_) => PoolRef::Type(index.cast()),
+            PoolRef::Class(_) => PoolRef::Class(index.cast()),
+            PoolRef::Function(_) => PoolRef::Function(index.cast()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Breakpoint {
-    line: u16,
-    line_start: u32,
-    col: u16,
-    length: u16,
-    enabled: bool,
-    padding: u64,
+    pub(crate) line: u16,
+    pub(crate) line_start: u32,
+    pub(crate) col: u16,
+    pub(crate) length: u16,
+    pub(crate) enabled: bool,
+    pub(crate) padding: u64,
 }
 
 impl Decode for Breakpoint {
@@ -942,7 +1090,7 @@ impl Encode for Breakpoint {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct StartProfiling(String, u8);
+pub struct StartProfiling(pub(crate) String, pub(crate) u8);
 
 impl Decode for StartProfiling {
     fn decode<I: io::Read>(input: &mut I) -> io::Result<Self> {
@@ -959,7 +1107,7 @@ impl Encode for StartProfiling {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Location {
     pub value: u16,
 }
@@ -1049,6 +1197,32 @@ impl<Loc: Clone> Code<Loc> {
     pub fn iter(&self) -> CodeIter<'_, Loc> {
         CodeIter::new(&self.0)
     }
+
+    #[inline]
+    pub fn into_vec(self) -> Vec<Instr<Loc>> {
+        self.0
+    }
+}
+
+impl Code<Label> {
+    /// Replaces labels with offsets. Every label below `labels` is expected to be placed in the code
+    /// with an `Instr::Target` marker, the markers are removed in the process.
+    pub fn resolve_labels(self, labels: usize) -> Code<Offset> {
+        let mut locations = Vec::with_capacity(labels);
+        locations.resize(labels, Location::new(0));
+
+        for (loc, instr) in self.iter() {
+            if let Instr::Target(label) = instr {
+                locations[label.index] = loc;
+            }
+        }
+
+        let mut resolved = Vec::with_capacity(self.len());
+        for (loc, instr) in self.iter().filter(|(_, instr)| !matches!(instr, Instr::Target(_))) {
+            resolved.push(instr.resolve_labels(loc, &locations));
+        }
+        Code::new(resolved)
+    }
 }
 
 impl<Loc> AsRef<[Instr<Loc>]> for Code<Loc> {
@@ -1195,6 +1369,129 @@ impl<'a, Loc: Clone> Iterator for CodeIter<'a, Loc> {
     }
 }
 
+// returns the number of arguments of an invocation and the index of its ParamEnd,
+// every argument is an expression optionally preceded by a Skip, omitted arguments are a Nop
+pub(crate) fn skip_args(code: &[(Location, Instr<Offset>)], start: usize) -> Option<(usize, usize)> {
+    let mut index = start;
+    let mut count = 0;
+    loop {
+        index = match code.get(index)?.1 {
+            Instr::ParamEnd => return Some((count, index)),
+            Instr::Skip(_) => skip_expr(code, index + 1)?,
+            _ => skip_expr(code, index)?,
+        };
+        count += 1;
+    }
+}
+
+// returns the index of the instruction that follows the expression
+pub(crate) fn skip_expr(code: &[(Location, Instr<Offset>)], start: usize) -> Option<usize> {
+    let instr = &code.get(start)?.1;
+    if let Instr::InvokeStatic(_, _, _, _) | Instr::InvokeVirtual(_, _, _, _) = instr {
+        return skip_args(code, start + 1).map(|(_, end)| end + 1);
+    }
+    (0..instr.operand_count()?).try_fold(start + 1, |index, _| skip_expr(code, index))
+}
+
+// the number of expressions an instruction consumes, none for instructions that aren't expressions
+fn operand_count<L>(instr: &Instr<L>) -> Option<usize> {
+    let count = match instr {
+        Instr::Nop
+        | Instr::Null
+        | Instr::I32One
+        | Instr::I32Zero
+        | Instr::I8Const(_)
+        | Instr::I16Const(_)
+        | Instr::I32Const(_)
+        | Instr::I64Const(_)
+        | Instr::U8Const(_)
+        | Instr::U16Const(_)
+        | Instr::U32Const(_)
+        | Instr::U64Const(_)
+        | Instr::F32Const(_)
+        | Instr::F64Const(_)
+        | Instr::NameConst(_)
+        | Instr::EnumConst(_, _)
+        | Instr::StringConst(_)
+        | Instr::TweakDbIdConst(_)
+        | Instr::ResourceConst(_)
+        | Instr::TrueConst
+        | Instr::FalseConst
+        | Instr::Local(_)
+        | Instr::Param(_)
+        | Instr::ObjectField(_)
+        | Instr::ExternalVar
+        | Instr::New(_)
+        | Instr::This
+        | Instr::WeakRefNull => 0,
+        Instr::Return
+        | Instr::StructField(_)
+        | Instr::Delete
+        | Instr::ArrayClear(_)
+        | Instr::ArraySize(_)
+        | Instr::ArrayPop(_)
+        | Instr::ArrayLast(_)
+        | Instr::ArraySort(_)
+        | Instr::StaticArraySize(_)
+        | Instr::StaticArrayLast(_)
+        | Instr::RefToBool
+        | Instr::WeakRefToBool
+        | Instr::EnumToI32(_, _)
+        | Instr::I32ToEnum(_, _)
+        | Instr::DynamicCast(_, _)
+        | Instr::ToString(_)
+        | Instr::ToVariant(_)
+        | Instr::FromVariant(_)
+        | Instr::VariantIsDefined
+        | Instr::VariantIsRef
+        | Instr::VariantIsArray
+        | Instr::VariantTypeName
+        | Instr::VariantToString
+        | Instr::WeakRefToRef
+        | Instr::RefToWeakRef
+        | Instr::AsRef(_)
+        | Instr::Deref(_) => 1,
+        Instr::Assign
+        | Instr::Context(_)
+        | Instr::Equals(_)
+        | Instr::RefStringEqualsString(_)
+        | Instr::StringEqualsRefString(_)
+        | Instr::NotEquals(_)
+        | Instr::RefStringNotEqualsString(_)
+        | Instr::StringNotEqualsRefString(_)
+        | Instr::ArrayResize(_)
+        | Instr::ArrayFindFirst(_)
+        | Instr::ArrayFindFirstFast(_)
+        | Instr::ArrayFindLast(_)
+        | Instr::ArrayFindLastFast(_)
+        | Instr::ArrayContains(_)
+        | Instr::ArrayContainsFast(_)
+        | Instr::ArrayCount(_)
+        | Instr::ArrayCountFast(_)
+        | Instr::ArrayPush(_)
+        | Instr::ArrayRemove(_)
+        | Instr::ArrayRemoveFast(_)
+        | Instr::ArrayGrow(_)
+        | Instr::ArrayErase(_)
+        | Instr::ArrayEraseFast(_)
+        | Instr::ArrayElement(_)
+        | Instr::ArraySortByPredicate(_)
+        | Instr::StaticArrayFindFirst(_)
+        | Instr::StaticArrayFindFirstFast(_)
+        | Instr::StaticArrayFindLast(_)
+        | Instr::StaticArrayFindLastFast(_)
+        | Instr::StaticArrayContains(_)
+        | Instr::StaticArrayContainsFast(_)
+        | Instr::StaticArrayCount(_)
+        | Instr::StaticArrayCountFast(_)
+        | Instr::StaticArrayElement(_) => 2,
+        Instr::Conditional(_, _) | Instr::ArrayInsert(_) => 3,
+        Instr::Construct(n, _) => (*n).into(),
+        _ => return None,
+    };
+    Some(count)
+}
+
 #[derive(Debug, Error)]
 pub enum CursorError {
     #[error("invalid instruction offset: {0}")]
@@ -1211,4 +1508,16 @@ mod tests {
     fn instr_size() {
         assert_eq!(std::mem::size_of::<Instr<Offset>>(), 16);
     }
+
+    #[test]
+    fn map_pool_refs() {
+        let mut instr: Instr<Offset> = Instr::EnumConst(PoolIndex::new(1), PoolIndex::new(2));
+        assert_eq!(
+            instr.pool_refs(),
+            [PoolRef::Enum(PoolIndex::new(1)), PoolRef::EnumValue(PoolIndex::new(2))]
+        );
+        instr.map_pool_refs(|ref_| ref_.with_index(PoolIndex::<()>::new(u32::from(ref_.index::<()>()) + 10)));
+        assert_eq!(instr, Instr::EnumConst(PoolIndex::new(11), PoolIndex::new(12)));
+        assert!(Instr::<Offset>::Jump(Offset::new(3)).pool_refs().is_empty());
+    }
 }
@@ -0,0 +1,452 @@
+use std::ops::Range;
+
+use hashbrown::{HashMap, HashSet};
+
+use super::{skip_expr, Code, CursorError, Instr, Location, Offset};
+
+/// The control-flow graph of a function body. Blocks are stored in the order of their code,
+/// the first one being the entry of the function.
+///
+/// Expressions are encoded in prefix order, so the edges of a branch start at the instruction
+/// that encodes it rather than at the end of its condition.
+#[derive(Debug, Clone, Default)]
+pub struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+}
+
+impl ControlFlowGraph {
+    /// Splits the code into basic blocks and links them. Fails if a jump does not target
+    /// an instruction boundary.
+    pub fn new(code: &Code<Offset>) -> Result<Self, CursorError> {
+        let instrs: Vec<_> = code.iter().collect();
+        let end = instrs
+            .last()
+            .map_or(Location::ZERO, |(loc, instr)| Location::new(loc.value + instr.size()));
+        // an instruction index for every location, the end of the code has no instruction
+        let index_of = |loc: Location| -> Result<Option<usize>, CursorError> {
+            match instrs.binary_search_by_key(&loc, |(loc, _)| *loc) {
+                Ok(index) => Ok(Some(index)),
+                Err(_) if loc == end => Ok(None),
+                Err(_) => Err(CursorError::InvalidInstructionOffset(loc)),
+            }
+        };
+
+        // arriving at the false branch of a conditional in any other way than through the condition
+        // means that the true branch has been evaluated, which continues at the exit of the conditional
+        let mut exits = HashMap::new();
+        // the end of a returned expression can only be reached from within it, after which the function returns
+        let mut returns = HashSet::new();
+        let mut leaders = vec![false; instrs.len()];
+        if let Some(first) = leaders.first_mut() {
+            *first = true;
+        }
+        for (i, (loc, instr)) in instrs.iter().enumerate() {
+            if let Instr::Conditional(when_false, exit) = instr {
+                exits.insert(when_false.absolute(*loc), exit.absolute(*loc));
+                if let Some(index) = index_of(exit.absolute(*loc))? {
+                    leaders[index] = true;
+                }
+            }
+            if matches!(instr, Instr::Return) {
+                // code that can't be parsed as an expression is assumed to be unreachable
+                let after = skip_expr(&instrs, i + 1).unwrap_or(i + 1);
+                returns.insert(instrs.get(after).map_or(end, |(loc, _)| *loc));
+                if let Some(leader) = leaders.get_mut(after) {
+                    *leader = true;
+                }
+            }
+            for (_, target) in branches(instr, *loc) {
+                if let Some(index) = index_of(target)? {
+                    leaders[index] = true;
+                }
+            }
+            if ends_block(instr) && i + 1 < instrs.len() {
+                leaders[i + 1] = true;
+            }
+        }
+        let starts: Vec<_> = (0..instrs.len()).filter(|&i| leaders[i]).collect();
+        let block_of = |index: usize| starts.partition_point(|&start| start <= index) - 1;
+
+        let mut blocks = Vec::with_capacity(starts.len());
+        for (n, &start) in starts.iter().enumerate() {
+            let stop = starts.get(n + 1).copied().unwrap_or(instrs.len());
+            let (loc, last) = &instrs[stop - 1];
+            let next = Location::new(loc.value + last.size());
+
+            let mut targets = vec![];
+            if !matches!(last, Instr::Jump(_)) {
+                targets.push((EdgeKind::Next, next));
+            }
+            if ends_block(last) {
+                targets.extend(branches(last, *loc));
+            }
+
+            let mut successors = vec![];
+            for (mut kind, mut target) in targets {
+                if matches!(kind, EdgeKind::Next | EdgeKind::Skip) {
+                    while let Some(&exit) = exits.get(&target) {
+                        kind = EdgeKind::Exit;
+                        target = exit;
+                    }
+                }
+                if matches!(kind, EdgeKind::Next | EdgeKind::Skip | EdgeKind::Exit) && returns.contains(&target) {
+                    continue;
+                }
+                if let Some(index) = index_of(target)? {
+                    successors.push(Edge {
+                        target: block_of(index),
+                        kind,
+                    });
+                }
+            }
+            blocks.push(BasicBlock {
+                start: instrs[start].0,
+                instructions: start..stop,
+                successors,
+                predecessors: vec![],
+            });
+        }
+
+        for n in 0..blocks.len() {
+            for i in 0..blocks[n].successors.len() {
+                let target = blocks[n].successors[i].target;
+                if !blocks[target].predecessors.contains(&n) {
+                    blocks[target].predecessors.push(n);
+                }
+            }
+        }
+        Ok(Self { blocks })
+    }
+
+    #[inline]
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// Computes the dominator tree of the graph.
+    pub fn dominators(&self) -> Dominators {
+        let postorder = self.postorder();
+        let mut order = vec![None; self.blocks.len()];
+        for (n, &block) in postorder.iter().enumerate() {
+            order[block] = Some(n);
+        }
+
+        let mut idoms = vec![None; self.blocks.len()];
+        if let Some(&entry) = postorder.last() {
+            idoms[entry] = Some(entry);
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in postorder.iter().rev().skip(1) {
+                let mut new_idom = None;
+                for &pred in &self.blocks[block].predecessors {
+                    if idoms[pred].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => intersect(&idoms, &order, pred, other),
+                    });
+                }
+                if new_idom.is_some() && idoms[block] != new_idom {
+                    idoms[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        Dominators { idoms }
+    }
+
+    // the blocks reachable from the entry, each one after all of its successors except for back edges
+    fn postorder(&self) -> Vec<usize> {
+        let mut postorder = vec![];
+        if self.blocks.is_empty() {
+            return postorder;
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((block, edge)) = stack.pop() {
+            match self.blocks[block].successors.get(edge) {
+                Some(succ) => {
+                    stack.push((block, edge + 1));
+                    if !visited[succ.target] {
+                        visited[succ.target] = true;
+                        stack.push((succ.target, 0));
+                    }
+                }
+                None => postorder.push(block),
+            }
+        }
+        postorder
+    }
+}
+
+// the closest common dominator of two blocks with their dominators computed
+fn intersect(idoms: &[Option<usize>], order: &[Option<usize>], mut lhs: usize, mut rhs: usize) -> usize {
+    while lhs != rhs {
+        while order[lhs] < order[rhs] {
+            lhs = idoms[lhs].expect("processed blocks should have a dominator");
+        }
+        while order[rhs] < order[lhs] {
+            rhs = idoms[rhs].expect("processed blocks should have a dominator");
+        }
+    }
+    lhs
+}
+
+/// A sequence of instructions that is only entered at its first instruction and only left
+/// after its last one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// The location of the first instruction.
+    pub start: Location,
+    /// The indices of the instructions in the code.
+    pub instructions: Range<usize>,
+    pub successors: Vec<Edge>,
+    pub predecessors: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    /// The index of the target block.
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction.
+    Next,
+    /// An unconditional jump.
+    Jump,
+    /// The condition of a `JumpIfFalse` or a `Conditional` is false.
+    IfFalse,
+    /// An argument is skipped.
+    Skip,
+    /// The true branch of a `Conditional` has been evaluated.
+    Exit,
+    /// The value of a switch case matches, or the previous case falls through.
+    Case,
+    /// The value of a switch case does not match.
+    NextCase,
+}
+
+impl EdgeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Next => "next",
+            EdgeKind::Jump => "jump",
+            EdgeKind::IfFalse => "if false",
+            EdgeKind::Skip => "skip",
+            EdgeKind::Exit => "exit",
+            EdgeKind::Case => "case",
+            EdgeKind::NextCase => "next case",
+        }
+    }
+}
+
+/// The immediate dominators of the blocks of a control-flow graph.
+#[derive(Debug, Clone)]
+pub struct Dominators {
+    // the entry is its own dominator, unreachable blocks have none
+    idoms: Vec<Option<usize>>,
+}
+
+impl Dominators {
+    /// Returns the immediate dominator of a block, or `None` for the entry and unreachable blocks.
+    pub fn immediate_dominator(&self, block: usize) -> Option<usize> {
+        self.idoms.get(block).copied().flatten().filter(|&idom| idom != block)
+    }
+
+    /// Returns whether every path from the entry to `block` goes through `dominator`.
+    pub fn dominates(&self, dominator: usize, block: usize) -> bool {
+        if self.idoms.get(block).copied().flatten().is_none() {
+            return false;
+        }
+        let mut current = block;
+        loop {
+            if current == dominator {
+                return true;
+            }
+            match self.immediate_dominator(current) {
+                Some(idom) => current = idom,
+                None => return false,
+            }
+        }
+    }
+}
+
+fn ends_block(instr: &Instr<Offset>) -> bool {
+    matches!(
+        instr,
+        Instr::Jump(_) | Instr::JumpIfFalse(_) | Instr::Skip(_) | Instr::Conditional(_, _) | Instr::SwitchLabel(_, _)
+    )
+}
+
+// the locations an instruction can transfer control to, other than the next instruction
+fn branches(instr: &Instr<Offset>, loc: Location) -> Vec<(EdgeKind, Location)> {
+    match instr {
+        Instr::Jump(target) => vec![(EdgeKind::Jump, target.absolute(loc))],
+        Instr::JumpIfFalse(target) => vec![(EdgeKind::IfFalse, target.absolute(loc))],
+        Instr::Skip(target) => vec![(EdgeKind::Skip, target.absolute(loc))],
+        Instr::Conditional(when_false, _) => vec![(EdgeKind::IfFalse, when_false.absolute(loc))],
+        // the first case is reached after evaluating the subject that follows
+        Instr::Switch(_, first) => vec![(EdgeKind::Next, first.absolute(loc))],
+        Instr::SwitchLabel(next, body) => vec![
+            (EdgeKind::Case, body.absolute(loc)),
+            (EdgeKind::NextCase, next.absolute(loc)),
+        ],
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::PoolIndex;
+    use crate::bytecode::Label;
+
+    const L0: Label = Label { index: 0 };
+    const L1: Label = Label { index: 1 };
+    const L2: Label = Label { index: 2 };
+    const L3: Label = Label { index: 3 };
+    const L4: Label = Label { index: 4 };
+    const L5: Label = Label { index: 5 };
+
+    fn graph(code: Vec<Instr<Label>>, labels: usize) -> ControlFlowGraph {
+        ControlFlowGraph::new(&Code::new(code).resolve_labels(labels)).unwrap()
+    }
+
+    fn edges(graph: &ControlFlowGraph) -> Vec<Vec<(usize, EdgeKind)>> {
+        graph
+            .blocks()
+            .iter()
+            .map(|block| block.successors.iter().map(|edge| (edge.target, edge.kind)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn split_returns() {
+        let graph = graph(
+            vec![
+                Instr::JumpIfFalse(L0),
+                Instr::TrueConst,
+                Instr::Return,
+                Instr::I32One,
+                Instr::Target(L0),
+                Instr::Return,
+                Instr::I32Zero,
+            ],
+            1,
+        );
+        assert_eq!(
+            edges(&graph),
+            [vec![(1, EdgeKind::Next), (2, EdgeKind::IfFalse)], vec![], vec![]]
+        );
+        let instructions: Vec<_> = graph.blocks().iter().map(|block| block.instructions.clone()).collect();
+        assert_eq!(instructions, [0..1, 1..4, 4..6]);
+
+        let dominators = graph.dominators();
+        assert_eq!(dominators.immediate_dominator(0), None);
+        assert_eq!(dominators.immediate_dominator(1), Some(0));
+        assert_eq!(dominators.immediate_dominator(2), Some(0));
+    }
+
+    #[test]
+    fn link_loops_and_conditionals() {
+        let graph = graph(
+            vec![
+                Instr::Target(L0),
+                Instr::JumpIfFalse(L3),
+                Instr::TrueConst,
+                Instr::Conditional(L1, L2),
+                Instr::TrueConst,
+                Instr::I32One,
+                Instr::Target(L1),
+                Instr::I32Zero,
+                Instr::Target(L2),
+                Instr::Jump(L0),
+                Instr::Target(L3),
+                Instr::Return,
+                Instr::Nop,
+            ],
+            4,
+        );
+        assert_eq!(
+            edges(&graph),
+            [
+                vec![(1, EdgeKind::Next), (5, EdgeKind::IfFalse)],
+                vec![(2, EdgeKind::Next), (3, EdgeKind::IfFalse)],
+                vec![(4, EdgeKind::Exit)],
+                vec![(4, EdgeKind::Next)],
+                vec![(0, EdgeKind::Jump)],
+                vec![],
+            ]
+        );
+        assert_eq!(graph.blocks()[0].predecessors, [4]);
+        assert_eq!(graph.blocks()[4].predecessors, [2, 3]);
+
+        let dominators = graph.dominators();
+        let idoms: Vec<_> = (0..6).map(|block| dominators.immediate_dominator(block)).collect();
+        assert_eq!(idoms, [None, Some(0), Some(1), Some(1), Some(1), Some(0)]);
+        assert!(dominators.dominates(0, 4));
+        assert!(dominators.dominates(1, 4));
+        assert!(!dominators.dominates(2, 4));
+        assert!(!dominators.dominates(4, 5));
+    }
+
+    #[test]
+    fn link_switch_cases() {
+        let graph = graph(
+            vec![
+                Instr::Switch(PoolIndex::UNDEFINED, L0),
+                Instr::I32One,
+                Instr::Target(L0),
+                Instr::SwitchLabel(L2, L1),
+                Instr::I32One,
+                Instr::Target(L1),
+                Instr::Nop,
+                Instr::Target(L2),
+                Instr::SwitchLabel(L4, L3),
+                Instr::I32Zero,
+                Instr::Target(L3),
+                Instr::Jump(L5),
+                Instr::Target(L4),
+                Instr::SwitchDefault,
+                Instr::Nop,
+                Instr::Target(L5),
+                Instr::Return,
+                Instr::Nop,
+            ],
+            6,
+        );
+        assert_eq!(
+            edges(&graph),
+            [
+                vec![(1, EdgeKind::Next)],
+                vec![(2, EdgeKind::Next), (3, EdgeKind::Case), (4, EdgeKind::NextCase)],
+                vec![(3, EdgeKind::Next)],
+                vec![(4, EdgeKind::Next)],
+                vec![(5, EdgeKind::Next), (6, EdgeKind::Case), (7, EdgeKind::NextCase)],
+                vec![(6, EdgeKind::Next)],
+                vec![(8, EdgeKind::Jump)],
+                vec![(8, EdgeKind::Next)],
+                vec![],
+            ]
+        );
+        let dominators = graph.dominators();
+        assert_eq!(dominators.immediate_dominator(8), Some(4));
+        assert_eq!(dominators.immediate_dominator(3), Some(1));
+    }
+
+    #[test]
+    fn reject_invalid_targets() {
+        let code = Code::new(vec![Instr::Jump(Offset::new(1)), Instr::Nop]);
+        assert!(matches!(
+            ControlFlowGraph::new(&code),
+            Err(CursorError::InvalidInstructionOffset(Location { value: 1 }))
+        ));
+    }
+}
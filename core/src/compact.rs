@@ -0,0 +1,241 @@
+use crate::bundle::{ConstantPool, PoolIndex};
+use crate::bytecode::PoolRef;
+use crate::definition::{AnyDefinition, Definition, Type};
+use crate::mapper::Relocation;
+
+/// Removes the entries of the pool that are not reachable from its root classes, enums, functions
+/// and primitive types, and renumbers the remaining ones. Primitive types are always retained,
+/// because the compiler resolves them by name. Returns the relocation that was applied,
+/// which can be used to update indices stored outside of the pool.
+pub fn compact(pool: &mut ConstantPool) -> Relocation {
+    let mut marker = Marker::new(pool);
+    for (idx, def) in pool.roots() {
+        if matches!(
+            def.value,
+            AnyDefinition::Class(_)
+                | AnyDefinition::Enum(_)
+                | AnyDefinition::Function(_)
+                | AnyDefinition::Type(Type::Prim)
+        ) {
+            marker.definition(idx);
+        }
+    }
+    while let Some(idx) = marker.queue.pop() {
+        if let Ok(def) = pool.definition(idx) {
+            marker.visit(def);
+        }
+    }
+
+    let relocation = Relocation::retaining(
+        &marker.definitions,
+        &marker.names,
+        &marker.tweakdb_ids,
+        &marker.resources,
+        &marker.strings,
+    );
+    relocation.apply(pool);
+    relocation
+}
+
+struct Marker {
+    definitions: Vec<bool>,
+    names: Vec<bool>,
+    tweakdb_ids: Vec<bool>,
+    resources: Vec<bool>,
+    strings: Vec<bool>,
+    queue: Vec<PoolIndex<Definition>>,
+}
+
+impl Marker {
+    fn new(pool: &ConstantPool) -> Self {
+        assert!(!pool.is_overlay(), "an overlay can't be compacted");
+        let mut marker = Self {
+            definitions: vec![false; pool.definitions.len()],
+            names: vec![false; pool.names.entries().len()],
+            tweakdb_ids: vec![false; pool.tweakdb_ids.entries().len()],
+            resources: vec![false; pool.resources.entries().len()],
+            strings: vec![false; pool.strings.entries().len()],
+            queue: vec![],
+        };
+        // the first definition and name stand for undefined entries
+        mark(&mut marker.definitions, PoolIndex::<Definition>::UNDEFINED);
+        mark(&mut marker.names, PoolIndex::<()>::UNDEFINED);
+        marker
+    }
+
+    fn definition<A>(&mut self, index: PoolIndex<A>) {
+        if mark(&mut self.definitions, index) {
+            self.queue.push(index.cast());
+        }
+    }
+
+    fn definitions<A>(&mut self, indexes: &[PoolIndex<A>]) {
+        for &idx in indexes {
+            self.definition(idx);
+        }
+    }
+
+    fn visit(&mut self, def: &Definition) {
+        mark(&mut self.names, def.name);
+        self.definition(def.parent);
+        match &def.value {
+            AnyDefinition::Type(type_) => match type_ {
+                Type::Ref(inner)
+                | Type::WeakRef(inner)
+                | Type::Array(inner)
+                | Type::StaticArray(inner, _)
+                | Type::ScriptRef(inner) => self.definition(*inner),
+                Type::Prim | Type::Class => {}
+            },
+            AnyDefinition::Class(class) => {
+                self.definition(class.base);
+                self.definitions(&class.functions);
+                self.definitions(&class.fields);
+                self.definitions(&class.overrides);
+            }
+            AnyDefinition::Enum(enum_) => self.definitions(&enum_.members),
+            AnyDefinition::Function(fun) => {
+                if let Some(source) = &fun.source {
+                    self.definition(source.file);
+                }
+                self.definitions(fun.return_type.as_slice());
+                self.definitions(fun.base_method.as_slice());
+                self.definitions(&fun.parameters);
+                self.definitions(&fun.locals);
+                self.definitions(&fun.unk2);
+                for (_, instr) in fun.code.iter() {
+                    for ref_ in instr.pool_refs() {
+                        self.pool_ref(ref_);
+                    }
+                }
+            }
+            AnyDefinition::Field(field) => self.definition(field.type_),
+            AnyDefinition::Local(local) => self.definition(local.type_),
+            AnyDefinition::Parameter(param) => self.definition(param.type_),
+            AnyDefinition::EnumValue(_) | AnyDefinition::SourceFile(_) => {}
+        }
+    }
+
+    fn pool_ref(&mut self, ref_: PoolRef) {
+        let marks = match ref_ {
+            PoolRef::Name(_) => &mut self.names,
+            PoolRef::TweakDbId(_) => &mut self.tweakdb_ids,
+            PoolRef::Resource(_) => &mut self.resources,
+            PoolRef::String(_) => &mut self.strings,
+            other => return self.definition(other.index::<Definition>()),
+        };
+        mark(marks, ref_.index::<()>());
+    }
+}
+
+// returns whether the entry was not marked before
+fn mark<A>(marks: &mut [bool], index: PoolIndex<A>) -> bool {
+    match marks.get_mut(u32::from(index) as usize) {
+        Some(marked) if !*marked => {
+            *marked = true;
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::compact;
+    use crate::bundle::{PoolIndex, ScriptBundle};
+    use crate::bytecode::Code;
+    use crate::definition::{
+        AnyDefinition, Class, ClassFlags, Definition, Function, FunctionFlags, Local, LocalFlags, Type, Visibility,
+    };
+    use crate::mapper::Relocation;
+
+    const PREDEF: &[u8] = include_bytes!("../../resources/predef.redscripts");
+
+    #[test]
+    fn remove_unreachable_entries() {
+        let mut bundle = ScriptBundle::load(&mut Cursor::new(PREDEF)).unwrap();
+        let pool = &mut bundle.pool;
+        let (type_idx, _) = pool.definitions().last().unwrap();
+        let is_prim = |def: &Definition| matches!(def.value, AnyDefinition::Type(Type::Prim));
+        let prims = pool.definitions().filter(|(_, def)| is_prim(def)).count();
+        let name = pool.names.add("orphan".into());
+        let orphan = pool.add_definition::<Local>(Definition::local(
+            name,
+            PoolIndex::UNDEFINED,
+            Local::new(type_idx.cast(), LocalFlags::new()),
+        ));
+
+        let relocation = compact(pool);
+        assert_eq!(relocation.definition(orphan), None);
+        assert_eq!(
+            relocation.definition(PoolIndex::<Definition>::UNDEFINED),
+            Some(PoolIndex::UNDEFINED)
+        );
+        // predef only contains types, only the primitive ones are retained without a reference
+        assert_eq!(pool.definitions().count(), prims);
+        assert!(pool.definitions().all(|(_, def)| is_prim(def)));
+        assert_eq!(pool.names.get_index("orphan"), None);
+        assert_eq!(pool.names.get(PoolIndex::UNDEFINED).unwrap().as_ref(), "None");
+    }
+
+    #[test]
+    fn drop_references_to_removed_definitions() {
+        let mut pool = ScriptBundle::load(&mut Cursor::new(PREDEF)).unwrap().pool;
+        let function = Function {
+            visibility: Visibility::Public,
+            flags: FunctionFlags::new(),
+            source: None,
+            return_type: None,
+            unk1: false,
+            base_method: None,
+            parameters: vec![],
+            locals: vec![],
+            operator: None,
+            cast: 0,
+            code: Code::EMPTY,
+            unk2: vec![],
+        };
+        let base_name = pool.names.add("Base".into());
+        let base =
+            pool.add_definition::<Function>(Definition::function(base_name, PoolIndex::UNDEFINED, function.clone()));
+        let class_name = pool.names.add("Class".into());
+        let class_idx = pool.reserve::<Class>();
+        let method_name = pool.names.add("Method".into());
+        let method_idx = pool.add_definition::<Function>(Definition::function(
+            method_name,
+            class_idx.cast(),
+            Function {
+                base_method: Some(base),
+                ..function
+            },
+        ));
+        let class = Class {
+            visibility: Visibility::Public,
+            flags: ClassFlags::new(),
+            base: PoolIndex::UNDEFINED,
+            functions: vec![base, method_idx],
+            fields: vec![],
+            overrides: vec![],
+        };
+        pool.put_definition(class_idx, Definition::class(class_name, class));
+
+        let size = pool.mark();
+        let mut definitions = vec![true; size.definitions];
+        definitions[u32::from(base) as usize] = false;
+        let relocation = Relocation::retaining(
+            &definitions,
+            &vec![true; size.names],
+            &vec![true; size.tweakdb_ids],
+            &vec![true; size.resources],
+            &vec![true; size.strings],
+        );
+        relocation.apply(&mut pool);
+
+        let method = pool.function(relocation.definition(method_idx).unwrap()).unwrap();
+        assert_eq!(method.base_method, None);
+        let class = pool.class(relocation.definition(class_idx).unwrap()).unwrap();
+        assert_eq!(class.functions, [relocation.definition(method_idx).unwrap()]);
+    }
+}
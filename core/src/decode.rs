@@ -1,6 +1,12 @@
-use std::io;
+use std::io::Read as _;
+use std::{fmt, io};
 
 use byteorder::{LittleEndian, ReadBytesExt};
+use thiserror::Error;
+
+// the capacity reserved up front for a sequence, larger sequences grow as they're read
+// so that a corrupted length cannot trigger a huge allocation
+pub(crate) const MAX_PREALLOCATED: usize = 4096;
 
 pub trait Decode: Sized {
     fn decode<I: io::Read>(input: &mut I) -> io::Result<Self>;
@@ -114,7 +120,7 @@ pub trait DecodeExt: io::Read + Sized {
 
     fn decode_vec<S: Into<u32>, A: Decode>(&mut self, count: S) -> io::Result<Vec<A>> {
         let size = count.into() as usize;
-        let mut vec = Vec::with_capacity(size);
+        let mut vec = Vec::with_capacity(size.min(MAX_PREALLOCATED));
         for _ in 0..size {
             vec.push(self.decode()?);
         }
@@ -127,9 +133,12 @@ pub trait DecodeExt: io::Read + Sized {
     }
 
     fn decode_bytes<S: Into<u32>>(&mut self, count: S) -> io::Result<Vec<u8>> {
-        let size = count.into() as usize;
-        let mut vec = vec![0; size];
-        self.read_exact(&mut vec)?;
+        let size = count.into();
+        let mut vec = Vec::with_capacity((size as usize).min(MAX_PREALLOCATED));
+        self.by_ref().take(size.into()).read_to_end(&mut vec)?;
+        if vec.len() != size as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
         Ok(vec)
     }
 
@@ -144,3 +153,102 @@ pub trait DecodeExt: io::Read + Sized {
 }
 
 impl<I: io::Read> DecodeExt for I {}
+
+/// An error produced when a bundle cannot be decoded, it points at the entry that was being read.
+#[derive(Debug)]
+pub struct DecodeError {
+    /// The table the entry belongs to.
+    pub table: Table,
+    /// The index of the entry in the table, if the error is specific to one.
+    pub index: Option<u32>,
+    /// The offset in the input at which decoding stopped, just past the last byte read.
+    pub offset: u64,
+    pub cause: io::Error,
+}
+
+impl DecodeError {
+    pub fn new(table: Table, index: Option<u32>, offset: u64, cause: io::Error) -> Self {
+        Self {
+            table,
+            index,
+            offset,
+            cause,
+        }
+    }
+
+    /// Returns a description of the data that was expected if the input contained an invalid value.
+    pub fn expected(&self) -> Option<&'static str> {
+        let invalid = self.cause.get_ref()?.downcast_ref::<InvalidValue>()?;
+        Some(invalid.expected)
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to decode the {}", self.table)?;
+        if let Some(index) = self.index {
+            write!(f, " entry {index}")?;
+        }
+        write!(f, " at offset {:#x}: {}", self.offset, self.cause)
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.cause)
+    }
+}
+
+impl From<DecodeError> for io::Error {
+    fn from(err: DecodeError) -> Self {
+        io::Error::new(err.cause.kind(), err)
+    }
+}
+
+/// A section of a bundle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Table {
+    Header,
+    StringData,
+    Names,
+    TweakDbIds,
+    Resources,
+    DefinitionHeaders,
+    Strings,
+    Definitions,
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            Table::Header => "header",
+            Table::StringData => "string data",
+            Table::Names => "name table",
+            Table::TweakDbIds => "TweakDB ID table",
+            Table::Resources => "resource table",
+            Table::DefinitionHeaders => "definition header table",
+            Table::Strings => "string table",
+            Table::Definitions => "definition table",
+        };
+        f.write_str(str)
+    }
+}
+
+/// A value that is not valid where it was found, like an unknown enum tag.
+#[derive(Debug, Error)]
+#[error("expected {expected}, found {found}")]
+pub struct InvalidValue {
+    pub expected: &'static str,
+    pub found: String,
+}
+
+impl InvalidValue {
+    /// Creates an [`io::Error`] of kind [`io::ErrorKind::InvalidData`] carrying the invalid value.
+    pub fn error(expected: &'static str, found: impl fmt::Display) -> io::Error {
+        let value = Self {
+            expected,
+            found: found.to_string(),
+        };
+        io::Error::new(io::ErrorKind::InvalidData, value)
+    }
+}
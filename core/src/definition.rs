@@ -6,7 +6,7 @@ use modular_bitfield::prelude::*;
 
 use crate::bundle::{CName, ConstantPool, DefinitionHeader, DefinitionType, PoolIndex};
 use crate::bytecode::{Code, Offset};
-use crate::decode::{Decode, DecodeExt};
+use crate::decode::{Decode, DecodeExt, InvalidValue};
 use crate::encode::{Encode, EncodeExt};
 
 #[derive(Debug, Clone)]
@@ -37,7 +37,7 @@ impl Definition {
             DefinitionType::Class => AnyDefinition::Class(input.decode()?),
             DefinitionType::EnumValue => AnyDefinition::EnumValue(input.decode()?),
             DefinitionType::Enum => AnyDefinition::Enum(input.decode()?),
-            DefinitionType::BitField => panic!("Bit field not supported"),
+            DefinitionType::BitField => return Err(InvalidValue::error("a supported definition type", "bit field")),
             DefinitionType::Function => AnyDefinition::Function(input.decode()?),
             DefinitionType::Parameter => AnyDefinition::Parameter(input.decode()?),
             DefinitionType::Local => AnyDefinition::Local(input.decode()?),
@@ -484,7 +484,7 @@ impl Decode for Type {
             4 => Ok(Type::Array(input.decode()?)),
             5 => Ok(Type::StaticArray(input.decode()?, input.decode()?)),
             6 => Ok(Type::ScriptRef(input.decode()?)),
-            _ => panic!("Unknown Type enum value {}", tag),
+            _ => Err(InvalidValue::error("a type tag", tag)),
         }
     }
 }
@@ -760,7 +760,8 @@ impl fmt::Display for Visibility {
 
 impl Decode for Visibility {
     fn decode<I: io::Read>(input: &mut I) -> io::Result<Self> {
-        Ok(Visibility::from_bytes(input.decode()?).expect("Invalid Visibility enum value"))
+        let byte: u8 = input.decode()?;
+        Visibility::from_bytes(byte).map_err(|_| InvalidValue::error("a visibility", byte))
     }
 }
 
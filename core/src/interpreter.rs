@@ -0,0 +1,1040 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::mem;
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+use thiserror::Error;
+
+use crate::bundle::{CName, ConstantPool, PoolError, PoolIndex};
+use crate::bytecode::{CodeCursor, CursorError, Instr, Location, Offset};
+use crate::definition::{AnyDefinition, Class, Definition, Field, Function, Local, Parameter, Type};
+use crate::Ref;
+
+mod natives;
+mod value;
+
+pub use natives::{NativeCall, NativeFn, Natives};
+pub use value::{Number, Object, ObjectRef, Struct, Value};
+
+/// Maximum depth of nested script calls before execution is aborted.
+const MAX_CALL_DEPTH: usize = 128;
+/// The method invoked on function objects passed as predicates.
+const FUNCTION_OBJECT_METHOD: &str = "Invoke";
+
+/// An interpreter that executes compiled bytecode straight from a constant pool,
+/// with native functions routed to Rust stubs registered in [`Natives`].
+pub struct Interpreter<'a> {
+    pool: &'a ConstantPool,
+    natives: Natives,
+    types: HashMap<Ref<str>, PoolIndex<Definition>>,
+    depth: usize,
+    caller: PoolIndex<Function>,
+    line: u16,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(pool: &'a ConstantPool, natives: Natives) -> Self {
+        let types = pool
+            .definitions()
+            .filter(|(_, def)| matches!(def.value, AnyDefinition::Class(_) | AnyDefinition::Enum(_)))
+            .filter_map(|(idx, def)| Some((pool.names.get(def.name).ok()?, idx)))
+            .collect();
+        Self {
+            pool,
+            natives,
+            types,
+            depth: 0,
+            caller: PoolIndex::UNDEFINED,
+            line: 0,
+        }
+    }
+
+    #[inline]
+    pub fn pool(&self) -> &'a ConstantPool {
+        self.pool
+    }
+
+    #[inline]
+    pub fn natives_mut(&mut self) -> &mut Natives {
+        &mut self.natives
+    }
+
+    /// Finds a global function by either its full or its short name.
+    pub fn find_function(&self, name: &str) -> Option<PoolIndex<Function>> {
+        self.pool.roots().find_map(|(idx, def)| match def.value {
+            AnyDefinition::Function(_) => {
+                let full_name = self.pool.names.get(def.name).ok()?;
+                let short_name = full_name.split(';').next().unwrap_or_default();
+                (full_name.as_ref() == name || short_name == name).then(|| idx.cast())
+            }
+            _ => None,
+        })
+    }
+
+    /// Calls a function with the given receiver and arguments.
+    /// Arguments for `out` parameters are updated in place once the call returns.
+    pub fn call(
+        &mut self,
+        index: PoolIndex<Function>,
+        this: Option<ObjectRef>,
+        args: &mut [Value],
+    ) -> Result<Value, RuntimeError> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::StackOverflow);
+        }
+        let pool = self.pool;
+        let fun = pool.function(index)?;
+        if fun.flags.is_native() {
+            return self.call_native(index, this, args);
+        }
+
+        let mut frame = Frame {
+            function: index,
+            this,
+            params: HashMap::new(),
+            locals: HashMap::new(),
+            code: fun.code.cursor(),
+        };
+        for (i, param) in fun.parameters.iter().enumerate() {
+            let val = match args.get(i) {
+                Some(val) => val.clone(),
+                None => self.default_value(pool.parameter(*param)?.type_)?,
+            };
+            frame.params.insert(*param, val);
+        }
+        for local in &fun.locals {
+            let val = self.default_value(pool.local(*local)?.type_)?;
+            frame.locals.insert(*local, val);
+        }
+
+        self.depth += 1;
+        let res = self.execute(&mut frame);
+        self.depth -= 1;
+        let res = res?;
+
+        for (param, arg) in fun.parameters.iter().zip(args.iter_mut()) {
+            if pool.parameter(*param)?.flags.is_out() {
+                if let Some(val) = frame.params.remove(param) {
+                    *arg = val;
+                }
+            }
+        }
+        match (res, fun.return_type) {
+            (Value::Void, Some(typ)) => self.default_value(typ),
+            (res, _) => Ok(res),
+        }
+    }
+
+    fn call_native(
+        &mut self,
+        index: PoolIndex<Function>,
+        this: Option<ObjectRef>,
+        args: &mut [Value],
+    ) -> Result<Value, RuntimeError> {
+        let stub = self.native_stub(index)?;
+        let mut call = NativeCall {
+            pool: self.pool,
+            function: index,
+            caller: self.caller,
+            line: self.line,
+            this,
+            args,
+            deferred: None,
+        };
+        stub(&mut call)
+    }
+
+    /// Calls a native function with arguments for short-circuit parameters left unevaluated.
+    /// The stub evaluates them on demand, starting at the recorded locations of the frame.
+    fn call_native_deferred(
+        &mut self,
+        frame: &mut Frame<'a>,
+        index: PoolIndex<Function>,
+        this: Option<ObjectRef>,
+        args: &mut [Value],
+        mut deferred: Vec<Option<Location>>,
+    ) -> Result<Value, RuntimeError> {
+        let stub = self.native_stub(index)?;
+        let pool = self.pool;
+        let caller = self.caller;
+        let line = self.line;
+        let mut eval = |i: usize| -> Result<Option<Value>, RuntimeError> {
+            let Some(start) = deferred.get_mut(i).and_then(Option::take) else {
+                return Ok(None);
+            };
+            let resume = frame.code.pos();
+            frame.code.seek_abs(start)?;
+            let res = self.eval(frame);
+            frame.code.seek_abs(resume)?;
+            res.map(Some)
+        };
+        let mut call = NativeCall {
+            pool,
+            function: index,
+            caller,
+            line,
+            this,
+            args,
+            deferred: Some(&mut eval),
+        };
+        stub(&mut call)
+    }
+
+    fn native_stub(&self, index: PoolIndex<Function>) -> Result<Rc<NativeFn>, RuntimeError> {
+        let def = self.pool.definition(index)?;
+        let name = self.pool.names.get(def.name)?;
+        let short_name = name.split(';').next().unwrap_or_default();
+        let qualified = if def.parent.is_undefined() {
+            None
+        } else {
+            Some(format!("{}::{}", self.pool.def_name(def.parent)?, short_name))
+        };
+        self.natives
+            .get_shared(&name)
+            .or_else(|| qualified.as_deref().and_then(|name| self.natives.get_shared(name)))
+            .or_else(|| self.natives.get_shared(short_name))
+            .ok_or_else(|| RuntimeError::NativeNotFound(name.as_ref().to_owned()))
+    }
+
+    fn execute(&mut self, frame: &mut Frame<'a>) -> Result<Value, RuntimeError> {
+        loop {
+            let pos = frame.code.pos();
+            let instr = match frame.code.pop() {
+                Ok(instr) => instr,
+                Err(CursorError::EndOfCode) => return Ok(Value::Void),
+                Err(err) => return Err(err.into()),
+            };
+            match instr {
+                Instr::Return => return self.eval(frame),
+                Instr::Jump(offset) => frame.code.seek_abs(offset.absolute(pos))?,
+                Instr::JumpIfFalse(offset) => {
+                    if !self.eval_bool(frame)? {
+                        frame.code.seek_abs(offset.absolute(pos))?;
+                    }
+                }
+                Instr::Switch(_, first) => self.execute_switch(frame, first.absolute(pos))?,
+                // falling through into the next case skips over its matcher
+                Instr::SwitchLabel(_, body) => frame.code.seek_abs(body.absolute(pos))?,
+                Instr::SwitchDefault | Instr::Nop | Instr::Breakpoint(_) | Instr::StartProfiling(_) => {}
+                other => {
+                    self.eval_instr(frame, pos, other, None)?;
+                }
+            }
+        }
+    }
+
+    fn execute_switch(&mut self, frame: &mut Frame<'a>, first: Location) -> Result<(), RuntimeError> {
+        let subject = self.eval(frame)?;
+        frame.code.seek_abs(first)?;
+        loop {
+            let pos = frame.code.pos();
+            match frame.code.peek() {
+                Some(Instr::SwitchLabel(next, body)) => {
+                    frame.code.pop()?;
+                    if self.eval(frame)? == subject {
+                        frame.code.seek_abs(body.absolute(pos))?;
+                        return Ok(());
+                    }
+                    frame.code.seek_abs(next.absolute(pos))?;
+                }
+                Some(Instr::SwitchDefault) => {
+                    frame.code.pop()?;
+                    return Ok(());
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    #[inline]
+    fn eval(&mut self, frame: &mut Frame<'a>) -> Result<Value, RuntimeError> {
+        self.eval_with(frame, None)
+    }
+
+    fn eval_with(&mut self, frame: &mut Frame<'a>, context: Option<Value>) -> Result<Value, RuntimeError> {
+        let pos = frame.code.pos();
+        let instr = frame.code.pop()?;
+        self.eval_instr(frame, pos, instr, context)
+    }
+
+    fn eval_bool(&mut self, frame: &mut Frame<'a>) -> Result<bool, RuntimeError> {
+        let val = self.eval(frame)?;
+        val.as_bool().ok_or_else(|| RuntimeError::type_mismatch("Bool", &val))
+    }
+
+    fn eval_index(&mut self, frame: &mut Frame<'a>) -> Result<usize, RuntimeError> {
+        let val = self.eval(frame)?;
+        match val.as_number() {
+            Some(Number::Int(i)) => i.try_into().map_err(|_| RuntimeError::InvalidIndex(i)),
+            _ => Err(RuntimeError::type_mismatch("integer", &val)),
+        }
+    }
+
+    fn eval_array(&mut self, frame: &mut Frame<'a>) -> Result<Vec<Value>, RuntimeError> {
+        match self.eval(frame)? {
+            Value::Array(items) => Ok(items),
+            other => Err(RuntimeError::type_mismatch("array", &other)),
+        }
+    }
+
+    fn eval_instr(
+        &mut self,
+        frame: &mut Frame<'a>,
+        pos: Location,
+        instr: Instr<Offset>,
+        context: Option<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let pool = self.pool;
+        let res = match instr {
+            Instr::Nop | Instr::Breakpoint(_) | Instr::StartProfiling(_) => Value::Void,
+            Instr::Null | Instr::WeakRefNull => Value::Null,
+            Instr::I32One => Value::I32(1),
+            Instr::I32Zero => Value::I32(0),
+            Instr::I8Const(i) => Value::I8(i),
+            Instr::I16Const(i) => Value::I16(i),
+            Instr::I32Const(i) => Value::I32(i),
+            Instr::I64Const(i) => Value::I64(i),
+            Instr::U8Const(i) => Value::U8(i),
+            Instr::U16Const(i) => Value::U16(i),
+            Instr::U32Const(i) => Value::U32(i),
+            Instr::U64Const(i) => Value::U64(i),
+            Instr::F32Const(f) => Value::F32(f),
+            Instr::F64Const(f) => Value::F64(f),
+            Instr::NameConst(idx) => Value::Name(pool.names.get(idx)?),
+            Instr::EnumConst(enum_, member) => Value::Enum(enum_, pool.enum_value(member)?),
+            Instr::StringConst(idx) => Value::String(pool.strings.get(idx)?),
+            Instr::TweakDbIdConst(idx) => Value::TweakDbId(pool.tweakdb_ids.get(idx)?),
+            Instr::ResourceConst(idx) => Value::Resource(pool.resources.get(idx)?),
+            Instr::TrueConst => Value::Bool(true),
+            Instr::FalseConst => Value::Bool(false),
+            Instr::Assign => {
+                let place = self.eval_place(frame, None)?;
+                let val = self.eval(frame)?;
+                Self::write_place(frame, &place, val)?;
+                Value::Void
+            }
+            Instr::Local(idx) => frame.locals.get(&idx).cloned().unwrap_or_default(),
+            Instr::Param(idx) => frame.params.get(&idx).cloned().unwrap_or_default(),
+            Instr::ObjectField(field) => {
+                let obj = Self::context_object(frame, context)?;
+                let val = obj.borrow().fields.get(&field).cloned().unwrap_or_default();
+                val
+            }
+            // an external variable refers to the value the expression is evaluated on
+            Instr::ExternalVar => match context {
+                Some(val) => val,
+                None => frame.this.clone().map_or(Value::Null, Value::Object),
+            },
+            Instr::Skip(_) => self.eval(frame)?,
+            Instr::Conditional(when_false, exit) => {
+                let res = if self.eval_bool(frame)? {
+                    self.eval(frame)?
+                } else {
+                    frame.code.seek_abs(when_false.absolute(pos))?;
+                    self.eval(frame)?
+                };
+                frame.code.seek_abs(exit.absolute(pos))?;
+                res
+            }
+            Instr::Construct(count, class) => {
+                let fields = &pool.class(class)?.fields;
+                let mut res = self.default_struct(class)?;
+                for i in 0..usize::from(count) {
+                    let val = self.eval(frame)?;
+                    if let Some(field) = fields.get(i) {
+                        res.fields.insert(*field, val);
+                    }
+                }
+                Value::Struct(Box::new(res))
+            }
+            Instr::InvokeStatic(_, line, fun, _) => self.eval_invoke(frame, fun, context, line)?,
+            Instr::InvokeVirtual(_, line, name, _) => {
+                let obj = match context {
+                    Some(val) => val.as_object().ok_or(RuntimeError::NullReference)?,
+                    None => frame.this.clone().ok_or(RuntimeError::MissingThis)?,
+                };
+                let class = obj.borrow().class;
+                let fun = self.resolve_virtual(class, name)?;
+                self.eval_invoke(frame, fun, Some(Value::Object(obj)), line)?
+            }
+            Instr::StructField(field) => match self.eval(frame)? {
+                Value::Struct(res) => res.fields.get(&field).cloned().unwrap_or_default(),
+                other => return Err(RuntimeError::type_mismatch("struct", &other)),
+            },
+            Instr::Context(_) => {
+                let ctx = self.eval(frame)?;
+                if ctx.is_null() {
+                    return Err(RuntimeError::NullReference);
+                }
+                self.eval_with(frame, Some(ctx))?
+            }
+            Instr::Equals(_) | Instr::RefStringEqualsString(_) | Instr::StringEqualsRefString(_) => {
+                let lhs = self.eval(frame)?;
+                let rhs = self.eval(frame)?;
+                Value::Bool(lhs == rhs)
+            }
+            Instr::NotEquals(_) | Instr::RefStringNotEqualsString(_) | Instr::StringNotEqualsRefString(_) => {
+                let lhs = self.eval(frame)?;
+                let rhs = self.eval(frame)?;
+                Value::Bool(lhs != rhs)
+            }
+            Instr::New(class) => Value::Object(self.instantiate(class)?),
+            Instr::Delete => {
+                self.eval(frame)?;
+                Value::Void
+            }
+            Instr::This => Value::Object(frame.this.clone().ok_or(RuntimeError::MissingThis)?),
+            Instr::ArrayClear(_) => {
+                let place = self.eval_place(frame, None)?;
+                Self::modify_array(frame, &place, |items| {
+                    items.clear();
+                    Ok(Value::Void)
+                })?
+            }
+            Instr::ArraySize(_) | Instr::StaticArraySize(_) => {
+                let len = self.eval_array(frame)?.len();
+                Value::I32(len.try_into().map_err(|_| RuntimeError::ArithmeticError)?)
+            }
+            Instr::ArrayResize(typ) => {
+                let place = self.eval_place(frame, None)?;
+                let size = self.eval_index(frame)?;
+                let default = self.default_value(self.element_type(typ)?)?;
+                Self::modify_array(frame, &place, |items| {
+                    items.resize(size, default);
+                    Ok(Value::Void)
+                })?
+            }
+            Instr::ArrayFindFirst(_)
+            | Instr::ArrayFindFirstFast(_)
+            | Instr::StaticArrayFindFirst(_)
+            | Instr::StaticArrayFindFirstFast(_) => {
+                let items = self.eval_array(frame)?;
+                let val = self.eval(frame)?;
+                Self::index_value(items.iter().position(|item| *item == val))?
+            }
+            Instr::ArrayFindLast(_)
+            | Instr::ArrayFindLastFast(_)
+            | Instr::StaticArrayFindLast(_)
+            | Instr::StaticArrayFindLastFast(_) => {
+                let items = self.eval_array(frame)?;
+                let val = self.eval(frame)?;
+                Self::index_value(items.iter().rposition(|item| *item == val))?
+            }
+            Instr::ArrayContains(_)
+            | Instr::ArrayContainsFast(_)
+            | Instr::StaticArrayContains(_)
+            | Instr::StaticArrayContainsFast(_) => {
+                let items = self.eval_array(frame)?;
+                let val = self.eval(frame)?;
+                Value::Bool(items.contains(&val))
+            }
+            Instr::ArrayCount(_)
+            | Instr::ArrayCountFast(_)
+            | Instr::StaticArrayCount(_)
+            | Instr::StaticArrayCountFast(_) => {
+                let items = self.eval_array(frame)?;
+                let val = self.eval(frame)?;
+                let count = items.iter().filter(|item| **item == val).count();
+                Value::I32(count.try_into().map_err(|_| RuntimeError::ArithmeticError)?)
+            }
+            Instr::ArrayPush(_) => {
+                let place = self.eval_place(frame, None)?;
+                let val = self.eval(frame)?;
+                Self::modify_array(frame, &place, |items| {
+                    items.push(val);
+                    Ok(Value::Void)
+                })?
+            }
+            Instr::ArrayPop(_) => {
+                let place = self.eval_place(frame, None)?;
+                Self::modify_array(frame, &place, |items| {
+                    items.pop().ok_or(RuntimeError::IndexOutOfBounds(0, 0))
+                })?
+            }
+            Instr::ArrayInsert(_) => {
+                let place = self.eval_place(frame, None)?;
+                let idx = self.eval_index(frame)?;
+                let val = self.eval(frame)?;
+                Self::modify_array(frame, &place, |items| {
+                    if idx > items.len() {
+                        return Err(RuntimeError::IndexOutOfBounds(idx, items.len()));
+                    }
+                    items.insert(idx, val);
+                    Ok(Value::Void)
+                })?
+            }
+            Instr::ArrayRemove(_) | Instr::ArrayRemoveFast(_) => {
+                let place = self.eval_place(frame, None)?;
+                let val = self.eval(frame)?;
+                Self::modify_array(frame, &place, |items| {
+                    match items.iter().position(|item| *item == val) {
+                        Some(idx) => {
+                            items.remove(idx);
+                            Ok(Value::Bool(true))
+                        }
+                        None => Ok(Value::Bool(false)),
+                    }
+                })?
+            }
+            Instr::ArrayGrow(typ) => {
+                let place = self.eval_place(frame, None)?;
+                let count = self.eval_index(frame)?;
+                let default = self.default_value(self.element_type(typ)?)?;
+                Self::modify_array(frame, &place, |items| {
+                    items.resize(items.len() + count, default);
+                    Ok(Value::Void)
+                })?
+            }
+            Instr::ArrayErase(_) | Instr::ArrayEraseFast(_) => {
+                let place = self.eval_place(frame, None)?;
+                let idx = self.eval_index(frame)?;
+                Self::modify_array(frame, &place, |items| {
+                    if idx < items.len() {
+                        items.remove(idx);
+                        Ok(Value::Bool(true))
+                    } else {
+                        Ok(Value::Bool(false))
+                    }
+                })?
+            }
+            Instr::ArrayLast(_) | Instr::StaticArrayLast(_) => {
+                let mut items = self.eval_array(frame)?;
+                items.pop().ok_or(RuntimeError::IndexOutOfBounds(0, 0))?
+            }
+            Instr::ArrayElement(_) | Instr::StaticArrayElement(_) => {
+                let mut items = self.eval_array(frame)?;
+                let idx = self.eval_index(frame)?;
+                if idx >= items.len() {
+                    return Err(RuntimeError::IndexOutOfBounds(idx, items.len()));
+                }
+                items.swap_remove(idx)
+            }
+            Instr::ArraySort(_) => {
+                let place = self.eval_place(frame, None)?;
+                Self::modify_array(frame, &place, |items| {
+                    items.sort_by(compare_values);
+                    Ok(Value::Void)
+                })?
+            }
+            Instr::ArraySortByPredicate(_) => {
+                let place = self.eval_place(frame, None)?;
+                let predicate = self.eval(frame)?;
+                Self::modify_array(frame, &place, |items| {
+                    self.sort_by_predicate(items, &predicate)?;
+                    Ok(Value::Void)
+                })?
+            }
+            Instr::RefToBool | Instr::WeakRefToBool => Value::Bool(!self.eval(frame)?.is_null()),
+            Instr::EnumToI32(_, size) => {
+                let val = self.eval(frame)?;
+                match (val.as_number(), size) {
+                    (Some(Number::Int(i)), 8) => Value::I64(i as i64),
+                    (Some(Number::Int(i)), _) => Value::I32(i as i32),
+                    _ => return Err(RuntimeError::type_mismatch("enum", &val)),
+                }
+            }
+            Instr::I32ToEnum(typ, _) => {
+                let val = self.eval(frame)?;
+                match val.as_number() {
+                    Some(Number::Int(i)) => Value::Enum(self.resolve_type(typ)?.cast(), i as i64),
+                    _ => return Err(RuntimeError::type_mismatch("integer", &val)),
+                }
+            }
+            Instr::DynamicCast(class, _) => {
+                let val = self.eval(frame)?;
+                match val.as_object() {
+                    Some(obj) if self.is_subclass(obj.borrow().class, class)? => val,
+                    _ => Value::Null,
+                }
+            }
+            Instr::ToString(_) | Instr::VariantToString => {
+                let val = self.eval(frame)?;
+                Value::String(Ref::from(self.format_value(&val)?))
+            }
+            Instr::ToVariant(typ) => Value::Variant(Some((typ, Box::new(self.eval(frame)?)))),
+            Instr::FromVariant(typ) => match self.eval(frame)? {
+                Value::Variant(Some((inner, val))) if self.is_variant_compatible(inner, typ, &val)? => *val,
+                _ => self.default_value(typ)?,
+            },
+            Instr::VariantIsDefined => Value::Bool(matches!(self.eval(frame)?, Value::Variant(Some(_)))),
+            Instr::VariantIsRef => match self.eval(frame)? {
+                Value::Variant(Some((typ, _))) => {
+                    Value::Bool(matches!(pool.type_(typ)?, Type::Ref(_) | Type::WeakRef(_)))
+                }
+                _ => Value::Bool(false),
+            },
+            Instr::VariantIsArray => match self.eval(frame)? {
+                Value::Variant(Some((typ, _))) => {
+                    Value::Bool(matches!(pool.type_(typ)?, Type::Array(_) | Type::StaticArray(_, _)))
+                }
+                _ => Value::Bool(false),
+            },
+            Instr::VariantTypeName => match self.eval(frame)? {
+                Value::Variant(Some((typ, _))) => Value::Name(pool.def_name(typ)?),
+                _ => Value::Name(Ref::from("None")),
+            },
+            Instr::WeakRefToRef => match self.eval(frame)? {
+                Value::WeakRef(weak) => weak.upgrade().map_or(Value::Null, Value::Object),
+                other => other,
+            },
+            Instr::RefToWeakRef => match self.eval(frame)? {
+                Value::Object(obj) => Value::WeakRef(Rc::downgrade(&obj)),
+                other => other,
+            },
+            Instr::AsRef(_) | Instr::Deref(_) => self.eval_with(frame, context)?,
+            other @ (Instr::Target(_)
+            | Instr::Switch(_, _)
+            | Instr::SwitchLabel(_, _)
+            | Instr::SwitchDefault
+            | Instr::Jump(_)
+            | Instr::JumpIfFalse(_)
+            | Instr::ParamEnd
+            | Instr::Return) => return Err(RuntimeError::UnexpectedInstruction(pos, format!("{other:?}"))),
+        };
+        Ok(res)
+    }
+
+    fn eval_invoke(
+        &mut self,
+        frame: &mut Frame<'a>,
+        index: PoolIndex<Function>,
+        context: Option<Value>,
+        line: u16,
+    ) -> Result<Value, RuntimeError> {
+        let pool = self.pool;
+        let fun = pool.function(index)?;
+        let mut args = Vec::with_capacity(fun.parameters.len());
+        let mut out_args = vec![];
+        let mut deferred = vec![];
+
+        for (i, param_idx) in fun.parameters.iter().enumerate() {
+            let param = pool.parameter(*param_idx)?;
+            let pos = frame.code.pos();
+            match frame.code.peek() {
+                None | Some(Instr::ParamEnd) => args.push(self.default_value(param.type_)?),
+                Some(Instr::Nop) => {
+                    frame.code.pop()?;
+                    args.push(self.default_value(param.type_)?);
+                }
+                // native functions evaluate short-circuit arguments themselves, only when needed
+                Some(Instr::Skip(offset)) if param.flags.is_short_circuit() && fun.flags.is_native() => {
+                    frame.code.pop()?;
+                    deferred.resize(i, None);
+                    deferred.push(Some(frame.code.pos()));
+                    frame.code.seek_abs(offset.absolute(pos))?;
+                    args.push(self.default_value(param.type_)?);
+                }
+                Some(Instr::Skip(_)) => {
+                    frame.code.pop()?;
+                    args.push(self.eval(frame)?);
+                }
+                Some(_) if param.flags.is_out() => {
+                    let place = self.eval_place(frame, None)?;
+                    args.push(Self::read_place(frame, &place)?);
+                    out_args.push((i, place));
+                }
+                Some(_) => args.push(self.eval(frame)?),
+            }
+        }
+        loop {
+            let pos = frame.code.pos();
+            match frame.code.pop()? {
+                Instr::ParamEnd => break,
+                Instr::Nop => {}
+                other => return Err(RuntimeError::UnexpectedInstruction(pos, format!("{other:?}"))),
+            }
+        }
+
+        let this = if fun.flags.is_static() {
+            None
+        } else {
+            match context {
+                Some(val) => val.as_object(),
+                None => frame.this.clone(),
+            }
+        };
+        self.caller = frame.function;
+        self.line = line;
+        let res = if deferred.is_empty() {
+            self.call(index, this, &mut args)?
+        } else {
+            self.call_native_deferred(frame, index, this, &mut args, deferred)?
+        };
+        for (i, place) in out_args {
+            Self::write_place(frame, &place, mem::take(&mut args[i]))?;
+        }
+        Ok(res)
+    }
+
+    fn eval_place(&mut self, frame: &mut Frame<'a>, context: Option<Value>) -> Result<Place, RuntimeError> {
+        let pos = frame.code.pos();
+        let place = match frame.code.pop()? {
+            Instr::Local(idx) => Place::Local(idx),
+            Instr::Param(idx) => Place::Param(idx),
+            Instr::ObjectField(field) => Place::Field(Self::context_object(frame, context)?, field),
+            Instr::Context(_) => {
+                let ctx = self.eval(frame)?;
+                if ctx.is_null() {
+                    return Err(RuntimeError::NullReference);
+                }
+                self.eval_place(frame, Some(ctx))?
+            }
+            Instr::StructField(field) => Place::StructField(Box::new(self.eval_place(frame, None)?), field),
+            Instr::ArrayElement(_) | Instr::StaticArrayElement(_) => {
+                let array = self.eval_place(frame, None)?;
+                let idx = self.eval_index(frame)?;
+                Place::Element(Box::new(array), idx)
+            }
+            Instr::AsRef(_) | Instr::Deref(_) => self.eval_place(frame, context)?,
+            other => Place::Temp(self.eval_instr(frame, pos, other, context)?),
+        };
+        Ok(place)
+    }
+
+    fn context_object(frame: &Frame<'a>, context: Option<Value>) -> Result<ObjectRef, RuntimeError> {
+        match context {
+            Some(val) => val.as_object().ok_or(RuntimeError::NullReference),
+            None => frame.this.clone().ok_or(RuntimeError::MissingThis),
+        }
+    }
+
+    fn read_place(frame: &Frame<'a>, place: &Place) -> Result<Value, RuntimeError> {
+        let res = match place {
+            Place::Local(idx) => frame.locals.get(idx).cloned().unwrap_or_default(),
+            Place::Param(idx) => frame.params.get(idx).cloned().unwrap_or_default(),
+            Place::Field(obj, field) => obj.borrow().fields.get(field).cloned().unwrap_or_default(),
+            Place::StructField(inner, field) => match Self::read_place(frame, inner)? {
+                Value::Struct(res) => res.fields.get(field).cloned().unwrap_or_default(),
+                other => return Err(RuntimeError::type_mismatch("struct", &other)),
+            },
+            Place::Element(inner, idx) => match Self::read_place(frame, inner)? {
+                Value::Array(mut items) if *idx < items.len() => items.swap_remove(*idx),
+                Value::Array(items) => return Err(RuntimeError::IndexOutOfBounds(*idx, items.len())),
+                other => return Err(RuntimeError::type_mismatch("array", &other)),
+            },
+            Place::Temp(val) => val.clone(),
+        };
+        Ok(res)
+    }
+
+    fn write_place(frame: &mut Frame<'a>, place: &Place, val: Value) -> Result<(), RuntimeError> {
+        match place {
+            Place::Local(idx) => {
+                frame.locals.insert(*idx, val);
+            }
+            Place::Param(idx) => {
+                frame.params.insert(*idx, val);
+            }
+            Place::Field(obj, field) => {
+                obj.borrow_mut().fields.insert(*field, val);
+            }
+            Place::StructField(inner, field) => match Self::read_place(frame, inner)? {
+                Value::Struct(mut res) => {
+                    res.fields.insert(*field, val);
+                    Self::write_place(frame, inner, Value::Struct(res))?;
+                }
+                other => return Err(RuntimeError::type_mismatch("struct", &other)),
+            },
+            Place::Element(inner, idx) => {
+                Self::modify_array(frame, inner, |items| {
+                    let len = items.len();
+                    let slot = items.get_mut(*idx).ok_or(RuntimeError::IndexOutOfBounds(*idx, len))?;
+                    *slot = val;
+                    Ok(())
+                })?;
+            }
+            Place::Temp(_) => {}
+        }
+        Ok(())
+    }
+
+    fn modify_array<A, F>(frame: &mut Frame<'a>, place: &Place, fun: F) -> Result<A, RuntimeError>
+    where
+        F: FnOnce(&mut Vec<Value>) -> Result<A, RuntimeError>,
+    {
+        match Self::read_place(frame, place)? {
+            Value::Array(mut items) => {
+                let res = fun(&mut items)?;
+                Self::write_place(frame, place, Value::Array(items))?;
+                Ok(res)
+            }
+            other => Err(RuntimeError::type_mismatch("array", &other)),
+        }
+    }
+
+    fn index_value(idx: Option<usize>) -> Result<Value, RuntimeError> {
+        match idx {
+            Some(idx) => Ok(Value::I32(idx.try_into().map_err(|_| RuntimeError::ArithmeticError)?)),
+            None => Ok(Value::I32(-1)),
+        }
+    }
+
+    /// Returns the default value of a type, as seen by freshly declared locals and fields.
+    pub fn default_value(&self, index: PoolIndex<Type>) -> Result<Value, RuntimeError> {
+        let pool = self.pool;
+        let res = match pool.type_(index)? {
+            Type::Prim => match pool.def_name(index)?.as_ref() {
+                "Bool" => Value::Bool(false),
+                "Int8" => Value::I8(0),
+                "Int16" => Value::I16(0),
+                "Int32" => Value::I32(0),
+                "Int64" => Value::I64(0),
+                "Uint8" => Value::U8(0),
+                "Uint16" => Value::U16(0),
+                "Uint32" => Value::U32(0),
+                "Uint64" => Value::U64(0),
+                "Float" => Value::F32(0.0),
+                "Double" => Value::F64(0.0),
+                "String" => Value::string(""),
+                "CName" => Value::Name(Ref::from("None")),
+                "TweakDBID" => Value::TweakDbId(Ref::from("")),
+                "ResRef" | "redResourceReferenceScriptToken" => Value::Resource(Ref::from("")),
+                "Variant" => Value::Variant(None),
+                _ => Value::Void,
+            },
+            Type::Class => match self.types.get(pool.def_name(index)?.as_ref()) {
+                Some(idx) => match &pool.definition(*idx)?.value {
+                    AnyDefinition::Class(class) if class.flags.is_struct() => {
+                        Value::Struct(Box::new(self.default_struct(idx.cast())?))
+                    }
+                    AnyDefinition::Enum(enum_) => {
+                        let first = enum_.members.first().map(|member| pool.enum_value(*member));
+                        Value::Enum(idx.cast(), first.transpose()?.unwrap_or_default())
+                    }
+                    _ => Value::Null,
+                },
+                None => Value::Null,
+            },
+            Type::Ref(_) | Type::WeakRef(_) | Type::ScriptRef(_) => Value::Null,
+            Type::Array(_) => Value::Array(vec![]),
+            Type::StaticArray(inner, size) => Value::Array(vec![self.default_value(*inner)?; *size as usize]),
+        };
+        Ok(res)
+    }
+
+    fn default_struct(&self, index: PoolIndex<Class>) -> Result<Struct, RuntimeError> {
+        let mut fields = BTreeMap::new();
+        for field in &self.pool.class(index)?.fields {
+            fields.insert(*field, self.default_value(self.pool.field(*field)?.type_)?);
+        }
+        Ok(Struct { class: index, fields })
+    }
+
+    /// Creates a new instance of a class with all fields, including inherited ones, set to their defaults.
+    pub fn instantiate(&self, index: PoolIndex<Class>) -> Result<ObjectRef, RuntimeError> {
+        let mut fields = BTreeMap::new();
+        let mut current = index;
+        while !current.is_undefined() {
+            let class = self.pool.class(current)?;
+            for field in &class.fields {
+                fields.insert(*field, self.default_value(self.pool.field(*field)?.type_)?);
+            }
+            current = class.base;
+        }
+        Ok(Rc::new(RefCell::new(Object { class: index, fields })))
+    }
+
+    fn resolve_virtual(
+        &self,
+        class: PoolIndex<Class>,
+        name: PoolIndex<CName>,
+    ) -> Result<PoolIndex<Function>, RuntimeError> {
+        let mut current = class;
+        while !current.is_undefined() {
+            let class = self.pool.class(current)?;
+            for fun in &class.functions {
+                if self.pool.definition(*fun)?.name == name {
+                    return Ok(*fun);
+                }
+            }
+            current = class.base;
+        }
+        Err(RuntimeError::MethodNotFound(
+            self.pool.names.get(name)?.as_ref().to_owned(),
+        ))
+    }
+
+    /// Sorts an array in place with a predicate telling whether its first argument goes before the second.
+    /// The predicate is either the name of a global function or a function object such as a lambda.
+    fn sort_by_predicate(&mut self, items: &mut [Value], predicate: &Value) -> Result<(), RuntimeError> {
+        let (fun, this) = match predicate {
+            Value::Name(name) => (
+                self.find_function(name)
+                    .ok_or_else(|| RuntimeError::MethodNotFound(name.as_ref().to_owned()))?,
+                None,
+            ),
+            Value::Object(obj) => (
+                self.resolve_method(obj.borrow().class, FUNCTION_OBJECT_METHOD)?,
+                Some(obj.clone()),
+            ),
+            other => return Err(RuntimeError::type_mismatch("function", other)),
+        };
+        // an insertion sort keeps equal elements in order and tolerates inconsistent predicates
+        for i in 1..items.len() {
+            let mut j = i;
+            while j > 0 {
+                let mut args = [items[j].clone(), items[j - 1].clone()];
+                match self.call(fun, this.clone(), &mut args)? {
+                    Value::Bool(true) => items.swap(j, j - 1),
+                    Value::Bool(false) => break,
+                    other => return Err(RuntimeError::type_mismatch("Bool", &other)),
+                }
+                j -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_method(&self, class: PoolIndex<Class>, name: &str) -> Result<PoolIndex<Function>, RuntimeError> {
+        let mut current = class;
+        while !current.is_undefined() {
+            let class = self.pool.class(current)?;
+            for fun in &class.functions {
+                let fun_name = self.pool.def_name(*fun)?;
+                if fun_name.split(';').next() == Some(name) {
+                    return Ok(*fun);
+                }
+            }
+            current = class.base;
+        }
+        Err(RuntimeError::MethodNotFound(name.to_owned()))
+    }
+
+    fn resolve_type(&self, index: PoolIndex<Type>) -> Result<PoolIndex<Definition>, RuntimeError> {
+        let name = self.pool.def_name(index)?;
+        Ok(self.types.get(name.as_ref()).copied().unwrap_or(PoolIndex::UNDEFINED))
+    }
+
+    fn is_subclass(&self, class: PoolIndex<Class>, base: PoolIndex<Class>) -> Result<bool, RuntimeError> {
+        let mut current = class;
+        while !current.is_undefined() {
+            if current == base {
+                return Ok(true);
+            }
+            current = self.pool.class(current)?.base;
+        }
+        Ok(false)
+    }
+
+    fn is_variant_compatible(
+        &self,
+        actual: PoolIndex<Type>,
+        expected: PoolIndex<Type>,
+        val: &Value,
+    ) -> Result<bool, RuntimeError> {
+        if actual == expected {
+            return Ok(true);
+        }
+        match (self.pool.type_(expected)?, val.as_object()) {
+            (Type::Ref(inner) | Type::WeakRef(inner), Some(obj)) => {
+                let class = self.resolve_type(*inner)?.cast();
+                self.is_subclass(obj.borrow().class, class)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn format_value(&self, val: &Value) -> Result<String, RuntimeError> {
+        let res = match val {
+            Value::Enum(idx, value) if !idx.is_undefined() => {
+                let mut res = value.to_string();
+                for member in &self.pool.enum_(*idx)?.members {
+                    if self.pool.enum_value(*member)? == *value {
+                        res = self.pool.def_name(*member)?.as_ref().to_owned();
+                        break;
+                    }
+                }
+                res
+            }
+            Value::Struct(res) => self.pool.def_name(res.class)?.as_ref().to_owned(),
+            Value::Object(obj) => self.pool.def_name(obj.borrow().class)?.as_ref().to_owned(),
+            Value::Variant(Some((_, inner))) => self.format_value(inner)?,
+            other => other.to_string(),
+        };
+        Ok(res)
+    }
+
+    fn element_type(&self, index: PoolIndex<Type>) -> Result<PoolIndex<Type>, RuntimeError> {
+        match self.pool.type_(index)? {
+            Type::Array(inner) | Type::StaticArray(inner, _) => Ok(*inner),
+            _ => Err(PoolError::UnexpectedEntry("array type").into()),
+        }
+    }
+}
+
+struct Frame<'a> {
+    function: PoolIndex<Function>,
+    this: Option<ObjectRef>,
+    params: HashMap<PoolIndex<Parameter>, Value>,
+    locals: HashMap<PoolIndex<Local>, Value>,
+    code: CodeCursor<'a, Offset>,
+}
+
+/// A location that can be assigned to.
+enum Place {
+    Local(PoolIndex<Local>),
+    Param(PoolIndex<Parameter>),
+    Field(ObjectRef, PoolIndex<Field>),
+    StructField(Box<Place>, PoolIndex<Field>),
+    Element(Box<Place>, usize),
+    Temp(Value),
+}
+
+fn compare_values(lhs: &Value, rhs: &Value) -> Ordering {
+    match (lhs, rhs) {
+        (Value::String(a), Value::String(b)) | (Value::Name(a), Value::Name(b)) => a.cmp(b),
+        _ => match (lhs.as_number(), rhs.as_number()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        },
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RuntimeError {
+    #[error("pool error: {0}")]
+    PoolError(#[from] PoolError),
+    #[error("code error: {0}")]
+    CursorError(#[from] CursorError),
+    #[error("no native stub registered for {0}")]
+    NativeNotFound(String),
+    #[error("method {0} not found")]
+    MethodNotFound(String),
+    #[error("null reference")]
+    NullReference,
+    #[error("'this' is not available in a static context")]
+    MissingThis,
+    #[error("index {0} out of bounds for an array of size {1}")]
+    IndexOutOfBounds(usize, usize),
+    #[error("invalid array index {0}")]
+    InvalidIndex(i128),
+    #[error("expected {0}, found {1}")]
+    TypeMismatch(&'static str, &'static str),
+    #[error("missing argument {0}")]
+    MissingArgument(usize),
+    #[error("arithmetic error")]
+    ArithmeticError,
+    #[error("unexpected instruction at {0}: {1}")]
+    UnexpectedInstruction(Location, String),
+    #[error("{0} is not supported")]
+    Unsupported(&'static str),
+    #[error("maximum call depth exceeded")]
+    StackOverflow,
+    #[error("assertion failed: {message}")]
+    AssertionFailed {
+        message: String,
+        function: PoolIndex<Function>,
+        line: u16,
+    },
+    #[error("{0}")]
+    Native(String),
+}
+
+impl RuntimeError {
+    #[inline]
+    pub fn type_mismatch(expected: &'static str, found: &Value) -> Self {
+        Self::TypeMismatch(expected, found.kind())
+    }
+}
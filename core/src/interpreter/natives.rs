@@ -0,0 +1,276 @@
+use std::fmt;
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+
+use super::value::{Number, ObjectRef, Value};
+use super::RuntimeError;
+use crate::bundle::{ConstantPool, PoolIndex};
+use crate::definition::Function;
+
+pub type NativeFn = Box<dyn Fn(&mut NativeCall<'_>) -> Result<Value, RuntimeError>>;
+
+/// Evaluates a short-circuit argument on demand, returning `None` if it has already been evaluated.
+pub(super) type DeferredFn<'a> = dyn FnMut(usize) -> Result<Option<Value>, RuntimeError> + 'a;
+
+/// The context passed to a native stub. Arguments for `out` parameters are written back to the caller.
+pub struct NativeCall<'a> {
+    pub pool: &'a ConstantPool,
+    pub function: PoolIndex<Function>,
+    /// The function that issued the call.
+    pub caller: PoolIndex<Function>,
+    /// The zero-based source line of the call site.
+    pub line: u16,
+    pub this: Option<ObjectRef>,
+    pub args: &'a mut [Value],
+    pub(super) deferred: Option<&'a mut DeferredFn<'a>>,
+}
+
+impl<'a> NativeCall<'a> {
+    pub fn arg(&self, index: usize) -> Result<&Value, RuntimeError> {
+        self.args.get(index).ok_or(RuntimeError::MissingArgument(index))
+    }
+
+    /// Returns an argument, evaluating it first if it was passed to a short-circuit parameter.
+    /// Until then, such arguments hold the default value of their type.
+    pub fn force_arg(&mut self, index: usize) -> Result<&Value, RuntimeError> {
+        if let Some(eval) = &mut self.deferred {
+            if let Some(val) = eval(index)? {
+                *self.args.get_mut(index).ok_or(RuntimeError::MissingArgument(index))? = val;
+            }
+        }
+        self.arg(index)
+    }
+
+    /// Returns the name of the primitive return type of the called function.
+    pub fn return_type_name(&self) -> Result<Option<String>, RuntimeError> {
+        match self.pool.function(self.function)?.return_type {
+            Some(idx) => Ok(Some(self.pool.def_name(idx)?.as_ref().to_owned())),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A registry of Rust stubs standing in for native functions.
+///
+/// Stubs are looked up by the full function name first (e.g. `OperatorAdd;Int32Int32;Int32`),
+/// then by `Class::Name` for methods and finally by the short name (e.g. `OperatorAdd`).
+#[derive(Default)]
+pub struct Natives {
+    stubs: HashMap<String, Rc<NativeFn>>,
+}
+
+impl Natives {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry with stubs for operators, casts and logging.
+    pub fn with_defaults() -> Self {
+        let mut natives = Self::new();
+
+        natives.register("OperatorAdd", add);
+        natives.register("OperatorSubtract", subtract);
+        natives.register("OperatorMultiply", multiply);
+        natives.register("OperatorDivide", divide);
+        natives.register("OperatorModulo", |call| {
+            arithmetic(call, i128::checked_rem, |a, b| a % b)
+        });
+        natives.register("OperatorAnd", and);
+        natives.register("OperatorOr", or);
+        natives.register("OperatorXor", |call| bitwise(call, |a, b| a ^ b, |a, b| a != b));
+        natives.register("OperatorLogicAnd", |call| logic(call, false, |a, b| a & b));
+        natives.register("OperatorLogicOr", |call| logic(call, true, |a, b| a | b));
+
+        natives.register("OperatorEqual", |call| Ok(Value::Bool(call.arg(0)? == call.arg(1)?)));
+        natives.register("OperatorNotEqual", |call| Ok(Value::Bool(call.arg(0)? != call.arg(1)?)));
+        natives.register("OperatorLess", |call| compare(call, std::cmp::Ordering::is_lt));
+        natives.register("OperatorLessEqual", |call| compare(call, std::cmp::Ordering::is_le));
+        natives.register("OperatorGreater", |call| compare(call, std::cmp::Ordering::is_gt));
+        natives.register("OperatorGreaterEqual", |call| compare(call, std::cmp::Ordering::is_ge));
+
+        natives.register("OperatorLogicNot", |call| match call.arg(0)? {
+            Value::Bool(b) => Ok(Value::Bool(!b)),
+            other => Err(RuntimeError::type_mismatch("Bool", other)),
+        });
+        natives.register("OperatorBitNot", |call| {
+            let val = call.arg(0)?;
+            match val.as_number() {
+                Some(Number::Int(i)) => convert_result(call, val, Number::Int(!i)),
+                _ => Err(RuntimeError::type_mismatch("integer", val)),
+            }
+        });
+        natives.register("OperatorNeg", |call| {
+            let val = call.arg(0)?;
+            match val.as_number() {
+                Some(Number::Int(i)) => convert_result(call, val, Number::Int(-i)),
+                Some(Number::Float(f)) => convert_result(call, val, Number::Float(-f)),
+                None => Err(RuntimeError::type_mismatch("number", val)),
+            }
+        });
+
+        natives.register("OperatorAssignAdd", |call| assign_with(call, add));
+        natives.register("OperatorAssignSubtract", |call| assign_with(call, subtract));
+        natives.register("OperatorAssignMultiply", |call| assign_with(call, multiply));
+        natives.register("OperatorAssignDivide", |call| assign_with(call, divide));
+        natives.register("OperatorAssignOr", |call| assign_with(call, or));
+        natives.register("OperatorAssignAnd", |call| assign_with(call, and));
+
+        natives.register("Cast", |call| {
+            let target = call
+                .return_type_name()?
+                .ok_or(RuntimeError::Unsupported("cast without a return type"))?;
+            let val = call.arg(0)?;
+            if target == "String" {
+                return Ok(Value::string(&val.to_string()));
+            }
+            val.as_number()
+                .and_then(|num| Value::from_number(&target, num))
+                .ok_or_else(|| RuntimeError::type_mismatch("number", val))
+        });
+
+        natives.register("Log", |call| {
+            log::info!("{}", call.arg(0)?);
+            Ok(Value::Void)
+        });
+        natives.register("LogWarning", |call| {
+            log::warn!("{}", call.arg(0)?);
+            Ok(Value::Void)
+        });
+        natives.register("LogError", |call| {
+            log::error!("{}", call.arg(0)?);
+            Ok(Value::Void)
+        });
+
+        natives
+    }
+
+    pub fn register<F>(&mut self, name: impl Into<String>, stub: F)
+    where
+        F: Fn(&mut NativeCall<'_>) -> Result<Value, RuntimeError> + 'static,
+    {
+        self.stubs.insert(name.into(), Rc::new(Box::new(stub)));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NativeFn> {
+        self.stubs.get(name).map(Rc::as_ref)
+    }
+
+    pub(super) fn get_shared(&self, name: &str) -> Option<Rc<NativeFn>> {
+        self.stubs.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.stubs.contains_key(name)
+    }
+}
+
+impl fmt::Debug for Natives {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.stubs.keys()).finish()
+    }
+}
+
+fn add(call: &mut NativeCall<'_>) -> Result<Value, RuntimeError> {
+    match (call.arg(0)?, call.arg(1)?) {
+        (Value::String(a), Value::String(b)) => Ok(Value::string(&format!("{a}{b}"))),
+        _ => arithmetic(call, |a, b| Some(a.wrapping_add(b)), |a, b| a + b),
+    }
+}
+
+fn subtract(call: &mut NativeCall<'_>) -> Result<Value, RuntimeError> {
+    arithmetic(call, |a, b| Some(a.wrapping_sub(b)), |a, b| a - b)
+}
+
+fn multiply(call: &mut NativeCall<'_>) -> Result<Value, RuntimeError> {
+    arithmetic(call, |a, b| Some(a.wrapping_mul(b)), |a, b| a * b)
+}
+
+fn divide(call: &mut NativeCall<'_>) -> Result<Value, RuntimeError> {
+    arithmetic(call, i128::checked_div, |a, b| a / b)
+}
+
+fn and(call: &mut NativeCall<'_>) -> Result<Value, RuntimeError> {
+    bitwise(call, |a, b| a & b, |a, b| a && b)
+}
+
+fn or(call: &mut NativeCall<'_>) -> Result<Value, RuntimeError> {
+    bitwise(call, |a, b| a | b, |a, b| a || b)
+}
+
+/// Integer results wrap around like in the game VM, `int_op` only fails on a division by zero.
+fn arithmetic(
+    call: &NativeCall<'_>,
+    int_op: fn(i128, i128) -> Option<i128>,
+    float_op: fn(f64, f64) -> f64,
+) -> Result<Value, RuntimeError> {
+    let lhs = call.arg(0)?;
+    let rhs = call.arg(1)?;
+    let res = match (lhs.as_number(), rhs.as_number()) {
+        (Some(Number::Int(a)), Some(Number::Int(b))) => Number::Int(int_op(a, b).ok_or(RuntimeError::ArithmeticError)?),
+        (Some(a), Some(b)) => Number::Float(float_op(a.to_f64(), b.to_f64())),
+        (None, _) => return Err(RuntimeError::type_mismatch("number", lhs)),
+        (_, None) => return Err(RuntimeError::type_mismatch("number", rhs)),
+    };
+    convert_result(call, lhs, res)
+}
+
+fn bitwise(
+    call: &NativeCall<'_>,
+    int_op: fn(i128, i128) -> i128,
+    bool_op: fn(bool, bool) -> bool,
+) -> Result<Value, RuntimeError> {
+    let lhs = call.arg(0)?;
+    let rhs = call.arg(1)?;
+    match (lhs, rhs) {
+        (Value::Bool(a), Value::Bool(b)) => Ok(Value::Bool(bool_op(*a, *b))),
+        _ => match (lhs.as_number(), rhs.as_number()) {
+            (Some(Number::Int(a)), Some(Number::Int(b))) => convert_result(call, lhs, Number::Int(int_op(a, b))),
+            _ => Err(RuntimeError::type_mismatch("integer", lhs)),
+        },
+    }
+}
+
+/// Evaluates the right operand only if the left one is not a `Bool` equal to `short_circuit`.
+fn logic(
+    call: &mut NativeCall<'_>,
+    short_circuit: bool,
+    int_op: fn(i128, i128) -> i128,
+) -> Result<Value, RuntimeError> {
+    if call.arg(0)? == &Value::Bool(short_circuit) {
+        return Ok(Value::Bool(short_circuit));
+    }
+    call.force_arg(1)?;
+    bitwise(call, int_op, |_, rhs| rhs)
+}
+
+fn compare(call: &NativeCall<'_>, check: fn(std::cmp::Ordering) -> bool) -> Result<Value, RuntimeError> {
+    let lhs = call.arg(0)?;
+    let rhs = call.arg(1)?;
+    let ord = match (lhs, rhs) {
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        _ => match (lhs.as_number(), rhs.as_number()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).ok_or(RuntimeError::ArithmeticError)?,
+            _ => return Err(RuntimeError::type_mismatch("number", lhs)),
+        },
+    };
+    Ok(Value::Bool(check(ord)))
+}
+
+fn convert_result(call: &NativeCall<'_>, lhs: &Value, res: Number) -> Result<Value, RuntimeError> {
+    let type_name = match call.return_type_name()? {
+        Some(name) => name,
+        None => lhs.prim_name().unwrap_or_default().to_owned(),
+    };
+    Value::from_number(&type_name, res).ok_or_else(|| RuntimeError::type_mismatch("number", lhs))
+}
+
+fn assign_with(
+    call: &mut NativeCall<'_>,
+    op: fn(&mut NativeCall<'_>) -> Result<Value, RuntimeError>,
+) -> Result<Value, RuntimeError> {
+    let res = op(call)?;
+    let slot = call.args.get_mut(0).ok_or(RuntimeError::MissingArgument(0))?;
+    *slot = res.clone();
+    Ok(res)
+}
@@ -0,0 +1,284 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use crate::bundle::PoolIndex;
+use crate::definition::{Class, Enum, Field, Type};
+use crate::Ref;
+
+pub type ObjectRef = Rc<RefCell<Object>>;
+
+#[derive(Debug, Clone, Default)]
+pub enum Value {
+    #[default]
+    Void,
+    Null,
+    Bool(bool),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    String(Ref<str>),
+    Name(Ref<str>),
+    TweakDbId(Ref<str>),
+    Resource(Ref<str>),
+    Enum(PoolIndex<Enum>, i64),
+    Struct(Box<Struct>),
+    Object(ObjectRef),
+    WeakRef(Weak<RefCell<Object>>),
+    Array(Vec<Value>),
+    Variant(Option<(PoolIndex<Type>, Box<Value>)>),
+}
+
+impl Value {
+    #[inline]
+    pub fn string(str: &str) -> Self {
+        Self::String(Ref::from(str))
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(str) | Self::Name(str) | Self::TweakDbId(str) | Self::Resource(str) => Some(str),
+            _ => None,
+        }
+    }
+
+    /// Returns a strong reference to the object, upgrading weak references.
+    pub fn as_object(&self) -> Option<ObjectRef> {
+        match self {
+            Self::Object(obj) => Some(obj.clone()),
+            Self::WeakRef(weak) => weak.upgrade(),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<Number> {
+        let res = match self {
+            Self::I8(i) => Number::Int((*i).into()),
+            Self::I16(i) => Number::Int((*i).into()),
+            Self::I32(i) => Number::Int((*i).into()),
+            Self::I64(i) | Self::Enum(_, i) => Number::Int((*i).into()),
+            Self::U8(i) => Number::Int((*i).into()),
+            Self::U16(i) => Number::Int((*i).into()),
+            Self::U32(i) => Number::Int((*i).into()),
+            Self::U64(i) => Number::Int((*i).into()),
+            Self::F32(f) => Number::Float((*f).into()),
+            Self::F64(f) => Number::Float(*f),
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    pub fn as_index(&self) -> Option<usize> {
+        match self.as_number()? {
+            Number::Int(i) => i.try_into().ok(),
+            Number::Float(_) => None,
+        }
+    }
+
+    /// Converts a number into a value of the given primitive type.
+    pub fn from_number(type_name: &str, num: Number) -> Option<Self> {
+        // truncating conversions mirror the wrapping behavior of the game VM
+        let int = match num {
+            Number::Int(i) => i,
+            Number::Float(f) => f as i128,
+        };
+        let float = match num {
+            Number::Int(i) => i as f64,
+            Number::Float(f) => f,
+        };
+        let res = match type_name {
+            "Int8" => Self::I8(int as i8),
+            "Int16" => Self::I16(int as i16),
+            "Int32" => Self::I32(int as i32),
+            "Int64" => Self::I64(int as i64),
+            "Uint8" => Self::U8(int as u8),
+            "Uint16" => Self::U16(int as u16),
+            "Uint32" => Self::U32(int as u32),
+            "Uint64" => Self::U64(int as u64),
+            "Float" => Self::F32(float as f32),
+            "Double" => Self::F64(float),
+            "Bool" => Self::Bool(int != 0 || float != 0.0),
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// Returns the name of the primitive type of this value, if it has one.
+    pub fn prim_name(&self) -> Option<&'static str> {
+        let res = match self {
+            Self::Bool(_) => "Bool",
+            Self::I8(_) => "Int8",
+            Self::I16(_) => "Int16",
+            Self::I32(_) => "Int32",
+            Self::I64(_) => "Int64",
+            Self::U8(_) => "Uint8",
+            Self::U16(_) => "Uint16",
+            Self::U32(_) => "Uint32",
+            Self::U64(_) => "Uint64",
+            Self::F32(_) => "Float",
+            Self::F64(_) => "Double",
+            Self::String(_) => "String",
+            Self::Name(_) => "CName",
+            Self::TweakDbId(_) => "TweakDBID",
+            Self::Resource(_) => "ResRef",
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// Returns a short description of the kind of this value for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Void => "Void",
+            Self::Null => "null",
+            Self::Enum(_, _) => "enum",
+            Self::Struct(_) => "struct",
+            Self::Object(_) => "ref",
+            Self::WeakRef(_) => "wref",
+            Self::Array(_) => "array",
+            Self::Variant(_) => "Variant",
+            other => other.prim_name().unwrap_or_default(),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        match self {
+            Self::Null => true,
+            Self::WeakRef(weak) => weak.strong_count() == 0,
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Void, Self::Void) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::String(a), Self::String(b))
+            | (Self::Name(a), Self::Name(b))
+            | (Self::TweakDbId(a), Self::TweakDbId(b))
+            | (Self::Resource(a), Self::Resource(b)) => a == b,
+            (Self::Enum(_, a), Self::Enum(_, b)) => a == b,
+            (Self::Struct(a), Self::Struct(b)) => a == b,
+            (Self::Array(a), Self::Array(b)) => a == b,
+            (Self::Variant(a), Self::Variant(b)) => a == b,
+            (
+                lhs @ (Self::Null | Self::Object(_) | Self::WeakRef(_)),
+                rhs @ (Self::Null | Self::Object(_) | Self::WeakRef(_)),
+            ) => match (lhs.as_object(), rhs.as_object()) {
+                (Some(a), Some(b)) => Rc::ptr_eq(&a, &b),
+                (None, None) => true,
+                _ => false,
+            },
+            (lhs, rhs) => match (lhs.as_number(), rhs.as_number()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Void => write!(f, "void"),
+            Self::Null | Self::Variant(None) => write!(f, "null"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::I8(i) => write!(f, "{i}"),
+            Self::I16(i) => write!(f, "{i}"),
+            Self::I32(i) => write!(f, "{i}"),
+            Self::I64(i) | Self::Enum(_, i) => write!(f, "{i}"),
+            Self::U8(i) => write!(f, "{i}"),
+            Self::U16(i) => write!(f, "{i}"),
+            Self::U32(i) => write!(f, "{i}"),
+            Self::U64(i) => write!(f, "{i}"),
+            Self::F32(x) => write!(f, "{x}"),
+            Self::F64(x) => write!(f, "{x}"),
+            Self::String(str) | Self::Name(str) | Self::TweakDbId(str) | Self::Resource(str) => write!(f, "{str}"),
+            Self::Struct(_) => write!(f, "struct"),
+            Self::WeakRef(weak) if weak.strong_count() == 0 => write!(f, "null"),
+            Self::Object(_) | Self::WeakRef(_) => write!(f, "object"),
+            Self::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Variant(Some((_, val))) => write!(f, "{val}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Number {
+    Int(i128),
+    Float(f64),
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (a, b) => a.to_f64() == b.to_f64(),
+        }
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a.partial_cmp(b),
+            (a, b) => a.to_f64().partial_cmp(&b.to_f64()),
+        }
+    }
+}
+
+impl Number {
+    #[inline]
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Self::Int(i) => i as f64,
+            Self::Float(f) => f,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Struct {
+    pub class: PoolIndex<Class>,
+    pub fields: BTreeMap<PoolIndex<Field>, Value>,
+}
+
+pub struct Object {
+    pub class: PoolIndex<Class>,
+    pub fields: BTreeMap<PoolIndex<Field>, Value>,
+}
+
+impl fmt::Debug for Object {
+    // fields are omitted because objects can form reference cycles
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Object")
+            .field("class", &self.class)
+            .finish_non_exhaustive()
+    }
+}
@@ -32,6 +32,15 @@ impl<S: io::Seek> StreamOffset<S> {
     }
 }
 
+/// Seeking moves the offset to the absolute position reported by the inner stream.
+impl<S: io::Seek> io::Seek for StreamOffset<S> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let offset = self.inner.seek(pos)?;
+        self.offset = offset as usize;
+        Ok(offset)
+    }
+}
+
 impl<R: io::Read> io::Read for StreamOffset<R> {
     #[inline]
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -1,11 +1,16 @@
+pub mod assembly;
 pub mod ast;
 pub mod bundle;
 pub mod bytecode;
+pub mod compact;
 pub mod decode;
 pub mod definition;
 pub mod encode;
+pub mod interpreter;
 pub mod io;
 pub mod mapper;
+pub mod text;
+pub mod verify;
 
 #[cfg(not(feature = "arc"))]
 pub type Ref<A> = std::rc::Rc<A>;
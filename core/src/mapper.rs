@@ -3,12 +3,20 @@ use std::marker::PhantomData;
 
 use hashbrown::HashMap;
 
-use crate::bundle::{ConstantPool, PoolIndex};
-use crate::bytecode::Instr;
-use crate::definition::{AnyDefinition, Class, Function};
+use crate::bundle::{CName, ConstantPool, DefaultString, PoolIndex, PoolMark, Resource, Strings, TweakDbId};
+use crate::bytecode::{Instr, PoolRef};
+use crate::definition::{AnyDefinition, Class, Definition, Enum, Field, Function, Local, Parameter, SourceFile, Type};
+use crate::Ref;
 
 pub trait Mapper<A> {
     fn apply(&self, value: A) -> A;
+
+    /// Returns `None` if the value has no counterpart, optional references to it are cleared
+    /// and it's removed from lists.
+    #[inline]
+    fn try_apply(&self, value: A) -> Option<A> {
+        Some(self.apply(value))
+    }
 }
 
 pub struct MultiMapper<A> {
@@ -39,72 +47,463 @@ impl<A> Mapper<A> for NoopMapper<A> {
     }
 }
 
-pub struct PoolMapper<CM, FM> {
-    map_class: CM,
-    map_function: FM,
+impl<A, M: Mapper<A> + ?Sized> Mapper<A> for &M {
+    #[inline]
+    fn apply(&self, value: A) -> A {
+        (**self).apply(value)
+    }
+
+    #[inline]
+    fn try_apply(&self, value: A) -> Option<A> {
+        (**self).try_apply(value)
+    }
+}
+
+type BoxedMapper<'a, A> = Box<dyn Mapper<PoolIndex<A>> + 'a>;
+
+/// Rewrites the indices stored in the definitions of a pool and in the operands of their code,
+/// with a separate mapper for every kind of entry. The entries themselves are not moved.
+pub struct PoolMapper<'a> {
+    map_class: BoxedMapper<'a, Class>,
+    map_function: BoxedMapper<'a, Function>,
+    map_field: BoxedMapper<'a, Field>,
+    map_type: BoxedMapper<'a, Type>,
+    map_local: BoxedMapper<'a, Local>,
+    map_parameter: BoxedMapper<'a, Parameter>,
+    map_enum: BoxedMapper<'a, Enum>,
+    map_enum_value: BoxedMapper<'a, i64>,
+    map_source_file: BoxedMapper<'a, SourceFile>,
+    map_name: BoxedMapper<'a, CName>,
+    map_tweakdb_id: BoxedMapper<'a, TweakDbId>,
+    map_resource: BoxedMapper<'a, Resource>,
+    map_string: BoxedMapper<'a, String>,
 }
 
-impl<CM, FM> PoolMapper<CM, FM>
-where
-    CM: Mapper<PoolIndex<Class>>,
-    FM: Mapper<PoolIndex<Function>>,
-{
+impl<'a> PoolMapper<'a> {
+    /// Maps every definition of the pool, which can't be an [overlay](crate::bundle::SharedPool::overlay).
     pub fn map(&self, pool: &mut ConstantPool) {
+        assert!(!pool.is_overlay(), "an overlay can't be mapped");
         for def in &mut pool.definitions {
-            match &mut def.value {
-                AnyDefinition::Class(class) => {
-                    class.base = self.map_class.apply(class.base);
-                }
-                AnyDefinition::Function(fun) => {
-                    def.parent = self.map_class.apply(def.parent.cast()).cast();
-                    for instr in fun.code.as_mut() {
-                        self.map_instr(instr);
-                    }
-                }
-                AnyDefinition::Parameter(_) | AnyDefinition::Local(_) => {
-                    def.parent = self.map_function.apply(def.parent.cast()).cast();
+            self.map_definition(def);
+        }
+    }
+
+    fn map_definition(&self, def: &mut Definition) {
+        def.name = self.map_name.apply(def.name);
+        match &mut def.value {
+            AnyDefinition::Type(type_) => match type_ {
+                Type::Ref(inner)
+                | Type::WeakRef(inner)
+                | Type::Array(inner)
+                | Type::StaticArray(inner, _)
+                | Type::ScriptRef(inner) => *inner = self.map_type.apply(*inner),
+                Type::Prim | Type::Class => {}
+            },
+            AnyDefinition::Class(class) => {
+                class.base = self.map_class.apply(class.base);
+                map_all(self.map_function.as_ref(), &mut class.functions);
+                map_all(self.map_field.as_ref(), &mut class.fields);
+                map_all(self.map_field.as_ref(), &mut class.overrides);
+            }
+            AnyDefinition::Enum(enum_) => map_all(self.map_enum_value.as_ref(), &mut enum_.members),
+            AnyDefinition::EnumValue(_) => {
+                def.parent = self.map_enum.apply(def.parent.cast()).cast();
+            }
+            AnyDefinition::Function(fun) => {
+                def.parent = self.map_class.apply(def.parent.cast()).cast();
+                if let Some(source) = &mut fun.source {
+                    source.file = self.map_source_file.apply(source.file);
                 }
-                AnyDefinition::Field(_) => {
-                    def.parent = self.map_class.apply(def.parent.cast()).cast();
+                fun.return_type = fun.return_type.and_then(|idx| self.map_type.try_apply(idx));
+                fun.base_method = fun.base_method.and_then(|idx| self.map_function.try_apply(idx));
+                map_all(self.map_parameter.as_ref(), &mut fun.parameters);
+                map_all(self.map_local.as_ref(), &mut fun.locals);
+                map_all(self.map_parameter.as_ref(), &mut fun.unk2);
+                for instr in fun.code.as_mut() {
+                    self.map_instr(instr);
                 }
-                AnyDefinition::Type(_)
-                | AnyDefinition::EnumValue(_)
-                | AnyDefinition::Enum(_)
-                | AnyDefinition::SourceFile(_) => {}
             }
+            AnyDefinition::Parameter(param) => {
+                def.parent = self.map_function.apply(def.parent.cast()).cast();
+                param.type_ = self.map_type.apply(param.type_);
+            }
+            AnyDefinition::Local(local) => {
+                def.parent = self.map_function.apply(def.parent.cast()).cast();
+                local.type_ = self.map_type.apply(local.type_);
+            }
+            AnyDefinition::Field(field) => {
+                def.parent = self.map_class.apply(def.parent.cast()).cast();
+                field.type_ = self.map_type.apply(field.type_);
+            }
+            AnyDefinition::SourceFile(_) => {}
         }
     }
 
-    fn map_instr<L>(&self, instr: &mut Instr<L>) {
-        match instr {
-            Instr::Construct(_, idx) | Instr::New(idx) | Instr::DynamicCast(idx, _) => {
-                *idx = self.map_class.apply(*idx);
-            }
-            Instr::InvokeStatic(_, _, idx, _) => *idx = self.map_function.apply(*idx),
-            _ => {}
+    pub fn map_instr<L>(&self, instr: &mut Instr<L>) {
+        instr.map_pool_refs(|ref_| match ref_ {
+            PoolRef::Name(idx) => PoolRef::Name(self.map_name.apply(idx)),
+            PoolRef::String(idx) => PoolRef::String(self.map_string.apply(idx)),
+            PoolRef::TweakDbId(idx) => PoolRef::TweakDbId(self.map_tweakdb_id.apply(idx)),
+            PoolRef::Resource(idx) => PoolRef::Resource(self.map_resource.apply(idx)),
+            PoolRef::Enum(idx) => PoolRef::Enum(self.map_enum.apply(idx)),
+            PoolRef::EnumValue(idx) => PoolRef::EnumValue(self.map_enum_value.apply(idx)),
+            PoolRef::Local(idx) => PoolRef::Local(self.map_local.apply(idx)),
+            PoolRef::Param(idx) => PoolRef::Param(self.map_parameter.apply(idx)),
+            PoolRef::Field(idx) => PoolRef::Field(self.map_field.apply(idx)),
+            PoolRef::Type(idx) => PoolRef::Type(self.map_type.apply(idx)),
+            PoolRef::Class(idx) => PoolRef::Class(self.map_class.apply(idx)),
+            PoolRef::Function(idx) => PoolRef::Function(self.map_function.apply(idx)),
+        });
+    }
+}
+
+macro_rules! mapper_setters {
+    ($($setter:ident($field:ident, $ty:ty)),*) => {
+        impl<'a> PoolMapper<'a> {
+            $(pub fn $setter<M: Mapper<PoolIndex<$ty>> + 'a>(mut self, mapper: M) -> Self {
+                self.$field = Box::new(mapper);
+                self
+            })*
+        }
+    };
+}
+
+mapper_setters!(
+    with_class_mapper(map_class, Class),
+    with_function_mapper(map_function, Function),
+    with_field_mapper(map_field, Field),
+    with_type_mapper(map_type, Type),
+    with_local_mapper(map_local, Local),
+    with_parameter_mapper(map_parameter, Parameter),
+    with_enum_mapper(map_enum, Enum),
+    with_enum_value_mapper(map_enum_value, i64),
+    with_source_file_mapper(map_source_file, SourceFile),
+    with_name_mapper(map_name, CName),
+    with_tweakdb_id_mapper(map_tweakdb_id, TweakDbId),
+    with_resource_mapper(map_resource, Resource),
+    with_string_mapper(map_string, String)
+);
+
+impl Default for PoolMapper<'_> {
+    fn default() -> Self {
+        Self {
+            map_class: Box::new(NoopMapper(PhantomData)),
+            map_function: Box::new(NoopMapper(PhantomData)),
+            map_field: Box::new(NoopMapper(PhantomData)),
+            map_type: Box::new(NoopMapper(PhantomData)),
+            map_local: Box::new(NoopMapper(PhantomData)),
+            map_parameter: Box::new(NoopMapper(PhantomData)),
+            map_enum: Box::new(NoopMapper(PhantomData)),
+            map_enum_value: Box::new(NoopMapper(PhantomData)),
+            map_source_file: Box::new(NoopMapper(PhantomData)),
+            map_name: Box::new(NoopMapper(PhantomData)),
+            map_tweakdb_id: Box::new(NoopMapper(PhantomData)),
+            map_resource: Box::new(NoopMapper(PhantomData)),
+            map_string: Box::new(NoopMapper(PhantomData)),
+        }
+    }
+}
+
+fn map_all<A>(mapper: &dyn Mapper<PoolIndex<A>>, indexes: &mut Vec<PoolIndex<A>>) {
+    indexes.retain_mut(|idx| match mapper.try_apply(*idx) {
+        Some(mapped) => {
+            *idx = mapped;
+            true
+        }
+        None => false,
+    });
+}
+
+/// Moves the entries of a pool to new positions. Entries are kept in their original order,
+/// and the ones that are not retained are removed when the relocation is applied.
+#[derive(Debug)]
+pub struct Relocation {
+    definitions: Positions,
+    names: Positions,
+    tweakdb_ids: Positions,
+    resources: Positions,
+    strings: Positions,
+}
+
+impl Relocation {
+    pub(crate) fn retaining(
+        definitions: &[bool],
+        names: &[bool],
+        tweakdb_ids: &[bool],
+        resources: &[bool],
+        strings: &[bool],
+    ) -> Self {
+        Self {
+            definitions: Positions::retaining(definitions),
+            names: Positions::retaining(names),
+            tweakdb_ids: Positions::retaining(tweakdb_ids),
+            resources: Positions::retaining(resources),
+            strings: Positions::retaining(strings),
+        }
+    }
+
+    /// Returns the new position of a definition, or `None` if it's removed.
+    pub fn definition<A>(&self, index: PoolIndex<A>) -> Option<PoolIndex<A>> {
+        self.definitions.get(index)
+    }
+
+    /// Returns the new position of any referenced entry, or `None` if it's removed.
+    pub fn pool_ref(&self, ref_: PoolRef) -> Option<PoolRef> {
+        let index = match ref_ {
+            PoolRef::Name(idx) => self.names.get(idx)?.cast(),
+            PoolRef::TweakDbId(idx) => self.tweakdb_ids.get(idx)?.cast(),
+            PoolRef::Resource(idx) => self.resources.get(idx)?.cast(),
+            PoolRef::String(idx) => self.strings.get(idx)?.cast(),
+            other => self.definitions.get(other.index::<()>())?,
+        };
+        Some(ref_.with_index(index))
+    }
+
+    /// Returns the number of definitions that are removed.
+    pub fn removed_definitions(&self) -> usize {
+        self.definitions.removed()
+    }
+
+    /// Returns the number of names, TweakDB IDs, resources and strings that are removed.
+    pub fn removed_strings(&self) -> usize {
+        self.names.removed() + self.tweakdb_ids.removed() + self.resources.removed() + self.strings.removed()
+    }
+
+    /// Removes the entries that are not retained and updates every index in the pool.
+    /// References to removed entries are dropped from lists and optional references,
+    /// and replaced with undefined indices elsewhere. The pool can't be an [overlay](crate::bundle::SharedPool::overlay).
+    pub fn apply(&self, pool: &mut ConstantPool) {
+        assert!(!pool.is_overlay(), "an overlay can't be relocated");
+        let definitions = std::mem::take(&mut pool.definitions);
+        pool.definitions = definitions
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| self.definitions.0.get(*i).copied().flatten().is_some())
+            .map(|(_, def)| def)
+            .collect();
+
+        pool.names = self.names.retain(&pool.names);
+        pool.tweakdb_ids = self.tweakdb_ids.retain(&pool.tweakdb_ids);
+        pool.resources = self.resources.retain(&pool.resources);
+        pool.strings = self.strings.retain(&pool.strings);
+
+        let definitions = &self.definitions;
+        PoolMapper::default()
+            .with_class_mapper(definitions)
+            .with_function_mapper(definitions)
+            .with_field_mapper(definitions)
+            .with_type_mapper(definitions)
+            .with_local_mapper(definitions)
+            .with_parameter_mapper(definitions)
+            .with_enum_mapper(definitions)
+            .with_enum_value_mapper(definitions)
+            .with_source_file_mapper(definitions)
+            .with_name_mapper(&self.names)
+            .with_tweakdb_id_mapper(&self.tweakdb_ids)
+            .with_resource_mapper(&self.resources)
+            .with_string_mapper(&self.strings)
+            .map(pool);
+    }
+}
+
+/// The new positions of the entries of a single table, indexed by the current positions.
+#[derive(Debug)]
+struct Positions(Vec<Option<u32>>);
+
+impl Positions {
+    fn retaining(retained: &[bool]) -> Self {
+        let mut next = 0;
+        let positions = retained
+            .iter()
+            .map(|&retain| {
+                retain.then(|| {
+                    next += 1;
+                    next - 1
+                })
+            })
+            .collect();
+        Self(positions)
+    }
+
+    fn get<A>(&self, index: PoolIndex<A>) -> Option<PoolIndex<A>> {
+        let position = self.0.get(u32::from(index) as usize).copied().flatten()?;
+        Some(PoolIndex::new(position))
+    }
+
+    fn removed(&self) -> usize {
+        self.0.iter().filter(|pos| pos.is_none()).count()
+    }
+
+    fn retain<K: DefaultString>(&self, strings: &Strings<K>) -> Strings<K> {
+        let entries = strings
+            .entries()
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.0.get(*i).copied().flatten().is_some())
+            .map(|(_, str)| str.clone())
+            .collect();
+        Strings::from_entries(entries)
+    }
+}
+
+impl<A> Mapper<PoolIndex<A>> for Positions {
+    fn apply(&self, index: PoolIndex<A>) -> PoolIndex<A> {
+        self.get(index).unwrap_or(PoolIndex::UNDEFINED)
+    }
+
+    fn try_apply(&self, index: PoolIndex<A>) -> Option<PoolIndex<A>> {
+        self.get(index)
+    }
+}
+
+/// The entries added to a copy of a pool after it was taken, staged to be merged into the original pool.
+/// Entries are merged in the order in which they were added, possibly in several steps.
+/// Strings are deduplicated against the target pool and references to staged entries
+/// are updated to the positions they're given there.
+#[derive(Debug)]
+pub struct PoolStage {
+    base: PoolMark,
+    definitions: Vec<Definition>,
+    names: Vec<Ref<str>>,
+    tweakdb_ids: Vec<Ref<str>>,
+    resources: Vec<Ref<str>>,
+    strings: Vec<Ref<str>>,
+    merged: Merged,
+}
+
+impl PoolStage {
+    /// Stages the entries added to `copy` since it was at `base`,
+    /// `copy` can also be an [overlay](crate::bundle::SharedPool::overlay) over the pool,
+    /// but only entries are staged, so it must not have modified any of the shared definitions.
+    pub fn new(mut copy: ConstantPool, base: PoolMark) -> Self {
+        assert!(!copy.has_overrides(), "modified shared definitions can't be staged");
+        Self {
+            base,
+            definitions: copy.split_definitions_off(base.definitions),
+            names: copy.names.entries_from(base.names).to_vec(),
+            tweakdb_ids: copy.tweakdb_ids.entries_from(base.tweakdb_ids).to_vec(),
+            resources: copy.resources.entries_from(base.resources).to_vec(),
+            strings: copy.strings.entries_from(base.strings).to_vec(),
+            merged: Merged::new(base),
         }
     }
 
-    pub fn with_class_mapper<CM2: Mapper<PoolIndex<Class>>>(self, mapper: CM2) -> PoolMapper<CM2, FM> {
-        PoolMapper {
-            map_class: mapper,
-            map_function: self.map_function,
+    /// Merges the staged entries that were added to the copy before it reached `mark`
+    /// and haven't been merged yet.
+    pub fn merge(&mut self, mark: PoolMark, pool: &mut ConstantPool) {
+        let base = self.base;
+        let merged = &mut self.merged;
+        merged
+            .names
+            .merge(&self.names[..mark.names - base.names], &mut pool.names);
+        merged.tweakdb_ids.merge(
+            &self.tweakdb_ids[..mark.tweakdb_ids - base.tweakdb_ids],
+            &mut pool.tweakdb_ids,
+        );
+        merged
+            .resources
+            .merge(&self.resources[..mark.resources - base.resources], &mut pool.resources);
+        merged
+            .strings
+            .merge(&self.strings[..mark.strings - base.strings], &mut pool.strings);
+
+        // positions are assigned up front, staged definitions can refer to the ones that follow them
+        let pending = &self.definitions[merged.definitions.positions.len()..mark.definitions - base.definitions];
+        let start = pool.definitions.len() as u32;
+        merged.definitions.positions.extend(start..start + pending.len() as u32);
+
+        let mapper = self.merged.mapper();
+        for def in pending {
+            let mut def = def.clone();
+            mapper.map_definition(&mut def);
+            pool.definitions.push(def);
         }
     }
 
-    pub fn with_function_mapper<FM2: Mapper<PoolIndex<Function>>>(self, mapper: FM2) -> PoolMapper<CM, FM2> {
-        PoolMapper {
-            map_class: self.map_class,
-            map_function: mapper,
+    /// Returns the position in the target pool of a definition of the copy,
+    /// staged definitions that haven't been merged yet are undefined.
+    pub fn definition<A>(&self, index: PoolIndex<A>) -> PoolIndex<A> {
+        self.merged.definitions.apply(index)
+    }
+
+    /// Returns a mapper from the indices of the copy to the indices of the target pool.
+    pub fn mapper(&self) -> PoolMapper<'_> {
+        self.merged.mapper()
+    }
+}
+
+/// The positions given to the staged entries of every table that were merged so far.
+#[derive(Debug)]
+struct Merged {
+    definitions: MergedPositions,
+    names: MergedPositions,
+    tweakdb_ids: MergedPositions,
+    resources: MergedPositions,
+    strings: MergedPositions,
+}
+
+impl Merged {
+    fn new(base: PoolMark) -> Self {
+        Self {
+            definitions: MergedPositions::new(base.definitions),
+            names: MergedPositions::new(base.names),
+            tweakdb_ids: MergedPositions::new(base.tweakdb_ids),
+            resources: MergedPositions::new(base.resources),
+            strings: MergedPositions::new(base.strings),
         }
     }
+
+    fn mapper(&self) -> PoolMapper<'_> {
+        let definitions = &self.definitions;
+        PoolMapper::default()
+            .with_class_mapper(definitions)
+            .with_function_mapper(definitions)
+            .with_field_mapper(definitions)
+            .with_type_mapper(definitions)
+            .with_local_mapper(definitions)
+            .with_parameter_mapper(definitions)
+            .with_enum_mapper(definitions)
+            .with_enum_value_mapper(definitions)
+            .with_source_file_mapper(definitions)
+            .with_name_mapper(&self.names)
+            .with_tweakdb_id_mapper(&self.tweakdb_ids)
+            .with_resource_mapper(&self.resources)
+            .with_string_mapper(&self.strings)
+    }
 }
 
-impl Default for PoolMapper<NoopMapper<PoolIndex<Class>>, NoopMapper<PoolIndex<Function>>> {
-    fn default() -> Self {
+/// The positions in the target pool of the staged entries of a single table,
+/// entries that precede the staged ones keep their positions.
+#[derive(Debug)]
+struct MergedPositions {
+    base: u32,
+    positions: Vec<u32>,
+}
+
+impl MergedPositions {
+    fn new(base: usize) -> Self {
         Self {
-            map_class: NoopMapper(PhantomData),
-            map_function: NoopMapper(PhantomData),
+            base: base as u32,
+            positions: vec![],
+        }
+    }
+
+    fn merge<K: DefaultString>(&mut self, staged: &[Ref<str>], strings: &mut Strings<K>) {
+        for str in &staged[self.positions.len()..] {
+            self.positions.push(strings.add(str.clone()).into());
+        }
+    }
+}
+
+impl<A> Mapper<PoolIndex<A>> for MergedPositions {
+    fn apply(&self, index: PoolIndex<A>) -> PoolIndex<A> {
+        self.try_apply(index).unwrap_or(PoolIndex::UNDEFINED)
+    }
+
+    fn try_apply(&self, index: PoolIndex<A>) -> Option<PoolIndex<A>> {
+        match u32::from(index).checked_sub(self.base) {
+            Some(offset) => self.positions.get(offset as usize).map(|&pos| PoolIndex::new(pos)),
+            None => Some(index),
         }
     }
 }
@@ -0,0 +1,1221 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::bundle::{
+    CName, ConstantPool, DefaultString, Header, PoolIndex, Resource, ScriptBundle, Strings, Timestamp, TweakDbId,
+    Version,
+};
+use crate::bytecode::{Breakpoint, Code, Instr, Offset, PoolRef, StartProfiling};
+use crate::definition::{
+    AnyDefinition, Class, ClassFlags, Definition, Enum, Field, FieldFlags, Function, FunctionFlags, Local, LocalFlags,
+    Parameter, ParameterFlags, Property, SourceFile, SourceReference, Type, Visibility,
+};
+use crate::Ref;
+
+const CLASS_FLAGS: &[&str] = &[
+    "is_native",
+    "is_abstract",
+    "is_final",
+    "is_struct",
+    "has_functions",
+    "has_fields",
+    "is_import_only",
+    "is_test_only",
+    "has_overrides",
+];
+
+const FUNCTION_FLAGS: &[&str] = &[
+    "is_static",
+    "is_exec",
+    "is_timer",
+    "is_final",
+    "is_native",
+    "unk1",
+    "is_callback",
+    "is_operator",
+    "has_return_value",
+    "has_base_method",
+    "has_parameters",
+    "has_locals",
+    "has_body",
+    "is_cast",
+    "is_implicit_cast",
+    "",
+    "",
+    "",
+    "is_thread_safe",
+    "is_const",
+    "unk2",
+    "unk3",
+    "is_quest",
+    "unk4",
+    "unk5",
+    "unk6",
+];
+
+const FIELD_FLAGS: &[&str] = &[
+    "is_native",
+    "is_editable",
+    "is_inline",
+    "is_const",
+    "is_replicated",
+    "has_hint",
+    "is_instance_editable",
+    "has_default",
+    "is_persistent",
+    "is_test_only",
+    "is_browsable",
+];
+
+const LOCAL_FLAGS: &[&str] = &["is_const"];
+
+const PARAMETER_FLAGS: &[&str] = &["is_optional", "is_out", "is_short_circuit", "is_const"];
+
+/// Writes the bundle in a textual format that can be read back with [`read_bundle`].
+///
+/// The output lists the header, the string tables and every definition along with its bytecode,
+/// reading it back and saving the result produces a bundle identical to one saved from the original.
+pub fn write_bundle<W: fmt::Write>(out: &mut W, bundle: &ScriptBundle) -> fmt::Result {
+    let header = &bundle.header;
+    writeln!(out, "version {}", header.version.number())?;
+    writeln!(out, "flags {:#x}", header.flags)?;
+    writeln!(
+        out,
+        "timestamp {:#x}",
+        u64::from_le_bytes(header.timestamp.into_bytes())
+    )?;
+    writeln!(out, "unk3 {:#x}", header.unk3)?;
+    writeln!(out, "chunks {}", header.chunks)?;
+    writeln!(out)?;
+    write_pool(out, &bundle.pool)
+}
+
+/// Reads a bundle written by [`write_bundle`].
+pub fn read_bundle(input: &str) -> Result<ScriptBundle, ParseError> {
+    let mut parser = Parser::new(input)?;
+
+    parser.keyword("version")?;
+    let version = Version::from_number_or_unknown(parser.int()?);
+    parser.keyword("flags")?;
+    let flags = parser.int()?;
+    parser.keyword("timestamp")?;
+    let timestamp = Timestamp::from_bytes(parser.int::<u64>()?.to_le_bytes());
+    parser.keyword("unk3")?;
+    let unk3 = parser.int()?;
+    parser.keyword("chunks")?;
+    let chunks = parser.int()?;
+
+    let header = Header::new(version, flags, timestamp, unk3, chunks);
+    let pool = parse_pool(&mut parser)?;
+    Ok(ScriptBundle { header, pool })
+}
+
+/// Writes the pool in the textual format used by [`write_bundle`], without the bundle header.
+/// The pool can't be an [overlay](crate::bundle::SharedPool::overlay).
+pub fn write_pool<W: fmt::Write>(out: &mut W, pool: &ConstantPool) -> fmt::Result {
+    assert!(!pool.is_overlay(), "an overlay can't be written");
+    write_strings(out, "names", &pool.names)?;
+    write_strings(out, "tweakdb_ids", &pool.tweakdb_ids)?;
+    write_strings(out, "resources", &pool.resources)?;
+    write_strings(out, "strings", &pool.strings)?;
+
+    for (idx, def) in pool.definitions.iter().enumerate().skip(1) {
+        writeln!(out)?;
+        write_definition(out, idx, def, pool)?;
+    }
+    Ok(())
+}
+
+/// Reads a pool written by [`write_pool`].
+pub fn read_pool(input: &str) -> Result<ConstantPool, ParseError> {
+    parse_pool(&mut Parser::new(input)?)
+}
+
+fn write_strings<W: fmt::Write, K: DefaultString>(out: &mut W, table: &str, strings: &Strings<K>) -> fmt::Result {
+    writeln!(out, "{table} {{")?;
+    for (idx, str) in strings.entries().iter().enumerate() {
+        writeln!(out, "    {idx} {:?}", &**str)?;
+    }
+    writeln!(out, "}}")
+}
+
+fn write_definition<W: fmt::Write>(out: &mut W, index: usize, def: &Definition, pool: &ConstantPool) -> fmt::Result {
+    let kind = match def.value {
+        AnyDefinition::Type(_) => "type",
+        AnyDefinition::Class(_) => "class",
+        AnyDefinition::EnumValue(_) => "enum_value",
+        AnyDefinition::Enum(_) => "enum",
+        AnyDefinition::Function(_) => "function",
+        AnyDefinition::Parameter(_) => "parameter",
+        AnyDefinition::Local(_) => "local",
+        AnyDefinition::Field(_) => "field",
+        AnyDefinition::SourceFile(_) => "source_file",
+    };
+    write!(
+        out,
+        "#{index} {kind} name #{} parent #{} unk {} {} {} {{",
+        def.name, def.parent, def.unk1, def.unk2, def.unk3
+    )?;
+    if let Some(name) = pool.names.get(def.name).ok().filter(|_| !def.name.is_undefined()) {
+        write!(out, " // {name}")?;
+    }
+    writeln!(out)?;
+
+    match &def.value {
+        AnyDefinition::Type(type_) => write_type(out, type_)?,
+        AnyDefinition::Class(class) => {
+            writeln!(out, "    visibility {}", class.visibility)?;
+            write_flags(
+                out,
+                u32::from(u16::from_le_bytes(class.flags.into_bytes())),
+                CLASS_FLAGS,
+            )?;
+            writeln!(out, "    base #{}", class.base)?;
+            write_refs(out, "functions", &class.functions)?;
+            write_refs(out, "fields", &class.fields)?;
+            write_refs(out, "overrides", &class.overrides)?;
+        }
+        AnyDefinition::EnumValue(value) => writeln!(out, "    value {value}")?,
+        AnyDefinition::Enum(enum_) => {
+            writeln!(out, "    flags {:#x}", enum_.flags)?;
+            writeln!(out, "    size {}", enum_.size)?;
+            write_refs(out, "members", &enum_.members)?;
+            writeln!(out, "    unk1 {}", enum_.unk1)?;
+        }
+        AnyDefinition::Function(fun) => write_function(out, fun, pool)?,
+        AnyDefinition::Parameter(param) => {
+            writeln!(out, "    type #{}", param.type_)?;
+            write_flags(out, u32::from(param.flags.into_bytes()[0]), PARAMETER_FLAGS)?;
+        }
+        AnyDefinition::Local(local) => {
+            writeln!(out, "    type #{}", local.type_)?;
+            write_flags(out, u32::from(local.flags.into_bytes()[0]), LOCAL_FLAGS)?;
+        }
+        AnyDefinition::Field(field) => {
+            writeln!(out, "    visibility {}", field.visibility)?;
+            writeln!(out, "    type #{}", field.type_)?;
+            write_flags(
+                out,
+                u32::from(u16::from_le_bytes(field.flags.into_bytes())),
+                FIELD_FLAGS,
+            )?;
+            match &field.hint {
+                Some(hint) => writeln!(out, "    hint {hint:?}")?,
+                None => writeln!(out, "    hint none")?,
+            }
+            write_properties(out, "attributes", &field.attributes)?;
+            write_properties(out, "defaults", &field.defaults)?;
+        }
+        AnyDefinition::SourceFile(file) => {
+            writeln!(out, "    id {}", file.id)?;
+            writeln!(out, "    path_hash {:#x}", file.path_hash)?;
+            writeln!(out, "    path {:?}", file.path.to_string_lossy())?;
+        }
+    }
+    writeln!(out, "}}")
+}
+
+fn write_type<W: fmt::Write>(out: &mut W, type_: &Type) -> fmt::Result {
+    match type_ {
+        Type::Prim => writeln!(out, "    prim"),
+        Type::Class => writeln!(out, "    class"),
+        Type::Ref(inner) => writeln!(out, "    ref #{inner}"),
+        Type::WeakRef(inner) => writeln!(out, "    wref #{inner}"),
+        Type::Array(inner) => writeln!(out, "    array #{inner}"),
+        Type::StaticArray(inner, size) => writeln!(out, "    static_array #{inner} {size}"),
+        Type::ScriptRef(inner) => writeln!(out, "    script_ref #{inner}"),
+    }
+}
+
+fn write_function<W: fmt::Write>(out: &mut W, fun: &Function, pool: &ConstantPool) -> fmt::Result {
+    writeln!(out, "    visibility {}", fun.visibility)?;
+    write_flags(out, u32::from_le_bytes(fun.flags.into_bytes()), FUNCTION_FLAGS)?;
+    match &fun.source {
+        Some(source) => writeln!(out, "    source #{} {}", source.file, source.line)?,
+        None => writeln!(out, "    source none")?,
+    }
+    write_optional_ref(out, "return_type", fun.return_type)?;
+    writeln!(out, "    unk1 {}", fun.unk1)?;
+    write_optional_ref(out, "base_method", fun.base_method)?;
+    write_refs(out, "parameters", &fun.parameters)?;
+    write_refs(out, "locals", &fun.locals)?;
+    match fun.operator {
+        Some(operator) => writeln!(out, "    operator {operator}")?,
+        None => writeln!(out, "    operator none")?,
+    }
+    writeln!(out, "    cast {}", fun.cast)?;
+    write_refs(out, "unk2", &fun.unk2)?;
+
+    writeln!(out, "    code {{")?;
+    for (location, instr) in fun.code.iter() {
+        write!(out, "        {}: ", location.value)?;
+        write_instr(
+            out,
+            &instr,
+            &Positional {
+                position: location.value,
+            },
+        )?;
+        match instr {
+            Instr::InvokeStatic(_, _, idx, _) => {
+                if let Ok(name) = pool.def_name(idx) {
+                    write!(out, " // {name}")?;
+                }
+            }
+            Instr::InvokeVirtual(_, _, idx, _) => {
+                if let Ok(name) = pool.names.get(idx) {
+                    write!(out, " // {name}")?;
+                }
+            }
+            _ => {}
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "    }}")
+}
+
+fn write_flags<W: fmt::Write>(out: &mut W, bits: u32, names: &[&str]) -> fmt::Result {
+    out.write_str("    flags [")?;
+    let mut first = true;
+    for bit in (0..32).filter(|bit| bits & (1 << bit) != 0) {
+        if !first {
+            out.write_char(' ')?;
+        }
+        first = false;
+        match names.get(bit) {
+            Some(name) if !name.is_empty() => out.write_str(name)?,
+            _ => write!(out, "bit{bit}")?,
+        }
+    }
+    writeln!(out, "]")
+}
+
+fn write_refs<W: fmt::Write, A>(out: &mut W, key: &str, refs: &[PoolIndex<A>]) -> fmt::Result {
+    write!(out, "    {key} [")?;
+    for (i, idx) in refs.iter().enumerate() {
+        if i > 0 {
+            out.write_char(' ')?;
+        }
+        write!(out, "#{idx}")?;
+    }
+    writeln!(out, "]")
+}
+
+fn write_optional_ref<W: fmt::Write, A>(out: &mut W, key: &str, index: Option<PoolIndex<A>>) -> fmt::Result {
+    match index {
+        Some(idx) => writeln!(out, "    {key} #{idx}"),
+        None => writeln!(out, "    {key} none"),
+    }
+}
+
+fn write_properties<W: fmt::Write>(out: &mut W, key: &str, properties: &[Property]) -> fmt::Result {
+    write!(out, "    {key} [")?;
+    for (i, prop) in properties.iter().enumerate() {
+        if i > 0 {
+            out.write_char(' ')?;
+        }
+        write!(out, "{:?} = {:?}", prop.name, prop.value)?;
+    }
+    writeln!(out, "]")
+}
+
+fn parse_pool(parser: &mut Parser<'_>) -> Result<ConstantPool, ParseError> {
+    let names = parse_strings(parser, "names")?;
+    let tweakdb_ids = parse_strings(parser, "tweakdb_ids")?;
+    let resources = parse_strings(parser, "resources")?;
+    let strings = parse_strings(parser, "strings")?;
+
+    let mut definitions = vec![Definition::DEFAULT];
+    while !parser.is_at_end() {
+        let index: PoolIndex<Definition> = parser.index()?;
+        if u32::from(index) as usize != definitions.len() {
+            return Err(parser.error(format!("expected definition #{}, found #{index}", definitions.len())));
+        }
+        definitions.push(parse_definition(parser)?);
+    }
+
+    Ok(ConstantPool {
+        names,
+        tweakdb_ids,
+        resources,
+        strings,
+        definitions,
+        shared: None,
+    })
+}
+
+fn parse_strings<K: DefaultString>(parser: &mut Parser<'_>, table: &str) -> Result<Strings<K>, ParseError> {
+    parser.keyword(table)?;
+    parser.punct('{')?;
+    let mut strings = vec![];
+    while !parser.eat_punct('}') {
+        let index: usize = parser.int()?;
+        if index != strings.len() {
+            return Err(parser.error(format!("expected entry {}, found {index}", strings.len())));
+        }
+        strings.push(Ref::from(parser.string()?));
+    }
+    Ok(Strings::from_entries(strings))
+}
+
+fn parse_definition(parser: &mut Parser<'_>) -> Result<Definition, ParseError> {
+    let kind = parser.word()?;
+    parser.keyword("name")?;
+    let name = parser.index()?;
+    parser.keyword("parent")?;
+    let parent = parser.index()?;
+    parser.keyword("unk")?;
+    let unk1 = parser.int()?;
+    let unk2 = parser.int()?;
+    let unk3 = parser.int()?;
+    parser.punct('{')?;
+
+    let value = match kind {
+        "type" => AnyDefinition::Type(parse_type(parser)?),
+        "class" => {
+            let visibility = parse_visibility(parser)?;
+            let flags = ClassFlags::from_bytes(parse_flags::<u16>(parser, CLASS_FLAGS)?.to_le_bytes());
+            parser.keyword("base")?;
+            let base = parser.index()?;
+            AnyDefinition::Class(Class {
+                visibility,
+                flags,
+                base,
+                functions: parser.field_refs("functions")?,
+                fields: parser.field_refs("fields")?,
+                overrides: parser.field_refs("overrides")?,
+            })
+        }
+        "enum_value" => {
+            parser.keyword("value")?;
+            AnyDefinition::EnumValue(parser.int()?)
+        }
+        "enum" => {
+            parser.keyword("flags")?;
+            let flags = parser.int()?;
+            parser.keyword("size")?;
+            let size = parser.int()?;
+            let members = parser.field_refs("members")?;
+            parser.keyword("unk1")?;
+            let unk1 = parser.bool()?;
+            AnyDefinition::Enum(Enum {
+                flags,
+                size,
+                members,
+                unk1,
+            })
+        }
+        "function" => AnyDefinition::Function(parse_function(parser)?),
+        "parameter" => {
+            parser.keyword("type")?;
+            let type_ = parser.index()?;
+            let flags = ParameterFlags::from_bytes([parse_flags::<u8>(parser, PARAMETER_FLAGS)?]);
+            AnyDefinition::Parameter(Parameter { type_, flags })
+        }
+        "local" => {
+            parser.keyword("type")?;
+            let type_ = parser.index()?;
+            let flags = LocalFlags::from_bytes([parse_flags::<u8>(parser, LOCAL_FLAGS)?]);
+            AnyDefinition::Local(Local { type_, flags })
+        }
+        "field" => {
+            let visibility = parse_visibility(parser)?;
+            parser.keyword("type")?;
+            let type_ = parser.index()?;
+            let flags = FieldFlags::from_bytes(parse_flags::<u16>(parser, FIELD_FLAGS)?.to_le_bytes());
+            parser.keyword("hint")?;
+            let hint = if parser.eat_keyword("none") {
+                None
+            } else {
+                Some(parser.string()?)
+            };
+            AnyDefinition::Field(Field {
+                visibility,
+                type_,
+                flags,
+                hint,
+                attributes: parse_properties(parser, "attributes")?,
+                defaults: parse_properties(parser, "defaults")?,
+            })
+        }
+        "source_file" => {
+            parser.keyword("id")?;
+            let id = parser.int()?;
+            parser.keyword("path_hash")?;
+            let path_hash = parser.int()?;
+            parser.keyword("path")?;
+            let path = PathBuf::from(parser.string()?);
+            AnyDefinition::SourceFile(SourceFile { id, path_hash, path })
+        }
+        other => return Err(parser.error(format!("unknown definition kind '{other}'"))),
+    };
+    parser.punct('}')?;
+
+    Ok(Definition {
+        name,
+        parent,
+        unk1,
+        unk2,
+        unk3,
+        value,
+    })
+}
+
+fn parse_type(parser: &mut Parser<'_>) -> Result<Type, ParseError> {
+    let type_ = match parser.word()? {
+        "prim" => Type::Prim,
+        "class" => Type::Class,
+        "ref" => Type::Ref(parser.index()?),
+        "wref" => Type::WeakRef(parser.index()?),
+        "array" => Type::Array(parser.index()?),
+        "static_array" => Type::StaticArray(parser.index()?, parser.int()?),
+        "script_ref" => Type::ScriptRef(parser.index()?),
+        other => return Err(parser.error(format!("unknown type kind '{other}'"))),
+    };
+    Ok(type_)
+}
+
+fn parse_function(parser: &mut Parser<'_>) -> Result<Function, ParseError> {
+    let visibility = parse_visibility(parser)?;
+    let flags = FunctionFlags::from_bytes(parse_flags::<u32>(parser, FUNCTION_FLAGS)?.to_le_bytes());
+    parser.keyword("source")?;
+    let source = if parser.eat_keyword("none") {
+        None
+    } else {
+        Some(SourceReference {
+            file: parser.index()?,
+            line: parser.int()?,
+        })
+    };
+    let return_type = parser.field_optional_ref("return_type")?;
+    parser.keyword("unk1")?;
+    let unk1 = parser.bool()?;
+    let base_method = parser.field_optional_ref("base_method")?;
+    let parameters = parser.field_refs("parameters")?;
+    let locals = parser.field_refs("locals")?;
+    parser.keyword("operator")?;
+    let operator = if parser.eat_keyword("none") {
+        None
+    } else {
+        Some(parser.int()?)
+    };
+    parser.keyword("cast")?;
+    let cast = parser.int()?;
+    let unk2 = parser.field_refs("unk2")?;
+
+    parser.keyword("code")?;
+    parser.punct('{')?;
+    let mut code = vec![];
+    let mut position: u16 = 0;
+    while !parser.eat_punct('}') {
+        // the offsets are informative, instructions are placed one after another
+        parser.int::<u16>()?;
+        parser.punct(':')?;
+        let mnemonic = parser.word()?;
+        let instr = parse_instr(parser, mnemonic, &mut Positional { position })?;
+        position = position
+            .checked_add(instr.size())
+            .ok_or_else(|| parser.error("the code is too large"))?;
+        code.push(instr);
+    }
+
+    Ok(Function {
+        visibility,
+        flags,
+        source,
+        return_type,
+        unk1,
+        base_method,
+        parameters,
+        locals,
+        operator,
+        cast,
+        code: Code::new(code),
+        unk2,
+    })
+}
+
+fn parse_visibility(parser: &mut Parser<'_>) -> Result<Visibility, ParseError> {
+    parser.keyword("visibility")?;
+    match parser.word()? {
+        "public" => Ok(Visibility::Public),
+        "protected" => Ok(Visibility::Protected),
+        "private" => Ok(Visibility::Private),
+        other => Err(parser.error(format!("unknown visibility '{other}'"))),
+    }
+}
+
+fn parse_flags<A: TryFrom<u32>>(parser: &mut Parser<'_>, names: &[&str]) -> Result<A, ParseError> {
+    parser.keyword("flags")?;
+    parser.punct('[')?;
+    let mut bits = 0u32;
+    while !parser.eat_punct(']') {
+        let flag = parser.word()?;
+        let bit = match names.iter().position(|name| !name.is_empty() && *name == flag) {
+            Some(bit) => bit as u32,
+            None => flag
+                .strip_prefix("bit")
+                .and_then(|bit| bit.parse().ok())
+                .filter(|bit| *bit < 32)
+                .ok_or_else(|| parser.error(format!("unknown flag '{flag}'")))?,
+        };
+        bits |= 1 << bit;
+    }
+    A::try_from(bits).map_err(|_| parser.error("flag out of range"))
+}
+
+fn parse_properties(parser: &mut Parser<'_>, key: &str) -> Result<Vec<Property>, ParseError> {
+    parser.keyword(key)?;
+    parser.punct('[')?;
+    let mut properties = vec![];
+    while !parser.eat_punct(']') {
+        let name = parser.string()?;
+        parser.punct('=')?;
+        let value = parser.string()?;
+        properties.push(Property { name, value });
+    }
+    Ok(properties)
+}
+
+/// Determines how the operands referring to pool entries and code locations are written.
+pub(crate) trait Printer: Sized {
+    type Loc: PrintOperand<Self>;
+
+    fn print_ref<W: fmt::Write>(&self, out: &mut W, ref_: PoolRef) -> fmt::Result;
+}
+
+/// Determines how the operands referring to pool entries and code locations are read.
+/// The kind of the reference passed to `read_ref` is the kind expected by the instruction.
+pub(crate) trait Reader: Sized {
+    type Loc: ReadOperand<Self>;
+
+    fn read_ref(&mut self, parser: &mut Parser<'_>, kind: PoolRef) -> Result<PoolRef, ParseError>;
+}
+
+pub(crate) trait PrintOperand<P> {
+    fn print<W: fmt::Write>(&self, out: &mut W, printer: &P) -> fmt::Result;
+}
+
+pub(crate) trait ReadOperand<R>: Sized {
+    fn read(parser: &mut Parser<'_>, reader: &mut R) -> Result<Self, ParseError>;
+}
+
+/// The syntax of the text format, pool entries are referred to by index and offsets are written
+/// as absolute positions in the code.
+struct Positional {
+    position: u16,
+}
+
+impl Printer for Positional {
+    type Loc = Offset;
+
+    fn print_ref<W: fmt::Write>(&self, out: &mut W, ref_: PoolRef) -> fmt::Result {
+        write!(out, "#{}", ref_.index::<()>())
+    }
+}
+
+impl Reader for Positional {
+    type Loc = Offset;
+
+    fn read_ref(&mut self, parser: &mut Parser<'_>, kind: PoolRef) -> Result<PoolRef, ParseError> {
+        Ok(kind.with_index::<()>(parser.index()?))
+    }
+}
+
+impl PrintOperand<Positional> for Offset {
+    fn print<W: fmt::Write>(&self, out: &mut W, printer: &Positional) -> fmt::Result {
+        write!(out, "@{}", i32::from(printer.position) + i32::from(self.value))
+    }
+}
+
+impl ReadOperand<Positional> for Offset {
+    fn read(parser: &mut Parser<'_>, reader: &mut Positional) -> Result<Self, ParseError> {
+        let word = parser.word()?;
+        let target = word
+            .strip_prefix('@')
+            .and_then(parse_int::<i32>)
+            .ok_or_else(|| parser.error(format!("expected a code offset, found '{word}'")))?;
+        let value = i16::try_from(target - i32::from(reader.position))
+            .map_err(|_| parser.error(format!("code offset {target} is out of range")))?;
+        Ok(Offset::new(value))
+    }
+}
+
+macro_rules! int_operands {
+    ($($ty:ty),*) => {
+        $(impl<P> PrintOperand<P> for $ty {
+            fn print<W: fmt::Write>(&self, out: &mut W, _printer: &P) -> fmt::Result {
+                write!(out, "{self}")
+            }
+        }
+
+        impl<R> ReadOperand<R> for $ty {
+            fn read(parser: &mut Parser<'_>, _reader: &mut R) -> Result<Self, ParseError> {
+                parser.int()
+            }
+        })*
+    };
+}
+
+int_operands!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+macro_rules! float_operands {
+    ($($ty:ty),*) => {
+        $(impl<P> PrintOperand<P> for $ty {
+            // non-finite values are written as raw bits to keep NaN payloads intact
+            fn print<W: fmt::Write>(&self, out: &mut W, _printer: &P) -> fmt::Result {
+                if self.is_finite() {
+                    write!(out, "{self:?}")
+                } else {
+                    write!(out, "{:#x}", self.to_bits())
+                }
+            }
+        }
+
+        impl<R> ReadOperand<R> for $ty {
+            fn read(parser: &mut Parser<'_>, _reader: &mut R) -> Result<Self, ParseError> {
+                let word = parser.word()?;
+                if word.starts_with("0x") {
+                    let bits = parse_int(word).ok_or_else(|| parser.error(format!("invalid float '{word}'")))?;
+                    Ok(<$ty>::from_bits(bits))
+                } else {
+                    word.parse().map_err(|_| parser.error(format!("invalid float '{word}'")))
+                }
+            }
+        })*
+    };
+}
+
+float_operands!(f32, f64);
+
+/// The kinds of pool entries that instructions can refer to.
+pub(crate) trait RefKind: Sized {
+    fn pool_ref(index: PoolIndex<Self>) -> PoolRef;
+}
+
+macro_rules! ref_kinds {
+    ($($ty:ty => $variant:ident),*) => {
+        $(impl RefKind for $ty {
+            fn pool_ref(index: PoolIndex<Self>) -> PoolRef {
+                PoolRef::$variant(index)
+            }
+        })*
+    };
+}
+
+ref_kinds!(
+    CName => Name,
+    String => String,
+    TweakDbId => TweakDbId,
+    Resource => Resource,
+    Enum => Enum,
+    i64 => EnumValue,
+    Local => Local,
+    Parameter => Param,
+    Field => Field,
+    Type => Type,
+    Class => Class,
+    Function => Function
+);
+
+impl<A: RefKind, P: Printer> PrintOperand<P> for PoolIndex<A> {
+    fn print<W: fmt::Write>(&self, out: &mut W, printer: &P) -> fmt::Result {
+        printer.print_ref(out, A::pool_ref(*self))
+    }
+}
+
+impl<A: RefKind, R: Reader> ReadOperand<R> for PoolIndex<A> {
+    fn read(parser: &mut Parser<'_>, reader: &mut R) -> Result<Self, ParseError> {
+        let ref_ = reader.read_ref(parser, A::pool_ref(PoolIndex::UNDEFINED))?;
+        Ok(ref_.index())
+    }
+}
+
+impl<P> PrintOperand<P> for Box<Breakpoint> {
+    fn print<W: fmt::Write>(&self, out: &mut W, _printer: &P) -> fmt::Result {
+        write!(
+            out,
+            "{} {} {} {} {} {}",
+            self.line, self.line_start, self.col, self.length, self.enabled, self.padding
+        )
+    }
+}
+
+impl<R> ReadOperand<R> for Box<Breakpoint> {
+    fn read(parser: &mut Parser<'_>, _reader: &mut R) -> Result<Self, ParseError> {
+        let breakpoint = Breakpoint {
+            line: parser.int()?,
+            line_start: parser.int()?,
+            col: parser.int()?,
+            length: parser.int()?,
+            enabled: parser.bool()?,
+            padding: parser.int()?,
+        };
+        Ok(Box::new(breakpoint))
+    }
+}
+
+impl<P> PrintOperand<P> for Box<StartProfiling> {
+    fn print<W: fmt::Write>(&self, out: &mut W, _printer: &P) -> fmt::Result {
+        write!(out, "{:?} {}", self.0, self.1)
+    }
+}
+
+impl<R> ReadOperand<R> for Box<StartProfiling> {
+    fn read(parser: &mut Parser<'_>, _reader: &mut R) -> Result<Self, ParseError> {
+        Ok(Box::new(StartProfiling(parser.string()?, parser.int()?)))
+    }
+}
+
+macro_rules! operand {
+    ($arg:ident, $parser:ident, $reader:ident) => {
+        ReadOperand::read($parser, $reader)?
+    };
+}
+
+macro_rules! instructions {
+    ($($variant:ident $(($($arg:ident),+))? => $mnemonic:literal,)*) => {
+        pub(crate) fn write_instr<P: Printer, W: fmt::Write>(
+            out: &mut W,
+            instr: &Instr<P::Loc>,
+            printer: &P,
+        ) -> fmt::Result {
+            match instr {
+                $(Instr::$variant $(($($arg),+))? => {
+                    out.write_str($mnemonic)?;
+                    $($(
+                        out.write_char(' ')?;
+                        $arg.print(out, printer)?;
+                    )+)?
+                })*
+            }
+            Ok(())
+        }
+
+        pub(crate) fn parse_instr<R: Reader>(
+            parser: &mut Parser<'_>,
+            mnemonic: &str,
+            reader: &mut R,
+        ) -> Result<Instr<R::Loc>, ParseError> {
+            let instr = match mnemonic {
+                $($mnemonic => Instr::$variant $(($(operand!($arg, parser, reader)),+))?,)*
+                other => return Err(parser.error(format!("unknown instruction '{other}'"))),
+            };
+            Ok(instr)
+        }
+    };
+}
+
+instructions! {
+    Nop => "nop",
+    Null => "null",
+    I32One => "i32_one",
+    I32Zero => "i32_zero",
+    I8Const(a) => "i8_const",
+    I16Const(a) => "i16_const",
+    I32Const(a) => "i32_const",
+    I64Const(a) => "i64_const",
+    U8Const(a) => "u8_const",
+    U16Const(a) => "u16_const",
+    U32Const(a) => "u32_const",
+    U64Const(a) => "u64_const",
+    F32Const(a) => "f32_const",
+    F64Const(a) => "f64_const",
+    NameConst(a) => "name_const",
+    EnumConst(a, b) => "enum_const",
+    StringConst(a) => "string_const",
+    TweakDbIdConst(a) => "tweakdb_id_const",
+    ResourceConst(a) => "resource_const",
+    TrueConst => "true_const",
+    FalseConst => "false_const",
+    Breakpoint(a) => "breakpoint",
+    Assign => "assign",
+    Target(a) => "target",
+    Local(a) => "local",
+    Param(a) => "param",
+    ObjectField(a) => "object_field",
+    ExternalVar => "external_var",
+    Switch(a, b) => "switch",
+    SwitchLabel(a, b) => "switch_label",
+    SwitchDefault => "switch_default",
+    Jump(a) => "jump",
+    JumpIfFalse(a) => "jump_if_false",
+    Skip(a) => "skip",
+    Conditional(a, b) => "conditional",
+    Construct(a, b) => "construct",
+    InvokeStatic(a, b, c, d) => "invoke_static",
+    InvokeVirtual(a, b, c, d) => "invoke_virtual",
+    ParamEnd => "param_end",
+    Return => "return",
+    StructField(a) => "struct_field",
+    Context(a) => "context",
+    Equals(a) => "equals",
+    RefStringEqualsString(a) => "ref_string_equals_string",
+    StringEqualsRefString(a) => "string_equals_ref_string",
+    NotEquals(a) => "not_equals",
+    RefStringNotEqualsString(a) => "ref_string_not_equals_string",
+    StringNotEqualsRefString(a) => "string_not_equals_ref_string",
+    New(a) => "new",
+    Delete => "delete",
+    This => "this",
+    StartProfiling(a) => "start_profiling",
+    ArrayClear(a) => "array_clear",
+    ArraySize(a) => "array_size",
+    ArrayResize(a) => "array_resize",
+    ArrayFindFirst(a) => "array_find_first",
+    ArrayFindFirstFast(a) => "array_find_first_fast",
+    ArrayFindLast(a) => "array_find_last",
+    ArrayFindLastFast(a) => "array_find_last_fast",
+    ArrayContains(a) => "array_contains",
+    ArrayContainsFast(a) => "array_contains_fast",
+    ArrayCount(a) => "array_count",
+    ArrayCountFast(a) => "array_count_fast",
+    ArrayPush(a) => "array_push",
+    ArrayPop(a) => "array_pop",
+    ArrayInsert(a) => "array_insert",
+    ArrayRemove(a) => "array_remove",
+    ArrayRemoveFast(a) => "array_remove_fast",
+    ArrayGrow(a) => "array_grow",
+    ArrayErase(a) => "array_erase",
+    ArrayEraseFast(a) => "array_erase_fast",
+    ArrayLast(a) => "array_last",
+    ArrayElement(a) => "array_element",
+    ArraySort(a) => "array_sort",
+    ArraySortByPredicate(a) => "array_sort_by_predicate",
+    StaticArraySize(a) => "static_array_size",
+    StaticArrayFindFirst(a) => "static_array_find_first",
+    StaticArrayFindFirstFast(a) => "static_array_find_first_fast",
+    StaticArrayFindLast(a) => "static_array_find_last",
+    StaticArrayFindLastFast(a) => "static_array_find_last_fast",
+    StaticArrayContains(a) => "static_array_contains",
+    StaticArrayContainsFast(a) => "static_array_contains_fast",
+    StaticArrayCount(a) => "static_array_count",
+    StaticArrayCountFast(a) => "static_array_count_fast",
+    StaticArrayLast(a) => "static_array_last",
+    StaticArrayElement(a) => "static_array_element",
+    RefToBool => "ref_to_bool",
+    WeakRefToBool => "weak_ref_to_bool",
+    EnumToI32(a, b) => "enum_to_i32",
+    I32ToEnum(a, b) => "i32_to_enum",
+    DynamicCast(a, b) => "dynamic_cast",
+    ToString(a) => "to_string",
+    ToVariant(a) => "to_variant",
+    FromVariant(a) => "from_variant",
+    VariantIsDefined => "variant_is_defined",
+    VariantIsRef => "variant_is_ref",
+    VariantIsArray => "variant_is_array",
+    VariantTypeName => "variant_type_name",
+    VariantToString => "variant_to_string",
+    WeakRefToRef => "weak_ref_to_ref",
+    RefToWeakRef => "ref_to_weak_ref",
+    WeakRefNull => "weak_ref_null",
+    AsRef(a) => "as_ref",
+    Deref(a) => "deref",
+}
+
+/// An error produced when the text cannot be read back.
+#[derive(Debug, Error)]
+#[error("line {line}: {message}")]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Str(String),
+    Punct(char),
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{word}'"),
+            Token::Str(str) => write!(f, "{str:?}"),
+            Token::Punct(char) => write!(f, "'{char}'"),
+        }
+    }
+}
+
+pub(crate) struct Parser<'a> {
+    tokens: Vec<(Token<'a>, usize)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    const PUNCTUATION: &'static [char] = &['{', '}', '[', ']', ':', '='];
+
+    pub(crate) fn new(input: &'a str) -> Result<Self, ParseError> {
+        let mut tokens = vec![];
+        let mut line = 1;
+        let mut rest = input;
+        while let Some(char) = rest.chars().next() {
+            if char == '\n' {
+                line += 1;
+                rest = &rest[1..];
+            } else if char.is_whitespace() {
+                rest = &rest[char.len_utf8()..];
+            } else if rest.starts_with("//") {
+                rest = rest.find('\n').map_or("", |end| &rest[end..]);
+            } else if char == '"' {
+                let (str, len) = unescape(rest).ok_or_else(|| ParseError {
+                    line,
+                    message: "invalid string literal".to_owned(),
+                })?;
+                tokens.push((Token::Str(str), line));
+                rest = &rest[len..];
+            } else if Self::is_punct(rest, 0) {
+                tokens.push((Token::Punct(char), line));
+                rest = &rest[1..];
+            } else {
+                let end = rest
+                    .char_indices()
+                    .find(|&(i, c)| c.is_whitespace() || c == '"' || Self::is_punct(rest, i))
+                    .map_or(rest.len(), |(i, _)| i);
+                tokens.push((Token::Word(&rest[..end]), line));
+                rest = &rest[end..];
+            }
+        }
+        Ok(Self { tokens, pos: 0 })
+    }
+
+    // colons only separate tokens when followed by whitespace, so that they can appear in type names
+    fn is_punct(str: &str, i: usize) -> bool {
+        let mut chars = str[i..].chars();
+        match chars.next() {
+            Some(':') => chars.next().map_or(true, char::is_whitespace),
+            Some(char) => Self::PUNCTUATION.contains(&char),
+            None => false,
+        }
+    }
+
+    pub(crate) fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line(),
+            message: message.into(),
+        }
+    }
+
+    /// Returns the line of the last consumed token.
+    pub(crate) fn line(&self) -> usize {
+        self.tokens.get(self.pos.saturating_sub(1)).map_or(1, |(_, line)| *line)
+    }
+
+    pub(crate) fn is_at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn next(&mut self) -> Result<&Token<'a>, ParseError> {
+        let (token, _) = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| self.error("unexpected end of input"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    pub(crate) fn peek_word(&self) -> Option<&'a str> {
+        match self.tokens.get(self.pos) {
+            Some(&(Token::Word(word), _)) => Some(word),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn word(&mut self) -> Result<&'a str, ParseError> {
+        match self.next()? {
+            &Token::Word(word) => Ok(word),
+            other => {
+                let message = format!("expected a word, found {other}");
+                Err(self.error(message))
+            }
+        }
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, ParseError> {
+        match self.next()? {
+            Token::Str(str) => Ok(str.clone()),
+            other => {
+                let message = format!("expected a string, found {other}");
+                Err(self.error(message))
+            }
+        }
+    }
+
+    pub(crate) fn keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        match self.next()? {
+            Token::Word(word) if *word == keyword => Ok(()),
+            other => {
+                let message = format!("expected '{keyword}', found {other}");
+                Err(self.error(message))
+            }
+        }
+    }
+
+    pub(crate) fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let matches = matches!(self.tokens.get(self.pos), Some((Token::Word(word), _)) if *word == keyword);
+        self.pos += usize::from(matches);
+        matches
+    }
+
+    /// Consumes a word followed by a colon and returns the word.
+    pub(crate) fn eat_label(&mut self) -> Option<&'a str> {
+        match self.tokens.get(self.pos..self.pos + 2) {
+            Some([(Token::Word(word), _), (Token::Punct(':'), _)]) => {
+                self.pos += 2;
+                Some(word)
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn punct(&mut self, punct: char) -> Result<(), ParseError> {
+        match self.next()? {
+            Token::Punct(char) if *char == punct => Ok(()),
+            other => {
+                let message = format!("expected '{punct}', found {other}");
+                Err(self.error(message))
+            }
+        }
+    }
+
+    pub(crate) fn eat_punct(&mut self, punct: char) -> bool {
+        let matches = matches!(self.tokens.get(self.pos), Some((Token::Punct(char), _)) if *char == punct);
+        self.pos += usize::from(matches);
+        matches
+    }
+
+    pub(crate) fn int<A: TryFrom<i128>>(&mut self) -> Result<A, ParseError> {
+        let word = self.word()?;
+        parse_int(word).ok_or_else(|| self.error(format!("expected an integer in range, found '{word}'")))
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, ParseError> {
+        match self.word()? {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            other => Err(self.error(format!("expected a boolean, found '{other}'"))),
+        }
+    }
+
+    pub(crate) fn index<A>(&mut self) -> Result<PoolIndex<A>, ParseError> {
+        let word = self.word()?;
+        word.strip_prefix('#')
+            .and_then(parse_int)
+            .map(PoolIndex::new)
+            .ok_or_else(|| self.error(format!("expected a pool index, found '{word}'")))
+    }
+
+    fn field_refs<A>(&mut self, key: &str) -> Result<Vec<PoolIndex<A>>, ParseError> {
+        self.keyword(key)?;
+        self.punct('[')?;
+        let mut refs = vec![];
+        while !self.eat_punct(']') {
+            refs.push(self.index()?);
+        }
+        Ok(refs)
+    }
+
+    fn field_optional_ref<A>(&mut self, key: &str) -> Result<Option<PoolIndex<A>>, ParseError> {
+        self.keyword(key)?;
+        if self.eat_keyword("none") {
+            Ok(None)
+        } else {
+            self.index().map(Some)
+        }
+    }
+}
+
+fn parse_int<A: TryFrom<i128>>(str: &str) -> Option<A> {
+    let value = match str.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None => i128::from_str(str).ok()?,
+    };
+    A::try_from(value).ok()
+}
+
+// reads a string literal as written by the `Debug` implementation of `str`,
+// returns the unescaped string and the length of the literal
+fn unescape(input: &str) -> Option<(String, usize)> {
+    let mut res = String::new();
+    let mut chars = input.char_indices().skip(1);
+    while let Some((i, char)) = chars.next() {
+        match char {
+            '"' => return Some((res, i + 1)),
+            '\\' => {
+                let escaped = match chars.next()?.1 {
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    '0' => '\0',
+                    'u' => {
+                        if chars.next()?.1 != '{' {
+                            return None;
+                        }
+                        let mut code = String::new();
+                        loop {
+                            match chars.next()?.1 {
+                                '}' => break,
+                                char => code.push(char),
+                            }
+                        }
+                        char::from_u32(u32::from_str_radix(&code, 16).ok()?)?
+                    }
+                    char => char,
+                };
+                res.push(escaped);
+            }
+            char => res.push(char),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{read_bundle, write_bundle};
+    use crate::bundle::{ScriptBundle, Version};
+
+    const PREDEF: &[u8] = include_bytes!("../../resources/predef.redscripts");
+
+    #[test]
+    fn round_trip_predef() {
+        let bundle = ScriptBundle::load(&mut Cursor::new(PREDEF)).unwrap();
+        let mut text = String::new();
+        write_bundle(&mut text, &bundle).unwrap();
+
+        let imported = read_bundle(&text).unwrap();
+        let mut expected = Cursor::new(vec![]);
+        bundle.save(&mut expected).unwrap();
+        let mut actual = Cursor::new(vec![]);
+        imported.save(&mut actual).unwrap();
+        assert_eq!(expected.into_inner(), actual.into_inner());
+    }
+
+    #[test]
+    fn keep_unknown_version() {
+        let bundle = ScriptBundle::load(&mut Cursor::new(PREDEF)).unwrap();
+        let mut text = String::new();
+        write_bundle(&mut text, &bundle).unwrap();
+
+        let text = text.replacen("version 13", "version 99", 1);
+        let imported = read_bundle(&text).unwrap();
+        assert_eq!(imported.version(), Version::Unknown(99));
+        let mut written = String::new();
+        write_bundle(&mut written, &imported).unwrap();
+        assert_eq!(written, text);
+    }
+
+    #[test]
+    fn report_parse_errors() {
+        let err = read_bundle("version 14\nflags 0x0\ntimestamp 0x0\nunk3 0x0\nchunks 0\n\nnames {\n    0 None\n}")
+            .unwrap_err();
+        assert_eq!(err.line, 8);
+        assert_eq!(err.to_string(), "line 8: expected a string, found 'None'");
+    }
+}
@@ -0,0 +1,450 @@
+use std::fmt::Display;
+
+use hashbrown::HashMap;
+use thiserror::Error;
+
+use crate::bundle::{ConstantPool, DefinitionType, PoolIndex};
+use crate::bytecode::{skip_args, Code, Instr, Location, Offset, PoolRef};
+use crate::definition::{AnyDefinition, Class, Definition, Enum, Function, Type};
+
+/// Checks the structural invariants the game relies on when loading a pool and returns every violation found.
+///
+/// The flags describing the content of a definition are recomputed when a pool is encoded,
+/// so they're only meaningful for pools that were decoded from a file.
+pub fn verify_pool(pool: &ConstantPool) -> Vec<VerifyError> {
+    let mut verifier = Verifier { pool, errors: vec![] };
+    for (index, def) in pool.definitions() {
+        verifier.definition(index, def);
+    }
+    verifier.errors
+}
+
+/// A violation of an invariant found in a definition.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{name}: {kind}")]
+pub struct VerifyError {
+    pub definition: PoolIndex<Definition>,
+    /// The qualified name of the definition.
+    pub name: String,
+    pub kind: VerifyErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum VerifyErrorKind {
+    #[error("{field} refers to {index}, which does not exist")]
+    DanglingReference { field: String, index: u32 },
+    #[error("{field} refers to {index}, expected a {expected} but found a {found}")]
+    InvalidReference {
+        field: String,
+        index: u32,
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("{field} refers to {index}, which is not in the {table} table")]
+    InvalidString {
+        field: String,
+        index: u32,
+        table: &'static str,
+    },
+    #[error("base class {0} is placed after the subclass")]
+    BaseAfterSubclass(String),
+    #[error("the {flag} flag does not match the content, expected it to be {expected}")]
+    FlagMismatch { flag: &'static str, expected: bool },
+    #[error("function is not native but has no source reference")]
+    MissingSource,
+    #[error("jump at {location} targets {target}, which is not an instruction boundary")]
+    InvalidJumpTarget { location: Location, target: Location },
+    #[error("invocation at {location} is not terminated by a ParamEnd right before its exit offset")]
+    UnterminatedInvocation { location: Location },
+    #[error("invocation at {location} passes {actual} argument(s), but the function has {expected} parameter(s)")]
+    ArgumentCountMismatch {
+        location: Location,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+struct Verifier<'a> {
+    pool: &'a ConstantPool,
+    errors: Vec<VerifyError>,
+}
+
+impl<'a> Verifier<'a> {
+    fn definition(&mut self, index: PoolIndex<Definition>, def: &Definition) {
+        let mut ctx = Context {
+            verifier: self,
+            index,
+            name: None,
+        };
+        if !matches!(def.value, AnyDefinition::SourceFile(_)) && ctx.verifier.pool.names.get(def.name).is_err() {
+            ctx.report(VerifyErrorKind::InvalidString {
+                field: "name".to_owned(),
+                index: def.name.into(),
+                table: "name",
+            });
+        }
+
+        let parent_kind = match def.value {
+            AnyDefinition::Function(_) | AnyDefinition::Field(_) => Some(DefinitionType::Class),
+            AnyDefinition::Parameter(_) | AnyDefinition::Local(_) => Some(DefinitionType::Function),
+            AnyDefinition::EnumValue(_) => Some(DefinitionType::Enum),
+            _ => None,
+        };
+        match parent_kind {
+            Some(kind) if !def.parent.is_undefined() => ctx.expect("parent", def.parent, kind),
+            _ => {}
+        }
+
+        match &def.value {
+            AnyDefinition::Type(typ) => ctx.type_(typ),
+            AnyDefinition::Class(class) => ctx.class(class),
+            AnyDefinition::Enum(enum_) => ctx.enum_(enum_),
+            AnyDefinition::Function(fun) => ctx.function(fun),
+            AnyDefinition::Parameter(param) => ctx.expect("type", param.type_, DefinitionType::Type),
+            AnyDefinition::Local(local) => ctx.expect("type", local.type_, DefinitionType::Type),
+            AnyDefinition::Field(field) => ctx.expect("type", field.type_, DefinitionType::Type),
+            AnyDefinition::EnumValue(_) | AnyDefinition::SourceFile(_) => {}
+        }
+    }
+}
+
+// verifies a single definition, its name is only resolved once there's something to report
+struct Context<'a, 'b> {
+    verifier: &'b mut Verifier<'a>,
+    index: PoolIndex<Definition>,
+    name: Option<String>,
+}
+
+impl<'a, 'b> Context<'a, 'b> {
+    fn type_(&mut self, typ: &Type) {
+        match typ {
+            Type::Ref(inner)
+            | Type::WeakRef(inner)
+            | Type::Array(inner)
+            | Type::StaticArray(inner, _)
+            | Type::ScriptRef(inner) => self.expect("inner type", *inner, DefinitionType::Type),
+            Type::Prim | Type::Class => {}
+        }
+    }
+
+    fn class(&mut self, class: &Class) {
+        if !class.base.is_undefined() {
+            self.expect("base class", class.base, DefinitionType::Class);
+            if class.base > self.index.cast() {
+                let base = qualified_name(self.verifier.pool, class.base.cast());
+                self.report(VerifyErrorKind::BaseAfterSubclass(base));
+            }
+        }
+        for &idx in &class.functions {
+            self.expect("method", idx, DefinitionType::Function);
+        }
+        for &idx in &class.fields {
+            self.expect("field", idx, DefinitionType::Field);
+        }
+        for &idx in &class.overrides {
+            self.expect("override", idx, DefinitionType::Field);
+        }
+        self.flag(
+            "has_functions",
+            class.flags.has_functions(),
+            !class.functions.is_empty(),
+        );
+        self.flag("has_fields", class.flags.has_fields(), !class.fields.is_empty());
+        self.flag(
+            "has_overrides",
+            class.flags.has_overrides(),
+            !class.overrides.is_empty(),
+        );
+    }
+
+    fn enum_(&mut self, enum_: &Enum) {
+        for &idx in &enum_.members {
+            self.expect("member", idx, DefinitionType::EnumValue);
+        }
+    }
+
+    fn function(&mut self, fun: &Function) {
+        if let Some(typ) = fun.return_type {
+            self.expect("return type", typ, DefinitionType::Type);
+        }
+        if let Some(base) = fun.base_method {
+            self.expect("base method", base, DefinitionType::Function);
+        }
+        for &idx in fun.parameters.iter().chain(&fun.unk2) {
+            self.expect("parameter", idx, DefinitionType::Parameter);
+        }
+        for &idx in &fun.locals {
+            self.expect("local", idx, DefinitionType::Local);
+        }
+        match &fun.source {
+            Some(source) => self.expect("source file", source.file, DefinitionType::SourceFile),
+            None if !fun.flags.is_native() => self.report(VerifyErrorKind::MissingSource),
+            None => {}
+        }
+
+        self.flag("has_body", fun.flags.has_body(), !fun.code.is_empty());
+        self.flag(
+            "has_parameters",
+            fun.flags.has_parameters(),
+            !fun.parameters.is_empty() || !fun.unk2.is_empty(),
+        );
+        self.flag("has_locals", fun.flags.has_locals(), !fun.locals.is_empty());
+        self.flag(
+            "has_return_value",
+            fun.flags.has_return_value(),
+            fun.return_type.is_some(),
+        );
+        self.flag(
+            "has_base_method",
+            fun.flags.has_base_method(),
+            fun.base_method.is_some(),
+        );
+        self.flag("is_operator", fun.flags.is_operator(), fun.operator.is_some());
+
+        self.code(&fun.code);
+    }
+
+    fn code(&mut self, code: &Code<Offset>) {
+        let instrs: Vec<_> = code.iter().collect();
+        let end = instrs
+            .last()
+            .map_or(Location::ZERO, |(loc, instr)| Location::new(loc.value + instr.size()));
+        // maps every instruction boundary to the index of the instruction that starts there
+        let boundaries: HashMap<Location, usize> = instrs
+            .iter()
+            .enumerate()
+            .map(|(i, (loc, _))| (*loc, i))
+            .chain([(end, instrs.len())])
+            .collect();
+
+        for (i, (location, instr)) in instrs.iter().enumerate() {
+            let location = *location;
+            for ref_ in instr.pool_refs() {
+                self.pool_ref(location, ref_);
+            }
+
+            for offset in jump_offsets(instr) {
+                let target = offset.absolute(location);
+                if !boundaries.contains_key(&target) {
+                    self.report(VerifyErrorKind::InvalidJumpTarget { location, target });
+                }
+            }
+
+            let exit = match instr {
+                Instr::InvokeStatic(exit, _, _, _) | Instr::InvokeVirtual(exit, _, _, _) => exit.absolute(location),
+                _ => continue,
+            };
+            let param_end = boundaries.get(&exit).and_then(|&idx| idx.checked_sub(1));
+            match skip_args(&instrs, i + 1) {
+                Some((count, end)) if Some(end) == param_end => {
+                    if let Instr::InvokeStatic(_, _, idx, _) = instr {
+                        if let Ok(fun) = self.verifier.pool.function(*idx) {
+                            if fun.parameters.len() != count {
+                                self.report(VerifyErrorKind::ArgumentCountMismatch {
+                                    location,
+                                    expected: fun.parameters.len(),
+                                    actual: count,
+                                });
+                            }
+                        }
+                    }
+                }
+                _ => self.report(VerifyErrorKind::UnterminatedInvocation { location }),
+            }
+        }
+    }
+
+    fn pool_ref(&mut self, location: Location, ref_: PoolRef) {
+        let field = || format!("instruction at {location}");
+        let pool = self.verifier.pool;
+        let (table, valid) = match ref_ {
+            PoolRef::Name(idx) => ("name", pool.names.get(idx).is_ok()),
+            PoolRef::String(idx) => ("string", pool.strings.get(idx).is_ok()),
+            PoolRef::TweakDbId(idx) => ("tweakdb id", pool.tweakdb_ids.get(idx).is_ok()),
+            PoolRef::Resource(idx) => ("resource", pool.resources.get(idx).is_ok()),
+            PoolRef::Enum(idx) => return self.expect(field(), idx, DefinitionType::Enum),
+            PoolRef::EnumValue(idx) => return self.expect(field(), idx, DefinitionType::EnumValue),
+            PoolRef::Local(idx) => return self.expect(field(), idx, DefinitionType::Local),
+            PoolRef::Param(idx) => return self.expect(field(), idx, DefinitionType::Parameter),
+            PoolRef::Field(idx) => return self.expect(field(), idx, DefinitionType::Field),
+            PoolRef::Type(idx) => return self.expect(field(), idx, DefinitionType::Type),
+            PoolRef::Class(idx) => return self.expect(field(), idx, DefinitionType::Class),
+            PoolRef::Function(idx) => return self.expect(field(), idx, DefinitionType::Function),
+        };
+        if !valid {
+            self.report(VerifyErrorKind::InvalidString {
+                field: field(),
+                index: ref_.index::<()>().into(),
+                table,
+            });
+        }
+    }
+
+    fn expect<A>(&mut self, field: impl Display, index: PoolIndex<A>, expected: DefinitionType) {
+        match self.verifier.pool.definition(index) {
+            Ok(def) if def.value.type_() == expected => {}
+            Ok(def) => self.report(VerifyErrorKind::InvalidReference {
+                field: field.to_string(),
+                index: index.into(),
+                expected: kind_name(expected),
+                found: kind_name(def.value.type_()),
+            }),
+            Err(_) => self.report(VerifyErrorKind::DanglingReference {
+                field: field.to_string(),
+                index: index.into(),
+            }),
+        }
+    }
+
+    fn flag(&mut self, flag: &'static str, actual: bool, expected: bool) {
+        if actual != expected {
+            self.report(VerifyErrorKind::FlagMismatch { flag, expected });
+        }
+    }
+
+    fn report(&mut self, kind: VerifyErrorKind) {
+        let pool = self.verifier.pool;
+        let index = self.index;
+        let name = self.name.get_or_insert_with(|| qualified_name(pool, index));
+        self.verifier.errors.push(VerifyError {
+            definition: index,
+            name: name.clone(),
+            kind,
+        });
+    }
+}
+
+fn jump_offsets(instr: &Instr<Offset>) -> Vec<Offset> {
+    match instr {
+        Instr::Jump(offset)
+        | Instr::JumpIfFalse(offset)
+        | Instr::Skip(offset)
+        | Instr::Switch(_, offset)
+        | Instr::Context(offset)
+        | Instr::InvokeStatic(offset, _, _, _)
+        | Instr::InvokeVirtual(offset, _, _, _) => vec![*offset],
+        Instr::Conditional(a, b) | Instr::SwitchLabel(a, b) => vec![*a, *b],
+        _ => vec![],
+    }
+}
+
+// parents are followed a few levels up at most, so that a cycle in a corrupted pool can't hang the verifier
+fn qualified_name(pool: &ConstantPool, index: PoolIndex<Definition>) -> String {
+    let mut parts = vec![];
+    let mut current = index;
+    while !current.is_undefined() && parts.len() < 4 {
+        let Ok(def) = pool.definition(current) else {
+            parts.push(format!("<{current}>"));
+            break;
+        };
+        let name = match &def.value {
+            AnyDefinition::SourceFile(file) => file.path.display().to_string(),
+            _ => pool
+                .names
+                .get(def.name)
+                .map_or_else(|_| format!("<{current}>"), |name| name.to_string()),
+        };
+        parts.push(name);
+        current = def.parent;
+    }
+    parts.reverse();
+    parts.join(".")
+}
+
+fn kind_name(kind: DefinitionType) -> &'static str {
+    match kind {
+        DefinitionType::Type => "type",
+        DefinitionType::Class => "class",
+        DefinitionType::EnumValue => "enum value",
+        DefinitionType::Enum => "enum",
+        DefinitionType::BitField => "bit field",
+        DefinitionType::Function => "function",
+        DefinitionType::Parameter => "parameter",
+        DefinitionType::Local => "local",
+        DefinitionType::Field => "field",
+        DefinitionType::SourceFile => "source file",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::bundle::ScriptBundle;
+    use crate::definition::{ClassFlags, FunctionFlags, Visibility};
+
+    const PREDEF: &[u8] = include_bytes!("../../resources/predef.redscripts");
+
+    fn class(base: PoolIndex<Class>) -> Class {
+        Class {
+            visibility: Visibility::Public,
+            flags: ClassFlags::new(),
+            base,
+            functions: vec![],
+            fields: vec![],
+            overrides: vec![],
+        }
+    }
+
+    fn function(flags: FunctionFlags, code: Vec<Instr<Offset>>) -> Function {
+        Function {
+            visibility: Visibility::Public,
+            flags,
+            source: None,
+            return_type: None,
+            unk1: false,
+            base_method: None,
+            parameters: vec![],
+            locals: vec![],
+            operator: None,
+            cast: 0,
+            code: Code::new(code),
+            unk2: vec![],
+        }
+    }
+
+    #[test]
+    fn verify_predef() {
+        let bundle = ScriptBundle::load(&mut Cursor::new(PREDEF)).unwrap();
+        assert_eq!(verify_pool(&bundle.pool), []);
+    }
+
+    #[test]
+    fn report_invalid_definitions() {
+        let mut pool = ScriptBundle::load(&mut Cursor::new(PREDEF)).unwrap().pool;
+        let sub_name = pool.names.add("Sub".into());
+        let base_name = pool.names.add("Base".into());
+        let fun_name = pool.names.add("Fun".into());
+        let sub_idx = pool.reserve::<Class>();
+        let base_idx = pool.add_definition(Definition::class(base_name, class(PoolIndex::UNDEFINED)));
+        pool.put_definition(sub_idx, Definition::class(sub_name, class(base_idx)));
+
+        let fun_idx = pool.reserve::<Function>();
+        let code = vec![
+            Instr::InvokeStatic(Offset::new(17), 0, fun_idx, 0),
+            Instr::I32One,
+            Instr::ParamEnd,
+            Instr::Jump(Offset::new(2)),
+            Instr::JumpIfFalse(Offset::new(4)),
+            Instr::TrueConst,
+            Instr::Local(base_idx.cast()),
+        ];
+        let fun = function(FunctionFlags::new().with_is_native(true), code);
+        pool.put_definition(fun_idx, Definition::function(fun_name, PoolIndex::UNDEFINED, fun));
+
+        let errors = verify_pool(&pool);
+        let messages: Vec<_> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                "Sub: base class Base is placed after the subclass".to_owned(),
+                "Fun: the has_body flag does not match the content, expected it to be true".to_owned(),
+                "Fun: invocation at 0 passes 1 argument(s), but the function has 0 parameter(s)".to_owned(),
+                "Fun: jump at 17 targets 19, which is not an instruction boundary".to_owned(),
+                format!("Fun: instruction at 24 refers to {base_idx}, expected a local but found a class"),
+            ]
+        );
+        assert_eq!(errors[0].definition, sub_idx.cast());
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+use redscript::bundle::{ConstantPool, PoolIndex};
+use redscript::bytecode::{Instr, Offset, PoolRef};
+use redscript::definition::{AnyDefinition, ClassFlags, Definition, FieldFlags, Function, FunctionFlags, Visibility};
+
+use crate::error::Error;
+use crate::print::{format_param, format_type};
+
+// the name of a flag and its getter
+type Flag<F> = (&'static str, fn(&F) -> bool);
+
+const CLASS_FLAGS: &[Flag<ClassFlags>] = &[
+    ("native", ClassFlags::is_native),
+    ("importonly", ClassFlags::is_import_only),
+    ("abstract", ClassFlags::is_abstract),
+    ("final", ClassFlags::is_final),
+    ("struct", ClassFlags::is_struct),
+    ("testonly", ClassFlags::is_test_only),
+];
+
+const FUNCTION_FLAGS: &[Flag<FunctionFlags>] = &[
+    ("static", FunctionFlags::is_static),
+    ("final", FunctionFlags::is_final),
+    ("native", FunctionFlags::is_native),
+    ("exec", FunctionFlags::is_exec),
+    ("timer", FunctionFlags::is_timer),
+    ("cb", FunctionFlags::is_callback),
+    ("cast", FunctionFlags::is_cast),
+    ("implicit", FunctionFlags::is_implicit_cast),
+    ("threadsafe", FunctionFlags::is_thread_safe),
+    ("const", FunctionFlags::is_const),
+    ("quest", FunctionFlags::is_quest),
+];
+
+const FIELD_FLAGS: &[Flag<FieldFlags>] = &[
+    ("native", FieldFlags::is_native),
+    ("edit", FieldFlags::is_editable),
+    ("instanceedit", FieldFlags::is_instance_editable),
+    ("inline", FieldFlags::is_inline),
+    ("const", FieldFlags::is_const),
+    ("replicated", FieldFlags::is_replicated),
+    ("persistent", FieldFlags::is_persistent),
+    ("testonly", FieldFlags::is_test_only),
+    ("browsable", FieldFlags::is_browsable),
+];
+
+/// Compares the definitions of two pools and returns the changes sorted by name.
+///
+/// Definitions are matched by their qualified name, functions additionally by the parameter types encoded
+/// in their name. A function that exists under a single overload in both pools is matched by its name alone,
+/// so that changes to its parameters are reported as signature changes.
+/// Members of classes and enums that were added or removed as a whole are not reported.
+pub fn diff_pools(old: &ConstantPool, new: &ConstantPool) -> Result<Vec<Change>, Error> {
+    let old_items = Item::collect(old)?;
+    let new_items = Item::collect(new)?;
+    let mut differ = Differ {
+        old,
+        new,
+        refs: HashMap::new(),
+        changes: vec![],
+    };
+
+    let mut removed = vec![];
+    for (key, item) in &old_items {
+        match new_items.get(key) {
+            Some(other) => differ.compare(item, other)?,
+            None => removed.push(item),
+        }
+    }
+    let mut added = new_items
+        .iter()
+        .filter(|(key, _)| !old_items.contains_key(*key))
+        .map(|(_, item)| item)
+        .collect_vec();
+
+    let removed_overloads = removed.iter().copied().into_group_map_by(|item| item.overload_key());
+    let added_overloads = added.iter().copied().into_group_map_by(|item| item.overload_key());
+    let mut paired = HashSet::new();
+    for (key, olds) in &removed_overloads {
+        if let (Some(_), [old], Some([new])) = (key, &olds[..], added_overloads.get(key).map(Vec::as_slice)) {
+            differ.compare(old, new)?;
+            paired.insert(key.clone());
+        }
+    }
+
+    let removed_parents: HashSet<_> = removed.iter().map(|item| item.key.clone()).collect();
+    let added_parents: HashSet<_> = added.iter().map(|item| item.key.clone()).collect();
+    removed.retain(|item| {
+        !paired.contains(&item.overload_key()) && item.parent.as_ref().map_or(true, |p| !removed_parents.contains(p))
+    });
+    added.retain(|item| {
+        !paired.contains(&item.overload_key()) && item.parent.as_ref().map_or(true, |p| !added_parents.contains(p))
+    });
+
+    let mut changes = differ.changes;
+    changes.extend(removed.into_iter().map(|item| item.change(ChangeKind::Removed)));
+    changes.extend(added.into_iter().map(|item| item.change(ChangeKind::Added)));
+    changes.sort_by(|a, b| (&a.name, a.item, &a.signature).cmp(&(&b.name, b.item, &b.signature)));
+    Ok(changes)
+}
+
+/// Returns the parameters and the return type of a function as they would be declared in source code.
+pub fn function_signature(fun: &Function, pool: &ConstantPool) -> Result<String, Error> {
+    let params = fun
+        .parameters
+        .iter()
+        .map(|&idx| format_param(pool.definition(idx)?, pool))
+        .collect::<Result<Vec<_>, _>>()?;
+    let return_type = match fun.return_type {
+        Some(idx) => format_type(pool.definition(idx)?, pool)?,
+        None => "Void".to_owned(),
+    };
+    Ok(format!("({}) -> {return_type}", params.join(", ")))
+}
+
+/// A difference between a definition in the old pool and its counterpart in the new one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub item: ItemKind,
+    /// The qualified name of the definition.
+    pub name: String,
+    /// The signature of the definition, taken from the new pool unless the definition was removed.
+    pub signature: String,
+    pub kind: ChangeKind,
+}
+
+impl Change {
+    /// Returns the definition as it would be declared in source code.
+    pub fn declaration(&self) -> String {
+        match self.item {
+            ItemKind::Class if self.signature.is_empty() => format!("class {}", self.name),
+            ItemKind::Class => format!("class {} extends {}", self.name, self.signature),
+            ItemKind::Enum => format!("enum {}", self.name),
+            ItemKind::EnumValue => format!("{} = {}", self.name, self.signature),
+            ItemKind::Function => format!("func {}{}", self.name, self.signature),
+            ItemKind::Field => format!("let {}: {}", self.name, self.signature),
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ChangeKind::Added => write!(f, "+ {}", self.declaration()),
+            ChangeKind::Removed => write!(f, "- {}", self.declaration()),
+            ChangeKind::Signature { old, new } => write!(
+                f,
+                "~ {} {}: signature changed from `{old}` to `{new}`",
+                self.item.name(),
+                self.name
+            ),
+            ChangeKind::Flags { old, new } => {
+                write!(f, "~ {}: flags changed from `{old}` to `{new}`", self.declaration())
+            }
+            ChangeKind::Body => write!(f, "~ {}: body changed", self.declaration()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Signature { old: String, new: String },
+    Flags { old: String, new: String },
+    Body,
+}
+
+impl ChangeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Signature { .. } => "signature",
+            ChangeKind::Flags { .. } => "flags",
+            ChangeKind::Body => "body",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ItemKind {
+    Class,
+    Enum,
+    EnumValue,
+    Function,
+    Field,
+}
+
+impl ItemKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ItemKind::Class => "class",
+            ItemKind::Enum => "enum",
+            ItemKind::EnumValue => "enum value",
+            ItemKind::Function => "function",
+            ItemKind::Field => "field",
+        }
+    }
+}
+
+type ItemKey = (ItemKind, String);
+
+struct Item<'a> {
+    key: ItemKey,
+    name: String,
+    // the key of the class or enum this item belongs to
+    parent: Option<ItemKey>,
+    signature: String,
+    flags: String,
+    function: Option<&'a Function>,
+}
+
+impl<'a> Item<'a> {
+    fn collect(pool: &'a ConstantPool) -> Result<BTreeMap<ItemKey, Item<'a>>, Error> {
+        let mut items = BTreeMap::new();
+        let mut add = |item: Item<'a>| {
+            items.insert(item.key.clone(), item);
+        };
+
+        for (_, def) in pool.roots() {
+            match &def.value {
+                AnyDefinition::Class(class) => {
+                    let name = pool.names.get(def.name)?.to_string();
+                    let base = if class.base.is_undefined() {
+                        String::new()
+                    } else {
+                        pool.def_name(class.base)?.to_string()
+                    };
+                    let key = (ItemKind::Class, name.clone());
+                    for &idx in &class.fields {
+                        let field_def = pool.definition(idx)?;
+                        let field = pool.field(idx)?;
+                        let field_name = format!("{name}.{}", pool.names.get(field_def.name)?);
+                        add(Item {
+                            key: (ItemKind::Field, field_name.clone()),
+                            name: field_name,
+                            parent: Some(key.clone()),
+                            signature: format_type(pool.definition(field.type_)?, pool)?,
+                            flags: format_flags(field.visibility, &field.flags, FIELD_FLAGS),
+                            function: None,
+                        });
+                    }
+                    for &idx in &class.functions {
+                        add(Item::function(pool.definition(idx)?, Some(&key), pool)?);
+                    }
+                    add(Item {
+                        key,
+                        name,
+                        parent: None,
+                        signature: base,
+                        flags: format_flags(class.visibility, &class.flags, CLASS_FLAGS),
+                        function: None,
+                    });
+                }
+                AnyDefinition::Enum(enum_) => {
+                    let name = pool.names.get(def.name)?.to_string();
+                    let key = (ItemKind::Enum, name.clone());
+                    for &idx in &enum_.members {
+                        let member_name = format!("{name}.{}", pool.def_name(idx)?);
+                        add(Item {
+                            key: (ItemKind::EnumValue, member_name.clone()),
+                            name: member_name,
+                            parent: Some(key.clone()),
+                            signature: pool.enum_value(idx)?.to_string(),
+                            flags: String::new(),
+                            function: None,
+                        });
+                    }
+                    add(Item {
+                        key,
+                        name,
+                        parent: None,
+                        signature: String::new(),
+                        flags: String::new(),
+                        function: None,
+                    });
+                }
+                AnyDefinition::Function(_) => add(Item::function(def, None, pool)?),
+                _ => {}
+            }
+        }
+        Ok(items)
+    }
+
+    fn function(def: &'a Definition, parent: Option<&ItemKey>, pool: &ConstantPool) -> Result<Self, Error> {
+        let fun = def.value.as_function().expect("Expected a function definition");
+        let mangled = pool.names.get(def.name)?;
+        let short = mangled.split(';').next().unwrap_or_default();
+        let (key, name) = match parent {
+            Some((_, class)) => (format!("{class}.{mangled}"), format!("{class}.{short}")),
+            None => (mangled.to_string(), short.to_owned()),
+        };
+        let item = Item {
+            key: (ItemKind::Function, key),
+            name,
+            parent: parent.cloned(),
+            signature: function_signature(fun, pool)?,
+            flags: format_flags(fun.visibility, &fun.flags, FUNCTION_FLAGS),
+            function: Some(fun),
+        };
+        Ok(item)
+    }
+
+    // functions are paired up by their name without the parameter types
+    fn overload_key(&self) -> Option<(Option<ItemKey>, String)> {
+        self.function.map(|_| (self.parent.clone(), self.name.clone()))
+    }
+
+    fn change(&self, kind: ChangeKind) -> Change {
+        Change {
+            item: self.key.0,
+            name: self.name.clone(),
+            signature: self.signature.clone(),
+            kind,
+        }
+    }
+}
+
+struct Differ<'a> {
+    old: &'a ConstantPool,
+    new: &'a ConstantPool,
+    // pool references of both pools are renumbered by their names to make the bytecode comparable
+    refs: HashMap<String, u32>,
+    changes: Vec<Change>,
+}
+
+impl<'a> Differ<'a> {
+    fn compare(&mut self, old: &Item<'_>, new: &Item<'_>) -> Result<(), Error> {
+        if old.signature != new.signature {
+            self.changes.push(new.change(ChangeKind::Signature {
+                old: old.signature.clone(),
+                new: new.signature.clone(),
+            }));
+        }
+        if old.flags != new.flags {
+            self.changes.push(new.change(ChangeKind::Flags {
+                old: old.flags.clone(),
+                new: new.flags.clone(),
+            }));
+        }
+        if let (Some(old_fun), Some(new_fun)) = (old.function, new.function) {
+            if self.normalized_code(old_fun, self.old)? != self.normalized_code(new_fun, self.new)? {
+                self.changes.push(new.change(ChangeKind::Body));
+            }
+        }
+        Ok(())
+    }
+
+    fn normalized_code(&mut self, fun: &Function, pool: &ConstantPool) -> Result<Vec<Instr<Offset>>, Error> {
+        let mut code = Vec::with_capacity(fun.code.len());
+        for instr in fun.code.as_ref() {
+            let mut ids = vec![];
+            for ref_ in instr.pool_refs() {
+                let name = ref_name(ref_, pool)?;
+                let next = self.refs.len() as u32;
+                ids.push(*self.refs.entry(name).or_insert(next));
+            }
+
+            let mut instr = instr.clone();
+            let mut ids = ids.into_iter();
+            instr.map_pool_refs(|ref_| ref_.with_index(PoolIndex::<()>::new(ids.next().unwrap_or_default())));
+            // line numbers change whenever anything above the function does
+            if let Instr::InvokeStatic(_, line, _, _) | Instr::InvokeVirtual(_, line, _, _) = &mut instr {
+                *line = 0;
+            }
+            code.push(instr);
+        }
+        Ok(code)
+    }
+}
+
+fn ref_name(ref_: PoolRef, pool: &ConstantPool) -> Result<String, Error> {
+    let name = match ref_ {
+        PoolRef::Name(idx) => format!("n:{}", pool.names.get(idx)?),
+        PoolRef::String(idx) => format!("s:{}", pool.strings.get(idx)?),
+        PoolRef::TweakDbId(idx) => format!("t:{}", pool.tweakdb_ids.get(idx)?),
+        PoolRef::Resource(idx) => format!("r:{}", pool.resources.get(idx)?),
+        PoolRef::Local(idx) => format!("l:{}:{}", pool.def_name(idx)?, pool.def_name(pool.local(idx)?.type_)?),
+        PoolRef::Param(idx) => format!(
+            "p:{}:{}",
+            pool.def_name(idx)?,
+            pool.def_name(pool.parameter(idx)?.type_)?
+        ),
+        PoolRef::Enum(_) | PoolRef::Type(_) | PoolRef::Class(_) => format!("d:{}", pool.def_name(ref_.index::<()>())?),
+        PoolRef::EnumValue(_) | PoolRef::Field(_) | PoolRef::Function(_) => {
+            let def = pool.definition(ref_.index::<()>())?;
+            let parent = if def.parent.is_undefined() {
+                String::new()
+            } else {
+                pool.def_name(def.parent)?.to_string()
+            };
+            format!("m:{parent}.{}", pool.names.get(def.name)?)
+        }
+    };
+    Ok(name)
+}
+
+fn format_flags<F>(visibility: Visibility, flags: &F, names: &[Flag<F>]) -> String {
+    let flags = names.iter().filter(|(_, is_set)| is_set(flags)).map(|(name, _)| *name);
+    [visibility.to_string()]
+        .into_iter()
+        .chain(flags.map(str::to_owned))
+        .join(" ")
+}
@@ -0,0 +1,324 @@
+use std::collections::VecDeque;
+
+use hashbrown::{HashMap, HashSet};
+use redscript::bundle::{CName, ConstantPool, PoolIndex};
+use redscript::bytecode::{Instr, Location, Offset};
+use redscript::definition::{AnyDefinition, Class, Definition, Function, Type};
+use redscript::Ref;
+
+use crate::error::Error;
+
+/// The definitions reachable from a set of roots, in the order they were reached.
+#[derive(Debug, Default)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub index: PoolIndex<Definition>,
+    /// The name qualified with the class, including the parameter types of functions.
+    pub name: String,
+    /// The number of edges from the closest root.
+    pub depth: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    /// The index of the source node.
+    pub from: usize,
+    /// The index of the target node.
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    Subclass,
+    StaticCall,
+    VirtualCall,
+}
+
+impl EdgeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Subclass => "subclass",
+            EdgeKind::StaticCall => "static call",
+            EdgeKind::VirtualCall => "virtual call",
+        }
+    }
+}
+
+/// Builds the inheritance tree of the root classes, with edges leading from classes to their subclasses.
+/// Every class without a base is a root when none are given.
+pub fn class_graph(pool: &ConstantPool, roots: &[PoolIndex<Class>], max_depth: Option<usize>) -> Result<Graph, Error> {
+    let hierarchy = Hierarchy::new(pool)?;
+    let roots: Vec<PoolIndex<Definition>> = if roots.is_empty() {
+        pool.definitions()
+            .filter(|(_, def)| matches!(&def.value, AnyDefinition::Class(class) if class.base.is_undefined()))
+            .map(|(idx, _)| idx)
+            .collect()
+    } else {
+        roots.iter().map(PoolIndex::cast).collect()
+    };
+    traverse(pool, &roots, max_depth, |idx| {
+        let subclasses = hierarchy.subclasses.get(&idx.cast()).into_iter().flatten();
+        Ok(subclasses.map(|sub| (sub.cast(), EdgeKind::Subclass)).collect())
+    })
+}
+
+/// Builds the graph of the functions called from the root functions. Every function is a root when
+/// none are given.
+///
+/// Virtual calls are resolved over the subclasses of the static type of the receiver when it can be
+/// determined from the code, and to every method with the same name otherwise.
+pub fn call_graph(
+    pool: &ConstantPool,
+    roots: &[PoolIndex<Function>],
+    max_depth: Option<usize>,
+) -> Result<Graph, Error> {
+    let hierarchy = Hierarchy::new(pool)?;
+    let roots: Vec<PoolIndex<Definition>> = if roots.is_empty() {
+        pool.definitions()
+            .filter(|(_, def)| matches!(def.value, AnyDefinition::Function(_)))
+            .map(|(idx, _)| idx)
+            .collect()
+    } else {
+        roots.iter().map(PoolIndex::cast).collect()
+    };
+    traverse(pool, &roots, max_depth, |idx| {
+        let caller = pool.definition(idx)?;
+        let fun = pool.function(idx.cast())?;
+        let code: Vec<_> = fun.code.iter().collect();
+        let mut callees = vec![];
+        for (i, (_, instr)) in code.iter().enumerate() {
+            match *instr {
+                Instr::InvokeStatic(_, _, callee, _) => callees.push((callee.cast(), EdgeKind::StaticCall)),
+                Instr::InvokeVirtual(_, _, name, _) => {
+                    let receiver = hierarchy.receiver(&code, i, caller);
+                    for callee in hierarchy.resolve_virtual(name, receiver)? {
+                        callees.push((callee.cast(), EdgeKind::VirtualCall));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(callees)
+    })
+}
+
+// a breadth-first search, so that every node gets the depth of its shortest path from a root
+fn traverse<F>(
+    pool: &ConstantPool,
+    roots: &[PoolIndex<Definition>],
+    max_depth: Option<usize>,
+    mut successors: F,
+) -> Result<Graph, Error>
+where
+    F: FnMut(PoolIndex<Definition>) -> Result<Vec<(PoolIndex<Definition>, EdgeKind)>, Error>,
+{
+    let mut traversal = Traversal {
+        pool,
+        graph: Graph::default(),
+        nodes: HashMap::new(),
+        queue: VecDeque::new(),
+    };
+    for &root in roots {
+        traversal.node(root, 0)?;
+    }
+
+    let mut edges = HashSet::new();
+    while let Some(from) = traversal.queue.pop_front() {
+        let Node { index, depth, .. } = traversal.graph.nodes[from];
+        if max_depth.map_or(false, |max| depth >= max) {
+            continue;
+        }
+        for (target, kind) in successors(index)? {
+            let to = traversal.node(target, depth + 1)?;
+            if edges.insert((from, to, kind)) {
+                traversal.graph.edges.push(Edge { from, to, kind });
+            }
+        }
+    }
+    Ok(traversal.graph)
+}
+
+struct Traversal<'a> {
+    pool: &'a ConstantPool,
+    graph: Graph,
+    nodes: HashMap<PoolIndex<Definition>, usize>,
+    queue: VecDeque<usize>,
+}
+
+impl Traversal<'_> {
+    // returns the node of a definition, adding it to the graph and the queue when it's reached first
+    fn node(&mut self, index: PoolIndex<Definition>, depth: usize) -> Result<usize, Error> {
+        if let Some(&node) = self.nodes.get(&index) {
+            return Ok(node);
+        }
+        let node = self.graph.nodes.len();
+        self.graph.nodes.push(Node {
+            index,
+            name: full_name(self.pool, index)?,
+            depth,
+        });
+        self.nodes.insert(index, node);
+        self.queue.push_back(node);
+        Ok(node)
+    }
+}
+
+/// Returns the name of a definition qualified with its parent, including the parameter types of functions.
+pub fn full_name(pool: &ConstantPool, index: PoolIndex<Definition>) -> Result<String, Error> {
+    let def = pool.definition(index)?;
+    let name = pool.names.get(def.name)?;
+    if def.parent.is_undefined() {
+        Ok(name.to_string())
+    } else {
+        Ok(format!("{}.{name}", pool.def_name(def.parent)?))
+    }
+}
+
+/// The class hierarchy of a pool, used to resolve the targets of virtual calls.
+pub(crate) struct Hierarchy<'a> {
+    pool: &'a ConstantPool,
+    subclasses: HashMap<PoolIndex<Class>, Vec<PoolIndex<Class>>>,
+    classes_by_name: HashMap<Ref<str>, PoolIndex<Class>>,
+    methods_by_name: HashMap<PoolIndex<CName>, Vec<PoolIndex<Function>>>,
+}
+
+impl<'a> Hierarchy<'a> {
+    pub(crate) fn new(pool: &'a ConstantPool) -> Result<Self, Error> {
+        let mut hierarchy = Self {
+            pool,
+            subclasses: HashMap::new(),
+            classes_by_name: HashMap::new(),
+            methods_by_name: HashMap::new(),
+        };
+        for (idx, def) in pool.definitions() {
+            match &def.value {
+                AnyDefinition::Class(class) => {
+                    if !class.base.is_undefined() {
+                        hierarchy.subclasses.entry(class.base).or_default().push(idx.cast());
+                    }
+                    hierarchy.classes_by_name.insert(pool.names.get(def.name)?, idx.cast());
+                }
+                AnyDefinition::Function(fun) if !def.parent.is_undefined() && !fun.flags.is_static() => {
+                    hierarchy.methods_by_name.entry(def.name).or_default().push(idx.cast());
+                }
+                _ => {}
+            }
+        }
+        Ok(hierarchy)
+    }
+
+    // the static class of the object a virtual call at an index is made on
+    pub(crate) fn receiver(
+        &self,
+        code: &[(Location, Instr<Offset>)],
+        index: usize,
+        caller: &Definition,
+    ) -> Option<PoolIndex<Class>> {
+        let (loc, Instr::InvokeVirtual(exit, _, _, _)) = &code[index] else {
+            return None;
+        };
+        let exit = exit.absolute(*loc);
+        // a call on an explicit object is wrapped in a context that ends along with the call
+        let context = code[..index]
+            .iter()
+            .rposition(|(loc, instr)| matches!(instr, Instr::Context(end) if end.absolute(*loc) == exit));
+        match context {
+            Some(context) => self.expr_class(code, context + 1, caller),
+            None => self.caller_class(caller),
+        }
+    }
+
+    fn expr_class(
+        &self,
+        code: &[(Location, Instr<Offset>)],
+        index: usize,
+        caller: &Definition,
+    ) -> Option<PoolIndex<Class>> {
+        let pool = self.pool;
+        let type_ = match code.get(index)? {
+            (_, Instr::This) => return self.caller_class(caller),
+            (_, Instr::New(class) | Instr::DynamicCast(class, _)) => return Some(*class),
+            (_, Instr::Local(idx)) => pool.local(*idx).ok()?.type_,
+            (_, Instr::Param(idx)) => pool.parameter(*idx).ok()?.type_,
+            (_, Instr::ObjectField(idx) | Instr::StructField(idx)) => pool.field(*idx).ok()?.type_,
+            (_, Instr::InvokeStatic(_, _, idx, _)) => pool.function(*idx).ok()?.return_type?,
+            // the type of a member access is the type of its last instruction
+            (loc, Instr::Context(exit)) => {
+                let exit = exit.absolute(*loc);
+                let member = code.partition_point(|(loc, _)| *loc < exit).checked_sub(1)?;
+                return (member > index)
+                    .then(|| self.expr_class(code, member, caller))
+                    .flatten();
+            }
+            _ => return None,
+        };
+        self.type_class(type_)
+    }
+
+    fn type_class(&self, index: PoolIndex<Type>) -> Option<PoolIndex<Class>> {
+        let def = self.pool.definition(index).ok()?;
+        match def.value {
+            AnyDefinition::Type(Type::Ref(inner) | Type::WeakRef(inner) | Type::ScriptRef(inner)) => {
+                self.type_class(inner)
+            }
+            AnyDefinition::Type(Type::Class) => {
+                let name = self.pool.names.get(def.name).ok()?;
+                self.classes_by_name.get(&name).copied()
+            }
+            AnyDefinition::Class(_) => Some(index.cast()),
+            _ => None,
+        }
+    }
+
+    fn caller_class(&self, caller: &Definition) -> Option<PoolIndex<Class>> {
+        let parent = caller.parent.cast();
+        self.pool.class(parent).is_ok().then_some(parent)
+    }
+
+    // the method found by walking up the supertypes of the receiver, along with its overrides in the subclasses
+    fn resolve_virtual(
+        &self,
+        name: PoolIndex<CName>,
+        receiver: Option<PoolIndex<Class>>,
+    ) -> Result<Vec<PoolIndex<Function>>, Error> {
+        let candidates = self.methods_by_name.get(&name).map_or(&[][..], Vec::as_slice);
+        let Some(receiver) = receiver else {
+            return Ok(candidates.to_vec());
+        };
+        let method_of = |class_idx: PoolIndex<Class>| -> Result<Option<PoolIndex<Function>>, Error> {
+            let class = self.pool.class(class_idx)?;
+            Ok(class.functions.iter().copied().find(|fun| candidates.contains(fun)))
+        };
+
+        let mut targets = vec![];
+        let mut visited = HashSet::new();
+        let mut current = receiver;
+        while !current.is_undefined() && visited.insert(current) {
+            if let Some(method) = method_of(current)? {
+                targets.push(method);
+                break;
+            }
+            current = self.pool.class(current)?.base;
+        }
+        if targets.is_empty() {
+            return Ok(candidates.to_vec());
+        }
+
+        let mut queue = vec![receiver];
+        while let Some(class_idx) = queue.pop() {
+            for &sub in self.subclasses.get(&class_idx).into_iter().flatten() {
+                if visited.insert(sub) {
+                    targets.extend(method_of(sub)?);
+                    queue.push(sub);
+                }
+            }
+        }
+        Ok(targets)
+    }
+}
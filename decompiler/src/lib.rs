@@ -6,9 +6,12 @@ use redscript::bundle::{ConstantPool, PoolIndex};
 use redscript::bytecode::{CodeCursor, CursorError, Instr, Location, Offset};
 use redscript::definition::{Definition, Function};
 
+pub mod diff;
 pub mod error;
 pub mod files;
+pub mod graph;
 pub mod print;
+pub mod xref;
 
 pub struct Decompiler<'a> {
     code: CodeCursor<'a, Offset>,
@@ -56,7 +59,7 @@ impl<'a> Decompiler<'a> {
         loop {
             if self.code.pos() >= target
                 || matches!(body.last(), Some(Expr::Goto(_, _)))
-                || matches!(body.last(), Some(Expr::Break(_)))
+                || matches!(body.last(), Some(Expr::Break(_) | Expr::Continue(_)))
                 || matches!(body.last(), Some(Expr::Return(_, _)))
             {
                 break;
@@ -434,6 +437,10 @@ impl<'a> Decompiler<'a> {
                         // while being at the tail of it - no control flow required
                         Expr::EMPTY
                     }
+                    Some(BlockContext::Loop { prologue, .. }) if jump_loc == prologue => {
+                        // we're jumping back to the beginning of the loop from the middle of it
+                        Expr::Continue(Span::ZERO)
+                    }
                     Some(BlockContext::Switch { epilogue }) if jump_loc == epilogue => {
                         // we're jumping out of the switch
                         Expr::Break(Span::ZERO)
@@ -2,9 +2,9 @@ use std::io::Write;
 use std::str::FromStr;
 
 use itertools::Itertools;
+use redscript::assembly;
 use redscript::ast::{BinOp, Constant, Expr, Ident, Literal, Seq, SourceAst, SwitchCase, TypeName, UnOp};
 use redscript::bundle::ConstantPool;
-use redscript::bytecode::Instr;
 use redscript::definition::{AnyDefinition, Definition, Function, Type};
 
 use crate::error::Error;
@@ -217,18 +217,11 @@ fn write_function_body<W: Write>(
                 write_definition(out, pool.definition(*local)?, pool, depth + 1, mode)?;
                 writeln!(out)?;
             }
-            for (offset, instr) in fun.code.iter() {
-                let op = format!("{:?}", instr).to_lowercase();
+            let mut code = String::new();
+            assembly::disassemble(&mut code, fun, pool)?;
+            for line in code.lines() {
                 write_indent(out, depth + 1)?;
-                match instr {
-                    Instr::InvokeStatic(_, _, fun, _) => {
-                        writeln!(out, "{}: {} // {}", offset.value, op, pool.def_name(fun)?)?;
-                    }
-                    Instr::InvokeVirtual(_, _, name, _) => {
-                        writeln!(out, "{}: {} // {}", offset.value, op, pool.names.get(name)?)?;
-                    }
-                    _ => writeln!(out, "{}: {}", offset.value, op)?,
-                }
+                writeln!(out, "{line}")?;
             }
         }
     }
@@ -389,6 +382,7 @@ fn write_expr_nested<W: Write>(
             write_unop(out, val, *op, verbose)?;
         }
         Expr::Break(_) => write!(out, "break")?,
+        Expr::Continue(_) => write!(out, "continue")?,
         Expr::Null(_) => write!(out, "null")?,
         Expr::This(_) => write!(out, "this")?,
         Expr::Super(_) => write!(out, "super")?,
@@ -461,7 +455,7 @@ fn write_unop<W: Write>(out: &mut W, param: &Expr<SourceAst>, op: UnOp, verbose:
     write_expr_nested(out, param, Some(ParentOp::UnOp), verbose, 0)
 }
 
-fn format_param(def: &Definition, pool: &ConstantPool) -> Result<String, Error> {
+pub(crate) fn format_param(def: &Definition, pool: &ConstantPool) -> Result<String, Error> {
     let param = def.value.as_parameter().expect("Expected a param definition");
     let type_name = format_type(pool.definition(param.type_)?, pool)?;
     let name = pool.names.get(def.name)?;
@@ -471,7 +465,7 @@ fn format_param(def: &Definition, pool: &ConstantPool) -> Result<String, Error>
     Ok(format!("{const_}{out}{optional}{name}: {type_name}"))
 }
 
-fn format_type(def: &Definition, pool: &ConstantPool) -> Result<String, Error> {
+pub(crate) fn format_type(def: &Definition, pool: &ConstantPool) -> Result<String, Error> {
     let type_ = def.value.as_type().expect("Expected a type definition");
     let result = match type_ {
         Type::Prim | Type::Class => pool.names.get(def.name)?.to_string(),
@@ -0,0 +1,308 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use hashbrown::{HashMap, HashSet};
+use redscript::bundle::{CName, ConstantPool, PoolIndex};
+use redscript::bytecode::Instr;
+use redscript::definition::{AnyDefinition, Class, Definition, Function};
+
+use crate::diff::function_signature;
+use crate::error::Error;
+use crate::graph::Hierarchy;
+use crate::print::format_type;
+
+/// An index of the places in the bytecode of a pool that refer to classes, fields and functions.
+///
+/// Static calls, field accesses, constructions, allocations and dynamic casts refer to their target
+/// directly. Virtual calls only carry the name of the method, so they are attributed to the instance
+/// methods with that name whose class is a supertype or a subtype of the static class of the receiver.
+/// When the receiver can't be determined from the code, they're attributed to all of them.
+pub struct XrefIndex {
+    sites: HashMap<PoolIndex<Definition>, Vec<Site>>,
+    virtual_calls: HashMap<PoolIndex<CName>, Vec<VirtualSite>>,
+    subclasses: HashMap<PoolIndex<Class>, Vec<PoolIndex<Class>>>,
+}
+
+impl XrefIndex {
+    /// Scans the code of every function in the pool.
+    pub fn new(pool: &ConstantPool) -> Result<Self, Error> {
+        let hierarchy = Hierarchy::new(pool)?;
+        let mut index = Self {
+            sites: HashMap::new(),
+            virtual_calls: HashMap::new(),
+            subclasses: HashMap::new(),
+        };
+        for (idx, def) in pool.definitions() {
+            match &def.value {
+                AnyDefinition::Function(fun) => index.scan(idx.cast(), def, fun, &hierarchy),
+                AnyDefinition::Class(class) if !class.base.is_undefined() => {
+                    index.subclasses.entry(class.base).or_default().push(idx.cast());
+                }
+                _ => {}
+            }
+        }
+        Ok(index)
+    }
+
+    fn scan(&mut self, function: PoolIndex<Function>, def: &Definition, fun: &Function, hierarchy: &Hierarchy<'_>) {
+        let code: Vec<_> = fun.code.iter().collect();
+        // the left-hand side of an assignment directly follows it, a member access on an explicit object
+        // is wrapped in a context which ends right after the member instruction
+        let mut assigned = HashSet::new();
+        for (i, (_, instr)) in code.iter().enumerate() {
+            if !matches!(instr, Instr::Assign) {
+                continue;
+            }
+            match code.get(i + 1) {
+                Some((loc, Instr::Context(exit))) => {
+                    let exit = exit.absolute(*loc);
+                    if let Some(last) = code.partition_point(|(loc, _)| *loc < exit).checked_sub(1) {
+                        assigned.insert(last);
+                    }
+                }
+                Some(_) => {
+                    assigned.insert(i + 1);
+                }
+                None => {}
+            }
+        }
+
+        for (i, (_, instr)) in code.iter().enumerate() {
+            let (target, kind, line) = match *instr {
+                Instr::InvokeStatic(_, line, idx, _) => (idx.cast(), XrefKind::Call, line),
+                Instr::InvokeVirtual(_, line, name, _) => {
+                    let site = Site {
+                        kind: XrefKind::VirtualCall,
+                        function,
+                        line,
+                    };
+                    let receiver = hierarchy.receiver(&code, i, def);
+                    self.virtual_calls
+                        .entry(name)
+                        .or_default()
+                        .push(VirtualSite { site, receiver });
+                    continue;
+                }
+                Instr::ObjectField(idx) | Instr::StructField(idx) if assigned.contains(&i) => {
+                    (idx.cast(), XrefKind::FieldWrite, 0)
+                }
+                Instr::ObjectField(idx) | Instr::StructField(idx) => (idx.cast(), XrefKind::FieldRead, 0),
+                Instr::Construct(_, idx) => (idx.cast(), XrefKind::Construct, 0),
+                Instr::New(idx) => (idx.cast(), XrefKind::New, 0),
+                Instr::DynamicCast(idx, _) => (idx.cast(), XrefKind::DynamicCast, 0),
+                _ => continue,
+            };
+            self.sites
+                .entry(target)
+                .or_default()
+                .push(Site { kind, function, line });
+        }
+    }
+
+    /// Returns the references to a definition sorted by their source location.
+    /// The references to a function include the methods overriding it.
+    pub fn references(&self, index: PoolIndex<Definition>, pool: &ConstantPool) -> Result<Vec<Xref>, Error> {
+        let def = pool.definition(index)?;
+        let mut sites = self.sites.get(&index).cloned().unwrap_or_default();
+        if let AnyDefinition::Function(fun) = &def.value {
+            if !def.parent.is_undefined() && !fun.flags.is_static() {
+                let class = def.parent.cast();
+                for &VirtualSite { site, receiver } in self.virtual_calls.get(&def.name).into_iter().flatten() {
+                    let is_related = match receiver {
+                        Some(receiver) => is_subclass(receiver, class, pool)? || is_subclass(class, receiver, pool)?,
+                        None => true,
+                    };
+                    if is_related {
+                        sites.push(site);
+                    }
+                }
+            }
+            for function in self.overriders(def, pool)? {
+                sites.push(Site {
+                    kind: XrefKind::Override,
+                    function,
+                    line: 0,
+                });
+            }
+        }
+
+        let mut refs = sites
+            .into_iter()
+            .map(|site| Xref::new(site, pool))
+            .collect::<Result<Vec<_>, _>>()?;
+        refs.sort();
+        refs.dedup();
+        Ok(refs)
+    }
+
+    // methods with the same name declared in the subclasses of the class of a method
+    fn overriders(&self, def: &Definition, pool: &ConstantPool) -> Result<Vec<PoolIndex<Function>>, Error> {
+        let mut overriders = vec![];
+        if def.parent.is_undefined() || pool.class(def.parent.cast()).is_err() {
+            return Ok(overriders);
+        }
+        let mut queue = vec![def.parent.cast::<Class>()];
+        while let Some(class_idx) = queue.pop() {
+            for &sub_idx in self.subclasses.get(&class_idx).into_iter().flatten() {
+                for &fun_idx in &pool.class(sub_idx)?.functions {
+                    if pool.definition(fun_idx)?.name == def.name {
+                        overriders.push(fun_idx);
+                    }
+                }
+                queue.push(sub_idx);
+            }
+        }
+        Ok(overriders)
+    }
+}
+
+fn is_subclass(class: PoolIndex<Class>, base: PoolIndex<Class>, pool: &ConstantPool) -> Result<bool, Error> {
+    let mut current = class;
+    while !current.is_undefined() {
+        if current == base {
+            return Ok(true);
+        }
+        current = pool.class(current)?.base;
+    }
+    Ok(false)
+}
+
+/// Finds the classes, fields and functions with a name.
+///
+/// Fields and methods are qualified with the name of their class, like `Class.member`.
+/// Functions match either their full name or the name without the parameter types.
+pub fn find_definitions(pool: &ConstantPool, name: &str) -> Result<Vec<PoolIndex<Definition>>, Error> {
+    let (parent, member) = match name.split_once('.') {
+        Some((parent, member)) => (Some(parent), member),
+        None => (None, name),
+    };
+    let mut matches = vec![];
+    for (idx, def) in pool.definitions() {
+        if !matches!(
+            def.value,
+            AnyDefinition::Class(_) | AnyDefinition::Function(_) | AnyDefinition::Field(_)
+        ) {
+            continue;
+        }
+        let def_name = pool.names.get(def.name)?;
+        if def_name.as_ref() != member && def_name.split(';').next() != Some(member) {
+            continue;
+        }
+        let parent_matches = match parent {
+            Some(parent) => !def.parent.is_undefined() && pool.def_name(def.parent)?.as_ref() == parent,
+            None => def.parent.is_undefined(),
+        };
+        if parent_matches {
+            matches.push(idx);
+        }
+    }
+    Ok(matches)
+}
+
+/// Returns a definition as it would be declared in source code.
+pub fn declaration(def: &Definition, pool: &ConstantPool) -> Result<String, Error> {
+    let name = qualified_name(def, pool)?;
+    let decl = match &def.value {
+        AnyDefinition::Class(_) => format!("class {name}"),
+        AnyDefinition::Function(fun) => format!("func {name}{}", function_signature(fun, pool)?),
+        AnyDefinition::Field(field) => format!("let {name}: {}", format_type(pool.definition(field.type_)?, pool)?),
+        _ => name,
+    };
+    Ok(decl)
+}
+
+fn qualified_name(def: &Definition, pool: &ConstantPool) -> Result<String, Error> {
+    let name = pool.names.get(def.name)?;
+    let short = name.split(';').next().unwrap_or_default();
+    if def.parent.is_undefined() {
+        Ok(short.to_owned())
+    } else {
+        Ok(format!("{}.{short}", pool.def_name(def.parent)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Site {
+    kind: XrefKind,
+    function: PoolIndex<Function>,
+    line: u16,
+}
+
+// a virtual call along with the static class of its receiver, if it's known
+#[derive(Debug, Clone, Copy)]
+struct VirtualSite {
+    site: Site,
+    receiver: Option<PoolIndex<Class>>,
+}
+
+/// A place in the code of a function that refers to a definition.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Xref {
+    /// The source file of the function, if it's known.
+    pub file: Option<PathBuf>,
+    /// The line of the call if it was recorded, otherwise the line the function starts at.
+    pub line: u32,
+    /// The qualified name of the function.
+    pub function: String,
+    pub kind: XrefKind,
+}
+
+impl Xref {
+    fn new(site: Site, pool: &ConstantPool) -> Result<Self, Error> {
+        let def = pool.definition(site.function)?;
+        let source = def.source();
+        let file = source
+            .and_then(|source| pool.definition(source.file).ok())
+            .and_then(|def| def.value.as_source_file())
+            .map(|file| file.path.clone());
+        let line = match site.line {
+            0 => source.map_or(0, |source| source.line),
+            line => line.into(),
+        };
+        Ok(Self {
+            file,
+            line,
+            function: qualified_name(def, pool)?,
+            kind: site.kind,
+        })
+    }
+}
+
+impl fmt::Display for Xref {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {}", self.kind.name(), self.function)?;
+        match (&self.file, self.line) {
+            (Some(file), 0) => write!(f, " ({})", file.display()),
+            (Some(file), line) => write!(f, " ({}:{line})", file.display()),
+            (None, 0) => Ok(()),
+            (None, line) => write!(f, " (line {line})"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum XrefKind {
+    Call,
+    VirtualCall,
+    Override,
+    FieldRead,
+    FieldWrite,
+    Construct,
+    New,
+    DynamicCast,
+}
+
+impl XrefKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            XrefKind::Call => "call",
+            XrefKind::VirtualCall => "virtual call",
+            XrefKind::Override => "override",
+            XrefKind::FieldRead => "read",
+            XrefKind::FieldWrite => "write",
+            XrefKind::Construct => "construction",
+            XrefKind::New => "allocation",
+            XrefKind::DynamicCast => "cast",
+        }
+    }
+}
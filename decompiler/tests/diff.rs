@@ -0,0 +1,76 @@
+use std::io::Cursor;
+
+use redscript::bundle::{ConstantPool, ScriptBundle};
+use redscript_compiler::parser;
+use redscript_compiler::source_map::Files;
+use redscript_compiler::unit::CompilationUnit;
+use redscript_decompiler::diff::diff_pools;
+
+const PREDEF: &[u8] = include_bytes!("../../resources/predef.redscripts");
+
+fn compiled(source: &str) -> ConstantPool {
+    let module = parser::parse_str(source).unwrap();
+    let mut scripts = ScriptBundle::load(&mut Cursor::new(PREDEF)).unwrap();
+    CompilationUnit::new_with_defaults(&mut scripts.pool)
+        .unwrap()
+        .compile(vec![module], &Files::default())
+        .unwrap();
+    scripts.pool
+}
+
+#[test]
+fn diff_definitions() {
+    let old = compiled(
+        "
+        class Foo {
+          let x: Int32;
+          let y: String;
+          func Bar(a: Int32) -> Int32 { return a; }
+          func Same() -> Int32 { return 1; }
+          final func Flag() {}
+        }
+        class Gone { func G() {} }
+        enum E { A = 1, B = 2 }
+        func Global(a: Int32) -> Int32 { return a; }
+        ",
+    );
+    let new = compiled(
+        "
+        class Foo {
+          let x: Int64;
+          let z: String;
+          func Bar(a: Int32, b: Bool) -> Int32 { return a; }
+
+          func Same() -> Int32 { return 1; }
+          func Flag() {}
+        }
+        class New extends Foo { func N() {} }
+        enum E { A = 1, B = 3, C = 4 }
+        func Global(a: Int32) -> Int32 { return 2; }
+        ",
+    );
+
+    let changes = diff_pools(&old, &new).unwrap();
+    let lines: Vec<_> = changes.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        lines,
+        [
+            "~ enum value E.B: signature changed from `2` to `3`",
+            "+ E.C = 4",
+            "~ function Foo.Bar: signature changed from `(a: Int32) -> Int32` to `(a: Int32, b: Bool) -> Int32`",
+            "~ func Foo.Flag() -> Void: flags changed from `private final` to `private`",
+            "~ field Foo.x: signature changed from `Int32` to `Int64`",
+            "- let Foo.y: String",
+            "+ let Foo.z: String",
+            "~ func Global(a: Int32) -> Int32: body changed",
+            "- class Gone",
+            "+ class New extends Foo",
+        ]
+    );
+}
+
+#[test]
+fn diff_identical_pools() {
+    let pool = compiled("class Foo { func Bar() -> Int32 { return 1; } }");
+    assert!(diff_pools(&pool, &pool.clone()).unwrap().is_empty());
+}
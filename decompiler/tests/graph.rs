@@ -0,0 +1,95 @@
+use std::io::Cursor;
+
+use redscript::bundle::{ConstantPool, PoolIndex, ScriptBundle};
+use redscript_compiler::parser;
+use redscript_compiler::source_map::Files;
+use redscript_compiler::unit::CompilationUnit;
+use redscript_decompiler::graph::{call_graph, class_graph, Graph};
+use redscript_decompiler::xref::find_definitions;
+
+const PREDEF: &[u8] = include_bytes!("../../resources/predef.redscripts");
+
+const SOURCE: &str = "
+    class A {
+      func M() {}
+      func Run() { this.M(); }
+    }
+    class B extends A { func M() {} }
+    class C extends B {}
+    class D extends A { func M() {} }
+    func Exec(b: ref<B>) {
+      b.M();
+      Helper();
+    }
+    func Helper() {
+      let a = new A();
+      a.Run();
+    }
+    ";
+
+fn compiled(source: &str) -> ConstantPool {
+    let module = parser::parse_str(source).unwrap();
+    let mut scripts = ScriptBundle::load(&mut Cursor::new(PREDEF)).unwrap();
+    CompilationUnit::new_with_defaults(&mut scripts.pool)
+        .unwrap()
+        .compile(vec![module], &Files::default())
+        .unwrap();
+    scripts.pool
+}
+
+fn root<A>(pool: &ConstantPool, name: &str) -> PoolIndex<A> {
+    find_definitions(pool, name).unwrap()[0].cast()
+}
+
+fn edges(graph: &Graph) -> Vec<String> {
+    graph
+        .edges
+        .iter()
+        .map(|edge| {
+            let from = &graph.nodes[edge.from].name;
+            let to = &graph.nodes[edge.to].name;
+            format!("{from} -> {to} ({})", edge.kind.name())
+        })
+        .collect()
+}
+
+#[test]
+fn build_class_hierarchy() {
+    let pool = compiled(SOURCE);
+
+    let graph = class_graph(&pool, &[root(&pool, "A")], None).unwrap();
+    assert_eq!(
+        edges(&graph),
+        ["A -> B (subclass)", "A -> D (subclass)", "B -> C (subclass)"]
+    );
+    let depths: Vec<_> = graph
+        .nodes
+        .iter()
+        .map(|node| (node.name.as_str(), node.depth))
+        .collect();
+    assert_eq!(depths, [("A", 0), ("B", 1), ("D", 1), ("C", 2)]);
+
+    let graph = class_graph(&pool, &[root(&pool, "A")], Some(1)).unwrap();
+    assert_eq!(edges(&graph), ["A -> B (subclass)", "A -> D (subclass)"]);
+}
+
+#[test]
+fn resolve_virtual_calls() {
+    let pool = compiled(SOURCE);
+
+    let graph = call_graph(&pool, &[root(&pool, "Exec")], None).unwrap();
+    assert_eq!(
+        edges(&graph),
+        [
+            "Exec;B -> B.M; (virtual call)",
+            "Exec;B -> Helper; (static call)",
+            "Helper; -> A.Run; (virtual call)",
+            "A.Run; -> A.M; (virtual call)",
+            "A.Run; -> B.M; (virtual call)",
+            "A.Run; -> D.M; (virtual call)",
+        ]
+    );
+
+    let graph = call_graph(&pool, &[root(&pool, "Exec")], Some(1)).unwrap();
+    assert_eq!(graph.nodes.len(), 3);
+}
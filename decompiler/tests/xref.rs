@@ -0,0 +1,124 @@
+use std::io::Cursor;
+
+use redscript::bundle::{ConstantPool, ScriptBundle};
+use redscript_compiler::parser;
+use redscript_compiler::source_map::Files;
+use redscript_compiler::unit::CompilationUnit;
+use redscript_decompiler::xref::{declaration, find_definitions, XrefIndex};
+
+const PREDEF: &[u8] = include_bytes!("../../resources/predef.redscripts");
+
+fn compiled(source: &str) -> ConstantPool {
+    let module = parser::parse_str(source).unwrap();
+    let mut scripts = ScriptBundle::load(&mut Cursor::new(PREDEF)).unwrap();
+    CompilationUnit::new_with_defaults(&mut scripts.pool)
+        .unwrap()
+        .compile(vec![module], &Files::default())
+        .unwrap();
+    scripts.pool
+}
+
+fn xrefs(pool: &ConstantPool, name: &str) -> Vec<String> {
+    let index = XrefIndex::new(pool).unwrap();
+    let mut lines = vec![];
+    for idx in find_definitions(pool, name).unwrap() {
+        lines.push(declaration(pool.definition(idx).unwrap(), pool).unwrap());
+        for xref in index.references(idx, pool).unwrap() {
+            lines.push(format!("  {xref}"));
+        }
+    }
+    lines
+}
+
+#[test]
+fn cross_reference_definitions() {
+    let pool = compiled(
+        "
+        class Base {
+          let health: Int32;
+          func Attach() {}
+          func Heal() { this.health = this.health; }
+        }
+        class Derived extends Base {
+          func Attach() { this.Heal(); }
+        }
+        struct Point { let x: Int32; }
+        func Damage(target: ref<Base>) {
+          target.health = 0;
+          target.Attach();
+          let derived = target as Derived;
+        }
+        func Spawn() -> Point {
+          let base = new Base();
+          Damage(base);
+          let point = new Point(1);
+          point.x = point.x;
+          return point;
+        }
+        ",
+    );
+
+    assert_eq!(
+        xrefs(&pool, "Base.Attach"),
+        [
+            "func Base.Attach() -> Void",
+            "  virtual call in Damage",
+            "  override in Derived.Attach",
+        ]
+    );
+    assert_eq!(
+        xrefs(&pool, "Base.Heal"),
+        ["func Base.Heal() -> Void", "  virtual call in Derived.Attach"]
+    );
+    assert_eq!(
+        xrefs(&pool, "Base.health"),
+        [
+            "let Base.health: Int32",
+            "  read in Base.Heal",
+            "  write in Base.Heal",
+            "  write in Damage",
+        ]
+    );
+    assert_eq!(
+        xrefs(&pool, "Damage"),
+        ["func Damage(target: ref<Base>) -> Void", "  call in Spawn"]
+    );
+    assert_eq!(
+        xrefs(&pool, "Point.x"),
+        ["let Point.x: Int32", "  read in Spawn", "  write in Spawn"]
+    );
+    assert_eq!(xrefs(&pool, "Base"), ["class Base", "  allocation in Spawn"]);
+    assert_eq!(xrefs(&pool, "Derived"), ["class Derived", "  cast in Damage"]);
+    assert_eq!(xrefs(&pool, "Point"), ["class Point", "  construction in Spawn"]);
+    assert!(xrefs(&pool, "Missing").is_empty());
+}
+
+#[test]
+fn cross_reference_virtual_calls_by_receiver() {
+    let pool = compiled(
+        "
+        class Door { func Open() {} }
+        class LockedDoor extends Door { func Open() {} }
+        class Menu { func Open() {} }
+        func OpenDoor(door: ref<Door>) { door.Open(); }
+        func OpenMenu(menu: ref<Menu>) { menu.Open(); }
+        ",
+    );
+
+    assert_eq!(
+        xrefs(&pool, "Door.Open"),
+        [
+            "func Door.Open() -> Void",
+            "  override in LockedDoor.Open",
+            "  virtual call in OpenDoor",
+        ]
+    );
+    assert_eq!(
+        xrefs(&pool, "LockedDoor.Open"),
+        ["func LockedDoor.Open() -> Void", "  virtual call in OpenDoor"]
+    );
+    assert_eq!(
+        xrefs(&pool, "Menu.Open"),
+        ["func Menu.Open() -> Void", "  virtual call in OpenMenu"]
+    );
+}
@@ -0,0 +1,640 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
+use lsp_types::{
+    CompletionItem, CompletionItemKind, DiagnosticSeverity, Location, NumberOrString, Position, Range,
+    SymbolInformation, SymbolKind, Url,
+};
+use redscript::ast::{Expr, Pos, Seq, Span};
+use redscript::bundle::{ConstantPool, PoolIndex};
+use redscript::definition::{AnyDefinition, Class, Definition, Enum, Function, Local, Type, Visibility};
+use redscript_compiler::error::Error;
+use redscript_compiler::scope::{Reference, Scope, TypeId, Value};
+use redscript_compiler::source_map::{File, FilePos, Files};
+use redscript_compiler::symbol::{FunctionSignature, Symbol};
+use redscript_compiler::typechecker::{type_of, Callable, Member, TypedAst, TypedExpr};
+use redscript_compiler::unit::{CompilationUnit, TypecheckOutput};
+use redscript_compiler::visit_expr;
+
+/// Loads the sources in the workspace, replacing the contents of the documents open in the editor.
+pub fn load_files(root: &Path, documents: &HashMap<PathBuf, String>) -> io::Result<Files> {
+    let on_disk = Files::from_dir(root)?;
+    let mut files = Files::new();
+    for file in on_disk.files() {
+        let source = documents.get(file.path()).map_or(file.source(), String::as_str);
+        files.add(file.path().to_owned(), source.to_owned());
+    }
+    // documents that were never saved are not on disk yet
+    let known: HashSet<_> = on_disk.files().map(File::path).collect();
+    for (path, source) in documents {
+        if path.starts_with(root) && !known.contains(path.as_path()) {
+            files.add(path.clone(), source.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// The results of typechecking the workspace, along with the pool they refer to.
+pub struct Analysis {
+    pool: ConstantPool,
+    files: Files,
+    output: TypecheckOutput,
+}
+
+impl Analysis {
+    pub fn new(mut pool: ConstantPool, files: Files) -> Result<Self, Error> {
+        // permissive mode keeps the functions with unresolved references, so they can still be queried
+        let output = CompilationUnit::new_with_defaults(&mut pool)?.typecheck_files(&files, false, true)?;
+        Ok(Self { pool, files, output })
+    }
+
+    /// Returns the diagnostics for every file in the workspace, including the files that have none.
+    pub fn diagnostics(&self) -> HashMap<PathBuf, Vec<lsp_types::Diagnostic>> {
+        let mut result: HashMap<_, _> = self
+            .files
+            .files()
+            .map(|file| (file.path().to_owned(), vec![]))
+            .collect();
+        for diagnostic in self.output.diagnostics() {
+            let Some((path, range)) = self.range(diagnostic.span()) else {
+                continue;
+            };
+            let severity = if diagnostic.is_fatal() {
+                DiagnosticSeverity::ERROR
+            } else {
+                DiagnosticSeverity::WARNING
+            };
+            result.entry(path).or_default().push(lsp_types::Diagnostic {
+                range,
+                severity: Some(severity),
+                code: Some(NumberOrString::String(diagnostic.code().to_owned())),
+                source: Some("redscript".to_owned()),
+                message: diagnostic.message(&self.files).to_string(),
+                ..lsp_types::Diagnostic::default()
+            });
+        }
+        result
+    }
+
+    /// Describes the innermost expression at the position along with its type.
+    pub fn hover(&self, path: &Path, position: Position) -> Option<String> {
+        let pos = self.pos(path, position)?;
+        let (code, scope) = self.function_at(pos)?;
+        find_at(code, pos, |expr| self.describe(expr, scope))
+    }
+
+    /// Finds where the symbol referenced by the innermost expression at the position is defined.
+    pub fn definition(&self, path: &Path, position: Position) -> Option<Location> {
+        let pos = self.pos(path, position)?;
+        let (code, _) = self.function_at(pos)?;
+        let span = find_at(code, pos, |expr| match expr {
+            Expr::Ident(Reference::Value(Value::Local(local)), _) => self.local_definition(code, *local),
+            Expr::Ident(Reference::Symbol(Symbol::Class(idx, _) | Symbol::Struct(idx, _)), _)
+            | Expr::New(TypeId::Class(idx) | TypeId::Struct(idx), _, _) => self.source_ref(*idx),
+            Expr::Ident(Reference::Symbol(Symbol::Enum(idx)), _) | Expr::Member(_, Member::EnumMember(idx, _), _) => {
+                self.source_ref(*idx)
+            }
+            Expr::Call(Callable::Function(idx), _, _, _) | Expr::MethodCall(_, idx, _, _) => self.source_ref(*idx),
+            Expr::Member(_, Member::ClassField(idx) | Member::StructField(idx), _) => self.source_ref(*idx),
+            _ => None,
+        })?;
+        let (path, range) = self.range(span)?;
+        Some(Location::new(Url::from_file_path(path).ok()?, range))
+    }
+
+    /// Completes the members of the expression preceding a dot at the position.
+    pub fn completions(&self, path: &Path, position: Position) -> Option<Vec<CompletionItem>> {
+        let file = self.file(path)?;
+        let pos = to_pos(file, position)?;
+        let context = self.context_at(file, pos)?;
+        let offset = usize::from(pos) - usize::from(file.byte_offset());
+        let chain = receiver_chain(&file.source()[..offset])?;
+
+        let mut receiver = self.resolve_root(&context, &chain[0])?;
+        for link in &chain[1..] {
+            let (Receiver::Instance(class) | Receiver::Static(class)) = receiver else {
+                return None;
+            };
+            let type_ = if link.is_call {
+                let candidates = Scope::resolve_method(link.name.into(), class, &self.pool).ok()?;
+                self.pool.function(*candidates.functions.first()?).ok()?.return_type?
+            } else {
+                self.pool
+                    .field(Scope::resolve_field(link.name.into(), class, &self.pool).ok()?)
+                    .ok()?
+                    .type_
+            };
+            receiver = self.receiver_of(&context.scope, type_)?;
+        }
+
+        let items = match receiver {
+            Receiver::Instance(class) => self.class_members(class, false, &context),
+            Receiver::Static(class) => self.class_members(class, true, &context),
+            Receiver::Enum(enum_) => self.enum_members(enum_),
+        };
+        Some(items)
+    }
+
+    /// Returns the definitions compiled from source with names containing the query.
+    pub fn symbols(&self, query: &str) -> Vec<SymbolInformation> {
+        let query = query.to_lowercase();
+        let mut symbols = vec![];
+        for source_ref in self.output.source_refs() {
+            let Ok(def) = self.pool.definition(source_ref.index()) else {
+                continue;
+            };
+            let Some((name, kind)) = self.symbol_name_and_kind(source_ref.index(), def) else {
+                continue;
+            };
+            if !name.to_lowercase().contains(&query) {
+                continue;
+            }
+            let Some((path, range)) = self.range(Span::new(source_ref.pos(), source_ref.pos())) else {
+                continue;
+            };
+            let Ok(uri) = Url::from_file_path(path) else {
+                continue;
+            };
+            let container_name = (!def.parent.is_undefined())
+                .then(|| self.pool.def_name(def.parent).ok())
+                .flatten()
+                .map(|name| name.as_ref().to_owned());
+
+            #[allow(deprecated)]
+            symbols.push(SymbolInformation {
+                name,
+                kind,
+                tags: None,
+                deprecated: None,
+                location: Location::new(uri, range),
+                container_name,
+            });
+        }
+        symbols
+    }
+
+    fn describe(&self, expr: &TypedExpr, scope: &Scope) -> Option<String> {
+        let res = match expr {
+            Expr::Ident(Reference::Value(Value::Local(idx)), _) | Expr::Declare(idx, _, _, _) => {
+                let local = self.pool.local(*idx).ok()?;
+                format!("let {}: {}", self.local_name(*idx)?, self.type_name(scope, local.type_))
+            }
+            Expr::Ident(Reference::Value(Value::Parameter(idx)), _) => {
+                let param = self.pool.parameter(*idx).ok()?;
+                format!(
+                    "{}: {}",
+                    self.pool.def_name(*idx).ok()?,
+                    self.type_name(scope, param.type_)
+                )
+            }
+            Expr::Ident(Reference::Symbol(Symbol::Class(idx, _)), _) => {
+                format!("class {}", self.pool.def_name(*idx).ok()?)
+            }
+            Expr::Ident(Reference::Symbol(Symbol::Struct(idx, _)), _) => {
+                format!("struct {}", self.pool.def_name(*idx).ok()?)
+            }
+            Expr::Ident(Reference::Symbol(Symbol::Enum(idx)), _) => format!("enum {}", self.pool.def_name(*idx).ok()?),
+            Expr::Call(Callable::Function(idx), _, _, _) | Expr::MethodCall(_, idx, _, _) => {
+                self.function_signature(scope, *idx)?
+            }
+            Expr::Member(_, Member::ClassField(idx) | Member::StructField(idx), _) => {
+                let field = self.pool.field(*idx).ok()?;
+                let owner = self.pool.def_name(self.pool.definition(*idx).ok()?.parent).ok()?;
+                let name = self.pool.def_name(*idx).ok()?;
+                format!("let {owner}.{name}: {}", self.type_name(scope, field.type_))
+            }
+            Expr::Member(_, Member::EnumMember(enum_, member), _) => {
+                let value = match self.pool.definition(*member).ok()?.value {
+                    AnyDefinition::EnumValue(value) => value,
+                    _ => return None,
+                };
+                let name = self.pool.def_name(*member).ok()?;
+                format!("{}.{name} = {value}", self.pool.def_name(*enum_).ok()?)
+            }
+            Expr::Call(Callable::Intrinsic(..), _, _, _) | Expr::New(..) | Expr::This(_) | Expr::Super(_) => {
+                type_of(expr, scope, &self.pool)
+                    .ok()?
+                    .pretty(&self.pool)
+                    .ok()?
+                    .to_string()
+            }
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    fn function_signature(&self, scope: &Scope, idx: PoolIndex<Function>) -> Option<String> {
+        let fun = self.pool.function(idx).ok()?;
+        let name = self.pool.def_name(idx).ok()?;
+        let params = fun
+            .parameters
+            .iter()
+            .map(|&param| {
+                let type_ = self.pool.parameter(param).ok()?.type_;
+                Some(format!(
+                    "{}: {}",
+                    self.pool.def_name(param).ok()?,
+                    self.type_name(scope, type_)
+                ))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let return_type = fun
+            .return_type
+            .map(|type_| format!(" -> {}", self.type_name(scope, type_)))
+            .unwrap_or_default();
+
+        let parent = self.pool.definition(idx).ok()?.parent;
+        let owner = if matches!(
+            self.pool.definition(parent).map(|def| &def.value),
+            Ok(AnyDefinition::Class(_))
+        ) {
+            format!("{}.", self.pool.def_name(parent).ok()?)
+        } else {
+            String::new()
+        };
+        let qualifier = if fun.flags.is_static() && !owner.is_empty() {
+            "static "
+        } else {
+            ""
+        };
+        Some(format!(
+            "{qualifier}func {owner}{}({}){return_type}",
+            FunctionSignature::from_raw(&name).name(),
+            params.iter().format(", ")
+        ))
+    }
+
+    fn type_name(&self, scope: &Scope, type_: PoolIndex<Type>) -> String {
+        scope
+            .resolve_type_from_pool(type_, &self.pool)
+            .ok()
+            .and_then(|type_| type_.pretty(&self.pool).ok())
+            .map_or_else(|| "?".to_owned(), |name| name.to_string())
+    }
+
+    // locals are stored with mangled names to keep them unique within a function
+    fn local_name(&self, idx: PoolIndex<Local>) -> Option<String> {
+        let name = self.pool.def_name(idx).ok()?;
+        let name = name.split_once("$local$").map_or(&*name, |(name, _)| name);
+        Some(name.to_owned())
+    }
+
+    fn local_definition(&self, code: &Seq<TypedAst>, local: PoolIndex<Local>) -> Option<Span> {
+        let mut result = None;
+        walk(code, |expr| {
+            match expr {
+                Expr::Declare(idx, _, _, span) | Expr::ForIn(idx, _, _, span) if *idx == local => {
+                    result = Some(*span);
+                }
+                _ => {}
+            }
+            result.is_none()
+        });
+        result
+    }
+
+    fn source_ref<A>(&self, idx: PoolIndex<A>) -> Option<Span> {
+        let idx: PoolIndex<Definition> = idx.cast();
+        let source_ref = self.output.source_refs().iter().find(|r| r.index() == idx)?;
+        Some(Span::new(source_ref.pos(), source_ref.pos()))
+    }
+
+    fn symbol_name_and_kind(&self, idx: PoolIndex<Definition>, def: &Definition) -> Option<(String, SymbolKind)> {
+        let name = self.pool.def_name(idx).ok()?;
+        let res = match &def.value {
+            AnyDefinition::Class(class) if class.flags.is_struct() => (name.as_ref().to_owned(), SymbolKind::STRUCT),
+            AnyDefinition::Class(_) => (name.as_ref().to_owned(), SymbolKind::CLASS),
+            AnyDefinition::Enum(_) => (name.as_ref().to_owned(), SymbolKind::ENUM),
+            AnyDefinition::Field(_) => (name.as_ref().to_owned(), SymbolKind::FIELD),
+            AnyDefinition::Function(_) => {
+                let kind = if def.parent.is_undefined() {
+                    SymbolKind::FUNCTION
+                } else {
+                    SymbolKind::METHOD
+                };
+                (FunctionSignature::from_raw(&name).name().to_owned(), kind)
+            }
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// Returns the body and the scope of the innermost function at the position.
+    fn function_at(&self, pos: Pos) -> Option<(&Seq<TypedAst>, &Scope)> {
+        let fun = self
+            .output
+            .functions()
+            .iter()
+            .filter(|fun| fun.span.contains(pos))
+            .min_by_key(|fun| usize::from(fun.span.high) - usize::from(fun.span.low))?;
+        Some((&fun.code, &fun.scope))
+    }
+
+    fn context_at(&self, file: &File, pos: Pos) -> Option<Context<'_>> {
+        if let Some(fun) = self
+            .output
+            .functions()
+            .iter()
+            .filter(|fun| fun.span.contains(pos))
+            .min_by_key(|fun| usize::from(fun.span.high) - usize::from(fun.span.low))
+        {
+            return Some(Context {
+                function: Some(fun.index),
+                class: fun.scope.class,
+                locals: &fun.locals,
+                scope: fun.scope.clone(),
+            });
+        }
+
+        // the function could not be typechecked, the closest declaration before the position is used instead
+        let function = self
+            .output
+            .source_refs()
+            .iter()
+            .filter(|r| file.span().contains(r.pos()) && r.pos() <= pos)
+            .filter(|r| {
+                matches!(
+                    self.pool.definition(r.index()).map(|def| &def.value),
+                    Ok(AnyDefinition::Function(_))
+                )
+            })
+            .max_by_key(|r| r.pos())
+            .map(|r| r.index().cast::<Function>());
+        let class = function
+            .and_then(|fun| self.pool.definition(fun).ok())
+            .map(|def| def.parent.cast::<Class>())
+            .filter(|class| !class.is_undefined());
+        Some(Context {
+            function,
+            class,
+            locals: &[],
+            scope: Scope::new(&self.pool).ok()?,
+        })
+    }
+
+    fn resolve_root(&self, context: &Context<'_>, link: &Link<'_>) -> Option<Receiver> {
+        match link.name {
+            "this" if !link.is_call => return context.class.map(Receiver::Instance),
+            "super" if !link.is_call => {
+                let base = self.pool.class(context.class?).ok()?.base;
+                return (!base.is_undefined()).then_some(Receiver::Instance(base));
+            }
+            _ => {}
+        }
+        if link.is_call {
+            let candidates = context.scope.resolve_function(link.name.into()).ok()?;
+            let return_type = self.pool.function(*candidates.functions.first()?).ok()?.return_type?;
+            return self.receiver_of(&context.scope, return_type);
+        }
+
+        let local = context
+            .locals
+            .iter()
+            .rev()
+            .find(|&&local| self.local_name(local).as_deref() == Some(link.name));
+        if let Some(&local) = local {
+            return self.receiver_of(&context.scope, self.pool.local(local).ok()?.type_);
+        }
+        if let Some(function) = context.function {
+            let param = self
+                .pool
+                .function(function)
+                .ok()?
+                .parameters
+                .iter()
+                .copied()
+                .find(|&param| {
+                    self.pool
+                        .def_name(param)
+                        .map_or(false, |name| name.as_ref() == link.name)
+                });
+            if let Some(param) = param {
+                return self.receiver_of(&context.scope, self.pool.parameter(param).ok()?.type_);
+            }
+        }
+
+        match context.scope.resolve_symbol(link.name.into()).ok()? {
+            Symbol::Class(idx, _) | Symbol::Struct(idx, _) => Some(Receiver::Static(idx)),
+            Symbol::Enum(idx) => Some(Receiver::Enum(idx)),
+            _ => None,
+        }
+    }
+
+    fn receiver_of(&self, scope: &Scope, type_: PoolIndex<Type>) -> Option<Receiver> {
+        match scope.resolve_type_from_pool(type_, &self.pool).ok()?.unwrapped() {
+            TypeId::Class(idx) | TypeId::Struct(idx) => Some(Receiver::Instance(*idx)),
+            _ => None,
+        }
+    }
+
+    fn class_members(&self, class: PoolIndex<Class>, statics: bool, context: &Context<'_>) -> Vec<CompletionItem> {
+        let mut items = vec![];
+        let mut seen = HashSet::new();
+        let mut current = class;
+        while let Ok(def) = self.pool.class(current) {
+            let accessible = |member: PoolIndex<Definition>, visibility: Visibility| {
+                visibility != Visibility::Private
+                    || context.class == Some(current)
+                    || context.scope.has_implicit_visibility(member)
+            };
+
+            for &field_idx in def.fields.iter().filter(|_| !statics) {
+                let (Ok(name), Ok(field)) = (self.pool.def_name(field_idx), self.pool.field(field_idx)) else {
+                    continue;
+                };
+                if accessible(field_idx.cast(), field.visibility) && seen.insert(name.clone()) {
+                    let detail = self.type_name(&context.scope, field.type_);
+                    items.push(completion(&name, CompletionItemKind::FIELD, Some(detail)));
+                }
+            }
+            for &method in &def.functions {
+                let (Ok(name), Ok(fun)) = (self.pool.def_name(method), self.pool.function(method)) else {
+                    continue;
+                };
+                let name = FunctionSignature::from_raw(&name).name().to_owned();
+                if fun.flags.is_static() == statics
+                    && accessible(method.cast(), fun.visibility)
+                    && seen.insert(name.as_str().into())
+                {
+                    let detail = self.function_signature(&context.scope, method);
+                    items.push(completion(&name, CompletionItemKind::METHOD, detail));
+                }
+            }
+            current = def.base;
+        }
+        items
+    }
+
+    fn enum_members(&self, enum_: PoolIndex<Enum>) -> Vec<CompletionItem> {
+        let Ok(def) = self.pool.enum_(enum_) else {
+            return vec![];
+        };
+        def.members
+            .iter()
+            .filter_map(|&member| self.pool.def_name(member).ok())
+            .map(|name| completion(&name, CompletionItemKind::ENUM_MEMBER, None))
+            .collect()
+    }
+
+    fn file(&self, path: &Path) -> Option<&File> {
+        self.files.files().find(|file| file.path() == path)
+    }
+
+    fn pos(&self, path: &Path, position: Position) -> Option<Pos> {
+        to_pos(self.file(path)?, position)
+    }
+
+    fn range(&self, span: Span) -> Option<(PathBuf, Range)> {
+        let loc = self.files.lookup(span)?;
+        let range = Range::new(to_position(loc.file, loc.start), to_position(loc.file, loc.end));
+        Some((loc.file.path().to_owned(), range))
+    }
+}
+
+/// The function enclosing the position at which completions are requested.
+struct Context<'a> {
+    function: Option<PoolIndex<Function>>,
+    class: Option<PoolIndex<Class>>,
+    locals: &'a [PoolIndex<Local>],
+    scope: Scope,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Receiver {
+    Instance(PoolIndex<Class>),
+    Static(PoolIndex<Class>),
+    Enum(PoolIndex<Enum>),
+}
+
+/// A segment of a member access chain like `this.field.Method().`.
+#[derive(Debug, PartialEq, Eq)]
+struct Link<'a> {
+    name: &'a str,
+    is_call: bool,
+}
+
+/// Splits the member access chain ending with a dot at the end of the text, ignoring a partially typed member name.
+fn receiver_chain(text: &str) -> Option<Vec<Link<'_>>> {
+    let is_ident_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut rest = text.trim_end_matches(is_ident_char).strip_suffix('.')?;
+    let mut links = vec![];
+    loop {
+        let is_call = rest.ends_with(')');
+        if is_call {
+            let mut depth = 0;
+            let open = rest.char_indices().rev().find_map(|(i, c)| {
+                match c {
+                    ')' => depth += 1,
+                    '(' => depth -= 1,
+                    _ => {}
+                }
+                (depth == 0).then_some(i)
+            })?;
+            rest = &rest[..open];
+        }
+        let name_start = rest.trim_end_matches(is_ident_char).len();
+        let name = &rest[name_start..];
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        links.push(Link { name, is_call });
+        match rest[..name_start].strip_suffix('.') {
+            Some(prefix) => rest = prefix,
+            None => break,
+        }
+    }
+    links.reverse();
+    Some(links)
+}
+
+fn completion(name: &str, kind: CompletionItemKind, detail: Option<String>) -> CompletionItem {
+    CompletionItem {
+        label: name.to_owned(),
+        kind: Some(kind),
+        detail,
+        ..CompletionItem::default()
+    }
+}
+
+/// Runs the query on every expression containing the position and returns the result for the innermost one.
+fn find_at<A>(code: &Seq<TypedAst>, pos: Pos, mut query: impl FnMut(&TypedExpr) -> Option<A>) -> Option<A> {
+    let mut result = None;
+    walk(code, |expr| {
+        if !expr.span().contains(pos) {
+            return false;
+        }
+        if let Some(res) = query(expr) {
+            result = Some(res);
+        }
+        true
+    });
+    result
+}
+
+/// Visits the expressions in a function body, the callback returns whether to visit the children as well.
+fn walk(code: &Seq<TypedAst>, fun: impl FnMut(&TypedExpr) -> bool) {
+    struct Walker<F>(F);
+
+    impl<F: FnMut(&TypedExpr) -> bool> Walker<F> {
+        fn visit(&mut self, expr: &TypedExpr) {
+            if (self.0)(expr) {
+                visit_expr!(self, visit, expr);
+            }
+        }
+    }
+
+    let mut walker = Walker(fun);
+    for expr in &code.exprs {
+        walker.visit(expr);
+    }
+}
+
+/// Converts an LSP position to a position in the source map, LSP columns are in UTF-16 code units.
+fn to_pos(file: &File, position: Position) -> Option<Pos> {
+    let source = file.source();
+    let line_start = match position.line {
+        0 => 0,
+        line => source.match_indices('\n').nth(line as usize - 1)?.0 + 1,
+    };
+    let line = source[line_start..].split('\n').next().unwrap_or_default();
+    let mut units = 0;
+    let col = line
+        .char_indices()
+        .find(|(_, c)| {
+            let found = units >= position.character as usize;
+            units += c.len_utf16();
+            found
+        })
+        .map_or(line.len(), |(i, _)| i);
+    Some(file.byte_offset() + line_start + col)
+}
+
+fn to_position(file: &File, pos: FilePos) -> Position {
+    let character = file
+        .enclosing_line(pos.line)
+        .chars()
+        .take(pos.col)
+        .map(char::len_utf16)
+        .sum::<usize>();
+    Position::new(pos.line as u32, character as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_receiver_chains() {
+        let link = |name, is_call| Link { name, is_call };
+
+        assert_eq!(receiver_chain("  let x = this."), Some(vec![link("this", false)]));
+        assert_eq!(
+            receiver_chain("foo.Bar(a, (b)).ba"),
+            Some(vec![link("foo", false), link("Bar", true)])
+        );
+        assert_eq!(receiver_chain("x + 1."), None);
+        assert_eq!(receiver_chain("Value"), None);
+    }
+}
@@ -0,0 +1,88 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use argh::FromArgs;
+use flexi_logger::{LevelFilter, LogSpecBuilder, Logger};
+use lsp_server::Connection;
+use lsp_types::{
+    CompletionOptions, HoverProviderCapability, InitializeParams, OneOf, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind,
+};
+use redscript::bundle::{ConstantPool, ScriptBundle};
+use server::Server;
+
+mod analysis;
+mod server;
+
+/// redscript language server, communicates over stdio
+#[derive(Debug, FromArgs)]
+struct Args {
+    /// path to a .redscripts file to typecheck against, can also be passed in the
+    /// 'bundlePath' initialization option
+    #[argh(option, short = 'b')]
+    bundle: Option<PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Args = argh::from_env();
+    // stdout is reserved for the protocol
+    Logger::with(LogSpecBuilder::new().default(LevelFilter::Info).build())
+        .log_to_stderr()
+        .start()
+        .expect("info logger should always start");
+
+    run(args).map_err(|err| {
+        log::error!("{}", err);
+        err
+    })
+}
+
+fn run(args: Args) -> anyhow::Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".to_owned()]),
+            ..CompletionOptions::default()
+        }),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    let params = connection.initialize(serde_json::to_value(capabilities)?)?;
+    let params: InitializeParams = serde_json::from_value(params)?;
+
+    let bundle_path = params
+        .initialization_options
+        .as_ref()
+        .and_then(|opts| opts.get("bundlePath"))
+        .and_then(|path| path.as_str())
+        .map(PathBuf::from)
+        .or(args.bundle)
+        .context("No bundle path provided, pass it with --bundle or the 'bundlePath' initialization option")?;
+    let pool = load_pool(&bundle_path)?;
+
+    #[allow(deprecated)]
+    let root = params
+        .workspace_folders
+        .and_then(|folders| folders.into_iter().next())
+        .map(|folder| folder.uri)
+        .or(params.root_uri)
+        .and_then(|uri| uri.to_file_path().ok())
+        .context("The client did not provide a workspace root")?;
+    log::info!("Serving {}", root.display());
+
+    Server::new(connection, root, pool).run()?;
+    io_threads.join()?;
+    Ok(())
+}
+
+fn load_pool(path: &Path) -> anyhow::Result<ConstantPool> {
+    let bytes = std::fs::read(path).context("Failed to open the script cache")?;
+    let bundle = ScriptBundle::load(&mut io::Cursor::new(bytes))
+        .map_err(|err| anyhow::anyhow!("Failed to load the script cache: {err}"))?;
+    Ok(bundle.pool)
+}
@@ -0,0 +1,181 @@
+use std::path::{Path, PathBuf};
+
+use hashbrown::{HashMap, HashSet};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidChangeWatchedFiles, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, Request as _, WorkspaceSymbolRequest};
+use lsp_types::{
+    CompletionResponse, DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    GotoDefinitionResponse, Hover, HoverContents, MarkupContent, MarkupKind, PublishDiagnosticsParams,
+    TextDocumentPositionParams, Url, WorkspaceSymbolResponse,
+};
+use redscript::bundle::ConstantPool;
+
+use crate::analysis::{self, Analysis};
+
+pub struct Server {
+    connection: Connection,
+    root: PathBuf,
+    pool: ConstantPool,
+    // contents of the documents open in the editor, they take precedence over the files on disk
+    documents: HashMap<PathBuf, String>,
+    analysis: Option<Analysis>,
+    published: HashSet<PathBuf>,
+}
+
+impl Server {
+    pub fn new(connection: Connection, root: PathBuf, pool: ConstantPool) -> Self {
+        Self {
+            connection,
+            root,
+            pool,
+            documents: HashMap::new(),
+            analysis: None,
+            published: HashSet::new(),
+        }
+    }
+
+    pub fn run(mut self) -> anyhow::Result<()> {
+        self.analyze()?;
+
+        while let Ok(msg) = self.connection.receiver.recv() {
+            match msg {
+                Message::Request(req) => {
+                    if self.connection.handle_shutdown(&req)? {
+                        break;
+                    }
+                    let response = self.handle_request(req);
+                    self.connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => self.handle_notification(notification)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, req: Request) -> Response {
+        match req.method.as_str() {
+            HoverRequest::METHOD => handle::<HoverRequest>(req, |params| {
+                self.at_position(&params.text_document_position_params, Analysis::hover)
+                    .map(|value| Hover {
+                        contents: HoverContents::Markup(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value: format!("```redscript\n{value}\n```"),
+                        }),
+                        range: None,
+                    })
+            }),
+            GotoDefinition::METHOD => handle::<GotoDefinition>(req, |params| {
+                self.at_position(&params.text_document_position_params, Analysis::definition)
+                    .map(GotoDefinitionResponse::Scalar)
+            }),
+            Completion::METHOD => handle::<Completion>(req, |params| {
+                self.at_position(&params.text_document_position, Analysis::completions)
+                    .map(CompletionResponse::Array)
+            }),
+            WorkspaceSymbolRequest::METHOD => handle::<WorkspaceSymbolRequest>(req, |params| {
+                let analysis = self.analysis.as_ref()?;
+                Some(WorkspaceSymbolResponse::Flat(analysis.symbols(&params.query)))
+            }),
+            method => {
+                let message = format!("unsupported request: {method}");
+                Response::new_err(req.id, ErrorCode::MethodNotFound as i32, message)
+            }
+        }
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> anyhow::Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+                if let Ok(path) = params.text_document.uri.to_file_path() {
+                    self.documents.insert(path, params.text_document.text);
+                }
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+                // the server only supports full document sync
+                if let (Ok(path), Some(change)) =
+                    (params.text_document.uri.to_file_path(), params.content_changes.pop())
+                {
+                    self.documents.insert(path, change.text);
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+                if let Ok(path) = params.text_document.uri.to_file_path() {
+                    self.documents.remove(&path);
+                }
+            }
+            DidSaveTextDocument::METHOD | DidChangeWatchedFiles::METHOD => {}
+            _ => return Ok(()),
+        }
+        self.analyze()
+    }
+
+    fn at_position<A, F>(&self, params: &TextDocumentPositionParams, query: F) -> Option<A>
+    where
+        F: FnOnce(&Analysis, &Path, lsp_types::Position) -> Option<A>,
+    {
+        let path = params.text_document.uri.to_file_path().ok()?;
+        query(self.analysis.as_ref()?, &path, params.position)
+    }
+
+    fn analyze(&mut self) -> anyhow::Result<()> {
+        let files = match analysis::load_files(&self.root, &self.documents) {
+            Ok(files) => files,
+            Err(err) => {
+                log::error!("Failed to load the source files: {err}");
+                return Ok(());
+            }
+        };
+        let analysis = match Analysis::new(self.pool.clone(), files) {
+            Ok(analysis) => analysis,
+            Err(err) => {
+                log::error!("Unexpected error during typechecking: {err}");
+                return Ok(());
+            }
+        };
+
+        let mut published = HashSet::new();
+        for (path, diagnostics) in analysis.diagnostics() {
+            self.publish(&path, diagnostics)?;
+            published.insert(path);
+        }
+        // files that are gone still need their diagnostics cleared
+        for path in self.published.difference(&published) {
+            self.publish(path, vec![])?;
+        }
+        self.published = published;
+        self.analysis = Some(analysis);
+        Ok(())
+    }
+
+    fn publish(&self, path: &Path, diagnostics: Vec<lsp_types::Diagnostic>) -> anyhow::Result<()> {
+        let Ok(uri) = Url::from_file_path(path) else {
+            return Ok(());
+        };
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_owned(), params);
+        self.connection.sender.send(Message::Notification(notification))?;
+        Ok(())
+    }
+}
+
+fn handle<R>(req: Request, handler: impl FnOnce(R::Params) -> R::Result) -> Response
+where
+    R: lsp_types::request::Request,
+{
+    match serde_json::from_value::<R::Params>(req.params) {
+        Ok(params) => Response::new_ok(req.id, handler(params)),
+        Err(err) => Response::new_err(req.id, ErrorCode::InvalidParams as i32, err.to_string()),
+    }
+}
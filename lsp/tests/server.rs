@@ -0,0 +1,243 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::{env, fs};
+
+use lsp_types::Url;
+use serde_json::{json, Value};
+
+const SOURCE: &str = "
+native func OperatorAdd(a: Int32, b: Int32) -> Int32
+
+class Counter {
+    let count: Int32;
+
+    func Increment(by: Int32) -> Int32 {
+        this.count = this.count + by;
+        return this.count;
+    }
+}
+
+func Test() -> Int32 {
+    let counter = new Counter();
+    let unused = 1;
+    return counter.Increment(2);
+}
+";
+
+/// A minimal LSP client talking to the server binary over stdio.
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: i64,
+    notifications: Vec<Value>,
+}
+
+impl Client {
+    fn start(root: &Path) -> Self {
+        let bundle = Path::new(env!("CARGO_MANIFEST_DIR")).join("../resources/predef.redscripts");
+        let mut child = Command::new(env!("CARGO_BIN_EXE_redscript-lsp"))
+            .arg("--bundle")
+            .arg(bundle)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut client = Self {
+            child,
+            stdin,
+            stdout,
+            next_id: 0,
+            notifications: vec![],
+        };
+
+        let root = Url::from_directory_path(root).unwrap();
+        client.request("initialize", json!({ "rootUri": root, "capabilities": {} }));
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let msg = self.receive();
+            if msg["id"] == json!(id) {
+                assert!(msg.get("error").is_none(), "{msg}");
+                return msg["result"].clone();
+            }
+            self.notifications.push(msg);
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Waits for the next diagnostics published for a file.
+    fn diagnostics(&mut self, uri: &Url) -> Value {
+        let is_match =
+            |msg: &Value| msg["method"] == "textDocument/publishDiagnostics" && msg["params"]["uri"] == uri.as_str();
+        if let Some(idx) = self.notifications.iter().position(is_match) {
+            return self.notifications.remove(idx)["params"]["diagnostics"].clone();
+        }
+        loop {
+            let msg = self.receive();
+            if is_match(&msg) {
+                return msg["params"]["diagnostics"].clone();
+            }
+        }
+    }
+
+    fn send(&mut self, msg: &Value) {
+        let body = msg.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            self.stdout.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(": ") {
+                Some(("Content-Length", value)) => length = value.parse().unwrap(),
+                Some(_) => {}
+                None => break,
+            }
+        }
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn shutdown(mut self) {
+        self.request("shutdown", Value::Null);
+        self.notify("exit", Value::Null);
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+fn position_of(source: &str, needle: &str) -> Value {
+    let offset = source.find(needle).unwrap();
+    let line = source[..offset].matches('\n').count();
+    let character = offset - source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    json!({ "line": line, "character": character })
+}
+
+fn workspace(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("redscript-lsp-{name}-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("main.reds"), SOURCE).unwrap();
+    root
+}
+
+#[test]
+fn serve_language_features() {
+    let root = workspace("features");
+    let uri = Url::from_file_path(root.join("main.reds")).unwrap();
+    let mut client = Client::start(&root);
+
+    let diagnostics = client.diagnostics(&uri);
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1, "{diagnostics}");
+    assert_eq!(diagnostics[0]["code"], "UNUSED_LOCAL");
+    assert_eq!(diagnostics[0]["range"]["start"], position_of(SOURCE, "let unused"));
+
+    let document = json!({ "uri": uri });
+    let hover = client.request(
+        "textDocument/hover",
+        json!({ "textDocument": document, "position": position_of(SOURCE, "counter.Increment") }),
+    );
+    assert!(
+        hover["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("let counter: ref<Counter>"),
+        "{hover}"
+    );
+    let hover = client.request(
+        "textDocument/hover",
+        json!({ "textDocument": document, "position": position_of(SOURCE, "Increment(2)") }),
+    );
+    assert!(
+        hover["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("func Counter.Increment(by: Int32) -> Int32"),
+        "{hover}"
+    );
+
+    let definition = client.request(
+        "textDocument/definition",
+        json!({ "textDocument": document, "position": position_of(SOURCE, "Increment(2)") }),
+    );
+    assert_eq!(definition["uri"], uri.as_str());
+    assert_eq!(definition["range"]["start"], position_of(SOURCE, "func Increment"));
+
+    let symbols = client.request("workspace/symbol", json!({ "query": "count" }));
+    let mut names: Vec<_> = symbols
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|symbol| symbol["name"].as_str().unwrap())
+        .collect();
+    names.sort_unstable();
+    assert_eq!(names, ["Counter", "count"]);
+
+    client.shutdown();
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn complete_members_of_edited_document() {
+    let root = workspace("completion");
+    let uri = Url::from_file_path(root.join("main.reds")).unwrap();
+    let mut client = Client::start(&root);
+    client.diagnostics(&uri);
+
+    let edited = SOURCE.replace("return counter.Increment(2);", "counter.\n    return 0;");
+    client.notify(
+        "textDocument/didOpen",
+        json!({ "textDocument": { "uri": uri, "languageId": "redscript", "version": 1, "text": SOURCE } }),
+    );
+    client.diagnostics(&uri);
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [{ "text": edited }]
+        }),
+    );
+    let diagnostics = client.diagnostics(&uri);
+    assert!(
+        diagnostics
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|diagnostic| diagnostic["code"] == "SYNTAX_ERR"),
+        "{diagnostics}"
+    );
+
+    let mut position = position_of(&edited, "counter.\n");
+    position["character"] = json!(position["character"].as_u64().unwrap() + "counter.".len() as u64);
+    let completions = client.request(
+        "textDocument/completion",
+        json!({ "textDocument": { "uri": uri }, "position": position }),
+    );
+    let mut labels: Vec<_> = completions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    labels.sort_unstable();
+    assert_eq!(labels, ["Increment", "count"]);
+
+    client.shutdown();
+    fs::remove_dir_all(root).unwrap();
+}
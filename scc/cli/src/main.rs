@@ -74,6 +74,8 @@ fn run(opts: Opts, r6_dir: &Path) -> anyhow::Result<bool> {
         custom_cache_file: Some(custom_cache_file.into()),
         output_cache_file: opts.output_cache_file.map(PathBuf::into_boxed_path),
         additional_script_paths,
+        optimize: opts.optimize,
+        threads: opts.threads,
     };
 
     let is_success = SccApi::load()?.compile(settings.into());
@@ -19,6 +19,8 @@ pub unsafe extern "C" fn scc_settings_new(r6_dir: *const i8) -> Box<SccSettings>
         custom_cache_file: None,
         output_cache_file: None,
         additional_script_paths: vec![],
+        optimize: false,
+        threads: 1,
     })
 }
 
@@ -169,6 +171,8 @@ pub struct SccSettings {
     pub custom_cache_file: Option<Box<Path>>,
     pub output_cache_file: Option<Box<Path>>,
     pub additional_script_paths: Vec<Box<Path>>,
+    pub optimize: bool,
+    pub threads: u8,
 }
 
 #[derive(Debug)]
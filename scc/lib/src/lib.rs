@@ -19,6 +19,7 @@ use redscript::bundle::{ConstantPool, ScriptBundle};
 use redscript::definition::{Definition, Enum};
 use redscript_compiler::error::Error;
 use redscript_compiler::source_map::Files;
+use redscript_compiler::typechecker::VisibilityCheck;
 use redscript_compiler::unit::CompilationUnit;
 use timestamp::CompileTimestamp;
 
@@ -76,7 +77,14 @@ fn try_compile(settings: &SccSettings) -> anyhow::Result<SccResult> {
 
     let files = Files::from_dirs(&script_paths).context("Could not load script sources")?;
 
-    match try_compile_files(&settings.r6_dir, &cache_path, output_cache_path, files) {
+    match try_compile_files(
+        &settings.r6_dir,
+        &cache_path,
+        output_cache_path,
+        files,
+        settings.optimize,
+        settings.threads.into(),
+    ) {
         Ok(output) => {
             log::info!("Output successfully saved to {}", output_cache_path.display());
             Ok(output)
@@ -101,6 +109,8 @@ fn try_compile_files(
     cache_path: &Path,
     output_cache_path: &Path,
     files: Files,
+    optimize: bool,
+    threads: usize,
 ) -> anyhow::Result<SccResult> {
     let backup_path = cache_path.with_extension(BACKUP_FILE_EXT);
     let timestamp_path = cache_path.with_extension(TIMESTAMP_FILE_EXT);
@@ -180,12 +190,14 @@ fn try_compile_files(
         let (map, _) = vmap::Map::with_options()
             .open(input_cache_path)
             .context("Failed to open the original script cache file")?;
-        ScriptBundle::load(&mut io::Cursor::new(map.as_ref())).context("Failed to load the original script cache")?
+        ScriptBundle::load(&mut io::Cursor::new(map.as_ref()))
+            .map_err(|err| anyhow::anyhow!("Failed to load the original script cache: {err}"))?
     };
     #[cfg(not(feature = "mmap"))]
     let mut bundle = {
         let file = File::open(input_cache_path).context("Failed to open the original script cache file")?;
-        ScriptBundle::load(&mut io::BufReader::new(file)).context("Failed to load the original script cache")?
+        ScriptBundle::load(&mut io::BufReader::new(file))
+            .map_err(|err| anyhow::anyhow!("Failed to load the original script cache: {err}"))?
     };
 
     if check_for_redscript_signature_def(&bundle.pool) {
@@ -203,8 +215,12 @@ fn try_compile_files(
             files.display(&default_scripts_dir)
         );
     }
+    // mods compiled before visibility was enforced are allowed to access private members for now
     match CompilationUnit::new(&mut bundle.pool, vec![])
         .map_err(|err| anyhow::anyhow!("Failed to create the compilation unit: {err}"))?
+        .with_visibility_check(VisibilityCheck::Warn)
+        .with_optimizations(optimize)
+        .with_threads(threads)
         .compile_and_report(&files)
     {
         Ok(compilation) => {